@@ -90,6 +90,10 @@ use utxo::{GenerateTxError, UtxoFeeDetails, UtxoTx};
 pub mod qrc20;
 use qrc20::{qrc20_coin_from_conf_and_request, Qrc20Coin, Qrc20FeeDetails};
 
+#[cfg(feature = "sim_coin")] pub mod sim_coin;
+#[cfg(feature = "sim_coin")]
+pub use sim_coin::{SimChain, SimCoin, SimMisbehaviour, SimTx};
+
 #[doc(hidden)]
 #[allow(unused_variables)]
 pub mod test_coin;
@@ -126,9 +130,13 @@ pub trait Transaction: fmt::Debug + 'static {
 pub enum TransactionEnum {
     UtxoTx(UtxoTx),
     SignedEthTx(SignedEthTx),
+    #[cfg(feature = "sim_coin")]
+    SimTx(SimTx),
 }
 ifrom!(TransactionEnum, UtxoTx);
 ifrom!(TransactionEnum, SignedEthTx);
+#[cfg(feature = "sim_coin")]
+ifrom!(TransactionEnum, SimTx);
 
 // NB: When stable and groked by IDEs, `enum_dispatch` can be used instead of `Deref` to speed things up.
 impl Deref for TransactionEnum {
@@ -137,6 +145,8 @@ impl Deref for TransactionEnum {
         match self {
             TransactionEnum::UtxoTx(ref t) => t,
             TransactionEnum::SignedEthTx(ref t) => t,
+            #[cfg(feature = "sim_coin")]
+            TransactionEnum::SimTx(ref t) => t,
         }
     }
 }
@@ -222,6 +232,54 @@ pub trait SwapOps {
         swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut;
 
+    /// Refunds the taker payment paying `fee_multiplier` times the regular fee.
+    /// Used to retry the refunds that were not mined, the coins that can't adjust the fee return an error
+    /// unless the `fee_multiplier` is 1.
+    fn send_taker_refunds_payment_with_fee_multiplier(
+        &self,
+        taker_payment_tx: &[u8],
+        time_lock: u32,
+        maker_pub: &[u8],
+        secret_hash: &[u8],
+        swap_contract_address: &Option<BytesJson>,
+        fee_multiplier: u64,
+    ) -> TransactionFut {
+        if fee_multiplier != 1 {
+            return Box::new(futures01::future::err(ERRL!("Refund fee escalation is not supported")));
+        }
+        self.send_taker_refunds_payment(
+            taker_payment_tx,
+            time_lock,
+            maker_pub,
+            secret_hash,
+            swap_contract_address,
+        )
+    }
+
+    /// Refunds the maker payment paying `fee_multiplier` times the regular fee.
+    /// Used to retry the refunds that were not mined, the coins that can't adjust the fee return an error
+    /// unless the `fee_multiplier` is 1.
+    fn send_maker_refunds_payment_with_fee_multiplier(
+        &self,
+        maker_payment_tx: &[u8],
+        time_lock: u32,
+        taker_pub: &[u8],
+        secret_hash: &[u8],
+        swap_contract_address: &Option<BytesJson>,
+        fee_multiplier: u64,
+    ) -> TransactionFut {
+        if fee_multiplier != 1 {
+            return Box::new(futures01::future::err(ERRL!("Refund fee escalation is not supported")));
+        }
+        self.send_maker_refunds_payment(
+            maker_payment_tx,
+            time_lock,
+            taker_pub,
+            secret_hash,
+            swap_contract_address,
+        )
+    }
+
     fn validate_fee(
         &self,
         fee_tx: &TransactionEnum,
@@ -931,6 +989,8 @@ pub enum MmCoinEnum {
     #[cfg(all(not(target_arch = "wasm32"), feature = "zhtlc"))]
     ZCoin(ZCoin),
     Test(TestCoin),
+    #[cfg(feature = "sim_coin")]
+    Sim(SimCoin),
 }
 
 impl From<UtxoStandardCoin> for MmCoinEnum {
@@ -945,6 +1005,11 @@ impl From<TestCoin> for MmCoinEnum {
     fn from(c: TestCoin) -> MmCoinEnum { MmCoinEnum::Test(c) }
 }
 
+#[cfg(feature = "sim_coin")]
+impl From<SimCoin> for MmCoinEnum {
+    fn from(c: SimCoin) -> MmCoinEnum { MmCoinEnum::Sim(c) }
+}
+
 impl From<QtumCoin> for MmCoinEnum {
     fn from(coin: QtumCoin) -> Self { MmCoinEnum::QtumCoin(coin) }
 }
@@ -970,6 +1035,8 @@ impl Deref for MmCoinEnum {
             #[cfg(all(not(target_arch = "wasm32"), feature = "zhtlc"))]
             MmCoinEnum::ZCoin(ref c) => c,
             MmCoinEnum::Test(ref c) => c,
+            #[cfg(feature = "sim_coin")]
+            MmCoinEnum::Sim(ref c) => c,
         }
     }
 }
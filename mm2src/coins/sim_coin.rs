@@ -0,0 +1,1066 @@
+//! The simulated chain backend driving the swaps deterministically in the unit tests.
+//!
+//! `SimChain` is shared by the `SimCoin` wallets of the swap participants. The blocks are mined on demand,
+//! so the test controls the confirmations, reorgs and fee spikes, while `SimMisbehaviour` makes a wallet
+//! act as a malicious counterparty. The payments of the saved swaps can be imported to the chain
+//! to replay the swap recovery with `MakerSwap::load_from_saved` and `TakerSwap::load_from_saved`.
+//! The coin waits for the confirmations, spends and refunds by the chain time, so the tests drive the swap deadlines
+//! by `SimChain::set_time`. The module is compiled with the `sim_coin` feature only.
+
+use super::{CanRefundHtlc, CoinBalance, HistorySyncState, MarketCoinOps, MmCoin, SwapOps, TradeFee, Transaction,
+            TransactionEnum, TransactionFut};
+use crate::{BalanceFut, FeeApproxStage, FoundSwapTxSpend, NegotiateSwapContractAddrErr, TradePreimageFut,
+            TradePreimageValue, ValidateAddressResult, WithdrawError, WithdrawFut, WithdrawRequest};
+use bigdecimal::BigDecimal;
+use bitcrypto::dhash160;
+use common::executor::Timer;
+use common::mm_ctx::MmArc;
+use common::mm_error::MmError;
+use common::mm_number::MmNumber;
+use common::now_ms;
+use futures::{FutureExt, TryFutureExt};
+use futures01::Future;
+use rpc::v1::types::Bytes as BytesJson;
+use serde_json::{self as json, Value as Json};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+
+const SIM_TX_FEE: &str = "0.0001";
+const SIM_DECIMALS: u8 = 8;
+/// The interval of the chain polling in seconds.
+const SIM_POLL_INTERVAL: f64 = 0.1;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type")]
+pub enum SimTxKind {
+    /// The dex fee paid to the `to` pubkey.
+    Fee,
+    /// The HTLC payment that can be spent by the `to` pubkey revealing the secret
+    /// or refunded to the `from` pubkey after the `time_lock`.
+    Payment { time_lock: u32, secret_hash: BytesJson },
+    /// The spend of the payment by the `to` pubkey.
+    Spend { payment: BytesJson, secret: BytesJson },
+    /// The refund of the payment to the `to` pubkey.
+    Refund { payment: BytesJson },
+    /// The payment imported from a saved swap, the chain doesn't know its HTLC conditions.
+    Imported { tx_hex: BytesJson },
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SimTx {
+    ticker: String,
+    /// The pubkey paying the `amount` and `fee`, the HTLC spends don't have it.
+    from: Option<BytesJson>,
+    to: Option<BytesJson>,
+    amount: BigDecimal,
+    fee: BigDecimal,
+    kind: SimTxKind,
+    /// Distinguishes the otherwise identical transactions.
+    nonce: u64,
+}
+
+impl Transaction for SimTx {
+    fn tx_hex(&self) -> Vec<u8> {
+        match &self.kind {
+            SimTxKind::Imported { tx_hex } => tx_hex.0.clone(),
+            _ => json::to_vec(self).expect("Serialization should not fail"),
+        }
+    }
+
+    fn tx_hash(&self) -> BytesJson { sim_tx_hash(&self.tx_hex()) }
+}
+
+impl SimTx {
+    fn is_htlc(&self) -> bool { matches!(self.kind, SimTxKind::Payment { .. } | SimTxKind::Imported { .. }) }
+
+    fn spent_payment(&self) -> Option<&BytesJson> {
+        match &self.kind {
+            SimTxKind::Spend { payment, .. } | SimTxKind::Refund { payment } => Some(payment),
+            _ => None,
+        }
+    }
+}
+
+fn sim_tx_hash(tx_hex: &[u8]) -> BytesJson { Sha256::digest(tx_hex).to_vec().into() }
+
+#[derive(Debug)]
+struct SimChainState {
+    ticker: String,
+    /// `blocks[i]` contains the transactions mined at the height `i + 1`.
+    blocks: Vec<Vec<SimTx>>,
+    mempool: Vec<SimTx>,
+    /// The initial funds of the pubkeys.
+    funds: HashMap<Vec<u8>, BigDecimal>,
+    /// The fee the wallets pay for every transaction.
+    tx_fee: BigDecimal,
+    /// The miners skip the transactions paying less than `tx_fee * fee_spike`.
+    fee_spike: u64,
+    /// Whether a block is mined on every broadcast transaction.
+    auto_mine: bool,
+    /// The chain time in seconds used to check the time locks instead of the system time.
+    time: Option<u64>,
+    nonce: u64,
+}
+
+impl SimChainState {
+    fn height(&self) -> u64 { self.blocks.len() as u64 }
+
+    fn now(&self) -> u64 { self.time.unwrap_or_else(|| now_ms() / 1000) }
+
+    fn all_txs(&self) -> impl Iterator<Item = &SimTx> { self.blocks.iter().flatten().chain(self.mempool.iter()) }
+
+    /// Returns the transaction and its block height, the height of the mempool transactions is `None`.
+    fn find(&self, tx_hash: &[u8]) -> Option<(&SimTx, Option<u64>)> {
+        for (i, block) in self.blocks.iter().enumerate() {
+            if let Some(tx) = block.iter().find(|tx| tx.tx_hash().0 == tx_hash) {
+                return Some((tx, Some(i as u64 + 1)));
+            }
+        }
+        self.mempool
+            .iter()
+            .find(|tx| tx.tx_hash().0 == tx_hash)
+            .map(|tx| (tx, None))
+    }
+
+    fn find_spend(&self, payment_hash: &[u8]) -> Option<&SimTx> {
+        self.all_txs()
+            .find(|tx| tx.spent_payment().map(|payment| payment.0.as_slice()) == Some(payment_hash))
+    }
+
+    fn balance(&self, pubkey: &[u8]) -> BigDecimal {
+        let mut balance = self.funds.get(pubkey).cloned().unwrap_or_else(|| 0.into());
+        for tx in self.all_txs() {
+            if tx.from.as_ref().map(|from| from.0.as_slice()) == Some(pubkey) {
+                balance = balance - (&tx.amount + &tx.fee);
+            }
+            if !tx.is_htlc() && tx.to.as_ref().map(|to| to.0.as_slice()) == Some(pubkey) {
+                balance = balance + &tx.amount;
+            }
+        }
+        balance
+    }
+
+    fn check_htlc_spend(&self, tx: &SimTx, payment_hash: &[u8]) -> Result<(), String> {
+        let payment = match self.find(payment_hash) {
+            Some((payment, _)) if payment.is_htlc() => payment,
+            Some(_) => return ERR!("Transaction {:?} is not a payment", payment_hash),
+            None => return ERR!("Missing inputs: payment {:?} is not found", payment_hash),
+        };
+        if self.find_spend(payment_hash).is_some() {
+            return ERR!("Payment {:?} is spent already", payment_hash);
+        }
+        if &tx.amount + &tx.fee > payment.amount {
+            return ERR!(
+                "Spend amount {} with fee {} exceeds the payment amount {}",
+                tx.amount,
+                tx.fee,
+                payment.amount
+            );
+        }
+        let (time_lock, secret_hash) = match &payment.kind {
+            SimTxKind::Payment { time_lock, secret_hash } => (*time_lock, secret_hash),
+            // the conditions of the imported payments are unknown
+            _ => return Ok(()),
+        };
+        match &tx.kind {
+            SimTxKind::Spend { secret, .. } => {
+                if tx.to != payment.to {
+                    return ERR!("Payment {:?} can be spent by the receiver only", payment_hash);
+                }
+                if dhash160(&secret.0).to_vec() != secret_hash.0 {
+                    return ERR!("Invalid secret of the payment {:?}", payment_hash);
+                }
+            },
+            SimTxKind::Refund { .. } => {
+                if tx.to != payment.from {
+                    return ERR!("Payment {:?} can be refunded to the sender only", payment_hash);
+                }
+                if self.now() < time_lock as u64 {
+                    return ERR!("Payment {:?} is locked until {}", payment_hash, time_lock);
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn broadcast(&mut self, tx: SimTx) -> Result<(), String> {
+        let tx_hash = tx.tx_hash();
+        if self.find(&tx_hash).is_some() {
+            return Ok(());
+        }
+        if let Some(from) = &tx.from {
+            let balance = self.balance(from);
+            if balance < &tx.amount + &tx.fee {
+                return ERR!(
+                    "Insufficient funds: balance {}, required {}",
+                    balance,
+                    &tx.amount + &tx.fee
+                );
+            }
+        }
+        if let Some(payment) = tx.spent_payment() {
+            try_s!(self.check_htlc_spend(&tx, &payment.0));
+        }
+        self.mempool.push(tx);
+        if self.auto_mine {
+            self.mine_block();
+        }
+        Ok(())
+    }
+
+    fn mine_block(&mut self) {
+        let min_fee = &self.tx_fee * BigDecimal::from(self.fee_spike);
+        let (block, mempool): (Vec<_>, Vec<_>) = self.mempool.drain(..).partition(|tx| tx.fee >= min_fee);
+        self.blocks.push(block);
+        self.mempool = mempool;
+    }
+}
+
+/// The simulated chain shared by the `SimCoin` wallets.
+#[derive(Clone, Debug)]
+pub struct SimChain(Arc<Mutex<SimChainState>>);
+
+impl SimChain {
+    /// Creates the chain mining a block on every broadcast transaction.
+    pub fn new(ticker: &str) -> SimChain {
+        SimChain(Arc::new(Mutex::new(SimChainState {
+            ticker: ticker.to_owned(),
+            blocks: Vec::new(),
+            mempool: Vec::new(),
+            funds: HashMap::new(),
+            tx_fee: SIM_TX_FEE.parse().expect("SIM_TX_FEE is valid"),
+            fee_spike: 1,
+            auto_mine: true,
+            time: None,
+            nonce: 0,
+        })))
+    }
+
+    pub fn ticker(&self) -> String { self.0.lock().unwrap().ticker.clone() }
+
+    pub fn fund(&self, pubkey: &[u8], amount: BigDecimal) {
+        let mut state = self.0.lock().unwrap();
+        let funds = state.funds.entry(pubkey.to_vec()).or_insert_with(|| 0.into());
+        *funds = &*funds + &amount;
+    }
+
+    pub fn balance(&self, pubkey: &[u8]) -> BigDecimal { self.0.lock().unwrap().balance(pubkey) }
+
+    pub fn height(&self) -> u64 { self.0.lock().unwrap().height() }
+
+    pub fn tx_fee(&self) -> BigDecimal { self.0.lock().unwrap().tx_fee.clone() }
+
+    pub fn set_auto_mine(&self, auto_mine: bool) { self.0.lock().unwrap().auto_mine = auto_mine; }
+
+    /// Makes the miners require `fee_spike` times the fee the wallets pay.
+    pub fn set_fee_spike(&self, fee_spike: u64) { self.0.lock().unwrap().fee_spike = fee_spike.max(1); }
+
+    /// Sets the chain time in seconds the time locks and the wait deadlines are checked against.
+    pub fn set_time(&self, time: u64) { self.0.lock().unwrap().time = Some(time); }
+
+    /// Returns the chain time in seconds, it's the system time unless set by `set_time`.
+    pub fn now(&self) -> u64 { self.0.lock().unwrap().now() }
+
+    /// Returns the time locks of the payments that are not spent or refunded yet.
+    pub fn unspent_payment_locks(&self) -> Vec<u32> {
+        let state = self.0.lock().unwrap();
+        state
+            .all_txs()
+            .filter_map(|tx| match &tx.kind {
+                SimTxKind::Payment { time_lock, .. } if state.find_spend(&tx.tx_hash()).is_none() => Some(*time_lock),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn mine_blocks(&self, count: u64) {
+        let mut state = self.0.lock().unwrap();
+        for _ in 0..count {
+            state.mine_block();
+        }
+    }
+
+    /// Disconnects the last `depth` blocks, their transactions return to the mempool.
+    pub fn reorg(&self, depth: u64) {
+        let mut state = self.0.lock().unwrap();
+        let new_height = state.blocks.len().saturating_sub(depth as usize);
+        let mut disconnected: Vec<SimTx> = state.blocks.drain(new_height..).flatten().collect();
+        disconnected.append(&mut state.mempool);
+        state.mempool = disconnected;
+    }
+
+    /// Drops the transaction from the mempool as if it was double spent.
+    pub fn evict(&self, tx_hash: &[u8]) { self.0.lock().unwrap().mempool.retain(|tx| tx.tx_hash().0 != tx_hash); }
+
+    /// Returns the number of the transaction confirmations, 0 if the transaction is in the mempool.
+    pub fn confirmations(&self, tx_hash: &[u8]) -> Option<u64> {
+        let state = self.0.lock().unwrap();
+        let (_, height) = state.find(tx_hash)?;
+        Some(height.map_or(0, |height| state.height() - height + 1))
+    }
+
+    pub fn is_in_mempool(&self, tx_hash: &[u8]) -> bool { self.confirmations(tx_hash) == Some(0) }
+
+    /// Imports the payment of a saved swap and mines it.
+    pub fn import_payment(&self, tx_hex: &[u8], amount: BigDecimal) -> SimTx {
+        let mut state = self.0.lock().unwrap();
+        let tx = SimTx {
+            ticker: state.ticker.clone(),
+            from: None,
+            to: None,
+            amount,
+            fee: 0.into(),
+            kind: SimTxKind::Imported {
+                tx_hex: tx_hex.to_vec().into(),
+            },
+            nonce: 0,
+        };
+        if state.find(&tx.tx_hash()).is_none() {
+            state.blocks.push(vec![tx.clone()]);
+        }
+        tx
+    }
+
+    fn tx_from_bytes(&self, bytes: &[u8]) -> Result<SimTx, String> {
+        if let Some((tx, _)) = self.0.lock().unwrap().find(&sim_tx_hash(bytes)) {
+            return Ok(tx.clone());
+        }
+        Ok(try_s!(json::from_slice(bytes)))
+    }
+
+    fn next_nonce(&self) -> u64 {
+        let mut state = self.0.lock().unwrap();
+        state.nonce += 1;
+        state.nonce
+    }
+}
+
+/// The malicious behaviour of the simulated wallet.
+#[derive(Clone, Debug, Default)]
+pub struct SimMisbehaviour {
+    /// The amount the payments are short of the expected amount.
+    pub underpay: Option<BigDecimal>,
+    /// Locks the payments with the corrupted secret hash.
+    pub wrong_secret_hash: bool,
+    /// Signs the transactions but never broadcasts them.
+    pub withhold_broadcast: bool,
+}
+
+/// The wallet of the `pubkey` on the simulated chain.
+#[derive(Clone, Debug)]
+pub struct SimCoin {
+    chain: SimChain,
+    ticker: String,
+    pubkey: Vec<u8>,
+    misbehaviour: SimMisbehaviour,
+    required_confirmations: Arc<AtomicU64>,
+    requires_nota: Arc<AtomicBool>,
+}
+
+impl SimCoin {
+    pub fn new(chain: SimChain, pubkey: &[u8]) -> SimCoin {
+        SimCoin {
+            ticker: chain.ticker(),
+            chain,
+            pubkey: pubkey.to_vec(),
+            misbehaviour: SimMisbehaviour::default(),
+            required_confirmations: Arc::new(AtomicU64::new(1)),
+            requires_nota: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_misbehaviour(mut self, misbehaviour: SimMisbehaviour) -> SimCoin {
+        self.misbehaviour = misbehaviour;
+        self
+    }
+
+    pub fn chain(&self) -> &SimChain { &self.chain }
+
+    fn new_tx(&self, from: Option<&[u8]>, to: &[u8], amount: BigDecimal, fee: BigDecimal, kind: SimTxKind) -> SimTx {
+        SimTx {
+            ticker: self.ticker.clone(),
+            from: from.map(|from| from.to_vec().into()),
+            to: Some(to.to_vec().into()),
+            amount,
+            fee,
+            kind,
+            nonce: self.chain.next_nonce(),
+        }
+    }
+
+    fn send(&self, tx: SimTx) -> Result<TransactionEnum, String> {
+        if !self.misbehaviour.withhold_broadcast {
+            try_s!(self.chain.0.lock().unwrap().broadcast(tx.clone()));
+        }
+        Ok(tx.into())
+    }
+
+    fn send_payment(&self, time_lock: u32, other_pub: &[u8], secret_hash: &[u8], amount: BigDecimal) -> TransactionFut {
+        let amount = match &self.misbehaviour.underpay {
+            Some(underpay) => amount - underpay,
+            None => amount,
+        };
+        let mut secret_hash = secret_hash.to_vec();
+        if self.misbehaviour.wrong_secret_hash {
+            secret_hash.iter_mut().for_each(|byte| *byte = !*byte);
+        }
+        let kind = SimTxKind::Payment {
+            time_lock,
+            secret_hash: secret_hash.into(),
+        };
+        let tx = self.new_tx(Some(&self.pubkey), other_pub, amount, self.chain.tx_fee(), kind);
+        Box::new(futures01::future::result(self.send(tx)))
+    }
+
+    fn spend_payment(&self, payment_tx: &[u8], secret: &[u8]) -> TransactionFut {
+        let payment = try_fus!(self.chain.tx_from_bytes(payment_tx));
+        let fee = self.chain.tx_fee();
+        let kind = SimTxKind::Spend {
+            payment: payment.tx_hash(),
+            secret: secret.to_vec().into(),
+        };
+        let tx = self.new_tx(None, &self.pubkey, &payment.amount - &fee, fee, kind);
+        Box::new(futures01::future::result(self.send(tx)))
+    }
+
+    fn refund_payment(&self, payment_tx: &[u8], fee_multiplier: u64) -> TransactionFut {
+        let payment = try_fus!(self.chain.tx_from_bytes(payment_tx));
+        let fee = self.chain.tx_fee() * BigDecimal::from(fee_multiplier);
+        let kind = SimTxKind::Refund {
+            payment: payment.tx_hash(),
+        };
+        let tx = self.new_tx(None, &self.pubkey, &payment.amount - &fee, fee, kind);
+        Box::new(futures01::future::result(self.send(tx)))
+    }
+
+    fn validate_payment(
+        &self,
+        payment_tx: &[u8],
+        time_lock: u32,
+        sender_pub: &[u8],
+        secret_hash: &[u8],
+        amount: BigDecimal,
+    ) -> Result<(), String> {
+        let payment = try_s!(self.chain.tx_from_bytes(payment_tx));
+        if self.chain.confirmations(&payment.tx_hash().0).is_none() {
+            return ERR!("Payment {:?} is not found on the chain", payment.tx_hash());
+        }
+        let expected_kind = SimTxKind::Payment {
+            time_lock,
+            secret_hash: secret_hash.to_vec().into(),
+        };
+        if payment.kind != expected_kind {
+            return ERR!(
+                "Payment {:?} doesn't match the expected {:?}",
+                payment.kind,
+                expected_kind
+            );
+        }
+        if payment.from.as_ref().map(|from| from.0.as_slice()) != Some(sender_pub) {
+            return ERR!(
+                "Payment sender {:?} doesn't match the expected {:?}",
+                payment.from,
+                sender_pub
+            );
+        }
+        if payment.to.as_ref().map(|to| to.0.as_slice()) != Some(self.pubkey.as_slice()) {
+            return ERR!("Payment receiver {:?} is not my pubkey", payment.to);
+        }
+        if payment.amount < amount {
+            return ERR!("Payment amount {} is less than expected {}", payment.amount, amount);
+        }
+        Ok(())
+    }
+
+    fn search_for_swap_tx_spend(&self, tx: &[u8]) -> Result<Option<FoundSwapTxSpend>, String> {
+        let payment_hash = sim_tx_hash(tx);
+        let state = self.chain.0.lock().unwrap();
+        let spend = match state.find_spend(&payment_hash) {
+            Some(spend) => spend.clone(),
+            None => return Ok(None),
+        };
+        match spend.kind {
+            SimTxKind::Refund { .. } => Ok(Some(FoundSwapTxSpend::Refunded(spend.into()))),
+            _ => Ok(Some(FoundSwapTxSpend::Spent(spend.into()))),
+        }
+    }
+
+    fn trade_fee(&self) -> TradeFee {
+        TradeFee {
+            coin: self.ticker.clone(),
+            amount: self.chain.tx_fee().into(),
+            paid_from_trading_vol: false,
+        }
+    }
+}
+
+impl MarketCoinOps for SimCoin {
+    fn ticker(&self) -> &str { &self.ticker }
+
+    fn my_address(&self) -> Result<String, String> { Ok(hex::encode(&self.pubkey)) }
+
+    fn my_balance(&self) -> BalanceFut<CoinBalance> {
+        let balance = CoinBalance {
+            spendable: self.chain.balance(&self.pubkey),
+            unspendable: 0.into(),
+        };
+        Box::new(futures01::future::ok(balance))
+    }
+
+    fn base_coin_balance(&self) -> BalanceFut<BigDecimal> {
+        Box::new(futures01::future::ok(self.chain.balance(&self.pubkey)))
+    }
+
+    fn send_raw_tx(&self, tx: &str) -> Box<dyn Future<Item = String, Error = String> + Send> {
+        let bytes = try_fus!(hex::decode(tx));
+        let tx: SimTx = try_fus!(json::from_slice(&bytes));
+        let tx_hash = hex::encode(&tx.tx_hash().0);
+        try_fus!(self.chain.0.lock().unwrap().broadcast(tx));
+        Box::new(futures01::future::ok(tx_hash))
+    }
+
+    fn get_raw_tx(&self, tx: &str) -> Box<dyn Future<Item = String, Error = String> + Send> {
+        let tx_hash = try_fus!(hex::decode(tx));
+        let state = self.chain.0.lock().unwrap();
+        match state.find(&tx_hash) {
+            Some((tx, _)) => Box::new(futures01::future::ok(hex::encode(tx.tx_hex()))),
+            None => Box::new(futures01::future::err(ERRL!("Transaction {} is not found", tx))),
+        }
+    }
+
+    fn wait_for_confirmations(
+        &self,
+        tx: &[u8],
+        confirmations: u64,
+        _requires_nota: bool,
+        wait_until: u64,
+        check_every: u64,
+    ) -> Box<dyn Future<Item = (), Error = String> + Send> {
+        let chain = self.chain.clone();
+        let tx_hash = sim_tx_hash(tx);
+        let fut = async move {
+            loop {
+                match chain.confirmations(&tx_hash) {
+                    Some(confs) if confs >= confirmations => return Ok(()),
+                    _ => (),
+                }
+                if chain.now() > wait_until {
+                    return ERR!(
+                        "Waited too long until {} for transaction {:?} to be confirmed {} times",
+                        wait_until,
+                        tx_hash,
+                        confirmations
+                    );
+                }
+                Timer::sleep((check_every as f64).min(SIM_POLL_INTERVAL)).await;
+            }
+        };
+        Box::new(fut.boxed().compat())
+    }
+
+    fn wait_for_tx_spend(
+        &self,
+        transaction: &[u8],
+        wait_until: u64,
+        _from_block: u64,
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> TransactionFut {
+        let coin = self.clone();
+        let transaction = transaction.to_vec();
+        let fut = async move {
+            loop {
+                match try_s!(coin.search_for_swap_tx_spend(&transaction)) {
+                    Some(FoundSwapTxSpend::Spent(tx)) | Some(FoundSwapTxSpend::Refunded(tx)) => return Ok(tx),
+                    None => (),
+                }
+                if coin.chain.now() > wait_until {
+                    return ERR!("Waited too long until {} for transaction to be spent", wait_until);
+                }
+                Timer::sleep(SIM_POLL_INTERVAL).await;
+            }
+        };
+        Box::new(fut.boxed().compat())
+    }
+
+    fn tx_enum_from_bytes(&self, bytes: &[u8]) -> Result<TransactionEnum, String> {
+        Ok(try_s!(self.chain.tx_from_bytes(bytes)).into())
+    }
+
+    fn current_block(&self) -> Box<dyn Future<Item = u64, Error = String> + Send> {
+        Box::new(futures01::future::ok(self.chain.height()))
+    }
+
+    fn display_priv_key(&self) -> String { String::new() }
+
+    fn min_tx_amount(&self) -> BigDecimal { SIM_TX_FEE.parse().expect("SIM_TX_FEE is valid") }
+
+    fn min_trading_vol(&self) -> MmNumber { MmNumber::from("0.00777") }
+}
+
+impl SwapOps for SimCoin {
+    fn send_taker_fee(&self, fee_addr: &[u8], amount: BigDecimal) -> TransactionFut {
+        let tx = self.new_tx(
+            Some(&self.pubkey),
+            fee_addr,
+            amount,
+            self.chain.tx_fee(),
+            SimTxKind::Fee,
+        );
+        Box::new(futures01::future::result(self.send(tx)))
+    }
+
+    fn send_maker_payment(
+        &self,
+        time_lock: u32,
+        taker_pub: &[u8],
+        secret_hash: &[u8],
+        amount: BigDecimal,
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> TransactionFut {
+        self.send_payment(time_lock, taker_pub, secret_hash, amount)
+    }
+
+    fn send_taker_payment(
+        &self,
+        time_lock: u32,
+        maker_pub: &[u8],
+        secret_hash: &[u8],
+        amount: BigDecimal,
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> TransactionFut {
+        self.send_payment(time_lock, maker_pub, secret_hash, amount)
+    }
+
+    fn send_maker_spends_taker_payment(
+        &self,
+        taker_payment_tx: &[u8],
+        _time_lock: u32,
+        _taker_pub: &[u8],
+        secret: &[u8],
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> TransactionFut {
+        self.spend_payment(taker_payment_tx, secret)
+    }
+
+    fn send_taker_spends_maker_payment(
+        &self,
+        maker_payment_tx: &[u8],
+        _time_lock: u32,
+        _maker_pub: &[u8],
+        secret: &[u8],
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> TransactionFut {
+        self.spend_payment(maker_payment_tx, secret)
+    }
+
+    fn send_taker_refunds_payment(
+        &self,
+        taker_payment_tx: &[u8],
+        _time_lock: u32,
+        _maker_pub: &[u8],
+        _secret_hash: &[u8],
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> TransactionFut {
+        self.refund_payment(taker_payment_tx, 1)
+    }
+
+    fn send_taker_refunds_payment_with_fee_multiplier(
+        &self,
+        taker_payment_tx: &[u8],
+        _time_lock: u32,
+        _maker_pub: &[u8],
+        _secret_hash: &[u8],
+        _swap_contract_address: &Option<BytesJson>,
+        fee_multiplier: u64,
+    ) -> TransactionFut {
+        self.refund_payment(taker_payment_tx, fee_multiplier)
+    }
+
+    fn send_maker_refunds_payment(
+        &self,
+        maker_payment_tx: &[u8],
+        _time_lock: u32,
+        _taker_pub: &[u8],
+        _secret_hash: &[u8],
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> TransactionFut {
+        self.refund_payment(maker_payment_tx, 1)
+    }
+
+    fn send_maker_refunds_payment_with_fee_multiplier(
+        &self,
+        maker_payment_tx: &[u8],
+        _time_lock: u32,
+        _taker_pub: &[u8],
+        _secret_hash: &[u8],
+        _swap_contract_address: &Option<BytesJson>,
+        fee_multiplier: u64,
+    ) -> TransactionFut {
+        self.refund_payment(maker_payment_tx, fee_multiplier)
+    }
+
+    fn validate_fee(
+        &self,
+        fee_tx: &TransactionEnum,
+        expected_sender: &[u8],
+        fee_addr: &[u8],
+        amount: &BigDecimal,
+        min_block_number: u64,
+    ) -> Box<dyn Future<Item = (), Error = String> + Send> {
+        let fee_tx = match fee_tx {
+            TransactionEnum::SimTx(tx) => tx,
+            _ => return Box::new(futures01::future::err(ERRL!("Unexpected TransactionEnum"))),
+        };
+        let state = self.chain.0.lock().unwrap();
+        let height = match state.find(&fee_tx.tx_hash()) {
+            Some((_, height)) => height.unwrap_or_else(|| state.height() + 1),
+            None => return Box::new(futures01::future::err(ERRL!("Fee tx is not found on the chain"))),
+        };
+        if height < min_block_number {
+            return Box::new(futures01::future::err(ERRL!(
+                "Fee tx {:?} was mined before the swap started",
+                fee_tx.tx_hash()
+            )));
+        }
+        let valid = fee_tx.kind == SimTxKind::Fee
+            && fee_tx.from.as_ref().map(|from| from.0.as_slice()) == Some(expected_sender)
+            && fee_tx.to.as_ref().map(|to| to.0.as_slice()) == Some(fee_addr)
+            && &fee_tx.amount >= amount;
+        if !valid {
+            return Box::new(futures01::future::err(ERRL!("Invalid fee tx {:?}", fee_tx)));
+        }
+        Box::new(futures01::future::ok(()))
+    }
+
+    fn validate_maker_payment(
+        &self,
+        payment_tx: &[u8],
+        time_lock: u32,
+        maker_pub: &[u8],
+        priv_bn_hash: &[u8],
+        amount: BigDecimal,
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> Box<dyn Future<Item = (), Error = String> + Send> {
+        let res = self.validate_payment(payment_tx, time_lock, maker_pub, priv_bn_hash, amount);
+        Box::new(futures01::future::result(res))
+    }
+
+    fn validate_taker_payment(
+        &self,
+        payment_tx: &[u8],
+        time_lock: u32,
+        taker_pub: &[u8],
+        priv_bn_hash: &[u8],
+        amount: BigDecimal,
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> Box<dyn Future<Item = (), Error = String> + Send> {
+        let res = self.validate_payment(payment_tx, time_lock, taker_pub, priv_bn_hash, amount);
+        Box::new(futures01::future::result(res))
+    }
+
+    fn check_if_my_payment_sent(
+        &self,
+        time_lock: u32,
+        other_pub: &[u8],
+        secret_hash: &[u8],
+        _search_from_block: u64,
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> Box<dyn Future<Item = Option<TransactionEnum>, Error = String> + Send> {
+        let expected_kind = SimTxKind::Payment {
+            time_lock,
+            secret_hash: secret_hash.to_vec().into(),
+        };
+        let state = self.chain.0.lock().unwrap();
+        let payment = state.all_txs().find(|tx| {
+            tx.kind == expected_kind
+                && tx.from.as_ref().map(|from| from.0.as_slice()) == Some(self.pubkey.as_slice())
+                && tx.to.as_ref().map(|to| to.0.as_slice()) == Some(other_pub)
+        });
+        Box::new(futures01::future::ok(payment.cloned().map(TransactionEnum::from)))
+    }
+
+    fn search_for_swap_tx_spend_my(
+        &self,
+        _time_lock: u32,
+        _other_pub: &[u8],
+        _secret_hash: &[u8],
+        tx: &[u8],
+        _search_from_block: u64,
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> Result<Option<FoundSwapTxSpend>, String> {
+        self.search_for_swap_tx_spend(tx)
+    }
+
+    fn search_for_swap_tx_spend_other(
+        &self,
+        _time_lock: u32,
+        _other_pub: &[u8],
+        _secret_hash: &[u8],
+        tx: &[u8],
+        _search_from_block: u64,
+        _swap_contract_address: &Option<BytesJson>,
+    ) -> Result<Option<FoundSwapTxSpend>, String> {
+        self.search_for_swap_tx_spend(tx)
+    }
+
+    fn extract_secret(&self, secret_hash: &[u8], spend_tx: &[u8]) -> Result<Vec<u8>, String> {
+        let spend = try_s!(self.chain.tx_from_bytes(spend_tx));
+        match spend.kind {
+            SimTxKind::Spend { secret, .. } if &*dhash160(&secret.0) == secret_hash => Ok(secret.0),
+            SimTxKind::Spend { .. } => ERR!("The revealed secret doesn't match the secret hash"),
+            _ => ERR!("Transaction {:?} is not a payment spend", spend.tx_hash()),
+        }
+    }
+
+    /// Checks the time lock against the chain time polling it every second, so the test can move the time forward.
+    fn can_refund_htlc(&self, locktime: u64) -> Box<dyn Future<Item = CanRefundHtlc, Error = String> + Send + '_> {
+        let result = if self.chain.now() > locktime {
+            CanRefundHtlc::CanRefundNow
+        } else {
+            CanRefundHtlc::HaveToWait(1)
+        };
+        Box::new(futures01::future::ok(result))
+    }
+
+    fn negotiate_swap_contract_addr(
+        &self,
+        _other_side_address: Option<&[u8]>,
+    ) -> Result<Option<BytesJson>, MmError<NegotiateSwapContractAddrErr>> {
+        Ok(None)
+    }
+}
+
+impl MmCoin for SimCoin {
+    fn is_asset_chain(&self) -> bool { false }
+
+    fn withdraw(&self, _req: WithdrawRequest) -> WithdrawFut {
+        Box::new(futures01::future::err(MmError::new(WithdrawError::InternalError(
+            "Withdraw is not supported by the simulated chain".to_owned(),
+        ))))
+    }
+
+    fn decimals(&self) -> u8 { SIM_DECIMALS }
+
+    fn convert_to_address(&self, from: &str, _to_address_format: Json) -> Result<String, String> { Ok(from.to_owned()) }
+
+    fn validate_address(&self, address: &str) -> ValidateAddressResult {
+        match hex::decode(address) {
+            Ok(ref pubkey) if pubkey.len() == 33 => ValidateAddressResult {
+                is_valid: true,
+                reason: None,
+            },
+            _ => ValidateAddressResult {
+                is_valid: false,
+                reason: Some("The address must be a hex encoded compressed pubkey".to_owned()),
+            },
+        }
+    }
+
+    fn process_history_loop(&self, _ctx: MmArc) -> Box<dyn Future<Item = (), Error = ()> + Send> {
+        Box::new(futures01::future::ok(()))
+    }
+
+    fn history_sync_status(&self) -> HistorySyncState { HistorySyncState::NotEnabled }
+
+    fn get_trade_fee(&self) -> Box<dyn Future<Item = TradeFee, Error = String> + Send> {
+        Box::new(futures01::future::ok(self.trade_fee()))
+    }
+
+    fn get_sender_trade_fee(&self, _value: TradePreimageValue, _stage: FeeApproxStage) -> TradePreimageFut<TradeFee> {
+        Box::new(futures01::future::ok(self.trade_fee()))
+    }
+
+    fn get_receiver_trade_fee(&self, _stage: FeeApproxStage) -> TradePreimageFut<TradeFee> {
+        Box::new(futures01::future::ok(self.trade_fee()))
+    }
+
+    fn get_fee_to_send_taker_fee(
+        &self,
+        _dex_fee_amount: BigDecimal,
+        _stage: FeeApproxStage,
+    ) -> TradePreimageFut<TradeFee> {
+        Box::new(futures01::future::ok(self.trade_fee()))
+    }
+
+    fn required_confirmations(&self) -> u64 { self.required_confirmations.load(AtomicOrdering::Relaxed) }
+
+    fn requires_notarization(&self) -> bool { self.requires_nota.load(AtomicOrdering::Relaxed) }
+
+    fn set_required_confirmations(&self, confirmations: u64) {
+        self.required_confirmations
+            .store(confirmations, AtomicOrdering::Relaxed);
+    }
+
+    fn set_requires_notarization(&self, requires_nota: bool) {
+        self.requires_nota.store(requires_nota, AtomicOrdering::Relaxed);
+    }
+
+    fn swap_contract_address(&self) -> Option<BytesJson> { None }
+
+    fn mature_confirmations(&self) -> Option<u32> { None }
+
+    fn coin_protocol_info(&self) -> Vec<u8> { Vec::new() }
+
+    fn is_coin_protocol_supported(&self, _info: &Option<Vec<u8>>) -> bool { true }
+}
+
+#[cfg(test)]
+mod sim_coin_tests {
+    use super::*;
+
+    const MAKER_PUB: [u8; 33] = [2; 33];
+    const TAKER_PUB: [u8; 33] = [3; 33];
+    const FEE_PUB: [u8; 33] = [4; 33];
+
+    fn funded_coins(chain: &SimChain) -> (SimCoin, SimCoin) {
+        chain.fund(&MAKER_PUB, 10.into());
+        chain.fund(&TAKER_PUB, 10.into());
+        (
+            SimCoin::new(chain.clone(), &MAKER_PUB),
+            SimCoin::new(chain.clone(), &TAKER_PUB),
+        )
+    }
+
+    #[test]
+    fn test_sim_payment_spend_reveals_secret() {
+        let chain = SimChain::new("SIM");
+        let (maker, taker) = funded_coins(&chain);
+        let secret = [1; 32];
+        let secret_hash = dhash160(&secret).to_vec();
+        let time_lock = (now_ms() / 1000) as u32 + 1000;
+
+        let fee = taker.send_taker_fee(&FEE_PUB, "0.01".parse().unwrap()).wait().unwrap();
+        maker
+            .validate_fee(&fee, &TAKER_PUB, &FEE_PUB, &"0.01".parse().unwrap(), 0)
+            .wait()
+            .unwrap();
+
+        let payment = maker
+            .send_maker_payment(time_lock, &TAKER_PUB, &secret_hash, 1.into(), &None)
+            .wait()
+            .unwrap();
+        taker
+            .validate_maker_payment(&payment.tx_hex(), time_lock, &MAKER_PUB, &secret_hash, 1.into(), &None)
+            .wait()
+            .unwrap();
+        // the refund is not possible until the time lock
+        maker
+            .send_maker_refunds_payment(&payment.tx_hex(), time_lock, &TAKER_PUB, &secret_hash, &None)
+            .wait()
+            .unwrap_err();
+
+        let spend = taker
+            .send_taker_spends_maker_payment(&payment.tx_hex(), time_lock, &MAKER_PUB, &secret, &None)
+            .wait()
+            .unwrap();
+        let found = maker
+            .search_for_swap_tx_spend_my(time_lock, &TAKER_PUB, &secret_hash, &payment.tx_hex(), 0, &None)
+            .unwrap();
+        assert_eq!(found, Some(FoundSwapTxSpend::Spent(spend.clone())));
+        assert_eq!(
+            maker.extract_secret(&secret_hash, &spend.tx_hex()).unwrap(),
+            secret.to_vec()
+        );
+
+        let tx_fee = chain.tx_fee();
+        let expected_maker = BigDecimal::from(9) - &tx_fee;
+        assert_eq!(chain.balance(&MAKER_PUB), expected_maker);
+        let expected_taker =
+            BigDecimal::from(11) - "0.01".parse::<BigDecimal>().unwrap() - &tx_fee * BigDecimal::from(2);
+        assert_eq!(chain.balance(&TAKER_PUB), expected_taker);
+    }
+
+    #[test]
+    fn test_sim_refund_fee_spike_and_reorg() {
+        let chain = SimChain::new("SIM");
+        let (maker, _) = funded_coins(&chain);
+        let time_lock = 1000;
+        chain.set_time(2000);
+
+        let payment = maker
+            .send_maker_payment(time_lock, &TAKER_PUB, &[0; 20], 1.into(), &None)
+            .wait()
+            .unwrap();
+        chain.set_fee_spike(3);
+        let refund = maker
+            .send_maker_refunds_payment(&payment.tx_hex(), time_lock, &TAKER_PUB, &[0; 20], &None)
+            .wait()
+            .unwrap();
+        // the refund paying the regular fee is stuck in the mempool
+        assert!(chain.is_in_mempool(&refund.tx_hash()));
+
+        chain.evict(&refund.tx_hash());
+        let refund = maker
+            .send_maker_refunds_payment_with_fee_multiplier(
+                &payment.tx_hex(),
+                time_lock,
+                &TAKER_PUB,
+                &[0; 20],
+                &None,
+                3,
+            )
+            .wait()
+            .unwrap();
+        assert_eq!(chain.confirmations(&refund.tx_hash()), Some(1));
+        chain.mine_blocks(2);
+        assert_eq!(chain.confirmations(&refund.tx_hash()), Some(3));
+
+        // the block 2 is empty as the regular refund got stuck, so the refund is mined at the height 3
+        chain.reorg(3);
+        assert_eq!(chain.confirmations(&payment.tx_hash()), Some(2));
+        assert!(chain.is_in_mempool(&refund.tx_hash()));
+        chain.mine_blocks(1);
+        assert_eq!(chain.confirmations(&refund.tx_hash()), Some(1));
+    }
+
+    #[test]
+    fn test_sim_misbehaving_counterparty() {
+        let chain = SimChain::new("SIM");
+        let (maker, taker) = funded_coins(&chain);
+        let secret_hash = dhash160(&[1; 32]).to_vec();
+        let time_lock = (now_ms() / 1000) as u32 + 1000;
+
+        let underpaying = maker.clone().with_misbehaviour(SimMisbehaviour {
+            underpay: Some("0.5".parse().unwrap()),
+            ..SimMisbehaviour::default()
+        });
+        let payment = underpaying
+            .send_maker_payment(time_lock, &TAKER_PUB, &secret_hash, 1.into(), &None)
+            .wait()
+            .unwrap();
+        taker
+            .validate_maker_payment(&payment.tx_hex(), time_lock, &MAKER_PUB, &secret_hash, 1.into(), &None)
+            .wait()
+            .unwrap_err();
+
+        let wrong_hash = maker.clone().with_misbehaviour(SimMisbehaviour {
+            wrong_secret_hash: true,
+            ..SimMisbehaviour::default()
+        });
+        let payment = wrong_hash
+            .send_maker_payment(time_lock, &TAKER_PUB, &secret_hash, 1.into(), &None)
+            .wait()
+            .unwrap();
+        taker
+            .validate_maker_payment(&payment.tx_hex(), time_lock, &MAKER_PUB, &secret_hash, 1.into(), &None)
+            .wait()
+            .unwrap_err();
+
+        let withholding = maker.with_misbehaviour(SimMisbehaviour {
+            withhold_broadcast: true,
+            ..SimMisbehaviour::default()
+        });
+        let payment = withholding
+            .send_maker_payment(time_lock, &TAKER_PUB, &secret_hash, 1.into(), &None)
+            .wait()
+            .unwrap();
+        assert_eq!(chain.confirmations(&payment.tx_hash()), None);
+        taker
+            .validate_maker_payment(&payment.tx_hex(), time_lock, &MAKER_PUB, &secret_hash, 1.into(), &None)
+            .wait()
+            .unwrap_err();
+    }
+}
@@ -377,7 +377,26 @@ impl SwapOps for QtumCoin {
         secret_hash: &[u8],
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        utxo_common::send_taker_refunds_payment(self.clone(), taker_payment_tx, time_lock, maker_pub, secret_hash)
+        utxo_common::send_taker_refunds_payment(self.clone(), taker_payment_tx, time_lock, maker_pub, secret_hash, 1)
+    }
+
+    fn send_taker_refunds_payment_with_fee_multiplier(
+        &self,
+        taker_payment_tx: &[u8],
+        time_lock: u32,
+        maker_pub: &[u8],
+        secret_hash: &[u8],
+        _swap_contract_address: &Option<BytesJson>,
+        fee_multiplier: u64,
+    ) -> TransactionFut {
+        utxo_common::send_taker_refunds_payment(
+            self.clone(),
+            taker_payment_tx,
+            time_lock,
+            maker_pub,
+            secret_hash,
+            fee_multiplier,
+        )
     }
 
     fn send_maker_refunds_payment(
@@ -388,7 +407,26 @@ impl SwapOps for QtumCoin {
         secret_hash: &[u8],
         _swap_contract_address: &Option<BytesJson>,
     ) -> TransactionFut {
-        utxo_common::send_maker_refunds_payment(self.clone(), maker_payment_tx, time_lock, taker_pub, secret_hash)
+        utxo_common::send_maker_refunds_payment(self.clone(), maker_payment_tx, time_lock, taker_pub, secret_hash, 1)
+    }
+
+    fn send_maker_refunds_payment_with_fee_multiplier(
+        &self,
+        maker_payment_tx: &[u8],
+        time_lock: u32,
+        taker_pub: &[u8],
+        secret_hash: &[u8],
+        _swap_contract_address: &Option<BytesJson>,
+        fee_multiplier: u64,
+    ) -> TransactionFut {
+        utxo_common::send_maker_refunds_payment(
+            self.clone(),
+            maker_payment_tx,
+            time_lock,
+            taker_pub,
+            secret_hash,
+            fee_multiplier,
+        )
     }
 
     fn validate_fee(
@@ -1826,6 +1826,16 @@ async fn electrum_last_chunk_loop(last_chunk: Arc<AtomicU64>) {
     }
 }
 
+/// Connects through the global SOCKS5 proxy if it's set, so the `addr` is resolved by the proxy.
+#[cfg(not(target_arch = "wasm32"))]
+async fn electrum_tcp_connect(addr: String) -> Result<TcpStream, String> {
+    if socks5::global_proxy().is_some() {
+        return socks5::connect_tcp(&addr).await.map_err(|e| ERRL!("{}", e));
+    }
+    let socket_addr = try_s!(addr_to_socket_addr(&addr));
+    TcpStream::connect(&socket_addr).await.map_err(|e| ERRL!("{}", e))
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 async fn connect_loop(
     config: ElectrumConfig,
@@ -1842,10 +1852,8 @@ async fn connect_loop(
             Timer::sleep(current_delay as f64).await;
         };
 
-        let socket_addr = try_loop!(addr_to_socket_addr(&addr), addr, delay);
-
         let connect_f = match config.clone() {
-            ElectrumConfig::TCP => Either::Left(TcpStream::connect(&socket_addr).map_ok(ElectrumStream::Tcp)),
+            ElectrumConfig::TCP => Either::Left(electrum_tcp_connect(addr.clone()).map_ok(ElectrumStream::Tcp)),
             ElectrumConfig::SSL {
                 dns_name,
                 skip_validation,
@@ -1859,12 +1867,15 @@ async fn connect_loop(
                 }
                 let tls_connector = TlsConnector::from(Arc::new(ssl_config));
 
-                Either::Right(TcpStream::connect(&socket_addr).and_then(move |stream| {
+                Either::Right(electrum_tcp_connect(addr.clone()).and_then(move |stream| {
                     // Can use `unwrap` cause `dns_name` is pre-checked.
                     let dns = DNSNameRef::try_from_ascii_str(&dns_name)
                         .map_err(|e| fomat!([e]))
                         .unwrap();
-                    tls_connector.connect(dns, stream).map_ok(ElectrumStream::Tls)
+                    tls_connector
+                        .connect(dns, stream)
+                        .map_ok(ElectrumStream::Tls)
+                        .map_err(|e| ERRL!("{}", e))
                 }))
             },
         };
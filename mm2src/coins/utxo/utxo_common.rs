@@ -37,6 +37,9 @@ use crate::{CanRefundHtlc, CoinBalance, TradePreimageValue, TxFeeDetails, Valida
 const MIN_BTC_TRADING_VOL: &str = "0.00777";
 pub const DEFAULT_SWAP_VOUT: usize = 0;
 pub const DEFAULT_FEE_VOUT: usize = 0;
+/// The refund inputs signal the BIP125 replaceability, so the refund can be re-sent with a higher fee.
+/// The sequence is still non-final, so the locktime of the refund is enforced.
+pub const SEQUENCE_REPLACEABLE: u32 = SEQUENCE_FINAL - 2;
 
 macro_rules! true_or {
     ($cond: expr, $etype: expr) => {
@@ -834,6 +837,7 @@ pub fn send_taker_refunds_payment<T>(
     time_lock: u32,
     maker_pub: &[u8],
     secret_hash: &[u8],
+    fee_multiplier: u64,
 ) -> TransactionFut
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Send + Sync + 'static,
@@ -848,19 +852,23 @@ where
         &try_fus!(Public::from_slice(maker_pub)),
     );
     let fut = async move {
-        let fee = try_s!(coin.get_htlc_spend_fee().await);
-        let script_pubkey = output_script(&coin.as_ref().my_address, ScriptType::P2PKH).to_bytes();
-        let output = TransactionOutput {
-            value: prev_tx.outputs[0].value - fee,
-            script_pubkey,
+        let fee = match try_s!(coin.get_htlc_spend_fee().await).checked_mul(fee_multiplier) {
+            Some(fee) => fee,
+            None => return ERR!("Refund fee overflow, fee multiplier {}", fee_multiplier),
+        };
+        let value = match prev_tx.outputs[0].value.checked_sub(fee) {
+            Some(value) => value,
+            None => return ERR!("Refund fee {} is greater than the payment value", fee),
         };
+        let script_pubkey = output_script(&coin.as_ref().my_address, ScriptType::P2PKH).to_bytes();
+        let output = TransactionOutput { value, script_pubkey };
         let transaction = try_s!(
             coin.p2sh_spending_tx(
                 prev_tx,
                 redeem_script.into(),
                 vec![output],
                 script_data,
-                SEQUENCE_FINAL - 1,
+                SEQUENCE_REPLACEABLE,
                 time_lock,
             )
             .await
@@ -878,6 +886,7 @@ pub fn send_maker_refunds_payment<T>(
     time_lock: u32,
     taker_pub: &[u8],
     secret_hash: &[u8],
+    fee_multiplier: u64,
 ) -> TransactionFut
 where
     T: AsRef<UtxoCoinFields> + UtxoCommonOps + Send + Sync + 'static,
@@ -892,19 +901,23 @@ where
         &try_fus!(Public::from_slice(taker_pub)),
     );
     let fut = async move {
-        let fee = try_s!(coin.get_htlc_spend_fee().await);
-        let script_pubkey = output_script(&coin.as_ref().my_address, ScriptType::P2PKH).to_bytes();
-        let output = TransactionOutput {
-            value: prev_tx.outputs[0].value - fee,
-            script_pubkey,
+        let fee = match try_s!(coin.get_htlc_spend_fee().await).checked_mul(fee_multiplier) {
+            Some(fee) => fee,
+            None => return ERR!("Refund fee overflow, fee multiplier {}", fee_multiplier),
         };
+        let value = match prev_tx.outputs[0].value.checked_sub(fee) {
+            Some(value) => value,
+            None => return ERR!("Refund fee {} is greater than the payment value", fee),
+        };
+        let script_pubkey = output_script(&coin.as_ref().my_address, ScriptType::P2PKH).to_bytes();
+        let output = TransactionOutput { value, script_pubkey };
         let transaction = try_s!(
             coin.p2sh_spending_tx(
                 prev_tx,
                 redeem_script.into(),
                 vec![output],
                 script_data,
-                SEQUENCE_FINAL - 1,
+                SEQUENCE_REPLACEABLE,
                 time_lock,
             )
             .await
@@ -1308,9 +1321,8 @@ pub fn send_raw_tx(coin: &UtxoCoinFields, tx: &str) -> Box<dyn Future<Item = Str
     )
 }
 
-
 pub fn get_raw_tx(coin: &UtxoCoinFields, tx: &str) -> Box<dyn Future<Item = String, Error = String> + Send> {
-   let tx_id = try_fus!(H256Json::from_str(tx));
+    let tx_id = try_fus!(H256Json::from_str(tx));
     Box::new(
         coin.rpc_client
             .get_transaction_bytes(tx_id)
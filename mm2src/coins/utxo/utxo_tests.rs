@@ -463,6 +463,52 @@ fn test_search_for_swap_tx_spend_electrum_was_refunded() {
     assert_eq!(FoundSwapTxSpend::Refunded(refund_tx), found);
 }
 
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_send_maker_refunds_payment_with_fee_multiplier_replaceable() {
+    const HTLC_SPEND_FEE: u64 = 1000;
+    const TIME_LOCK: u32 = 1591933469;
+
+    UtxoStandardCoin::get_htlc_spend_fee
+        .mock_safe(|_| MockResult::Return(Box::pin(futures::future::ok(HTLC_SPEND_FEE))));
+    UtxoStandardCoin::get_current_mtp.mock_safe(|_| MockResult::Return(Box::pin(futures::future::ok(TIME_LOCK + 100))));
+    NativeClient::send_transaction
+        .mock_safe(|_, _| MockResult::Return(Box::new(futures01::future::ok(H256Json::default()))));
+
+    let coin = utxo_coin_for_test(
+        UtxoRpcClientEnum::Native(native_client_for_test()),
+        Some("spice describe gravity federal blast come thank unfair canal monkey style afraid"),
+        false,
+    );
+    // raw tx bytes of https://rick.kmd.dev/tx/78ea7839f6d1b0dafda2ba7e34c1d8218676a58bd1b33f03a5f76391f61b72b0
+    let payment_tx_bytes = hex::decode("0400008085202f8902bf17bf7d1daace52e08f732a6b8771743ca4b1cb765a187e72fd091a0aabfd52000000006a47304402203eaaa3c4da101240f80f9c5e9de716a22b1ec6d66080de6a0cca32011cd77223022040d9082b6242d6acf9a1a8e658779e1c655d708379862f235e8ba7b8ca4e69c6012102031d4256c4bc9f99ac88bf3dba21773132281f65f9bf23a59928bce08961e2f3ffffffffff023ca13c0e9e085dd13f481f193e8a3e8fd609020936e98b5587342d994f4d020000006b483045022100c0ba56adb8de923975052312467347d83238bd8d480ce66e8b709a7997373994022048507bcac921fdb2302fa5224ce86e41b7efc1a2e20ae63aa738dfa99b7be826012102031d4256c4bc9f99ac88bf3dba21773132281f65f9bf23a59928bce08961e2f3ffffffff0300e1f5050000000017a9141ee6d4c38a3c078eab87ad1a5e4b00f21259b10d870000000000000000166a1400000000000000000000000000000000000000001b94d736000000001976a91405aab5342166f8594baf17a7d9bef5d56744332788ac2d08e35e000000000000000000000000000000")
+        .unwrap();
+    let payment_tx: UtxoTx = deserialize(payment_tx_bytes.as_slice()).unwrap();
+
+    let refund_tx = coin
+        .send_maker_refunds_payment_with_fee_multiplier(
+            &payment_tx_bytes,
+            TIME_LOCK,
+            &*coin.my_public_key(),
+            &[0; 20],
+            &None,
+            3,
+        )
+        .wait()
+        .unwrap();
+    let refund_tx = match refund_tx {
+        TransactionEnum::UtxoTx(tx) => tx,
+        tx => panic!("Unexpected transaction {:?}", tx),
+    };
+    // the refund signals BIP125, so it can be replaced by the next retry paying the higher fee
+    assert_eq!(refund_tx.inputs[0].sequence, 0xfffffffd);
+    assert_eq!(refund_tx.lock_time, TIME_LOCK + 99);
+    assert_eq!(
+        refund_tx.outputs[0].value,
+        payment_tx.outputs[0].value - 3 * HTLC_SPEND_FEE
+    );
+}
+
 #[test]
 #[cfg(not(target_arch = "wasm32"))]
 fn test_withdraw_impl_set_fixed_fee() {
@@ -668,14 +668,19 @@ pub mod wio {
     use futures01::{Async, Future, Poll};
     use futures_cpupool::CpuPool;
     use gstuff::{duration_to_float, now_float};
-    use http::{HeaderMap, Request, StatusCode};
+    use http::{HeaderMap, Request, StatusCode, Uri};
     use hyper::client::HttpConnector;
+    use hyper::service::Service;
     use hyper::{Body, Client};
     use hyper_rustls::HttpsConnector;
+    use socks5::{global_proxy, socks5_connect};
     use std::fmt;
-    use std::sync::Mutex;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::Context;
     use std::thread::JoinHandle;
     use std::time::Duration;
+    use tokio::net::TcpStream;
     use tokio::runtime::Runtime;
 
     fn start_core_thread() -> Mm2Runtime { Mm2Runtime(Runtime::new().unwrap()) }
@@ -828,11 +833,62 @@ pub mod wio {
         super::black_box(&*super::trace_name_buf());
     }
 
+    type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+    /// Connects through the global SOCKS5 proxy if it's set, or directly otherwise.
+    #[derive(Clone)]
+    pub struct ProxyConnector {
+        http: HttpConnector,
+    }
+
+    impl ProxyConnector {
+        fn new() -> ProxyConnector {
+            let mut http = HttpConnector::new();
+            http.enforce_http(false);
+            ProxyConnector { http }
+        }
+    }
+
+    impl Service<Uri> for ProxyConnector {
+        type Response = TcpStream;
+        type Error = BoxError;
+        type Future = Pin<Box<dyn std::future::Future<Output = Result<TcpStream, BoxError>> + Send>>;
+
+        fn poll_ready(&mut self, cx: &mut Context<'_>) -> std::task::Poll<Result<(), BoxError>> {
+            self.http.poll_ready(cx).map_err(Into::into)
+        }
+
+        fn call(&mut self, uri: Uri) -> Self::Future {
+            let mut http = self.http.clone();
+            Box::pin(async move {
+                let proxy = match global_proxy() {
+                    Some(proxy) => proxy,
+                    None => return http.call(uri).await.map_err(Into::into),
+                };
+                let host = uri.host().ok_or_else(|| ERRL!("No host in {}", uri))?;
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let default_port = if uri.scheme_str() == Some("https") { 443 } else { 80 };
+                let port = uri.port_u16().unwrap_or(default_port);
+                match socks5_connect(&proxy, host, port).await {
+                    Ok(stream) => Ok(stream),
+                    Err(e) if proxy.refuse_clearnet_fallback => Err(e.into()),
+                    Err(e) => {
+                        log!("Error "(e)" connecting to "(uri)" through the proxy, connecting directly");
+                        http.call(uri).await.map_err(Into::into)
+                    },
+                }
+            })
+        }
+    }
+
     lazy_static! {
         /// NB: With a shared client there is a possibility that keep-alive connections will be reused.
-        pub static ref HYPER: Client<HttpsConnector<HttpConnector>> = {
+        pub static ref HYPER: Client<HttpsConnector<ProxyConnector>> = {
             // Please note there was a problem on iOS if [`HttpsConnector::with_native_roots`] is used instead.
-            let https = HttpsConnector::with_webpki_roots();
+            let mut tls_config = rustls::ClientConfig::new();
+            tls_config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+            tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+            let https = HttpsConnector::from((ProxyConnector::new(), Arc::new(tls_config)));
             Client::builder()
                 .executor(&*CORE)
                 // Hyper had a lot of Keep-Alive bugs over the years and I suspect
@@ -79,6 +79,8 @@ fn migration_6() -> Vec<(&'static str, Vec<String>)> {
     ]
 }
 
+fn migration_7() -> Vec<(&'static str, Vec<String>)> { vec![(stats_swaps::ADD_FINISHED_AT_INDEX, vec![])] }
+
 fn statements_for_migration(ctx: &MmArc, current_migration: i64) -> Option<Vec<(&'static str, Vec<String>)>> {
     match current_migration {
         1 => Some(migration_1(ctx)),
@@ -87,6 +89,7 @@ fn statements_for_migration(ctx: &MmArc, current_migration: i64) -> Option<Vec<(
         4 => Some(migration_4()),
         5 => Some(migration_5()),
         6 => Some(migration_6()),
+        7 => Some(migration_7()),
         _ => None,
     }
 }
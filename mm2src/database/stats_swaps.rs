@@ -2,9 +2,10 @@ use crate::mm2::lp_swap::{stats_maker_swap_dir, stats_taker_swap_dir, MakerSaved
 use common::{log::{debug, error, warn},
              mm_ctx::MmArc,
              read_dir,
-             rusqlite::{Connection, OptionalExtension},
+             rusqlite::{Connection, OptionalExtension, Result as SqlResult, ToSql},
              slurp};
 use serde_json::{self as json};
+use sql_builder::SqlBuilder;
 use std::collections::HashSet;
 use uuid::Uuid;
 
@@ -71,6 +72,8 @@ const ADD_SPLIT_TICKERS: &[&str] = &[
 
 pub const ADD_STARTED_AT_INDEX: &str = "CREATE INDEX timestamp_index ON stats_swaps (started_at);";
 
+pub const ADD_FINISHED_AT_INDEX: &str = "CREATE INDEX finished_at_index ON stats_swaps (finished_at);";
+
 const SELECT_ID_BY_UUID: &str = "SELECT id FROM stats_swaps WHERE uuid = ?1";
 
 /// Returns SQL statements to initially fill stats_swaps table using existing DB with JSON files
@@ -327,6 +330,71 @@ pub fn add_and_split_tickers() -> Vec<(&'static str, Vec<String>)> {
     ADD_SPLIT_TICKERS.iter().map(|sql| (*sql, vec![])).collect()
 }
 
+/// The successful swap of the pair as it is stored in the stats_swaps table
+#[derive(Debug)]
+pub struct StatsSwapTrade {
+    pub uuid: String,
+    pub maker_coin: String,
+    pub taker_coin: String,
+    pub maker_amount: String,
+    pub taker_amount: String,
+    pub finished_at: u64,
+}
+
+/// Selects the successful swaps of the `base/rel` pair in both directions finished in `[from, to)` range,
+/// the most recent first.
+pub fn select_pair_trades(
+    conn: &Connection,
+    base: &str,
+    rel: &str,
+    from: u64,
+    to: u64,
+    limit: Option<usize>,
+) -> SqlResult<Vec<StatsSwapTrade>> {
+    let mut query_builder = SqlBuilder::select_from("stats_swaps");
+    query_builder
+        .field("uuid")
+        .field("maker_coin")
+        .field("taker_coin")
+        // the amounts can be stored as REAL due to the DECIMAL affinity
+        .field("CAST(maker_amount AS TEXT)")
+        .field("CAST(taker_amount AS TEXT)")
+        .field("finished_at")
+        .and_where("is_success = 1")
+        .and_where("((maker_coin = :base AND taker_coin = :rel) OR (maker_coin = :rel AND taker_coin = :base))")
+        .and_where("finished_at >= :from")
+        .and_where("finished_at < :to")
+        .order_desc("finished_at");
+    if let Some(limit) = limit {
+        query_builder.limit(limit);
+    }
+
+    let params = vec![
+        (":base", base.to_owned()),
+        (":rel", rel.to_owned()),
+        (":from", from.to_string()),
+        (":to", to.to_string()),
+    ];
+    let query = query_builder.sql().expect("SQL query builder should never fail here");
+    debug!("Trying to execute SQL query {} with params {:?}", query, params);
+
+    let params_as_trait: Vec<_> = params.iter().map(|(key, value)| (*key, value as &dyn ToSql)).collect();
+    let mut stmt = conn.prepare(&query)?;
+    let trades = stmt
+        .query_map_named(params_as_trait.as_slice(), |row| {
+            Ok(StatsSwapTrade {
+                uuid: row.get(0)?,
+                maker_coin: row.get(1)?,
+                taker_coin: row.get(2)?,
+                maker_amount: row.get(3)?,
+                taker_amount: row.get(4)?,
+                finished_at: row.get::<_, i64>(5)? as u64,
+            })
+        })?
+        .collect::<SqlResult<Vec<StatsSwapTrade>>>()?;
+    Ok(trades)
+}
+
 #[test]
 fn test_split_coin() {
     let input = "";
@@ -349,3 +417,53 @@ fn test_split_coin() {
     let actual = split_coin(input);
     assert_eq!(expected, actual);
 }
+
+#[test]
+fn test_select_pair_trades() {
+    use common::rusqlite::NO_PARAMS;
+
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute(CREATE_STATS_SWAPS_TABLE, NO_PARAMS).unwrap();
+    for sql in ADD_SPLIT_TICKERS {
+        conn.execute(sql, NO_PARAMS).unwrap();
+    }
+
+    let swaps = [
+        ("RICK", "MORTY", "1", 100, "1"),
+        ("MORTY", "RICK", "2", 200, "1"),
+        ("RICK", "MORTY", "3", 300, "0"),
+        ("RICK", "ETH", "4", 400, "1"),
+        ("RICK", "MORTY", "5", 500, "1"),
+    ];
+    for (maker_coin, taker_coin, uuid, finished_at, is_success) in swaps.iter() {
+        let (maker_coin_ticker, maker_coin_platform) = split_coin(maker_coin);
+        let (taker_coin_ticker, taker_coin_platform) = split_coin(taker_coin);
+        let params = vec![
+            maker_coin.to_string(),
+            maker_coin_ticker,
+            maker_coin_platform,
+            taker_coin.to_string(),
+            taker_coin_ticker,
+            taker_coin_platform,
+            uuid.to_string(),
+            finished_at.to_string(),
+            finished_at.to_string(),
+            "1.5".to_owned(),
+            "0.00001".to_owned(),
+            is_success.to_string(),
+        ];
+        conn.execute(INSERT_STATS_SWAP, &params).unwrap();
+    }
+
+    let trades = select_pair_trades(&conn, "RICK", "MORTY", 0, 1000, None).unwrap();
+    let uuids: Vec<_> = trades.iter().map(|trade| trade.uuid.as_str()).collect();
+    assert_eq!(uuids, vec!["5", "2", "1"]);
+    assert_eq!(trades[1].maker_coin, "MORTY");
+    assert_eq!(trades[1].finished_at, 200);
+    assert_eq!(trades[1].maker_amount.parse::<f64>().unwrap(), 1.5);
+    assert_eq!(trades[1].taker_amount.parse::<f64>().unwrap(), 0.00001);
+
+    let trades = select_pair_trades(&conn, "MORTY", "RICK", 150, 500, Some(1)).unwrap();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].uuid, "2");
+}
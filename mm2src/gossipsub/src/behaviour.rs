@@ -21,6 +21,7 @@
 use crate::config::GossipsubConfig;
 use crate::handler::GossipsubHandler;
 use crate::mcache::MessageCache;
+use crate::peer_score::{PeerScore, PeerScoreInfo, ScoreThreshold};
 use crate::protocol::{GossipsubControlAction, GossipsubMessage, GossipsubSubscription, GossipsubSubscriptionAction,
                       MessageId};
 use crate::topic::{Topic, TopicHash};
@@ -40,6 +41,21 @@ use wasm_timer::{Instant, Interval};
 
 mod tests;
 
+/// The message counters of the topic since the node start.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TopicMessageStats {
+    /// The messages published by our node
+    pub published: u64,
+    /// The first deliveries of the messages received from the other peers
+    pub received: u64,
+    /// The size of the first delivered messages in bytes
+    pub received_bytes: u64,
+    /// The deliveries of the already received messages
+    pub duplicates: u64,
+    /// The received messages rejected as invalid
+    pub invalid: u64,
+}
+
 /// Network behaviour that handles the gossipsub protocol.
 pub struct Gossipsub {
     /// Configuration providing gossipsub performance parameters.
@@ -98,6 +114,12 @@ pub struct Gossipsub {
 
     /// The relay list which are forcefully kept in relay mesh
     explicit_relay_list: Vec<PeerId>,
+
+    /// The scores of the peers, is None if the peer scoring is disabled.
+    peer_score: Option<PeerScore>,
+
+    /// The message counters by the topics.
+    topic_stats: HashMap<TopicHash, TopicMessageStats>,
 }
 
 impl Gossipsub {
@@ -139,6 +161,8 @@ impl Gossipsub {
             included_to_relays_mesh: HashSet::new(),
             connected_addresses: Vec::new(),
             explicit_relay_list: Vec::new(),
+            peer_score: gs_config.peer_score_params.map(PeerScore::new),
+            topic_stats: HashMap::new(),
         }
     }
 
@@ -233,6 +257,7 @@ impl Gossipsub {
         // call LEAVE(topic)
         // this will remove the topic from the mesh
         self.leave(topic_hash);
+        self.topic_stats.remove(topic_hash);
 
         info!("Unsubscribed from topic: {:?}", topic_hash);
         true
@@ -274,7 +299,10 @@ impl Gossipsub {
                 } else {
                     // we have no fanout peers, select mesh_n of them and add them to the fanout
                     let mesh_n = self.config.mesh_n;
-                    let new_peers = Self::get_random_peers(&self.topic_peers, topic_hash, mesh_n, |_| true);
+                    let peer_score = &self.peer_score;
+                    let new_peers = Self::get_random_peers(&self.topic_peers, topic_hash, mesh_n, |peer| {
+                        !Self::is_score_below(peer_score, peer, ScoreThreshold::Publish)
+                    });
                     // add the new peers to the fanout and recipient peers
                     self.fanout.insert(topic_hash.clone(), new_peers.clone());
                     for peer in new_peers {
@@ -287,6 +315,10 @@ impl Gossipsub {
             }
         }
 
+        for topic_hash in &message.topics {
+            self.topic_stats.entry(topic_hash.clone()).or_default().published += 1;
+        }
+
         // add published message to our received caches
         let msg_id = (self.config.message_id_fn)(&message);
         self.mcache.put(message.clone());
@@ -302,6 +334,10 @@ impl Gossipsub {
         });
         // Send to peers we know are subscribed to the topic.
         for peer_id in recipient_peers.iter() {
+            if Self::is_score_below(&self.peer_score, peer_id, ScoreThreshold::Publish) {
+                debug!("Skip publishing to peer {:?} with a low score", peer_id);
+                continue;
+            }
             debug!("Sending message to peer: {:?}", peer_id);
             self.notify_primary(*peer_id, event.clone());
         }
@@ -329,6 +365,40 @@ impl Gossipsub {
         true
     }
 
+    /// This function should be called when the user rejects a received message as invalid.
+    /// The message is removed from the cache, so it's not propagated anymore, and if the peer scoring is enabled,
+    /// every peer delivered the message is penalized in the message topics.
+    pub fn reject_message(&mut self, message_id: &MessageId) {
+        let message = match self.mcache.remove(message_id) {
+            Some(message) => message,
+            None => {
+                warn!("Rejected message not in cache. Message Id: {}", message_id.0);
+                return;
+            },
+        };
+        for topic_hash in &message.topics {
+            self.topic_stats.entry(topic_hash.clone()).or_default().invalid += 1;
+        }
+        let peer_score = match self.peer_score.as_mut() {
+            Some(peer_score) => peer_score,
+            None => return,
+        };
+        if let Some(delivered_by) = self.received.get(message_id) {
+            for peer_id in delivered_by.iter().filter(|peer_id| **peer_id != self.local_peer_id) {
+                debug!("Penalizing peer {:?} for the invalid message {:?}", peer_id, message_id);
+                peer_score.invalid_message_delivery(peer_id, &message.topics);
+            }
+        }
+    }
+
+    /// This function should be called when the user can't process a received message that isn't provably invalid,
+    /// e.g. the message of a newer protocol version. The message is removed from the cache without penalizing anyone.
+    pub fn ignore_message(&mut self, message_id: &MessageId) {
+        if self.mcache.remove(message_id).is_none() {
+            debug!("Ignored message not in cache. Message Id: {}", message_id.0);
+        }
+    }
+
     /// Gossipsub JOIN(topic) - adds topic peers to mesh and sends them GRAFT messages.
     fn join(&mut self, topic_hash: &TopicHash) {
         debug!("Running JOIN for topic: {:?}", topic_hash);
@@ -361,11 +431,12 @@ impl Gossipsub {
         // check if we need to get more peers, which we randomly select
         if added_peers.len() < self.config.mesh_n {
             // get the peers
+            let peer_score = &self.peer_score;
             let new_peers = Self::get_random_peers(
                 &self.topic_peers,
                 topic_hash,
                 self.config.mesh_n - added_peers.len(),
-                |_| true,
+                |peer| !Self::is_score_below(peer_score, peer, ScoreThreshold::Mesh),
             );
             added_peers.extend_from_slice(&new_peers);
             // add them to the mesh
@@ -405,6 +476,10 @@ impl Gossipsub {
     /// requests it with an IWANT control message.
     fn handle_ihave(&mut self, peer_id: &PeerId, ihave_msgs: Vec<(TopicHash, Vec<MessageId>)>) {
         debug!("Handling IHAVE for peer: {:?}", peer_id);
+        if Self::is_score_below(&self.peer_score, peer_id, ScoreThreshold::Gossip) {
+            debug!("IHAVE: Ignoring IHAVE from peer {:?} with a low score", peer_id);
+            return;
+        }
         // use a hashset to avoid duplicates efficiently
         let mut iwant_ids = HashSet::new();
 
@@ -437,6 +512,10 @@ impl Gossipsub {
     /// forwarded to the requesting peer.
     fn handle_iwant(&mut self, peer_id: &PeerId, iwant_msgs: Vec<MessageId>) {
         debug!("Handling IWANT for peer: {:?}", peer_id);
+        if Self::is_score_below(&self.peer_score, peer_id, ScoreThreshold::Gossip) {
+            debug!("IWANT: Ignoring IWANT from peer {:?} with a low score", peer_id);
+            return;
+        }
         // build a hashmap of available messages
         let mut cached_messages = HashMap::new();
 
@@ -467,8 +546,12 @@ impl Gossipsub {
         debug!("Handling GRAFT message for peer: {:?}", peer_id);
 
         let mut to_prune_topics = HashSet::new();
+        let negative_score = Self::is_score_below(&self.peer_score, peer_id, ScoreThreshold::Mesh);
         for topic_hash in topics {
-            if let Some(peers) = self.mesh.get_mut(&topic_hash) {
+            if negative_score {
+                info!("GRAFT: Refusing peer {:?} with a negative score", peer_id);
+                to_prune_topics.insert(topic_hash);
+            } else if let Some(peers) = self.mesh.get_mut(&topic_hash) {
                 // if we are subscribed, add peer to the mesh, if not already added
                 info!(
                     "GRAFT: Mesh link added for peer: {:?} in topic: {:?}",
@@ -526,7 +609,9 @@ impl Gossipsub {
         if self.peer_topics.entry(*peer_id).or_insert_with(Vec::new).is_empty() && is_relay {
             info!("IAmrelay: Adding peer: {:?} to the relays list", peer_id);
             self.connected_relays.insert(*peer_id);
-            if self.relays_mesh.len() < self.config.mesh_n_low {
+            if self.relays_mesh.len() < self.config.mesh_n_low
+                && !Self::is_score_below(&self.peer_score, peer_id, ScoreThreshold::Mesh)
+            {
                 info!("IAmrelay: Adding peer: {:?} to the relay mesh", peer_id);
                 self.add_peers_to_relays_mesh(vec![*peer_id]);
             }
@@ -578,12 +663,23 @@ impl Gossipsub {
             TimeCacheEntry::Occupied(entry) => {
                 debug!("Message already received, ignoring. Message: {:?}", msg_id);
                 entry.into_mut().push(*propagation_source);
+                for topic_hash in &msg.topics {
+                    self.topic_stats.entry(topic_hash.clone()).or_default().duplicates += 1;
+                }
                 return;
             },
             TimeCacheEntry::Vacant(entry) => {
                 entry.insert(SmallVec::from_elem(*propagation_source, 1));
             },
         }
+        for topic_hash in &msg.topics {
+            let stats = self.topic_stats.entry(topic_hash.clone()).or_default();
+            stats.received += 1;
+            stats.received_bytes += msg.data.len() as u64;
+        }
+        if let Some(peer_score) = self.peer_score.as_mut() {
+            peer_score.first_message_delivery(propagation_source, &msg.topics);
+        }
         // add to the memcache
         self.mcache.put(msg.clone());
 
@@ -645,7 +741,9 @@ impl Gossipsub {
                     }
 
                     // if the mesh needs peers add the peer to the mesh
-                    if let Some(peers) = self.mesh.get_mut(&subscription.topic_hash) {
+                    let negative_score =
+                        Self::is_score_below(&self.peer_score, propagation_source, ScoreThreshold::Mesh);
+                    if let Some(peers) = self.mesh.get_mut(&subscription.topic_hash).filter(|_| !negative_score) {
                         if peers.len() < self.config.mesh_n_low {
                             debug!("SUBSCRIPTION: Adding peer {:?} to the mesh", propagation_source,);
                         }
@@ -694,8 +792,26 @@ impl Gossipsub {
         let mut to_graft = HashMap::new();
         let mut to_prune = HashMap::new();
 
+        if let Some(peer_score) = self.peer_score.as_mut() {
+            peer_score.refresh_scores();
+        }
+        let peer_score = &self.peer_score;
+
         // maintain the mesh for each topic
         for (topic_hash, peers) in self.mesh.iter_mut() {
+            // remove the peers with a negative score
+            peers.retain(|peer| {
+                if Self::is_score_below(peer_score, peer, ScoreThreshold::Mesh) {
+                    debug!(
+                        "HEARTBEAT: Removing peer {:?} with a negative score from the mesh",
+                        peer
+                    );
+                    to_prune.entry(*peer).or_insert_with(Vec::new).push(topic_hash.clone());
+                    return false;
+                }
+                true
+            });
+
             // too little peers - add some
             if peers.len() < self.config.mesh_n_low {
                 debug!(
@@ -707,7 +823,7 @@ impl Gossipsub {
                 // not enough peers - get mesh_n - current_length more
                 let desired_peers = self.config.mesh_n - peers.len();
                 let peer_list = Self::get_random_peers(&self.topic_peers, topic_hash, desired_peers, {
-                    |peer| !peers.contains(peer)
+                    |peer| !peers.contains(peer) && !Self::is_score_below(peer_score, peer, ScoreThreshold::Mesh)
                 });
                 for peer in &peer_list {
                     let current_topic = to_graft.entry(*peer).or_insert_with(Vec::new);
@@ -775,7 +891,9 @@ impl Gossipsub {
                     },
                 }
             }
-            peers.retain(|peer| !to_remove_peers.contains(peer));
+            peers.retain(|peer| {
+                !to_remove_peers.contains(peer) && !Self::is_score_below(peer_score, peer, ScoreThreshold::Publish)
+            });
 
             // not enough peers
             if peers.len() < self.config.mesh_n {
@@ -786,7 +904,7 @@ impl Gossipsub {
                 );
                 let needed_peers = self.config.mesh_n - peers.len();
                 let new_peers = Self::get_random_peers(&self.topic_peers, topic_hash, needed_peers, |peer| {
-                    !peers.contains(peer)
+                    !peers.contains(peer) && !Self::is_score_below(peer_score, peer, ScoreThreshold::Publish)
                 });
                 peers.extend(new_peers);
             }
@@ -804,13 +922,30 @@ impl Gossipsub {
 
         // shift the memcache
         self.mcache.shift();
+        self.evict_topic_stats();
         debug!("Completed Heartbeat");
     }
 
+    /// Removes the message counters of the topics nobody is subscribed to anymore,
+    /// so the counters of the short-lived topics, e.g. the swap ones, don't accumulate.
+    /// The relay keeps the counters of the topics its peers are subscribed to.
+    fn evict_topic_stats(&mut self) {
+        let mesh = &self.mesh;
+        let fanout = &self.fanout;
+        let topic_peers = &self.topic_peers;
+        let i_am_relay = self.config.i_am_relay;
+        self.topic_stats.retain(|topic_hash, _| {
+            mesh.contains_key(topic_hash)
+                || fanout.contains_key(topic_hash)
+                || (i_am_relay && topic_peers.get(topic_hash).map_or(false, |peers| !peers.is_empty()))
+        });
+    }
+
     /// Emits gossip - Send IHAVE messages to a random set of gossip peers. This is applied to mesh
     /// and fanout peers
     fn emit_gossip(&mut self) {
         debug!("Started gossip");
+        let peer_score = &self.peer_score;
         for (topic_hash, peers) in self.mesh.iter().chain(self.fanout.iter()) {
             let message_ids = self.mcache.get_gossip_ids(topic_hash);
             if message_ids.is_empty() {
@@ -819,7 +954,7 @@ impl Gossipsub {
 
             // get gossip_lazy random peers
             let to_msg_peers = Self::get_random_peers(&self.topic_peers, topic_hash, self.config.gossip_lazy, |peer| {
-                !peers.contains(peer)
+                !peers.contains(peer) && !Self::is_score_below(peer_score, peer, ScoreThreshold::Gossip)
             });
             for peer in to_msg_peers {
                 // send an IHAVE message
@@ -897,6 +1032,7 @@ impl Gossipsub {
                         if peer_id != source
                             && peer_id != &message.source
                             && self.included_to_relays_mesh.contains(peer_id)
+                            && !Self::is_score_below(&self.peer_score, peer_id, ScoreThreshold::Publish)
                         {
                             recipient_peers.insert(*peer_id);
                         }
@@ -908,7 +1044,10 @@ impl Gossipsub {
             for topic in &message.topics {
                 if let Some(mesh_peers) = self.mesh.get(topic) {
                     for peer_id in mesh_peers {
-                        if peer_id != source && peer_id != &message.source {
+                        if peer_id != source
+                            && peer_id != &message.source
+                            && !Self::is_score_below(&self.peer_score, peer_id, ScoreThreshold::Publish)
+                        {
                             recipient_peers.insert(*peer_id);
                         }
                     }
@@ -953,7 +1092,10 @@ impl Gossipsub {
                     }
                 }
 
-                if relay != *source && relay != message_source {
+                if relay != *source
+                    && relay != message_source
+                    && !Self::is_score_below(&self.peer_score, &relay, ScoreThreshold::Publish)
+                {
                     debug!("Sending message: {:?} to relay {:?}", msg_id, relay);
                     self.notify_primary(relay, event.clone());
                 }
@@ -1011,6 +1153,13 @@ impl Gossipsub {
         relays[..n].to_vec()
     }
 
+    /// Checks if the score of the peer is below the `threshold`, is always false if the peer scoring is disabled.
+    fn is_score_below(peer_score: &Option<PeerScore>, peer_id: &PeerId, threshold: ScoreThreshold) -> bool {
+        peer_score
+            .as_ref()
+            .map_or(false, |peer_score| peer_score.is_below(peer_id, threshold))
+    }
+
     // adds a control action to control_pool
     fn control_pool_add(
         control_pool: &mut HashMap<PeerId, Vec<GossipsubControlAction>>,
@@ -1071,6 +1220,14 @@ impl Gossipsub {
 
     pub fn get_config(&self) -> &GossipsubConfig { &self.config }
 
+    /// Get the message counters by the topics.
+    pub fn get_topic_stats(&self) -> &HashMap<TopicHash, TopicMessageStats> { &self.topic_stats }
+
+    /// Get the scores of the known peers, is empty if the peer scoring is disabled.
+    pub fn get_peer_scores(&self) -> HashMap<PeerId, PeerScoreInfo> {
+        self.peer_score.as_ref().map(PeerScore::peer_scores).unwrap_or_default()
+    }
+
     /// Adds peers to relays mesh and notifies them they are added
     fn add_peers_to_relays_mesh(&mut self, peers: Vec<PeerId>) {
         for peer in &peers {
@@ -1082,7 +1239,6 @@ impl Gossipsub {
         }
     }
 
-    #[allow(dead_code)]
     fn remove_peer_from_relay_mesh(&mut self, peer: &PeerId) {
         if self.relays_mesh.remove(peer).is_some() {
             self.notify_excluded_from_relay_mesh(*peer)
@@ -1201,6 +1357,20 @@ impl Gossipsub {
     }
 
     fn maintain_relays_mesh(&mut self) {
+        let negative_score_relays: Vec<_> = self
+            .relays_mesh
+            .keys()
+            .filter(|relay| Self::is_score_below(&self.peer_score, relay, ScoreThreshold::Mesh))
+            .cloned()
+            .collect();
+        for relay in negative_score_relays {
+            info!(
+                "HEARTBEAT: Removing relay {:?} with a negative score from the relays mesh",
+                relay
+            );
+            self.remove_peer_from_relay_mesh(&relay);
+        }
+
         if self.relays_mesh.len() < self.config.mesh_n_low {
             info!(
                 "HEARTBEAT: relays low. Contains: {:?} needs: {:?}",
@@ -1210,8 +1380,9 @@ impl Gossipsub {
             // add peers 1 by 1 to avoid overloading peaks when node connects to several other nodes at once
             let required = 1;
             // get `n` relays that are not in the `relays_mesh`
-            let to_add =
-                Self::get_random_relays(&self.connected_relays, required, |p| !self.relays_mesh.contains_key(p));
+            let to_add = Self::get_random_relays(&self.connected_relays, required, |p| {
+                !self.relays_mesh.contains_key(p) && !Self::is_score_below(&self.peer_score, p, ScoreThreshold::Mesh)
+            });
             self.add_peers_to_relays_mesh(to_add);
         }
 
@@ -1282,6 +1453,9 @@ impl NetworkBehaviour for Gossipsub {
         }
         // For the time being assume all gossipsub peers
         self.peer_topics.insert(*id, Vec::new());
+        if let Some(peer_score) = self.peer_score.as_mut() {
+            peer_score.add_peer(*id);
+        }
     }
 
     fn inject_disconnected(&mut self, id: &PeerId) {
@@ -1333,6 +1507,9 @@ impl NetworkBehaviour for Gossipsub {
         self.connected_relays.remove(id);
         self.included_to_relays_mesh.remove(id);
         self.peer_connections.remove(id);
+        if let Some(peer_score) = self.peer_score.as_mut() {
+            peer_score.remove_peer(id);
+        }
         // remove peer from peer_topics
         let was_in = self.peer_topics.remove(id);
         debug_assert!(was_in.is_some());
@@ -1370,6 +1547,10 @@ impl NetworkBehaviour for Gossipsub {
         // Handle subscriptions
         // Update connected peers topics
         debug!("Event injected {:?}, source {:?}", event, propagation_source);
+        if Self::is_score_below(&self.peer_score, &propagation_source, ScoreThreshold::Graylist) {
+            debug!("Ignoring RPC from the graylisted peer {:?}", propagation_source);
+            return;
+        }
         self.handle_received_subscriptions(&event.subscriptions, &propagation_source);
 
         // Handle messages
@@ -23,7 +23,7 @@
 #[cfg(test)]
 mod tests {
     use super::super::*;
-    use crate::GossipsubConfigBuilder;
+    use crate::{GossipsubConfigBuilder, PeerScoreParams};
 
     // helper functions for testing
 
@@ -892,4 +892,120 @@ mod tests {
             _ => panic!("Invalid NetworkBehaviourAction variant"),
         }
     }
+
+    #[test]
+    fn test_reject_message_penalizes_and_graylists_peer() {
+        let config = GossipsubConfigBuilder::default()
+            .manual_propagation()
+            .peer_score_params(PeerScoreParams::default())
+            .build();
+        let (mut gs, peers, topic_hashes) = build_and_inject_nodes(6, vec![String::from("topic1")], config, true);
+        assert!(gs.mesh.get(&topic_hashes[0]).unwrap().contains(&peers[0]));
+
+        let id = gs.config.message_id_fn;
+        for sequence_number in 0..3 {
+            let message = GossipsubMessage {
+                source: peers[0],
+                data: vec![1, 2, 3, 4],
+                sequence_number,
+                topics: topic_hashes.clone(),
+            };
+            let msg_id = id(&message);
+            gs.handle_received_message(message, &peers[0]);
+            gs.reject_message(&msg_id);
+            assert!(gs.mcache.get(&msg_id).is_none());
+        }
+
+        let scores = gs.get_peer_scores();
+        let peer_score = scores.get(&peers[0]).unwrap();
+        assert!(peer_score.graylisted);
+        assert_eq!(peer_score.topics[&topic_hashes[0]].invalid_message_deliveries, 3.);
+        // the other peers are not penalized
+        assert!(scores.get(&peers[1]).is_none());
+
+        gs.heartbeat();
+        assert!(!gs.mesh.get(&topic_hashes[0]).unwrap().contains(&peers[0]));
+    }
+
+    #[test]
+    fn test_ignore_message_doesnt_penalize_peer() {
+        let config = GossipsubConfigBuilder::default()
+            .manual_propagation()
+            .peer_score_params(PeerScoreParams::default())
+            .build();
+        let (mut gs, peers, topic_hashes) = build_and_inject_nodes(6, vec![String::from("topic1")], config, true);
+
+        let id = gs.config.message_id_fn;
+        for sequence_number in 0..3 {
+            let message = GossipsubMessage {
+                source: peers[0],
+                data: vec![1, 2, 3, 4],
+                sequence_number,
+                topics: topic_hashes.clone(),
+            };
+            let msg_id = id(&message);
+            gs.handle_received_message(message, &peers[0]);
+            gs.ignore_message(&msg_id);
+            assert!(gs.mcache.get(&msg_id).is_none());
+        }
+
+        let scores = gs.get_peer_scores();
+        assert!(scores.get(&peers[0]).map_or(true, |score| !score.graylisted));
+        assert_eq!(gs.get_topic_stats()[&topic_hashes[0]].invalid, 0);
+
+        gs.heartbeat();
+        assert!(gs.mesh.get(&topic_hashes[0]).unwrap().contains(&peers[0]));
+    }
+
+    #[test]
+    fn test_topic_stats() {
+        let config = GossipsubConfigBuilder::default().manual_propagation().build();
+        let (mut gs, peers, topic_hashes) = build_and_inject_nodes(3, vec![String::from("topic1")], config, true);
+
+        let id = gs.config.message_id_fn;
+        let message = GossipsubMessage {
+            source: peers[0],
+            data: vec![1, 2, 3, 4],
+            sequence_number: 1,
+            topics: topic_hashes.clone(),
+        };
+        let msg_id = id(&message);
+        gs.handle_received_message(message.clone(), &peers[0]);
+        gs.handle_received_message(message.clone(), &peers[1]);
+        gs.handle_received_message(message, &peers[2]);
+        gs.reject_message(&msg_id);
+        gs.publish(&Topic::new(String::from("topic1")), vec![5, 6]);
+
+        let expected = TopicMessageStats {
+            published: 1,
+            received: 1,
+            received_bytes: 4,
+            duplicates: 2,
+            invalid: 1,
+        };
+        assert_eq!(gs.get_topic_stats()[&topic_hashes[0]], expected);
+    }
+
+    #[test]
+    fn test_topic_stats_eviction() {
+        let config = GossipsubConfigBuilder::default().manual_propagation().build();
+        let (mut gs, peers, topic_hashes) = build_and_inject_nodes(1, vec![String::from("topic1")], config, true);
+
+        let not_subscribed = gs.topic_hash(Topic::new(String::from("topic2")));
+        let message = GossipsubMessage {
+            source: peers[0],
+            data: vec![1, 2, 3, 4],
+            sequence_number: 1,
+            topics: vec![topic_hashes[0].clone(), not_subscribed],
+        };
+        gs.handle_received_message(message, &peers[0]);
+        assert_eq!(gs.get_topic_stats().len(), 2);
+
+        gs.heartbeat();
+        assert_eq!(gs.get_topic_stats().len(), 1);
+        assert!(gs.get_topic_stats().contains_key(&topic_hashes[0]));
+
+        assert!(gs.unsubscribe(Topic::new(String::from("topic1"))));
+        assert!(gs.get_topic_stats().is_empty());
+    }
 }
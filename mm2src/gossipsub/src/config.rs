@@ -18,6 +18,7 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+use crate::peer_score::PeerScoreParams;
 use crate::protocol::{GossipsubMessage, MessageId};
 use std::borrow::Cow;
 use std::time::Duration;
@@ -93,6 +94,9 @@ pub struct GossipsubConfig {
     pub message_id_fn: fn(&GossipsubMessage) -> MessageId,
 
     pub i_am_relay: bool,
+
+    /// The peer scoring parameters, the peers are not scored if not set (default is None).
+    pub peer_score_params: Option<PeerScoreParams>,
 }
 
 impl Default for GossipsubConfig {
@@ -120,6 +124,7 @@ impl Default for GossipsubConfig {
                 MessageId(source_string)
             },
             i_am_relay: false,
+            peer_score_params: None,
         }
     }
 }
@@ -237,6 +242,13 @@ impl GossipsubConfigBuilder {
         self
     }
 
+    pub fn peer_score_params(&mut self, peer_score_params: PeerScoreParams) -> &mut Self {
+        let validation = peer_score_params.validate();
+        assert!(validation.is_ok(), "Invalid peer score params: {:?}", validation);
+        self.config.peer_score_params = Some(peer_score_params);
+        self
+    }
+
     pub fn build(&self) -> GossipsubConfig { self.config.clone() }
 }
 
@@ -258,6 +270,7 @@ impl std::fmt::Debug for GossipsubConfig {
         let _ = builder.field("no_source_id", &self.no_source_id);
         let _ = builder.field("manual_propagation", &self.manual_propagation);
         let _ = builder.field("i_am_relay", &self.i_am_relay);
+        let _ = builder.field("peer_score_params", &self.peer_score_params);
         builder.finish()
     }
 }
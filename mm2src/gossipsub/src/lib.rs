@@ -141,13 +141,15 @@ mod behaviour;
 mod config;
 mod handler;
 mod mcache;
+mod peer_score;
 mod topic;
 
 mod rpc_proto {
     include!(concat!(env!("OUT_DIR"), "/gossipsub.pb.rs"));
 }
 
-pub use self::behaviour::{Gossipsub, GossipsubEvent, GossipsubRpc};
+pub use self::behaviour::{Gossipsub, GossipsubEvent, GossipsubRpc, TopicMessageStats};
 pub use self::config::{GossipsubConfig, GossipsubConfigBuilder};
+pub use self::peer_score::{PeerScoreInfo, PeerScoreParams, PeerScoreThresholds, TopicScoreParams, TopicScoreStats};
 pub use self::protocol::{GossipsubMessage, MessageId};
 pub use self::topic::{Topic, TopicHash};
@@ -84,6 +84,10 @@ impl MessageCache {
     /// Get a message with `message_id`
     pub fn get(&self, message_id: &MessageId) -> Option<&GossipsubMessage> { self.msgs.get(message_id) }
 
+    /// Remove a message with `message_id`, it's still announced by the gossip until shifted from the history
+    /// but is not sent to the peers requesting it anymore
+    pub fn remove(&mut self, message_id: &MessageId) -> Option<GossipsubMessage> { self.msgs.remove(message_id) }
+
     /// Get a list of GossipIds for a given topic
     pub fn get_gossip_ids(&self, topic: &TopicHash) -> Vec<MessageId> {
         self.history[..self.gossip]
@@ -0,0 +1,351 @@
+//! Peer scoring in the style of gossipsub v1.1.
+//!
+//! Every peer is scored by the topics it delivers the messages to:
+//! the first deliveries of the valid messages increase the score,
+//! the messages rejected by the application decrease it by the square of the invalid deliveries counter.
+//! The counters decay over time, so the peer recovers from the penalties unless it keeps misbehaving.
+//!
+//! The score is compared to the thresholds to exclude the peer from the mesh, the gossip, the publishing,
+//! and finally to ignore all RPCs of the peer when it drops below the graylist threshold.
+
+use crate::topic::TopicHash;
+use libp2p_core::PeerId;
+use std::collections::HashMap;
+use std::time::Duration;
+use wasm_timer::Instant;
+
+/// The score parameters applied to every topic.
+#[derive(Clone, Debug)]
+pub struct TopicScoreParams {
+    /// The weight of the topic score in the peer score (default is 1).
+    pub topic_weight: f64,
+
+    /// The weight of the first message deliveries counter, must be positive (default is 1).
+    pub first_message_deliveries_weight: f64,
+
+    /// The decay factor of the first message deliveries counter applied every decay interval (default is 0.9).
+    pub first_message_deliveries_decay: f64,
+
+    /// The max value of the first message deliveries counter (default is 20).
+    pub first_message_deliveries_cap: f64,
+
+    /// The weight of the squared invalid message deliveries counter, must be negative (default is -10).
+    pub invalid_message_deliveries_weight: f64,
+
+    /// The decay factor of the invalid message deliveries counter applied every decay interval (default is 0.997).
+    pub invalid_message_deliveries_decay: f64,
+}
+
+impl Default for TopicScoreParams {
+    fn default() -> TopicScoreParams {
+        TopicScoreParams {
+            topic_weight: 1.,
+            first_message_deliveries_weight: 1.,
+            first_message_deliveries_decay: 0.9,
+            first_message_deliveries_cap: 20.,
+            invalid_message_deliveries_weight: -10.,
+            invalid_message_deliveries_decay: 0.997,
+        }
+    }
+}
+
+/// The score thresholds, must satisfy `graylist_threshold <= publish_threshold <= gossip_threshold <= 0`.
+#[derive(Clone, Debug)]
+pub struct PeerScoreThresholds {
+    /// Below this score the gossip is neither emitted to nor accepted from the peer (default is -10).
+    pub gossip_threshold: f64,
+
+    /// Below this score the published and forwarded messages are not sent to the peer (default is -50).
+    pub publish_threshold: f64,
+
+    /// Below this score all RPCs of the peer are ignored (default is -80).
+    pub graylist_threshold: f64,
+}
+
+impl Default for PeerScoreThresholds {
+    fn default() -> PeerScoreThresholds {
+        PeerScoreThresholds {
+            gossip_threshold: -10.,
+            publish_threshold: -50.,
+            graylist_threshold: -80.,
+        }
+    }
+}
+
+/// The parameters of the peer scoring.
+#[derive(Clone, Debug)]
+pub struct PeerScoreParams {
+    /// The score parameters of every topic.
+    pub topic_params: TopicScoreParams,
+
+    /// The max sum of the positive topic scores (default is 100).
+    pub topic_score_cap: f64,
+
+    pub thresholds: PeerScoreThresholds,
+
+    /// The interval of the counters decay (default is 1 second).
+    pub decay_interval: Duration,
+
+    /// The counter is reset to 0 once it decays below this value (default is 0.01).
+    pub decay_to_zero: f64,
+
+    /// How long the counters of the disconnected peer with a non-positive score are kept (default is 1 hour).
+    pub retain_score: Duration,
+}
+
+impl Default for PeerScoreParams {
+    fn default() -> PeerScoreParams {
+        PeerScoreParams {
+            topic_params: TopicScoreParams::default(),
+            topic_score_cap: 100.,
+            thresholds: PeerScoreThresholds::default(),
+            decay_interval: Duration::from_secs(1),
+            decay_to_zero: 0.01,
+            retain_score: Duration::from_secs(3600),
+        }
+    }
+}
+
+impl PeerScoreParams {
+    pub fn validate(&self) -> Result<(), String> {
+        let thresholds = &self.thresholds;
+        if thresholds.gossip_threshold > 0.
+            || thresholds.publish_threshold > thresholds.gossip_threshold
+            || thresholds.graylist_threshold > thresholds.publish_threshold
+        {
+            return Err("The thresholds must satisfy graylist <= publish <= gossip <= 0".into());
+        }
+        let topic = &self.topic_params;
+        if topic.topic_weight < 0. {
+            return Err("topic_weight must be non-negative".into());
+        }
+        if topic.first_message_deliveries_weight < 0. || topic.first_message_deliveries_cap < 0. {
+            return Err("first_message_deliveries_weight and cap must be non-negative".into());
+        }
+        if topic.invalid_message_deliveries_weight > 0. {
+            return Err("invalid_message_deliveries_weight must be non-positive".into());
+        }
+        let decays = [
+            topic.first_message_deliveries_decay,
+            topic.invalid_message_deliveries_decay,
+        ];
+        if decays.iter().any(|decay| *decay <= 0. || *decay >= 1.) {
+            return Err("The decay factors must be in the (0, 1) range".into());
+        }
+        if self.decay_interval < Duration::from_millis(100) {
+            return Err("decay_interval must be at least 100ms".into());
+        }
+        Ok(())
+    }
+}
+
+/// The score counters of the peer in the topic.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TopicScoreStats {
+    pub first_message_deliveries: f64,
+    pub invalid_message_deliveries: f64,
+}
+
+/// The score of the peer exposed for the inspection.
+#[derive(Clone, Debug)]
+pub struct PeerScoreInfo {
+    pub score: f64,
+    pub graylisted: bool,
+    pub topics: HashMap<TopicHash, TopicScoreStats>,
+}
+
+#[derive(Default)]
+struct PeerStats {
+    topics: HashMap<TopicHash, TopicScoreStats>,
+    /// Is set when the peer is disconnected to drop its counters after `retain_score`
+    disconnected_at: Option<Instant>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum ScoreThreshold {
+    /// The peer with a negative score is excluded from the mesh
+    Mesh,
+    Gossip,
+    Publish,
+    Graylist,
+}
+
+pub(crate) struct PeerScore {
+    params: PeerScoreParams,
+    peer_stats: HashMap<PeerId, PeerStats>,
+    last_decay: Instant,
+}
+
+impl PeerScore {
+    pub(crate) fn new(params: PeerScoreParams) -> PeerScore {
+        PeerScore {
+            params,
+            peer_stats: HashMap::new(),
+            last_decay: Instant::now(),
+        }
+    }
+
+    pub(crate) fn score(&self, peer_id: &PeerId) -> f64 {
+        let stats = match self.peer_stats.get(peer_id) {
+            Some(stats) => stats,
+            None => return 0.,
+        };
+        let params = &self.params.topic_params;
+        let mut positive = 0.;
+        let mut negative = 0.;
+        for topic_stats in stats.topics.values() {
+            positive +=
+                params.topic_weight * params.first_message_deliveries_weight * topic_stats.first_message_deliveries;
+            negative += params.topic_weight
+                * params.invalid_message_deliveries_weight
+                * topic_stats.invalid_message_deliveries.powi(2);
+        }
+        positive.min(self.params.topic_score_cap) + negative
+    }
+
+    pub(crate) fn is_below(&self, peer_id: &PeerId, threshold: ScoreThreshold) -> bool {
+        let thresholds = &self.params.thresholds;
+        let threshold = match threshold {
+            ScoreThreshold::Mesh => 0.,
+            ScoreThreshold::Gossip => thresholds.gossip_threshold,
+            ScoreThreshold::Publish => thresholds.publish_threshold,
+            ScoreThreshold::Graylist => thresholds.graylist_threshold,
+        };
+        self.score(peer_id) < threshold
+    }
+
+    pub(crate) fn add_peer(&mut self, peer_id: PeerId) {
+        self.peer_stats
+            .entry(peer_id)
+            .or_insert_with(PeerStats::default)
+            .disconnected_at = None;
+    }
+
+    /// Drops the counters of the peer with a positive score,
+    /// keeps the others for `retain_score` so the peer can't reset the penalties by reconnecting.
+    pub(crate) fn remove_peer(&mut self, peer_id: &PeerId) {
+        if self.score(peer_id) > 0. {
+            self.peer_stats.remove(peer_id);
+        } else if let Some(stats) = self.peer_stats.get_mut(peer_id) {
+            stats.disconnected_at = Some(Instant::now());
+        }
+    }
+
+    pub(crate) fn first_message_delivery(&mut self, peer_id: &PeerId, topics: &[TopicHash]) {
+        let cap = self.params.topic_params.first_message_deliveries_cap;
+        let stats = self.peer_stats.entry(*peer_id).or_insert_with(PeerStats::default);
+        for topic in topics {
+            let topic_stats = stats
+                .topics
+                .entry(topic.clone())
+                .or_insert_with(TopicScoreStats::default);
+            topic_stats.first_message_deliveries = (topic_stats.first_message_deliveries + 1.).min(cap);
+        }
+    }
+
+    pub(crate) fn invalid_message_delivery(&mut self, peer_id: &PeerId, topics: &[TopicHash]) {
+        let stats = self.peer_stats.entry(*peer_id).or_insert_with(PeerStats::default);
+        for topic in topics {
+            let topic_stats = stats
+                .topics
+                .entry(topic.clone())
+                .or_insert_with(TopicScoreStats::default);
+            topic_stats.invalid_message_deliveries += 1.;
+        }
+    }
+
+    /// Decays the counters once per `decay_interval` and drops the expired counters of the disconnected peers.
+    pub(crate) fn refresh_scores(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_decay);
+        if elapsed < self.params.decay_interval {
+            return;
+        }
+        let intervals = (elapsed.as_millis() / self.params.decay_interval.as_millis()) as i32;
+        self.last_decay = now;
+
+        let params = &self.params.topic_params;
+        let first_decay = params.first_message_deliveries_decay.powi(intervals);
+        let invalid_decay = params.invalid_message_deliveries_decay.powi(intervals);
+        let decay_to_zero = self.params.decay_to_zero;
+        let retain_score = self.params.retain_score;
+        self.peer_stats.retain(|_, stats| {
+            if let Some(disconnected_at) = stats.disconnected_at {
+                if disconnected_at + retain_score < now {
+                    return false;
+                }
+            }
+            stats.topics.retain(|_, topic_stats| {
+                topic_stats.first_message_deliveries *= first_decay;
+                if topic_stats.first_message_deliveries < decay_to_zero {
+                    topic_stats.first_message_deliveries = 0.;
+                }
+                topic_stats.invalid_message_deliveries *= invalid_decay;
+                if topic_stats.invalid_message_deliveries < decay_to_zero {
+                    topic_stats.invalid_message_deliveries = 0.;
+                }
+                *topic_stats != TopicScoreStats::default()
+            });
+            !stats.topics.is_empty() || stats.disconnected_at.is_none()
+        });
+    }
+
+    pub(crate) fn peer_scores(&self) -> HashMap<PeerId, PeerScoreInfo> {
+        self.peer_stats
+            .iter()
+            .map(|(peer_id, stats)| {
+                let info = PeerScoreInfo {
+                    score: self.score(peer_id),
+                    graylisted: self.is_below(peer_id, ScoreThreshold::Graylist),
+                    topics: stats.topics.clone(),
+                };
+                (*peer_id, info)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Topic;
+
+    #[test]
+    fn test_peer_score_penalties_and_decay() {
+        let params = PeerScoreParams {
+            decay_interval: Duration::from_millis(100),
+            ..PeerScoreParams::default()
+        };
+        params.validate().unwrap();
+        let mut peer_score = PeerScore::new(params);
+        let peer = PeerId::random();
+        let topics = vec![Topic::new("orbk/RICK:MORTY".into()).no_hash()];
+
+        peer_score.add_peer(peer);
+        peer_score.first_message_delivery(&peer, &topics);
+        peer_score.first_message_delivery(&peer, &topics);
+        assert_eq!(peer_score.score(&peer), 2.);
+
+        peer_score.invalid_message_delivery(&peer, &topics);
+        assert_eq!(peer_score.score(&peer), -8.);
+        assert!(peer_score.is_below(&peer, ScoreThreshold::Mesh));
+        assert!(!peer_score.is_below(&peer, ScoreThreshold::Gossip));
+
+        peer_score.invalid_message_delivery(&peer, &topics);
+        peer_score.invalid_message_delivery(&peer, &topics);
+        // 2 - 10 * 3^2
+        assert_eq!(peer_score.score(&peer), -88.);
+        assert!(peer_score.is_below(&peer, ScoreThreshold::Graylist));
+
+        // the penalties are retained after the reconnection
+        peer_score.remove_peer(&peer);
+        peer_score.add_peer(peer);
+        assert!(peer_score.is_below(&peer, ScoreThreshold::Graylist));
+
+        std::thread::sleep(Duration::from_millis(250));
+        peer_score.refresh_scores();
+        let stats = &peer_score.peer_scores()[&peer].topics[&topics[0]];
+        assert!(stats.first_message_deliveries < 2.);
+        assert!(stats.invalid_message_deliveries < 3.);
+    }
+}
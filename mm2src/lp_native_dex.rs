@@ -18,10 +18,11 @@
 //
 
 use coins::register_balance_update_handler;
-use mm2_libp2p::{spawn_gossipsub, NodeType};
+use mm2_libp2p::{spawn_gossipsub, ConnectionLimitsConfig, DnsConfig, NodeKey, NodeType};
 use rand::rngs::SmallRng;
 use rand::{random, Rng, SeedableRng};
 use serde_json::{self as json};
+use socks5::{self, Socks5ProxyConfig};
 use std::fs;
 use std::io::{Read, Write};
 use std::net::{IpAddr, Ipv4Addr};
@@ -30,9 +31,18 @@ use std::str;
 
 #[cfg(not(target_arch = "wasm32"))]
 use crate::mm2::database::init_and_migrate_db;
-use crate::mm2::lp_network::{lp_ports, p2p_event_process_loop, P2PContext};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::mm2::lp_network::peer_store_save_loop;
+use crate::mm2::lp_network::{load_peer_store, lp_ports, network_diagnostics_metrics_loop, p2p_event_process_loop,
+                             P2PContext};
 use crate::mm2::lp_ordermatch::{broadcast_maker_orders_keep_alive_loop, clean_memory_loop, init_ordermatch_context,
-                                lp_ordermatch_loop, orders_kick_start, BalanceUpdateOrdermatchHandler};
+                                lp_ordermatch_loop, lp_private_offers_loop, orders_kick_start,
+                                BalanceUpdateOrdermatchHandler};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::mm2::lp_ordermatch::{load_orderbook_snapshot, lp_grid_strategy_loop, lp_orderbook_snapshot_loop,
+                                lp_price_peg_loop};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::mm2::lp_swap::{load_banned_pubkeys, swap_refund_manager_loop};
 use crate::mm2::lp_swap::{running_swaps_num, swap_kick_starts};
 use crate::mm2::rpc::spawn_rpc;
 use crate::mm2::{MM_DATETIME, MM_VERSION};
@@ -180,6 +190,12 @@ fn fix_directories(ctx: &MmCtx) -> Result<(), String> {
     if !ensure_dir_is_writable(&dbdir.join("ORDERS").join("MY").join("HISTORY")) {
         return ERR!("ORDERS/MY/HISTORY db dir is not writable");
     }
+    if !ensure_dir_is_writable(&dbdir.join("ORDERS").join("MY").join("MULTI_MAKER")) {
+        return ERR!("ORDERS/MY/MULTI_MAKER db dir is not writable");
+    }
+    if !ensure_dir_is_writable(&dbdir.join("ORDERS").join("MY").join("STRATEGIES")) {
+        return ERR!("ORDERS/MY/STRATEGIES db dir is not writable");
+    }
     if !ensure_dir_is_writable(&dbdir.join("TX_CACHE")) {
         return ERR!("TX_CACHE db dir is not writable");
     }
@@ -315,6 +331,7 @@ fn test_ip(ctx: &MmArc, ip: IpAddr) -> Result<(), String> {
 pub async fn lp_init(ctx: MmArc) -> Result<(), String> {
     info!("Version: {} DT {}", MM_VERSION, MM_DATETIME);
     try_s!(lp_passphrase_init(&ctx));
+    try_s!(init_socks5_proxy(&ctx));
 
     try_s!(fix_directories(&ctx));
     #[cfg(not(target_arch = "wasm32"))]
@@ -322,6 +339,7 @@ pub async fn lp_init(ctx: MmArc) -> Result<(), String> {
         try_s!(ctx.init_sqlite_connection());
         try_s!(init_and_migrate_db(&ctx, &ctx.sqlite_connection()));
         try_s!(migrate_db(&ctx));
+        try_s!(load_banned_pubkeys(&ctx));
     }
 
     try_s!(init_ordermatch_context(&ctx));
@@ -339,14 +357,30 @@ pub async fn lp_init(ctx: MmArc) -> Result<(), String> {
         let mut coins_needed_for_kick_start = swap_kick_starts(ctx.clone());
         coins_needed_for_kick_start.extend(try_s!(orders_kick_start(&ctx).await));
         *(try_s!(ctx.coins_needed_for_kick_start.lock())) = coins_needed_for_kick_start;
+
+        if let Err(e) = load_orderbook_snapshot(&ctx).await {
+            error!("Error loading the orderbook snapshot: {}", e);
+        }
     }
 
     spawn(lp_ordermatch_loop(ctx.clone()));
 
+    #[cfg(not(target_arch = "wasm32"))]
+    spawn(lp_price_peg_loop(ctx.clone()));
+    #[cfg(not(target_arch = "wasm32"))]
+    spawn(lp_grid_strategy_loop(ctx.clone()));
+    spawn(lp_private_offers_loop(ctx.clone()));
+
     spawn(broadcast_maker_orders_keep_alive_loop(ctx.clone()));
 
     spawn(clean_memory_loop(ctx.clone()));
 
+    #[cfg(not(target_arch = "wasm32"))]
+    spawn(swap_refund_manager_loop(ctx.clone()));
+
+    #[cfg(not(target_arch = "wasm32"))]
+    spawn(lp_orderbook_snapshot_loop(ctx.clone()));
+
     let ctx_id = try_s!(ctx.ffi_handle());
 
     spawn_rpc(ctx_id);
@@ -484,22 +518,64 @@ async fn myipaddr(ctx: MmArc) -> Result<IpAddr, String> {
     Ok(myipaddr)
 }
 
+/// Routes the outbound P2P, Electrum and HTTP connections through the `socks5_proxy` of the config if it's set.
+fn init_socks5_proxy(ctx: &MmArc) -> Result<(), String> {
+    if ctx.conf["socks5_proxy"].is_null() {
+        return Ok(());
+    }
+    let proxy: Socks5ProxyConfig = try_s!(json::from_value(ctx.conf["socks5_proxy"].clone()));
+    #[cfg(target_arch = "wasm32")]
+    return ERR!("'socks5_proxy' is only supported in native mode, got {:?}", proxy);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        info!(
+            "Using the SOCKS5 proxy {}, clearnet fallback refused: {}",
+            proxy.address, proxy.refuse_clearnet_fallback
+        );
+        socks5::set_global_proxy(Some(proxy));
+        Ok(())
+    }
+}
+
 async fn init_p2p(ctx: MmArc) -> Result<(), String> {
     let i_am_seed = ctx.conf["i_am_seed"].as_bool().unwrap_or(false);
     let netid = ctx.netid();
 
-    let seednodes: Vec<String> = if ctx.conf["seednodes"].is_null() {
-        default_seednodes(netid)
+    let dns_config: DnsConfig = if ctx.conf["p2p_dns"].is_null() {
+        DnsConfig::default()
     } else {
+        try_s!(json::from_value(ctx.conf["p2p_dns"].clone()))
+    };
+
+    let seednodes: Vec<String> = if !ctx.conf["seednodes"].is_null() {
         try_s!(json::from_value(ctx.conf["seednodes"].clone()))
+    } else if dns_config == DnsConfig::Disabled {
+        info!("DNS is disabled, the default seednodes are not resolved");
+        Vec::new()
+    } else {
+        default_seednodes(netid)
     };
 
+    // discover the relays of the same netid in the local network
+    let mdns_enabled = ctx.conf["p2p_mdns"].as_bool().unwrap_or(false);
+
+    let connection_limits: ConnectionLimitsConfig = if ctx.conf["p2p_connection_limits"].is_null() {
+        ConnectionLimitsConfig::default()
+    } else {
+        try_s!(json::from_value(ctx.conf["p2p_connection_limits"].clone()))
+    };
+
+    let stored_peers = load_peer_store(&ctx);
     let ctx_on_poll = ctx.clone();
-    let force_p2p_key = if i_am_seed {
+    let node_key = if i_am_seed {
         let key = sha256(&*ctx.secp256k1_key_pair().private().secret);
-        Some(key.take())
+        NodeKey::Ed25519Seed(key.take())
+    } else if ctx.conf["accept_private_offers"].as_bool().unwrap_or(false) {
+        // the makers send the private offers directly to the peer id derived from the taker pubkey
+        NodeKey::Secp256k1(*ctx.secp256k1_key_pair().private().secret)
     } else {
-        None
+        NodeKey::Random
     };
 
     let node_type = if i_am_seed {
@@ -521,8 +597,18 @@ async fn init_p2p(ctx: MmArc) -> Result<(), String> {
         NodeType::Light { network_port }
     };
 
-    let (cmd_tx, event_rx, peer_id, p2p_abort) =
-        spawn_gossipsub(netid, force_p2p_key, spawn_boxed, seednodes, node_type, move |swarm| {
+    let (cmd_tx, event_rx, peer_id, p2p_abort) = spawn_gossipsub(
+        netid,
+        node_key,
+        spawn_boxed,
+        seednodes,
+        stored_peers,
+        node_type,
+        dns_config,
+        mdns_enabled,
+        connection_limits,
+        socks5::global_proxy(),
+        move |swarm| {
             let behaviour = swarm.behaviour();
             mm_gauge!(
                 ctx_on_poll.metrics,
@@ -550,8 +636,43 @@ async fn init_p2p(ctx: MmArc) -> Result<(), String> {
                 "p2p.connected_peers.count",
                 connected_peers_count as i64
             );
-        })
-        .await;
+
+            let network_info = swarm.network_info();
+            let counters = network_info.connection_counters();
+            mm_gauge!(
+                ctx_on_poll.metrics,
+                "p2p.connections.established_incoming",
+                counters.num_established_incoming() as i64
+            );
+            mm_gauge!(
+                ctx_on_poll.metrics,
+                "p2p.connections.established_outgoing",
+                counters.num_established_outgoing() as i64
+            );
+            mm_gauge!(
+                ctx_on_poll.metrics,
+                "p2p.connections.pending",
+                counters.num_pending() as i64
+            );
+
+            let limits = behaviour.connection_limits();
+            if let Some(limit) = limits.max_established_incoming {
+                mm_gauge!(
+                    ctx_on_poll.metrics,
+                    "p2p.connection_limits.max_established_incoming",
+                    limit as i64
+                );
+            }
+            if let Some(limit) = limits.max_established_outgoing {
+                mm_gauge!(
+                    ctx_on_poll.metrics,
+                    "p2p.connection_limits.max_established_outgoing",
+                    limit as i64
+                );
+            }
+        },
+    )
+    .await;
     let mut p2p_abort = Some(p2p_abort);
     ctx.on_stop(Box::new(move || {
         if let Some(handle) = p2p_abort.take() {
@@ -563,6 +684,9 @@ async fn init_p2p(ctx: MmArc) -> Result<(), String> {
     let p2p_context = P2PContext::new(cmd_tx);
     p2p_context.store_to_mm_arc(&ctx);
     spawn(p2p_event_process_loop(ctx.weak(), event_rx, i_am_seed));
+    spawn(network_diagnostics_metrics_loop(ctx.clone()));
+    #[cfg(not(target_arch = "wasm32"))]
+    spawn(peer_store_save_loop(ctx.clone()));
 
     Ok(())
 }
@@ -23,10 +23,11 @@ use common::mm_error::prelude::*;
 use common::mm_metrics::{ClockOps, MetricsOps};
 use derive_more::Display;
 use futures::{channel::oneshot, lock::Mutex as AsyncMutex, StreamExt};
-use mm2_libp2p::atomicdex_behaviour::{AdexBehaviourCmd, AdexBehaviourEvent, AdexCmdTx, AdexEventRx, AdexResponse,
-                                      AdexResponseChannel};
-use mm2_libp2p::peers_exchange::PeerAddresses;
-use mm2_libp2p::{decode_message, encode_message, GossipsubMessage, MessageId, PeerId, TOPIC_SEPARATOR};
+use mm2_libp2p::atomicdex_behaviour::{get_network_diagnostics, AdexBehaviourCmd, AdexBehaviourEvent, AdexCmdTx,
+                                      AdexEventRx, AdexResponse, AdexResponseChannel};
+use mm2_libp2p::peers_exchange::{PeerAddresses, PeerStore};
+use mm2_libp2p::{decode_message, encode_message, is_signed_envelope_valid, GossipsubMessage, MessageId, PeerId,
+                 TOPIC_SEPARATOR};
 #[cfg(test)] use mocktopus::macros::*;
 use serde::de;
 use std::net::ToSocketAddrs;
@@ -54,10 +55,36 @@ impl From<rmp_serde::decode::Error> for P2PRequestError {
     fn from(e: rmp_serde::decode::Error) -> Self { P2PRequestError::DecodeError(e.to_string()) }
 }
 
+/// The error of the gossip message processing.
+#[derive(Debug, Display)]
+pub enum P2PMessageError {
+    /// The message is provably invalid, e.g. it has the bad signature or the malformed envelope,
+    /// the peers delivered it are penalized.
+    #[display(fmt = "Invalid message: {}", _0)]
+    Invalid(String),
+    /// The message can't be processed by this node, e.g. the message of an unknown version,
+    /// it's dropped without penalizing anyone.
+    #[display(fmt = "Unsupported message: {}", _0)]
+    Unsupported(String),
+}
+
+impl P2PMessageError {
+    /// Classifies the signed message failed the decoding: the undecodable payload of the valid envelope
+    /// may be sent by a node of a newer version.
+    pub fn from_decode_signed_error(msg: &[u8], error: impl std::fmt::Display) -> P2PMessageError {
+        if is_signed_envelope_valid(msg) {
+            P2PMessageError::Unsupported(error.to_string())
+        } else {
+            P2PMessageError::Invalid(error.to_string())
+        }
+    }
+}
+
 #[derive(Eq, Debug, Deserialize, PartialEq, Serialize)]
 pub enum P2PRequest {
     Ordermatch(lp_ordermatch::OrdermatchRequest),
     NetworkInfo(lp_stats::NetworkInfoRequest),
+    Swap(lp_swap::SwapRequest),
 }
 
 pub struct P2PContext {
@@ -132,7 +159,11 @@ async fn process_p2p_message(
                 }
             },
             Some(lp_swap::SWAP_PREFIX) => {
-                lp_swap::process_msg(ctx.clone(), split.next().unwrap_or_default(), &message.data);
+                if let Err(e) = lp_swap::process_msg(ctx.clone(), split.next().unwrap_or_default(), &message.data) {
+                    log::warn!("Error on the swap message {:?} from {}: {}", message_id, peer_id, e);
+                    drop_message(&ctx, message_id, &e);
+                    return;
+                }
                 to_propagate = true;
             },
             None | Some(_) => (),
@@ -147,8 +178,19 @@ async fn process_p2p_message(
             &message.data,
             i_am_relay,
         );
-        if process_fut.await {
-            to_propagate = true;
+        match process_fut.await {
+            Ok(true) => to_propagate = true,
+            Ok(false) => (),
+            Err(e) => {
+                log::warn!(
+                    "Error on the ordermatch message {:?} from {}: {}",
+                    message_id,
+                    peer_id,
+                    e
+                );
+                drop_message(&ctx, message_id, &e);
+                return;
+            },
         }
     }
 
@@ -159,14 +201,15 @@ async fn process_p2p_message(
 
 async fn process_p2p_request(
     ctx: MmArc,
-    _peer_id: PeerId,
+    peer_id: PeerId,
     request: Vec<u8>,
     response_channel: AdexResponseChannel,
 ) -> P2PRequestResult<()> {
     let request = decode_message::<P2PRequest>(&request)?;
     let result = match request {
-        P2PRequest::Ordermatch(req) => lp_ordermatch::process_peer_request(ctx.clone(), req).await,
+        P2PRequest::Ordermatch(req) => lp_ordermatch::process_peer_request(ctx.clone(), peer_id.to_string(), req).await,
         P2PRequest::NetworkInfo(req) => lp_stats::process_info_request(ctx.clone(), req).await,
+        P2PRequest::Swap(req) => lp_swap::process_peer_request(ctx.clone(), peer_id, req).await,
     };
 
     let res = match result {
@@ -351,6 +394,38 @@ pub fn propagate_message(ctx: &MmArc, message_id: MessageId, propagation_source:
     });
 }
 
+/// Drops the message failed the processing, the peers delivered it are penalized only if it's provably invalid.
+fn drop_message(ctx: &MmArc, message_id: MessageId, error: &P2PMessageError) {
+    match error {
+        P2PMessageError::Invalid(_) => reject_message(ctx, message_id),
+        P2PMessageError::Unsupported(_) => ignore_message(ctx, message_id),
+    }
+}
+
+/// Drops the provably invalid message and penalizes the peers delivered it.
+pub fn reject_message(ctx: &MmArc, message_id: MessageId) {
+    let ctx = ctx.clone();
+    spawn(async move {
+        let p2p_ctx = P2PContext::fetch_from_mm_arc(&ctx);
+        let cmd = AdexBehaviourCmd::RejectMessage { message_id };
+        if let Err(e) = p2p_ctx.cmd_tx.lock().await.try_send(cmd) {
+            log::error!("reject_message cmd_tx.send error {:?}", e);
+        };
+    });
+}
+
+/// Drops the message that can't be processed without penalizing the peers delivered it.
+pub fn ignore_message(ctx: &MmArc, message_id: MessageId) {
+    let ctx = ctx.clone();
+    spawn(async move {
+        let p2p_ctx = P2PContext::fetch_from_mm_arc(&ctx);
+        let cmd = AdexBehaviourCmd::IgnoreMessage { message_id };
+        if let Err(e) = p2p_ctx.cmd_tx.lock().await.try_send(cmd) {
+            log::error!("ignore_message cmd_tx.send error {:?}", e);
+        };
+    });
+}
+
 pub fn add_reserved_peer_addresses(ctx: &MmArc, peer: PeerId, addresses: PeerAddresses) {
     let ctx = ctx.clone();
     spawn(async move {
@@ -362,6 +437,122 @@ pub fn add_reserved_peer_addresses(ctx: &MmArc, peer: PeerId, addresses: PeerAdd
     });
 }
 
+/// The interval of the peer store saving in seconds.
+#[cfg(not(target_arch = "wasm32"))]
+const PEER_STORE_SAVE_INTERVAL: f64 = 60.;
+/// The interval of the network diagnostics export to the metrics in seconds.
+const NETWORK_DIAGNOSTICS_METRICS_INTERVAL: f64 = 30.;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn peer_store_path(ctx: &MmArc) -> std::path::PathBuf { ctx.dbdir().join("PEERS") }
+
+/// Loads the peers with their connection statistics saved on the previous run.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_peer_store(ctx: &MmArc) -> PeerStore {
+    let content = gstuff::slurp(&peer_store_path(ctx));
+    if content.is_empty() {
+        return PeerStore::new();
+    }
+    match decode_message(&content) {
+        Ok(store) => store,
+        Err(e) => {
+            log::error!("Error decoding the peer store: {}", e);
+            PeerStore::new()
+        },
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn load_peer_store(_ctx: &MmArc) -> PeerStore { PeerStore::new() }
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn save_peer_store(ctx: &MmArc) -> Result<(), String> {
+    let p2p_ctx = P2PContext::fetch_from_mm_arc(ctx);
+    let cmd_tx = p2p_ctx.cmd_tx.lock().await.clone();
+    let store = mm2_libp2p::atomicdex_behaviour::get_peer_store(cmd_tx).await;
+    let encoded = try_s!(encode_message(&store));
+    try_s!(common::write(&peer_store_path(ctx), &encoded));
+    Ok(())
+}
+
+/// Saves the known peers with their connection statistics every `PEER_STORE_SAVE_INTERVAL` seconds.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn peer_store_save_loop(ctx: MmArc) {
+    loop {
+        common::executor::Timer::sleep(PEER_STORE_SAVE_INTERVAL).await;
+        if ctx.is_stopping() {
+            break;
+        }
+        if let Err(e) = save_peer_store(&ctx).await {
+            log::error!("Error saving the peer store: {}", e);
+        }
+    }
+}
+
+/// Exports the network diagnostics aggregated over the peers and the topics to the metrics
+/// every `NETWORK_DIAGNOSTICS_METRICS_INTERVAL` seconds.
+/// The per-peer and per-topic values are returned by the `get_network_diagnostics` RPC only
+/// to keep the number of the metrics independent of the number of the peers and topics.
+pub async fn network_diagnostics_metrics_loop(ctx: MmArc) {
+    loop {
+        common::executor::Timer::sleep(NETWORK_DIAGNOSTICS_METRICS_INTERVAL).await;
+        if ctx.is_stopping() {
+            break;
+        }
+        let cmd_tx = P2PContext::fetch_from_mm_arc(&ctx).cmd_tx.lock().await.clone();
+        let diagnostics = get_network_diagnostics(cmd_tx).await;
+
+        let rtts = &diagnostics.peers_rtt_ms;
+        let max_rtt = rtts.values().max().copied().unwrap_or_default();
+        let avg_rtt = if rtts.is_empty() {
+            0
+        } else {
+            rtts.values().sum::<u64>() / rtts.len() as u64
+        };
+        mm_gauge!(ctx.metrics, "p2p.peers_rtt_ms.max", max_rtt as i64);
+        mm_gauge!(ctx.metrics, "p2p.peers_rtt_ms.avg", avg_rtt as i64);
+
+        let topics = diagnostics.topics.values();
+        mm_gauge!(ctx.metrics, "p2p.topics.count", diagnostics.topics.len() as i64);
+        mm_gauge!(
+            ctx.metrics,
+            "p2p.topics.published",
+            topics.clone().map(|stats| stats.published).sum::<u64>() as i64
+        );
+        mm_gauge!(
+            ctx.metrics,
+            "p2p.topics.received",
+            topics.clone().map(|stats| stats.received).sum::<u64>() as i64
+        );
+        mm_gauge!(
+            ctx.metrics,
+            "p2p.topics.received_bytes",
+            topics.clone().map(|stats| stats.received_bytes).sum::<u64>() as i64
+        );
+        mm_gauge!(
+            ctx.metrics,
+            "p2p.topics.duplicates",
+            topics.clone().map(|stats| stats.duplicates).sum::<u64>() as i64
+        );
+        mm_gauge!(
+            ctx.metrics,
+            "p2p.topics.invalid",
+            topics.map(|stats| stats.invalid).sum::<u64>() as i64
+        );
+
+        let requests = diagnostics.requests;
+        mm_gauge!(ctx.metrics, "p2p.requests.sent", requests.sent as i64);
+        mm_gauge!(ctx.metrics, "p2p.requests.succeeded", requests.succeeded as i64);
+        mm_gauge!(ctx.metrics, "p2p.requests.failed", requests.failed as i64);
+        mm_gauge!(ctx.metrics, "p2p.requests.timed_out", requests.timed_out as i64);
+        mm_gauge!(
+            ctx.metrics,
+            "p2p.requests.inbound_failures",
+            requests.inbound_failures as i64
+        );
+    }
+}
+
 #[derive(Debug, Display)]
 pub enum ParseAddressError {
     #[display(fmt = "Address/Seed {} resolved to IPv6 which is not supported", _0)]
@@ -21,6 +21,7 @@
 use async_trait::async_trait;
 use best_orders::BestOrdersAction;
 use bigdecimal::BigDecimal;
+use bitcrypto::sha256;
 use blake2::digest::{Update, VariableOutput};
 use blake2::VarBlake2b;
 use coins::utxo::{compressed_pub_key_from_priv_raw, ChecksumType};
@@ -31,17 +32,25 @@ use common::mm_ctx::{from_ctx, MmArc, MmWeak};
 use common::mm_number::{Fraction, MmNumber};
 use common::time_cache::TimeCache;
 use common::{bits256, json_dir_entries, log, new_uuid, now_ms, remove_file, write};
+use confirmations_policy::ConfirmationsPolicy;
 use derive_more::Display;
 use futures::{compat::Future01CompatExt, lock::Mutex as AsyncMutex, StreamExt, TryFutureExt};
 use gstuff::slurp;
 use hash256_std_hasher::Hash256StdHasher;
 use hash_db::Hasher;
 use http::Response;
-use keys::AddressFormat;
+use keys::{AddressFormat, Public};
 use mm2_libp2p::{decode_signed, encode_and_sign, encode_message, pub_sub_topic, TopicPrefix, TOPIC_SEPARATOR};
 #[cfg(test)] use mocktopus::macros::*;
+use multi_maker::lp_multi_maker_order;
+#[cfg(not(target_arch = "wasm32"))]
+use multi_maker::{cancel_multi_maker_order, multi_maker_order_status, remove_finished_multi_maker_orders,
+                  MULTI_MAKER_CLEANUP_INTERVAL};
 use num_rational::BigRational;
 use num_traits::identities::Zero;
+use order_requests_tracker::{OrderRequestsTracker, PeerRequestStatus};
+use price_peg::{initial_pegged_price, PricePeg};
+use risk_limits::{RiskLimitViolation, RiskLimits, RiskLimitsState};
 use rpc::v1::types::H256 as H256Json;
 use serde_json::{self as json, Value as Json};
 use sp_trie::{delta_trie_root, MemoryDB, Trie, TrieConfiguration, TrieDB, TrieDBMut, TrieHash, TrieMut};
@@ -50,31 +59,54 @@ use std::collections::{BTreeSet, HashSet};
 use std::convert::TryInto;
 use std::fmt;
 use std::fs::DirEntry;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
 use std::sync::Arc;
 use std::time::Duration;
 use trie_db::NodeCodec as NodeCodecT;
 use uuid::Uuid;
 
-use crate::mm2::lp_network::{broadcast_p2p_msg, request_any_relay, request_one_peer, subscribe_to_topic, P2PRequest};
+use crate::mm2::lp_network::{broadcast_p2p_msg, request_any_relay, request_one_peer, subscribe_to_topic,
+                             P2PMessageError, P2PRequest};
 use crate::mm2::lp_swap::{calc_max_maker_vol, check_balance_for_maker_swap, check_balance_for_taker_swap,
                           check_other_coin_balance_for_swap, insert_new_swap_to_db, is_pubkey_banned,
                           lp_atomic_locktime, run_maker_swap, run_taker_swap, AtomicLocktimeVersion, MakerSwap,
                           RunMakerSwapInput, RunTakerSwapInput, SwapConfirmationsSettings, TakerSwap};
 
 pub use best_orders::best_orders_rpc;
+pub use grid_strategy::{grid_strategy_status, lp_grid_strategy_loop, start_grid_strategy, stop_grid_strategy};
+#[cfg(not(target_arch = "wasm32"))]
+pub use market_data::{ohlcv, ticker_24h, trade_tape};
 pub use orderbook_depth::orderbook_depth_rpc;
 pub use orderbook_rpc::orderbook_rpc;
+#[cfg(not(target_arch = "wasm32"))]
+pub use orderbook_snapshot::{load_orderbook_snapshot, lp_orderbook_snapshot_loop};
+pub use price_peg::{lp_price_peg_loop, push_oracle_price};
+pub use private_offers::{lp_private_offers_loop, private_offers};
 
 #[path = "lp_ordermatch/best_orders.rs"] mod best_orders;
+#[path = "lp_ordermatch/confirmations_policy.rs"]
+mod confirmations_policy;
+#[path = "lp_ordermatch/grid_strategy.rs"] mod grid_strategy;
+#[cfg(not(target_arch = "wasm32"))]
+#[path = "lp_ordermatch/market_data.rs"]
+mod market_data;
+#[path = "lp_ordermatch/multi_maker.rs"] mod multi_maker;
 #[path = "lp_ordermatch/new_protocol.rs"] mod new_protocol;
 #[path = "lp_ordermatch/order_requests_tracker.rs"]
 mod order_requests_tracker;
 #[path = "lp_ordermatch/orderbook_depth.rs"] mod orderbook_depth;
 #[path = "lp_ordermatch/orderbook_rpc.rs"] mod orderbook_rpc;
+#[cfg(not(target_arch = "wasm32"))]
+#[path = "lp_ordermatch/orderbook_snapshot.rs"]
+mod orderbook_snapshot;
 #[cfg(all(test, not(target_arch = "wasm32")))]
 #[path = "ordermatch_tests.rs"]
 pub mod ordermatch_tests;
+#[path = "lp_ordermatch/price_peg.rs"] mod price_peg;
+#[path = "lp_ordermatch/private_offers.rs"] mod private_offers;
+#[path = "lp_ordermatch/risk_limits.rs"] mod risk_limits;
 
 pub const ORDERBOOK_PREFIX: TopicPrefix = "orbk";
 const MIN_ORDER_KEEP_ALIVE_INTERVAL: u64 = 30;
@@ -83,6 +115,20 @@ const TAKER_ORDER_TIMEOUT: u64 = 30;
 const ORDER_MATCH_TIMEOUT: u64 = 30;
 const ORDERBOOK_REQUESTING_TIMEOUT: u64 = MIN_ORDER_KEEP_ALIVE_INTERVAL * 2;
 const MAX_ORDERS_NUMBER_IN_ORDERBOOK_RESPONSE: usize = 1000;
+/// The max number of orders in the orderbook chunk unless a single pubkey has more
+const ORDERBOOK_CHUNK_MAX_ORDERS: usize = 500;
+/// The max number of the orderbook chunks requested from the relay
+const MAX_ORDERBOOK_CHUNKS: usize = 100;
+/// The max number of pairs whose trie roots are sent in a single keep alive message
+const KEEP_ALIVE_MAX_PAIRS: usize = 100;
+/// The default max number of the ordermatch requests served per peer per second
+const PEER_REQUESTS_LIMIT_PER_SEC: usize = 10;
+/// The interval in seconds between the orderbook chunk requests to the same relay,
+/// the chunks are requested at half of the default rate limit leaving room for the other requests
+const ORDERBOOK_CHUNK_REQUEST_INTERVAL: f64 = 2. / PEER_REQUESTS_LIMIT_PER_SEC as f64;
+/// The default number of the dropped requests per minute leading to the peer ban
+const PEER_REQUESTS_MAX_VIOLATIONS: usize = 30;
+const PEER_REQUESTS_BAN_DURATION: u64 = 300;
 #[cfg(not(test))]
 const TRIE_STATE_HISTORY_TIMEOUT: u64 = 14400;
 #[cfg(test)]
@@ -111,6 +157,9 @@ impl From<(new_protocol::MakerOrderCreated, String)> for OrderbookItem {
             created_at: order.created_at,
             base_protocol_info: order.base_protocol_info,
             rel_protocol_info: order.rel_protocol_info,
+            expires_at: order.expires_at,
+            nonce: order.nonce,
+            signature: order.signature,
         }
     }
 }
@@ -128,13 +177,15 @@ fn process_pubkey_full_trie(
     alb_pair: &str,
     new_trie_orders: PubkeyOrders,
     protocol_infos: &HashMap<Uuid, BaseRelProtocolInfo>,
+    sync_infos: &HashMap<Uuid, OrderSyncInfo>,
 ) -> H64 {
     remove_pubkey_pair_orders(orderbook, pubkey, alb_pair);
 
     for (uuid, order) in new_trie_orders {
-        orderbook.insert_or_update_order_update_trie(OrderbookItem::from_p2p_and_proto_info(
+        orderbook.insert_or_update_order_update_trie(OrderbookItem::from_p2p_and_info(
             order,
             protocol_infos.get(&uuid).cloned().unwrap_or_default(),
+            sync_infos.get(&uuid).cloned().unwrap_or_default(),
         ));
     }
 
@@ -152,12 +203,14 @@ fn process_trie_delta(
     alb_pair: &str,
     delta_orders: HashMap<Uuid, Option<OrderbookP2PItem>>,
     protocol_infos: &HashMap<Uuid, BaseRelProtocolInfo>,
+    sync_infos: &HashMap<Uuid, OrderSyncInfo>,
 ) -> H64 {
     for (uuid, order) in delta_orders {
         match order {
-            Some(order) => orderbook.insert_or_update_order_update_trie(OrderbookItem::from_p2p_and_proto_info(
+            Some(order) => orderbook.insert_or_update_order_update_trie(OrderbookItem::from_p2p_and_info(
                 order,
                 protocol_infos.get(&uuid).cloned().unwrap_or_default(),
+                sync_infos.get(&uuid).cloned().unwrap_or_default(),
             )),
             None => {
                 orderbook.remove_order_trie_update(uuid);
@@ -208,12 +261,22 @@ async fn process_orders_keep_alive(
     let mut orderbook = ordermatch_ctx.orderbook.lock().await;
     for (pair, diff) in response.pair_orders_diff {
         let _new_root = match diff {
-            DeltaOrFullTrie::Delta(delta) => {
-                process_trie_delta(&mut orderbook, &from_pubkey, &pair, delta, &response.protocol_infos)
-            },
-            DeltaOrFullTrie::FullTrie(values) => {
-                process_pubkey_full_trie(&mut orderbook, &from_pubkey, &pair, values, &response.protocol_infos)
-            },
+            DeltaOrFullTrie::Delta(delta) => process_trie_delta(
+                &mut orderbook,
+                &from_pubkey,
+                &pair,
+                delta,
+                &response.protocol_infos,
+                &response.sync_infos,
+            ),
+            DeltaOrFullTrie::FullTrie(values) => process_pubkey_full_trie(
+                &mut orderbook,
+                &from_pubkey,
+                &pair,
+                values,
+                &response.protocol_infos,
+                &response.sync_infos,
+            ),
         };
     }
     true
@@ -228,8 +291,29 @@ async fn process_maker_order_updated(
     let uuid = updated_msg.uuid();
     let mut orderbook = ordermatch_ctx.orderbook.lock().await;
     match orderbook.find_order_by_uuid_and_pubkey(&uuid, &from_pubkey) {
+        Some(ref order) if order.is_stale_nonce(updated_msg.nonce()) => {
+            log::debug!("Skip stale update of the order {}", uuid);
+            false
+        },
+        // the signing makers send the unsigned copy of every update to the older nodes,
+        // so the copy is forwarded to them but not applied since it's not protected from the replays
+        Some(ref order) if order.nonce != 0 && updated_msg.nonce() == 0 => {
+            log::debug!(
+                "Forward unsigned update of the signed order {} without applying it",
+                uuid
+            );
+            true
+        },
         Some(mut order) => {
             order.apply_updated(&updated_msg);
+            let pubkey = hex::decode(&from_pubkey).unwrap_or_default();
+            if !is_order_signature_valid(&pubkey, &order.signing_payload(), updated_msg.signature()) {
+                log::warn!(
+                    "Invalid signature of the order {} update, it will be synced upon pubkey keep alive",
+                    uuid
+                );
+                return false;
+            }
             orderbook.insert_or_update_order_update_trie(order);
             true
         },
@@ -265,20 +349,27 @@ async fn process_maker_order_updated(
 ///
 /// The function locks [`MmCtx::p2p_ctx`] and [`MmCtx::ordermatch_ctx`]
 async fn request_and_fill_orderbook(ctx: &MmArc, base: &str, rel: &str) -> Result<(), String> {
-    let request = OrdermatchRequest::GetOrderbook {
-        base: base.to_string(),
-        rel: rel.to_string(),
+    let response = match request_orderbook_chunks(ctx, base, rel).await {
+        Ok(Some(response)) => Some(response),
+        // the relays might not support the chunked requests yet
+        chunks_res => {
+            if let Err(e) = chunks_res {
+                log::warn!("Error {} requesting {}/{} orderbook by chunks", e, base, rel);
+            }
+            let request = OrdermatchRequest::GetOrderbook {
+                base: base.to_string(),
+                rel: rel.to_string(),
+            };
+            try_s!(request_any_relay::<GetOrderbookRes>(ctx.clone(), P2PRequest::Ordermatch(request)).await)
+                .map(|(response, _peer_id)| response)
+        },
     };
-
-    let response = try_s!(request_any_relay::<GetOrderbookRes>(ctx.clone(), P2PRequest::Ordermatch(request)).await);
-    let (pubkey_orders, protocol_infos) = match response {
-        Some((
-            GetOrderbookRes {
-                pubkey_orders,
-                protocol_infos,
-            },
-            _peer_id,
-        )) => (pubkey_orders, protocol_infos),
+    let (pubkey_orders, protocol_infos, sync_infos) = match response {
+        Some(GetOrderbookRes {
+            pubkey_orders,
+            protocol_infos,
+            sync_infos,
+        }) => (pubkey_orders, protocol_infos, sync_infos),
         None => return Ok(()),
     };
 
@@ -303,7 +394,8 @@ async fn request_and_fill_orderbook(ctx: &MmArc, base: &str, rel: &str) -> Resul
             log::warn!("Pubkey {} is banned", pubkey);
             continue;
         }
-        let _new_root = process_pubkey_full_trie(&mut orderbook, &pubkey, &alb_pair, orders, &protocol_infos);
+        let _new_root =
+            process_pubkey_full_trie(&mut orderbook, &pubkey, &alb_pair, orders, &protocol_infos, &sync_infos);
     }
 
     let topic = orderbook_topic_from_base_rel(base, rel);
@@ -314,6 +406,68 @@ async fn request_and_fill_orderbook(ctx: &MmArc, base: &str, rel: &str) -> Resul
     Ok(())
 }
 
+/// Requests the orderbook of the given pair chunk by chunk from the same relay.
+/// The chunks are requested every [`ORDERBOOK_CHUNK_REQUEST_INTERVAL`] to not exceed the relay rate limit.
+/// Returns `None` if none of the relays responded to the chunk request.
+async fn request_orderbook_chunks(ctx: &MmArc, base: &str, rel: &str) -> Result<Option<GetOrderbookRes>, String> {
+    let request = OrdermatchRequest::GetOrderbookChunk {
+        base: base.to_string(),
+        rel: rel.to_string(),
+        from_pubkey: None,
+    };
+    let (chunk, peer_id) =
+        match try_s!(request_any_relay::<GetOrderbookChunkRes>(ctx.clone(), P2PRequest::Ordermatch(request)).await) {
+            Some(response) => response,
+            None => return Ok(None),
+        };
+
+    let mut orderbook = chunk.orderbook;
+    let mut next_pubkey = chunk.next_pubkey;
+    for _ in 1..MAX_ORDERBOOK_CHUNKS {
+        let from_pubkey = match next_pubkey.take() {
+            Some(pubkey) => pubkey,
+            None => break,
+        };
+        Timer::sleep(ORDERBOOK_CHUNK_REQUEST_INTERVAL).await;
+        let request = OrdermatchRequest::GetOrderbookChunk {
+            base: base.to_string(),
+            rel: rel.to_string(),
+            from_pubkey: Some(from_pubkey),
+        };
+        let response =
+            request_one_peer::<GetOrderbookChunkRes>(ctx.clone(), P2PRequest::Ordermatch(request), peer_id.to_string())
+                .await;
+        let chunk = match response {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => {
+                log::warn!(
+                    "Error {} requesting {}/{} orderbook chunk from {}",
+                    e,
+                    base,
+                    rel,
+                    peer_id
+                );
+                break;
+            },
+        };
+        orderbook.pubkey_orders.extend(chunk.orderbook.pubkey_orders);
+        orderbook.protocol_infos.extend(chunk.orderbook.protocol_infos);
+        orderbook.sync_infos.extend(chunk.orderbook.sync_infos);
+        next_pubkey = chunk.next_pubkey;
+    }
+
+    if next_pubkey.is_some() {
+        log::warn!(
+            "{}/{} orderbook exceeds {} chunks, the rest of the orders will be synced on the keep alive messages",
+            base,
+            rel,
+            MAX_ORDERBOOK_CHUNKS
+        );
+    }
+    Ok(Some(orderbook))
+}
+
 /// Insert or update an order `req`.
 /// Note this function locks the [`OrdermatchContext::orderbook`] async mutex.
 async fn insert_or_update_order(ctx: &MmArc, item: OrderbookItem) {
@@ -322,15 +476,72 @@ async fn insert_or_update_order(ctx: &MmArc, item: OrderbookItem) {
     orderbook.insert_or_update_order_update_trie(item)
 }
 
-async fn delete_order(ctx: &MmArc, pubkey: &str, uuid: Uuid) {
+/// Removes the order cancelled by the maker, returns false if the cancellation is older than the latest order message.
+async fn delete_order(ctx: &MmArc, pubkey: &str, uuid: Uuid, nonce: u64) -> bool {
     let ordermatch_ctx = OrdermatchContext::from_ctx(ctx).expect("from_ctx failed");
 
     let mut orderbook = ordermatch_ctx.orderbook.lock().await;
     if let Some(order) = orderbook.order_set.get(&uuid) {
-        if order.pubkey == pubkey {
-            orderbook.remove_order_trie_update(uuid);
+        if order.pubkey != pubkey {
+            return true;
+        }
+        if order.is_stale_nonce(nonce) {
+            log::debug!("Skip stale cancellation of the order {}", uuid);
+            return false;
+        }
+        let nonce = std::cmp::max(nonce, order.nonce);
+        orderbook.remove_order_trie_update(uuid);
+        orderbook.cancelled_orders.insert(uuid, (nonce, now_ms() / 1000));
+    }
+    true
+}
+
+/// Inserts the order from the `MakerOrderCreated` message unless the message is replayed,
+/// returns whether the order is inserted.
+async fn insert_or_update_order_if_newer(ctx: &MmArc, item: OrderbookItem) -> bool {
+    let ordermatch_ctx = OrdermatchContext::from_ctx(ctx).expect("from_ctx failed");
+    let mut orderbook = ordermatch_ctx.orderbook.lock().await;
+    if let Some(existing) = orderbook.order_set.get(&item.uuid) {
+        if existing.is_stale_nonce(item.nonce) {
+            log::debug!("Skip replayed message of the order {}", item.uuid);
+            return false;
+        }
+    }
+    if let Some((cancelled_nonce, _)) = orderbook.cancelled_orders.get(&item.uuid) {
+        if item.nonce == 0 || item.nonce <= *cancelled_nonce {
+            log::debug!("Skip replayed message of the cancelled order {}", item.uuid);
+            return false;
         }
     }
+    orderbook.insert_or_update_order_update_trie(item);
+    true
+}
+
+/// Signs the order message payload with the node key, so the order can be verified independently of the transport.
+fn sign_order_payload(ctx: &MmArc, payload: &[u8]) -> Vec<u8> {
+    let key_pair = ctx.secp256k1_key_pair.or(&&|| panic!());
+    let signature = key_pair
+        .private()
+        .sign(&sha256(payload))
+        .expect("Signing should never fail");
+    signature.to_vec()
+}
+
+/// Checks the signature of the order message made by the `pubkey`.
+/// The messages of the older nodes are not signed and accepted as is.
+fn is_order_signature_valid(pubkey: &[u8], payload: &[u8], signature: &[u8]) -> bool {
+    if signature.is_empty() {
+        return true;
+    }
+    match Public::from_slice(pubkey) {
+        Ok(public) => public.verify(&sha256(payload), &signature.into()).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+fn next_order_nonce(ctx: &MmArc) -> u64 {
+    let ordermatch_ctx = OrdermatchContext::from_ctx(ctx).expect("from_ctx failed");
+    ordermatch_ctx.next_order_nonce()
 }
 
 async fn delete_my_order(ctx: &MmArc, uuid: Uuid) {
@@ -374,54 +585,84 @@ fn remove_pubkey_pair_orders(orderbook: &mut Orderbook, pubkey: &str, alb_pair:
 }
 
 /// Attempts to decode a message and process it returning whether the message is valid and worth rebroadcasting
-pub async fn process_msg(ctx: MmArc, _topics: Vec<String>, from_peer: String, msg: &[u8], i_am_relay: bool) -> bool {
+pub async fn process_msg(
+    ctx: MmArc,
+    _topics: Vec<String>,
+    from_peer: String,
+    msg: &[u8],
+    i_am_relay: bool,
+) -> Result<bool, P2PMessageError> {
     match decode_signed::<new_protocol::OrdermatchMessage>(msg) {
         Ok((message, _sig, pubkey)) => {
             if is_pubkey_banned(&ctx, &pubkey.unprefixed().into()) {
                 log::warn!("Pubkey {} is banned", pubkey.to_hex());
-                return false;
+                return Ok(false);
             }
             match message {
                 new_protocol::OrdermatchMessage::MakerOrderCreated(created_msg) => {
-                    let order: OrderbookItem = (created_msg, hex::encode(pubkey.to_bytes().as_slice())).into();
-                    insert_or_update_order(&ctx, order).await;
-                    true
+                    let pubkey_bytes = pubkey.to_bytes();
+                    if !is_order_signature_valid(&pubkey_bytes, &created_msg.signing_payload(), &created_msg.signature)
+                    {
+                        return Err(P2PMessageError::Invalid(format!(
+                            "Invalid signature of the order {}",
+                            Uuid::from(created_msg.uuid)
+                        )));
+                    }
+                    let order: OrderbookItem = (created_msg, hex::encode(pubkey_bytes.as_slice())).into();
+                    if order.is_expired(now_ms() / 1000) {
+                        log::debug!("Skip expired order {}", order.uuid);
+                        return Ok(false);
+                    }
+                    Ok(insert_or_update_order_if_newer(&ctx, order).await)
                 },
                 new_protocol::OrdermatchMessage::PubkeyKeepAlive(keep_alive) => {
-                    process_orders_keep_alive(ctx, from_peer, pubkey.to_hex(), keep_alive, i_am_relay).await
+                    Ok(process_orders_keep_alive(ctx, from_peer, pubkey.to_hex(), keep_alive, i_am_relay).await)
                 },
                 new_protocol::OrdermatchMessage::TakerRequest(taker_request) => {
                     let msg = TakerRequest::from_new_proto_and_pubkey(taker_request, pubkey.unprefixed().into());
                     process_taker_request(ctx, pubkey.unprefixed().into(), msg).await;
-                    true
+                    Ok(true)
                 },
                 new_protocol::OrdermatchMessage::MakerReserved(maker_reserved) => {
                     let msg = MakerReserved::from_new_proto_and_pubkey(maker_reserved, pubkey.unprefixed().into());
                     // spawn because process_maker_reserved may take significant time to run
                     spawn(process_maker_reserved(ctx, pubkey.unprefixed().into(), msg));
-                    true
+                    Ok(true)
                 },
                 new_protocol::OrdermatchMessage::TakerConnect(taker_connect) => {
                     process_taker_connect(ctx, pubkey.unprefixed().into(), taker_connect.into()).await;
-                    true
+                    Ok(true)
                 },
                 new_protocol::OrdermatchMessage::MakerConnected(maker_connected) => {
                     process_maker_connected(ctx, pubkey.unprefixed().into(), maker_connected.into()).await;
-                    true
+                    Ok(true)
+                },
+                new_protocol::OrdermatchMessage::MakerConnectRefused(refused) => {
+                    process_maker_connect_refused(ctx, pubkey.unprefixed().into(), refused).await;
+                    Ok(true)
                 },
                 new_protocol::OrdermatchMessage::MakerOrderCancelled(cancelled_msg) => {
-                    delete_order(&ctx, &pubkey.to_hex(), cancelled_msg.uuid.into()).await;
-                    true
+                    if !is_order_signature_valid(
+                        &pubkey.to_bytes(),
+                        &cancelled_msg.signing_payload(),
+                        &cancelled_msg.signature,
+                    ) {
+                        return Err(P2PMessageError::Invalid(format!(
+                            "Invalid signature of the order {} cancellation",
+                            Uuid::from(cancelled_msg.uuid)
+                        )));
+                    }
+                    Ok(delete_order(&ctx, &pubkey.to_hex(), cancelled_msg.uuid.into(), cancelled_msg.nonce).await)
                 },
                 new_protocol::OrdermatchMessage::MakerOrderUpdated(updated_msg) => {
-                    process_maker_order_updated(ctx, pubkey.to_hex(), updated_msg).await
+                    Ok(process_maker_order_updated(ctx, pubkey.to_hex(), updated_msg).await)
                 },
             }
         },
-        Err(e) => {
-            log::error!("Error {} while decoding signed message", e);
-            false
-        },
+        Err(e) => Err(P2PMessageError::from_decode_signed_error(
+            msg,
+            format!("Error {} while decoding signed message", e),
+        )),
     }
 }
 
@@ -432,6 +673,12 @@ pub enum OrdermatchRequest {
         base: String,
         rel: String,
     },
+    /// Get a chunk of the orderbook for the given pair starting from the `from_pubkey` in the ascending order.
+    GetOrderbookChunk {
+        base: String,
+        rel: String,
+        from_pubkey: Option<String>,
+    },
     /// Sync specific pubkey orderbook state if our known Patricia trie state doesn't match the latest keep alive message
     SyncPubkeyOrderbookState {
         pubkey: String,
@@ -446,6 +693,10 @@ pub enum OrdermatchRequest {
     OrderbookDepth {
         pairs: Vec<(String, String)>,
     },
+    /// The private offer message encrypted to the taker, see [`private_offers`].
+    PrivateOffer {
+        msg: Vec<u8>,
+    },
 }
 
 #[derive(Debug)]
@@ -485,10 +736,41 @@ impl TryFromBytes for Uuid {
     }
 }
 
-pub async fn process_peer_request(ctx: MmArc, request: OrdermatchRequest) -> Result<Option<Vec<u8>>, String> {
-    log::debug!("Got ordermatch request {:?}", request);
+pub async fn process_peer_request(
+    ctx: MmArc,
+    peer: String,
+    request: OrdermatchRequest,
+) -> Result<Option<Vec<u8>>, String> {
+    log::debug!("Got ordermatch request {:?} from {}", request, peer);
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(&ctx));
+    let status = ordermatch_ctx
+        .peer_requests_tracker
+        .lock()
+        .await
+        .check_peer_request(&peer);
+    match status {
+        PeerRequestStatus::Allowed => (),
+        PeerRequestStatus::LimitReached => {
+            mm_counter!(ctx.metrics, "ordermatch.requests.dropped", 1, "reason" => "rate_limit");
+            return ERR!("Requests limit reached");
+        },
+        PeerRequestStatus::PeerBanned => {
+            log::warn!("Peer {} exceeded the ordermatch requests limit and is banned", peer);
+            mm_counter!(ctx.metrics, "ordermatch.requests.dropped", 1, "reason" => "rate_limit");
+            mm_counter!(ctx.metrics, "ordermatch.peers.banned", 1);
+            return ERR!("Requests limit reached, the peer is banned");
+        },
+        PeerRequestStatus::Banned => {
+            mm_counter!(ctx.metrics, "ordermatch.requests.dropped", 1, "reason" => "banned");
+            return ERR!("The peer is banned");
+        },
+    }
+
     match request {
         OrdermatchRequest::GetOrderbook { base, rel } => process_get_orderbook_request(ctx, base, rel).await,
+        OrdermatchRequest::GetOrderbookChunk { base, rel, from_pubkey } => {
+            process_get_orderbook_chunk_request(ctx, base, rel, from_pubkey).await
+        },
         OrdermatchRequest::SyncPubkeyOrderbookState { pubkey, trie_roots } => {
             let response = process_sync_pubkey_orderbook_state(ctx, pubkey, trie_roots).await;
             response.map(|res| res.map(|r| encode_message(&r).expect("Serialization failed")))
@@ -499,6 +781,7 @@ pub async fn process_peer_request(ctx: MmArc, request: OrdermatchRequest) -> Res
         OrdermatchRequest::OrderbookDepth { pairs } => {
             orderbook_depth::process_orderbook_depth_p2p_request(ctx, pairs).await
         },
+        OrdermatchRequest::PrivateOffer { msg } => private_offers::process_private_offer_request(&ctx, &msg).await,
     }
 }
 
@@ -521,19 +804,43 @@ struct BaseRelProtocolInfo {
     rel: Vec<u8>,
 }
 
+/// The order data that isn't a part of the trie state, but is synced along with the trie orders.
+/// The fields are defaulted to decode the responses of the nodes of the previous versions.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+struct OrderSyncInfo {
+    #[serde(default)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    nonce: u64,
+    #[serde(default)]
+    signature: Vec<u8>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct GetOrderbookRes {
     /// Asks and bids grouped by pubkey.
     pubkey_orders: HashMap<String, GetOrderbookPubkeyItem>,
     #[serde(default)]
     protocol_infos: HashMap<Uuid, BaseRelProtocolInfo>,
+    #[serde(default)]
+    sync_infos: HashMap<Uuid, OrderSyncInfo>,
 }
 
-fn get_pubkeys_orders(
-    orderbook: &Orderbook,
-    base: String,
-    rel: String,
-) -> (usize, HashMap<String, PubkeyOrders>, HashMap<Uuid, BaseRelProtocolInfo>) {
+#[derive(Debug, Deserialize, Serialize)]
+struct GetOrderbookChunkRes {
+    orderbook: GetOrderbookRes,
+    /// The pubkey to request the next chunk from, `None` if this chunk is the last one.
+    next_pubkey: Option<String>,
+}
+
+type PubkeysOrders = (
+    usize,
+    HashMap<String, PubkeyOrders>,
+    HashMap<Uuid, BaseRelProtocolInfo>,
+    HashMap<Uuid, OrderSyncInfo>,
+);
+
+fn get_pubkeys_orders(orderbook: &Orderbook, base: String, rel: String) -> PubkeysOrders {
     let asks = orderbook.unordered.get(&(base.clone(), rel.clone()));
     let bids = orderbook.unordered.get(&(rel, base));
 
@@ -546,6 +853,7 @@ fn get_pubkeys_orders(
 
     let mut uuids_by_pubkey = HashMap::new();
     let mut protocol_infos = HashMap::new();
+    let mut sync_infos = HashMap::new();
     for uuid in orders {
         let order = orderbook
             .order_set
@@ -553,17 +861,18 @@ fn get_pubkeys_orders(
             .expect("Orderbook::ordered contains an uuid that is not in Orderbook::order_set");
         let uuids = uuids_by_pubkey.entry(order.pubkey.clone()).or_insert_with(Vec::new);
         protocol_infos.insert(order.uuid, order.base_rel_proto_info());
+        sync_infos.insert(order.uuid, order.sync_info());
         uuids.push((*uuid, order.clone().into()))
     }
 
-    (total_orders_number, uuids_by_pubkey, protocol_infos)
+    (total_orders_number, uuids_by_pubkey, protocol_infos, sync_infos)
 }
 
 async fn process_get_orderbook_request(ctx: MmArc, base: String, rel: String) -> Result<Option<Vec<u8>>, String> {
     let ordermatch_ctx = OrdermatchContext::from_ctx(&ctx).unwrap();
     let orderbook = ordermatch_ctx.orderbook.lock().await;
 
-    let (total_orders_number, orders, protocol_infos) = get_pubkeys_orders(&orderbook, base, rel);
+    let (total_orders_number, orders, protocol_infos, sync_infos) = get_pubkeys_orders(&orderbook, base, rel);
     if total_orders_number > MAX_ORDERS_NUMBER_IN_ORDERBOOK_RESPONSE {
         return ERR!("Orderbook too large");
     }
@@ -571,18 +880,7 @@ async fn process_get_orderbook_request(ctx: MmArc, base: String, rel: String) ->
     let orders_to_send: Result<HashMap<_, _>, String> = orders
         .into_iter()
         .map(|(pubkey, orders)| {
-            let pubkey_state = orderbook.pubkeys_state.get(&pubkey).ok_or(ERRL!(
-                "Orderbook::pubkeys_state is expected to contain the {:?} pubkey",
-                pubkey
-            ))?;
-
-            let item = GetOrderbookPubkeyItem {
-                last_keep_alive: pubkey_state.last_keep_alive,
-                orders,
-                // TODO save last signed payload to pubkey state
-                last_signed_pubkey_payload: vec![],
-            };
-
+            let item = pubkey_orderbook_item(&orderbook, &pubkey, orders)?;
             Ok((pubkey, item))
         })
         .collect();
@@ -591,6 +889,72 @@ async fn process_get_orderbook_request(ctx: MmArc, base: String, rel: String) ->
     let response = GetOrderbookRes {
         pubkey_orders,
         protocol_infos,
+        sync_infos,
+    };
+    let encoded = try_s!(encode_message(&response));
+    Ok(Some(encoded))
+}
+
+fn pubkey_orderbook_item(
+    orderbook: &Orderbook,
+    pubkey: &str,
+    orders: PubkeyOrders,
+) -> Result<GetOrderbookPubkeyItem, String> {
+    let pubkey_state = orderbook.pubkeys_state.get(pubkey).ok_or(ERRL!(
+        "Orderbook::pubkeys_state is expected to contain the {:?} pubkey",
+        pubkey
+    ))?;
+
+    Ok(GetOrderbookPubkeyItem {
+        last_keep_alive: pubkey_state.last_keep_alive,
+        orders,
+        // TODO save last signed payload to pubkey state
+        last_signed_pubkey_payload: vec![],
+    })
+}
+
+async fn process_get_orderbook_chunk_request(
+    ctx: MmArc,
+    base: String,
+    rel: String,
+    from_pubkey: Option<String>,
+) -> Result<Option<Vec<u8>>, String> {
+    let ordermatch_ctx = OrdermatchContext::from_ctx(&ctx).unwrap();
+    let orderbook = ordermatch_ctx.orderbook.lock().await;
+
+    let (_total_orders_number, orders, mut protocol_infos, mut sync_infos) = get_pubkeys_orders(&orderbook, base, rel);
+    let mut pubkeys: Vec<_> = orders
+        .into_iter()
+        .filter(|(pubkey, _)| from_pubkey.as_ref().map_or(true, |from| pubkey >= from))
+        .collect();
+    pubkeys.sort_unstable_by(|x, y| x.0.cmp(&y.0));
+
+    let mut pubkey_orders = HashMap::new();
+    let mut chunk_orders_number = 0;
+    let mut next_pubkey = None;
+    for (pubkey, orders) in pubkeys {
+        if !pubkey_orders.is_empty() && chunk_orders_number + orders.len() > ORDERBOOK_CHUNK_MAX_ORDERS {
+            next_pubkey = Some(pubkey);
+            break;
+        }
+        chunk_orders_number += orders.len();
+        let item = try_s!(pubkey_orderbook_item(&orderbook, &pubkey, orders));
+        pubkey_orders.insert(pubkey, item);
+    }
+
+    let chunk_uuids: HashSet<_> = pubkey_orders
+        .values()
+        .flat_map(|item| item.orders.iter().map(|(uuid, _)| *uuid))
+        .collect();
+    protocol_infos.retain(|uuid, _| chunk_uuids.contains(uuid));
+    sync_infos.retain(|uuid, _| chunk_uuids.contains(uuid));
+    let response = GetOrderbookChunkRes {
+        orderbook: GetOrderbookRes {
+            pubkey_orders,
+            protocol_infos,
+            sync_infos,
+        },
+        next_pubkey,
     };
     let encoded = try_s!(encode_message(&response));
     Ok(Some(encoded))
@@ -703,6 +1067,8 @@ struct SyncPubkeyOrderbookStateRes {
     pair_orders_diff: HashMap<AlbOrderedOrderbookPair, DeltaOrFullTrie<Uuid, OrderbookP2PItem>>,
     #[serde(default)]
     protocol_infos: HashMap<Uuid, BaseRelProtocolInfo>,
+    #[serde(default)]
+    sync_infos: HashMap<Uuid, OrderSyncInfo>,
 }
 
 async fn process_sync_pubkey_orderbook_state(
@@ -742,6 +1108,7 @@ async fn process_sync_pubkey_orderbook_state(
 
     let pair_orders_diff = try_s!(pair_orders_diff);
     let mut protocol_infos = HashMap::new();
+    let mut sync_infos = HashMap::new();
     let pair_orders_diff = pair_orders_diff
         .into_iter()
         .map(|(pair, trie)| {
@@ -751,9 +1118,11 @@ async fn process_sync_pubkey_orderbook_state(
                         base: o.base_protocol_info.clone(),
                         rel: o.rel_protocol_info.clone(),
                     });
+                    sync_infos.insert(o.uuid, o.sync_info());
                 },
                 None => {
                     protocol_infos.remove(uuid);
+                    sync_infos.remove(uuid);
                 },
             });
             (pair, new_trie)
@@ -764,6 +1133,7 @@ async fn process_sync_pubkey_orderbook_state(
         last_signed_pubkey_payload,
         pair_orders_diff,
         protocol_infos,
+        sync_infos,
     };
     Ok(Some(result))
 }
@@ -826,7 +1196,7 @@ async fn maker_order_created_p2p_notify(
     rel_protocol_info: Vec<u8>,
 ) {
     let topic = order.orderbook_topic();
-    let message = new_protocol::MakerOrderCreated {
+    let mut message = new_protocol::MakerOrderCreated {
         uuid: order.uuid.into(),
         base: order.base_orderbook_ticker().to_owned(),
         rel: order.rel_orderbook_ticker().to_owned(),
@@ -839,7 +1209,15 @@ async fn maker_order_created_p2p_notify(
         pair_trie_root: H64::default(),
         base_protocol_info,
         rel_protocol_info,
+        expires_at: order.expires_at,
+        nonce: next_order_nonce(&ctx),
+        signature: Vec::new(),
     };
+    message.signature = sign_order_payload(&ctx, &message.signing_payload());
+    if let Some(allowed) = &order.private_for {
+        private_offers::publish_private_offer(&ctx, allowed.clone(), message).await;
+        return;
+    }
 
     let key_pair = ctx.secp256k1_key_pair.or(&&|| panic!());
     let to_broadcast = new_protocol::OrdermatchMessage::MakerOrderCreated(message.clone());
@@ -849,31 +1227,49 @@ async fn maker_order_created_p2p_notify(
     broadcast_p2p_msg(&ctx, vec![topic], encoded_msg);
 }
 
-async fn process_my_maker_order_updated(ctx: &MmArc, message: &new_protocol::MakerOrderUpdated) {
+/// Applies the update to my order in the orderbook and signs the resulting order state.
+async fn process_my_maker_order_updated(ctx: &MmArc, message: &mut new_protocol::MakerOrderUpdated) {
     let ordermatch_ctx = OrdermatchContext::from_ctx(ctx).expect("from_ctx failed");
     let mut orderbook = ordermatch_ctx.orderbook.lock().await;
 
     let uuid = message.uuid();
     if let Some(mut order) = orderbook.find_order_by_uuid(&uuid) {
         order.apply_updated(message);
+        order.signature = sign_order_payload(ctx, &order.signing_payload());
+        message.with_signature(order.signature.clone());
         orderbook.insert_or_update_order_update_trie(order);
     }
 }
 
-async fn maker_order_updated_p2p_notify(ctx: MmArc, topic: String, message: new_protocol::MakerOrderUpdated) {
-    let msg: new_protocol::OrdermatchMessage = message.clone().into();
+async fn maker_order_updated_p2p_notify(ctx: MmArc, topic: String, mut message: new_protocol::MakerOrderUpdated) {
+    if private_offers::update_private_offer(&ctx, &message).await {
+        return;
+    }
+    message.with_nonce(next_order_nonce(&ctx));
+    process_my_maker_order_updated(&ctx, &mut message).await;
     let key_pair = ctx.secp256k1_key_pair.or(&&|| panic!());
-    let encoded_msg = encode_and_sign(&msg, &*key_pair.private().secret).unwrap();
-    process_my_maker_order_updated(&ctx, &message).await;
-    broadcast_p2p_msg(&ctx, vec![topic], encoded_msg);
+    // the nodes not aware of the signed updates drop them, so the unsigned copy is sent during the transition
+    for msg in vec![message.clone(), message.to_v2()] {
+        let msg: new_protocol::OrdermatchMessage = msg.into();
+        let encoded_msg = encode_and_sign(&msg, &*key_pair.private().secret).unwrap();
+        broadcast_p2p_msg(&ctx, vec![topic.clone()], encoded_msg);
+    }
 }
 
 async fn maker_order_cancelled_p2p_notify(ctx: MmArc, order: &MakerOrder) {
-    let message = new_protocol::OrdermatchMessage::MakerOrderCancelled(new_protocol::MakerOrderCancelled {
+    let mut cancelled = new_protocol::MakerOrderCancelled {
         uuid: order.uuid.into(),
         timestamp: now_ms() / 1000,
         pair_trie_root: H64::default(),
-    });
+        nonce: next_order_nonce(&ctx),
+        signature: Vec::new(),
+    };
+    cancelled.signature = sign_order_payload(&ctx, &cancelled.signing_payload());
+    if order.is_private() {
+        private_offers::cancel_private_offer(&ctx, cancelled).await;
+        return;
+    }
+    let message = new_protocol::OrdermatchMessage::MakerOrderCancelled(cancelled);
     delete_my_order(&ctx, order.uuid).await;
     log::debug!("maker_order_cancelled_p2p_notify called, message {:?}", message);
     broadcast_ordermatch_message(&ctx, vec![order.orderbook_topic()], message);
@@ -979,6 +1375,10 @@ pub struct TakerRequest {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rel_protocol_info: Option<Vec<u8>>,
+    /// Whether the sender accepts the swap messages encrypted to its pubkey, absent if sent by an older node
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap_msg_encryption: Option<bool>,
 }
 
 impl TakerRequest {
@@ -999,6 +1399,7 @@ impl TakerRequest {
             conf_settings: Some(message.conf_settings),
             base_protocol_info: message.base_protocol_info,
             rel_protocol_info: message.rel_protocol_info,
+            swap_msg_encryption: message.swap_msg_encryption,
         }
     }
 
@@ -1044,6 +1445,7 @@ impl From<TakerOrder> for new_protocol::OrdermatchMessage {
             conf_settings: taker_order.request.conf_settings.unwrap(),
             base_protocol_info: taker_order.request.base_protocol_info,
             rel_protocol_info: taker_order.request.rel_protocol_info,
+            swap_msg_encryption: taker_order.request.swap_msg_encryption,
         })
     }
 }
@@ -1277,6 +1679,7 @@ impl<'a> TakerOrderBuilder<'a> {
                 conf_settings: self.conf_settings,
                 base_protocol_info: Some(self.base_coin.coin_protocol_info()),
                 rel_protocol_info: Some(self.rel_coin.coin_protocol_info()),
+                swap_msg_encryption: Some(true),
             },
             matches: Default::default(),
             min_volume,
@@ -1306,6 +1709,7 @@ impl<'a> TakerOrderBuilder<'a> {
                 conf_settings: self.conf_settings,
                 base_protocol_info: Some(self.base_coin.coin_protocol_info()),
                 rel_protocol_info: Some(self.rel_coin.coin_protocol_info()),
+                swap_msg_encryption: Some(true),
             },
             matches: HashMap::new(),
             min_volume: Default::default(),
@@ -1335,6 +1739,8 @@ impl Default for MatchBy {
 enum OrderType {
     FillOrKill,
     GoodTillCancelled,
+    /// Is cancelled if not matched in the first matching round
+    ImmediateOrCancel,
 }
 
 impl Default for OrderType {
@@ -1367,6 +1773,8 @@ enum MatchReservedResult {
 }
 
 impl TakerOrder {
+    pub fn uuid(&self) -> &Uuid { &self.request.uuid }
+
     fn is_cancellable(&self) -> bool { self.matches.is_empty() }
 
     fn match_reserved(&self, reserved: &MakerReserved) -> MatchReservedResult {
@@ -1464,6 +1872,14 @@ pub struct MakerOrder {
     base_orderbook_ticker: Option<String>,
     #[serde(default)]
     rel_orderbook_ticker: Option<String>,
+    /// The timestamp in seconds the order is cancelled at
+    #[serde(default)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    price_peg: Option<PricePeg>,
+    /// The pubkeys of the takers the order is offered to, the order isn't published to the orderbook if set
+    #[serde(default)]
+    private_for: Option<Vec<String>>,
 }
 
 pub struct MakerOrderBuilder<'a> {
@@ -1476,6 +1892,9 @@ pub struct MakerOrderBuilder<'a> {
     rel_orderbook_ticker: Option<String>,
     conf_settings: Option<OrderConfirmationsSettings>,
     save_in_history: bool,
+    expires_at: Option<u64>,
+    price_peg: Option<PricePeg>,
+    private_for: Option<Vec<String>>,
 }
 
 pub enum MakerOrderBuildError {
@@ -1505,6 +1924,14 @@ pub enum MakerOrderBuildError {
         min: MmNumber,
         max: MmNumber,
     },
+    /// Expiry timestamp is not in the future
+    ExpiresAtInThePast {
+        expires_at: u64,
+        now: u64,
+    },
+    /// The private order must be offered to at least one taker
+    PrivateForIsEmpty,
+    PrivateForInvalidPubkey(String),
 }
 
 impl fmt::Display for MakerOrderBuildError {
@@ -1542,6 +1969,17 @@ impl fmt::Display for MakerOrderBuildError {
                 max.to_decimal(),
                 min.to_decimal()
             ),
+            MakerOrderBuildError::ExpiresAtInThePast { expires_at, now } => write!(
+                f,
+                "Expiry timestamp {} must be greater than the current timestamp {}",
+                expires_at, now
+            ),
+            MakerOrderBuildError::PrivateForIsEmpty => {
+                write!(f, "The private order must have at least one taker pubkey")
+            },
+            MakerOrderBuildError::PrivateForInvalidPubkey(pubkey) => {
+                write!(f, "Invalid taker pubkey {}, expected a compressed pubkey hex", pubkey)
+            },
         }
     }
 }
@@ -1559,6 +1997,20 @@ fn validate_price(price: MmNumber) -> Result<(), MakerOrderBuildError> {
     Ok(())
 }
 
+/// Checks the taker pubkeys of the private order and converts them to the lowercase hex.
+fn validate_private_for(pubkeys: Vec<String>) -> Result<Vec<String>, MakerOrderBuildError> {
+    if pubkeys.is_empty() {
+        return Err(MakerOrderBuildError::PrivateForIsEmpty);
+    }
+    pubkeys
+        .into_iter()
+        .map(|pubkey| match hex::decode(&pubkey) {
+            Ok(bytes) if bytes.len() == 33 => Ok(hex::encode(bytes)),
+            _ => Err(MakerOrderBuildError::PrivateForInvalidPubkey(pubkey)),
+        })
+        .collect()
+}
+
 fn validate_and_get_min_vol(
     min_base_amount: MmNumber,
     min_rel_amount: MmNumber,
@@ -1622,6 +2074,9 @@ impl<'a> MakerOrderBuilder<'a> {
             price: 0.into(),
             conf_settings: None,
             save_in_history: true,
+            expires_at: None,
+            price_peg: None,
+            private_for: None,
         }
     }
 
@@ -1660,6 +2115,21 @@ impl<'a> MakerOrderBuilder<'a> {
         self
     }
 
+    pub fn with_expires_at(mut self, expires_at: Option<u64>) -> Self {
+        self.expires_at = expires_at;
+        self
+    }
+
+    pub fn with_price_peg(mut self, price_peg: Option<PricePeg>) -> Self {
+        self.price_peg = price_peg;
+        self
+    }
+
+    pub fn with_private_for(mut self, private_for: Option<Vec<String>>) -> Self {
+        self.private_for = private_for;
+        self
+    }
+
     /// Build MakerOrder
     pub fn build(self) -> Result<MakerOrder, MakerOrderBuildError> {
         if self.base_coin.ticker() == self.rel_coin.ticker() {
@@ -1690,6 +2160,18 @@ impl<'a> MakerOrderBuilder<'a> {
             self.price.clone(),
         )?;
 
+        if let Some(expires_at) = self.expires_at {
+            let now = now_ms() / 1000;
+            if expires_at <= now {
+                return Err(MakerOrderBuildError::ExpiresAtInThePast { expires_at, now });
+            }
+        }
+
+        let private_for = match self.private_for {
+            Some(pubkeys) => Some(validate_private_for(pubkeys)?),
+            None => None,
+        };
+
         Ok(MakerOrder {
             base: self.base_coin.ticker().to_owned(),
             rel: self.rel_coin.ticker().to_owned(),
@@ -1706,6 +2188,9 @@ impl<'a> MakerOrderBuilder<'a> {
             save_in_history: self.save_in_history,
             base_orderbook_ticker: self.base_orderbook_ticker,
             rel_orderbook_ticker: self.rel_orderbook_ticker,
+            expires_at: self.expires_at,
+            price_peg: self.price_peg,
+            private_for,
         })
     }
 
@@ -1727,6 +2212,9 @@ impl<'a> MakerOrderBuilder<'a> {
             save_in_history: false,
             base_orderbook_ticker: None,
             rel_orderbook_ticker: None,
+            expires_at: self.expires_at,
+            price_peg: self.price_peg,
+            private_for: self.private_for,
         }
     }
 }
@@ -1735,6 +2223,8 @@ impl<'a> MakerOrderBuilder<'a> {
 fn zero_rat() -> BigRational { BigRational::zero() }
 
 impl MakerOrder {
+    pub fn uuid(&self) -> &Uuid { &self.uuid }
+
     fn available_amount(&self) -> MmNumber { &self.max_base_vol - &self.reserved_amount() }
 
     fn reserved_amount(&self) -> MmNumber {
@@ -1746,6 +2236,22 @@ impl MakerOrder {
 
     fn is_cancellable(&self) -> bool { !self.has_ongoing_matches() }
 
+    fn is_expired(&self, now: u64) -> bool { self.expires_at.map_or(false, |expires_at| expires_at <= now) }
+
+    fn is_private(&self) -> bool { self.private_for.is_some() }
+
+    /// Whether the taker with the `pubkey` is allowed to match the order.
+    fn is_allowed_taker(&self, pubkey: &H256Json) -> bool {
+        match &self.private_for {
+            // the allowed pubkeys are compressed, the taker pubkey is the x coordinate only
+            Some(pubkeys) => {
+                let pubkey = hex::encode(pubkey.0);
+                pubkeys.iter().any(|allowed| allowed[2..] == pubkey)
+            },
+            None => true,
+        }
+    }
+
     fn has_ongoing_matches(&self) -> bool {
         for (_, order_match) in self.matches.iter() {
             // if there's at least 1 ongoing match the order is not cancellable
@@ -1851,6 +2357,9 @@ impl From<TakerOrder> for MakerOrder {
                 save_in_history: taker_order.save_in_history,
                 base_orderbook_ticker: taker_order.base_orderbook_ticker,
                 rel_orderbook_ticker: taker_order.rel_orderbook_ticker,
+                expires_at: None,
+                price_peg: None,
+                private_for: None,
             },
             // The "buy" taker order is recreated with reversed pair as Maker order is always considered as "sell"
             TakerAction::Buy => {
@@ -1872,6 +2381,9 @@ impl From<TakerOrder> for MakerOrder {
                     save_in_history: taker_order.save_in_history,
                     base_orderbook_ticker: taker_order.rel_orderbook_ticker,
                     rel_orderbook_ticker: taker_order.base_orderbook_ticker,
+                    expires_at: None,
+                    price_peg: None,
+                    private_for: None,
                 }
             },
         }
@@ -1924,6 +2436,10 @@ pub struct MakerReserved {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rel_protocol_info: Option<Vec<u8>>,
+    /// Whether the sender accepts the swap messages encrypted to its pubkey, absent if sent by an older node
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap_msg_encryption: Option<bool>,
 }
 
 impl MakerReserved {
@@ -1951,6 +2467,7 @@ impl MakerReserved {
             conf_settings: Some(message.conf_settings),
             base_protocol_info: message.base_protocol_info,
             rel_protocol_info: message.rel_protocol_info,
+            swap_msg_encryption: message.swap_msg_encryption,
         }
     }
 }
@@ -1967,6 +2484,7 @@ impl From<MakerReserved> for new_protocol::OrdermatchMessage {
             conf_settings: maker_reserved.conf_settings.unwrap(),
             base_protocol_info: maker_reserved.base_protocol_info,
             rel_protocol_info: maker_reserved.rel_protocol_info,
+            swap_msg_encryption: maker_reserved.swap_msg_encryption,
         })
     }
 }
@@ -2012,22 +2530,26 @@ pub async fn broadcast_maker_orders_keep_alive_loop(ctx: MmArc) {
             None => continue,
         };
 
-        let mut trie_roots = HashMap::new();
-        let mut topics = HashSet::new();
-        for (alb_pair, root) in state.trie_roots.iter() {
-            if *root == H64::default() && *root == hashed_null_node::<Layout>() {
-                continue;
+        let mut pairs: Vec<_> = state
+            .trie_roots
+            .iter()
+            .filter(|(_, root)| !(**root == H64::default() && **root == hashed_null_node::<Layout>()))
+            .collect();
+        pairs.sort_unstable_by(|x, y| x.0.cmp(y.0));
+
+        // the roots of all pairs are batched into as few messages as possible, each sent to the topics of its pairs
+        let timestamp = now_ms() / 1000;
+        for batch in pairs.chunks(KEEP_ALIVE_MAX_PAIRS) {
+            let mut trie_roots = HashMap::new();
+            let mut topics = HashSet::new();
+            for (alb_pair, root) in batch {
+                topics.insert(orderbook_topic_from_ordered_pair(alb_pair));
+                trie_roots.insert((*alb_pair).clone(), **root);
             }
-            topics.insert(orderbook_topic_from_ordered_pair(alb_pair));
-            trie_roots.insert(alb_pair.clone(), *root);
-        }
-
-        let message = new_protocol::PubkeyKeepAlive {
-            trie_roots,
-            timestamp: now_ms() / 1000,
-        };
 
-        broadcast_ordermatch_message(&ctx, topics, message.into());
+            let message = new_protocol::PubkeyKeepAlive { trie_roots, timestamp };
+            broadcast_ordermatch_message(&ctx, topics, message.into());
+        }
     }
 }
 
@@ -2059,7 +2581,7 @@ enum OrderbookRequestingState {
 
 type H64 = [u8; 8];
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Eq, PartialEq, Serialize)]
 struct TrieDiff<Key, Value> {
     delta: Vec<(Key, Option<Value>)>,
     next_root: H64,
@@ -2212,6 +2734,12 @@ struct Orderbook {
     topics_subscribed_to: HashMap<String, OrderbookRequestingState>,
     /// MemoryDB instance to store Patricia Tries data
     memory_db: MemoryDB<Blake2Hasher64>,
+    /// The nonces of the recently cancelled orders with the cancellation timestamps
+    /// Used to reject the replayed `MakerOrderCreated` messages of the cancelled orders
+    cancelled_orders: HashMap<Uuid, (u64, u64)>,
+    /// The topics restored from the orderbook snapshot, they are synced by the keep alive messages
+    /// instead of the full orderbook request upon the subscription
+    restored_topics: HashSet<String>,
 }
 
 fn hashed_null_node<T: TrieConfiguration>() -> TrieHash<T> { <T::Codec as NodeCodecT>::hashed_null_node() }
@@ -2445,6 +2973,22 @@ struct OrdermatchContext {
     /// Pending MakerReserved messages for a specific TakerOrder UUID
     /// Used to select a trade with the best price upon matching
     pending_maker_reserved: AsyncMutex<HashMap<Uuid, Vec<MakerReserved>>>,
+    /// The prices pushed by the local oracle with their timestamps by the (base, rel) pairs
+    oracle_prices: AsyncMutex<HashMap<(String, String), (MmNumber, u64)>>,
+    /// The running market making strategies
+    grid_strategies: AsyncMutex<HashMap<Uuid, grid_strategy::GridStrategy>>,
+    /// The ordermatch requests of the peers to enforce the per-peer limits
+    peer_requests_tracker: AsyncMutex<OrderRequestsTracker>,
+    /// The sequence number of the latest order message of this node
+    last_order_nonce: AtomicU64,
+    /// The private orders of this node offered to the allowed takers
+    my_private_offers: AsyncMutex<HashMap<Uuid, private_offers::MyPrivateOffer>>,
+    /// The private orders offered to this node by the makers
+    private_offers: AsyncMutex<HashMap<Uuid, private_offers::ReceivedPrivateOffer>>,
+    /// The configured order-level risk limits
+    risk_limits: RiskLimits,
+    /// The started swaps volumes and the rejected matches accounted by the risk limits
+    risk_limits_state: AsyncMutex<RiskLimitsState>,
 }
 
 pub fn init_ordermatch_context(ctx: &MmArc) -> Result<(), String> {
@@ -2468,9 +3012,26 @@ pub fn init_ordermatch_context(ctx: &MmArc) -> Result<(), String> {
         }
     }
 
+    let limit_per_sec = ctx.conf["ordermatch_requests_limit_per_sec"]
+        .as_u64()
+        .unwrap_or(PEER_REQUESTS_LIMIT_PER_SEC as u64);
+    let limit_per_sec =
+        try_s!(NonZeroUsize::new(limit_per_sec as usize).ok_or("ordermatch_requests_limit_per_sec must be positive"));
+    let max_violations = ctx.conf["ordermatch_requests_max_violations"]
+        .as_u64()
+        .unwrap_or(PEER_REQUESTS_MAX_VIOLATIONS as u64);
+    let ban_duration = ctx.conf["ordermatch_requests_ban_secs"]
+        .as_u64()
+        .unwrap_or(PEER_REQUESTS_BAN_DURATION);
+    let peer_requests_tracker = OrderRequestsTracker::new(limit_per_sec)
+        .with_ban_policy(max_violations as usize, Duration::from_secs(ban_duration));
+    let risk_limits = try_s!(RiskLimits::from_conf(ctx));
+
     let ordermatch_context = OrdermatchContext {
         orderbook_tickers,
         original_tickers,
+        peer_requests_tracker: AsyncMutex::new(peer_requests_tracker),
+        risk_limits,
         ..Default::default()
     };
 
@@ -2495,6 +3056,22 @@ impl OrdermatchContext {
 
     fn orderbook_ticker(&self, ticker: &str) -> Option<String> { self.orderbook_tickers.get(ticker).cloned() }
 
+    /// Returns the next sequence number of the order message.
+    /// It starts from the current timestamp in milliseconds, so the nonces keep increasing after the restart.
+    fn next_order_nonce(&self) -> u64 {
+        let mut last = self.last_order_nonce.load(AtomicOrdering::SeqCst);
+        loop {
+            let next = std::cmp::max(last + 1, now_ms());
+            match self
+                .last_order_nonce
+                .compare_exchange(last, next, AtomicOrdering::SeqCst, AtomicOrdering::SeqCst)
+            {
+                Ok(_) => return next,
+                Err(actual) => last = actual,
+            }
+        }
+    }
+
     fn orderbook_ticker_bypass(&self, ticker: &str) -> String {
         self.orderbook_ticker(ticker).unwrap_or_else(|| ticker.to_owned())
     }
@@ -2507,8 +3084,25 @@ impl OrdermatchContext {
     }
 }
 
+/// Checks the risk limits and starts the maker swap.
+/// The match is refused with the reason displayed by `order_status` if the limits are exceeded.
 #[cfg_attr(test, mockable)]
-fn lp_connect_start_bob(ctx: MmArc, maker_match: MakerMatch, maker_order: MakerOrder) {
+async fn lp_connect_start_bob(
+    ctx: MmArc,
+    maker_match: MakerMatch,
+    maker_order: MakerOrder,
+) -> Result<(), RiskLimitViolation> {
+    let ordermatch_ctx = OrdermatchContext::from_ctx(&ctx).unwrap();
+    risk_limits::check_maker_swap_start(
+        &ctx,
+        &ordermatch_ctx,
+        &maker_order,
+        maker_match.request.uuid,
+        &maker_match.request.sender_pubkey,
+        maker_match.reserved.get_base_amount(),
+    )
+    .await?;
+
     spawn(async move {
         // aka "maker_loop"
         let taker_coin = match lp_coinfind(&ctx, &maker_order.rel).await {
@@ -2540,8 +3134,9 @@ fn lp_connect_start_bob(ctx: MmArc, maker_match: MakerMatch, maker_order: MakerO
         let privkey = &ctx.secp256k1_key_pair().private().secret;
         let my_persistent_pub = compressed_pub_key_from_priv_raw(&privkey[..], ChecksumType::DSHA256).unwrap();
         let uuid = maker_match.request.uuid;
+        // the reserved settings take the value of the taker payment into account
         let my_conf_settings = choose_maker_confs_and_notas(
-            maker_order.conf_settings,
+            maker_match.reserved.conf_settings,
             &maker_match.request,
             &maker_coin,
             &taker_coin,
@@ -2588,9 +3183,11 @@ fn lp_connect_start_bob(ctx: MmArc, maker_match: MakerMatch, maker_order: MakerO
             maker_coin,
             taker_coin,
             lock_time,
+            maker_match.request.swap_msg_encryption.unwrap_or(false),
         );
         run_maker_swap(RunMakerSwapInput::StartNew(maker_swap), ctx).await;
     });
+    Ok(())
 }
 
 fn lp_connected_alice(ctx: MmArc, taker_order: TakerOrder, taker_match: TakerMatch) {
@@ -2628,6 +3225,8 @@ fn lp_connected_alice(ctx: MmArc, taker_order: TakerOrder, taker_match: TakerMat
         let maker_amount = taker_match.reserved.get_base_amount().clone();
         let taker_amount = taker_match.reserved.get_rel_amount().clone();
         let uuid = taker_match.reserved.taker_order_uuid;
+        let ordermatch_ctx = OrdermatchContext::from_ctx(&ctx).unwrap();
+        risk_limits::record_taker_swap_start(&ctx, &ordermatch_ctx, taker_coin.ticker(), &taker_amount).await;
 
         let my_conf_settings =
             choose_taker_confs_and_notas(&taker_order.request, &taker_match.reserved, &maker_coin, &taker_coin);
@@ -2676,6 +3275,7 @@ fn lp_connected_alice(ctx: MmArc, taker_order: TakerOrder, taker_match: TakerMat
             maker_coin,
             taker_coin,
             locktime,
+            taker_match.reserved.swap_msg_encryption.unwrap_or(false),
         );
         run_taker_swap(RunTakerSwapInput::StartNew(taker_swap), ctx).await
     });
@@ -2684,6 +3284,8 @@ fn lp_connected_alice(ctx: MmArc, taker_order: TakerOrder, taker_match: TakerMat
 pub async fn lp_ordermatch_loop(ctx: MmArc) {
     let my_pubsecp = hex::encode(&**ctx.secp256k1_key_pair().public());
     let maker_order_timeout = ctx.conf["maker_order_timeout"].as_u64().unwrap_or(MAKER_ORDER_TIMEOUT);
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut multi_maker_cleaned_at = 0;
     loop {
         if ctx.is_stopping() {
             break;
@@ -2733,6 +3335,13 @@ pub async fn lp_ordermatch_loop(ctx: MmArc) {
                     }
                 })
                 .collect();
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                if multi_maker_cleaned_at + MULTI_MAKER_CLEANUP_INTERVAL < now_ms() {
+                    remove_finished_multi_maker_orders(&ctx, &my_taker_orders);
+                    multi_maker_cleaned_at = now_ms();
+                }
+            }
             // remove timed out unfinished matches to unlock the reserved amount
             my_maker_orders.iter_mut().for_each(|(_, order)| {
                 let old_len = order.matches.len();
@@ -2747,7 +3356,11 @@ pub async fn lp_ordermatch_loop(ctx: MmArc) {
                 .filter_map(|(uuid, order)| {
                     let ctx = ctx.clone();
                     async move {
-                        if order.available_amount() < order.min_base_vol && !order.has_ongoing_matches() {
+                        if order.is_expired(now_ms() / 1000) && !order.has_ongoing_matches() {
+                            delete_my_maker_order(&ctx, &order, MakerOrderCancellationReason::Expired);
+                            maker_order_cancelled_p2p_notify(ctx.clone(), &order).await;
+                            None
+                        } else if order.available_amount() < order.min_base_vol && !order.has_ongoing_matches() {
                             if order.matches.is_empty() {
                                 delete_my_maker_order(&ctx, &order, MakerOrderCancellationReason::InsufficientBalance);
                             } else {
@@ -2778,6 +3391,13 @@ pub async fn lp_ordermatch_loop(ctx: MmArc) {
                     pubkeys_to_remove.push(pubkey.clone());
                 }
             }
+            // remove the expired orders of the other pubkeys, ours are removed on cancellation
+            let now = now_ms() / 1000;
+            for (uuid, order) in orderbook.order_set.iter() {
+                if order.pubkey != my_pubsecp && order.is_expired(now) {
+                    uuids_to_remove.push(*uuid);
+                }
+            }
 
             for uuid in uuids_to_remove {
                 orderbook.remove_order_trie_update(uuid);
@@ -2794,7 +3414,12 @@ pub async fn lp_ordermatch_loop(ctx: MmArc) {
             let mut to_cancel = vec![];
 
             for (uuid, order) in my_maker_orders.iter_mut() {
-                if !ordermatch_ctx.orderbook.lock().await.order_set.contains_key(uuid) {
+                let is_published = if order.is_private() {
+                    private_offers::is_private_offer_published(&ctx, uuid).await
+                } else {
+                    ordermatch_ctx.orderbook.lock().await.order_set.contains_key(uuid)
+                };
+                if !is_published {
                     let (base, rel) = match find_pair(&ctx, &order.base, &order.rel).await {
                         Ok(Some(coins)) => coins,
                         _ => continue,
@@ -2872,6 +3497,18 @@ pub async fn clean_memory_loop(ctx: MmArc) {
             let mut orderbook = ordermatch_ctx.orderbook.lock().await;
             orderbook.memory_db.purge();
         }
+        {
+            let mut orderbook = ordermatch_ctx.orderbook.lock().await;
+            let now = now_ms() / 1000;
+            orderbook
+                .cancelled_orders
+                .retain(|_, (_, cancelled_at)| *cancelled_at + TRIE_STATE_HISTORY_TIMEOUT > now);
+        }
+        ordermatch_ctx
+            .peer_requests_tracker
+            .lock()
+            .await
+            .remove_inactive_peers();
         Timer::sleep(600.).await;
     }
 }
@@ -2930,6 +3567,15 @@ async fn process_maker_reserved(ctx: MmArc, from_pubkey: H256Json, reserved_msg:
                 && base_coin.is_coin_protocol_supported(&reserved_msg.base_protocol_info)
                 && rel_coin.is_coin_protocol_supported(&reserved_msg.rel_protocol_info)
             {
+                if let Err(e) = risk_limits::check_counterparty(&ctx, &ordermatch_ctx, &reserved_msg.sender_pubkey) {
+                    log::warn!(
+                        "Skip the reserved message of the order {} for the taker order {}: {}",
+                        reserved_msg.maker_order_uuid,
+                        reserved_msg.taker_order_uuid,
+                        e
+                    );
+                    continue;
+                }
                 let connect = TakerConnect {
                     sender_pubkey: H256Json::from(our_public_id.bytes),
                     dest_pub_key: reserved_msg.sender_pubkey.clone(),
@@ -2952,6 +3598,12 @@ async fn process_maker_reserved(ctx: MmArc, from_pubkey: H256Json, reserved_msg:
             }
         }
     }
+
+    // the first matching round is over, the order is not worth waiting for the other makers
+    if my_order.order_type == OrderType::ImmediateOrCancel && my_order.matches.is_empty() {
+        let order = my_taker_orders.remove(&uuid).expect("Order is checked to exist above");
+        delete_my_taker_order(&ctx, &order, TakerOrderCancellationReason::Unmatched);
+    }
 }
 
 async fn process_maker_connected(ctx: MmArc, from_pubkey: H256Json, connected: MakerConnected) {
@@ -2990,6 +3642,35 @@ async fn process_maker_connected(ctx: MmArc, from_pubkey: H256Json, connected: M
     my_order_entry.remove();
 }
 
+async fn process_maker_connect_refused(ctx: MmArc, from_pubkey: H256Json, refused: new_protocol::MakerConnectRefused) {
+    log::debug!("Processing MakerConnectRefused {:?}", refused);
+    let ordermatch_ctx = OrdermatchContext::from_ctx(&ctx).unwrap();
+    let taker_order_uuid = Uuid::from(refused.taker_order_uuid);
+    let maker_order_uuid = Uuid::from(refused.maker_order_uuid);
+
+    let mut my_taker_orders = ordermatch_ctx.my_taker_orders.lock().await;
+    let my_order = match my_taker_orders.get_mut(&taker_order_uuid) {
+        Some(o) => o,
+        None => return,
+    };
+    let is_pending_match = matches!(
+        my_order.matches.get(&maker_order_uuid),
+        Some(order_match) if order_match.reserved.sender_pubkey == from_pubkey && order_match.connected.is_none()
+    );
+    if !is_pending_match {
+        return;
+    }
+    log::warn!(
+        "The maker refused the match of the order {} with the taker order {}: {}",
+        maker_order_uuid,
+        taker_order_uuid,
+        refused.reason
+    );
+    // remove the refused match to let the order match again or time out
+    my_order.matches.remove(&maker_order_uuid);
+    save_my_taker_order(&ctx, my_order);
+}
+
 async fn process_taker_request(ctx: MmArc, from_pubkey: H256Json, taker_request: TakerRequest) {
     let our_public_id: H256Json = ctx.public_id().unwrap().bytes.into();
     if our_public_id == from_pubkey {
@@ -3003,10 +3684,11 @@ async fn process_taker_request(ctx: MmArc, from_pubkey: H256Json, taker_request:
     }
 
     let ordermatch_ctx = OrdermatchContext::from_ctx(&ctx).unwrap();
+    let confs_policy = ConfirmationsPolicy::from_ctx(&ctx);
     let mut my_orders = ordermatch_ctx.my_maker_orders.lock().await;
     let filtered = my_orders
         .iter_mut()
-        .filter(|(uuid, _)| taker_request.can_match_with_uuid(uuid));
+        .filter(|(uuid, order)| taker_request.can_match_with_uuid(uuid) && order.is_allowed_taker(&from_pubkey));
 
     for (uuid, order) in filtered {
         if let OrderMatchResult::Matched((base_amount, rel_amount)) = order.match_with_request(&taker_request) {
@@ -3019,6 +3701,14 @@ async fn process_taker_request(ctx: MmArc, from_pubkey: H256Json, taker_request:
                 && base_coin.is_coin_protocol_supported(taker_request.base_protocol_info_for_maker())
                 && rel_coin.is_coin_protocol_supported(taker_request.rel_protocol_info_for_maker())
             {
+                let conf_settings = order.conf_settings.unwrap_or_else(|| OrderConfirmationsSettings {
+                    base_confs: base_coin.required_confirmations(),
+                    base_nota: base_coin.requires_notarization(),
+                    rel_confs: rel_coin.required_confirmations(),
+                    rel_nota: rel_coin.requires_notarization(),
+                });
+                // the taker pays the rel coin, so its confirmations depend on the taker payment value
+                let conf_settings = confs_policy.apply_to_rel(conf_settings, &order.rel, &rel_amount);
                 let reserved = MakerReserved {
                     dest_pub_key: taker_request.sender_pubkey.clone(),
                     sender_pubkey: our_public_id,
@@ -3028,16 +3718,10 @@ async fn process_taker_request(ctx: MmArc, from_pubkey: H256Json, taker_request:
                     rel: order.rel_orderbook_ticker().to_owned(),
                     taker_order_uuid: taker_request.uuid,
                     maker_order_uuid: *uuid,
-                    conf_settings: order.conf_settings.or_else(|| {
-                        Some(OrderConfirmationsSettings {
-                            base_confs: base_coin.required_confirmations(),
-                            base_nota: base_coin.requires_notarization(),
-                            rel_confs: rel_coin.required_confirmations(),
-                            rel_nota: rel_coin.requires_notarization(),
-                        })
-                    }),
+                    conf_settings: Some(conf_settings),
                     base_protocol_info: Some(base_coin.coin_protocol_info()),
                     rel_protocol_info: Some(rel_coin.coin_protocol_info()),
+                    swap_msg_encryption: Some(true),
                 };
                 let topic = order.orderbook_topic();
                 log::debug!("Request matched sending reserved {:?}", reserved);
@@ -3087,6 +3771,31 @@ async fn process_taker_connect(ctx: MmArc, sender_pubkey: H256Json, connect_msg:
     }
 
     if order_match.connected.is_none() && order_match.connect.is_none() {
+        let maker_match = order_match.clone();
+        if let Err(e) = lp_connect_start_bob(ctx.clone(), maker_match, my_order.clone()).await {
+            log::warn!(
+                "Refusing the match of the order {} with the taker order {}: {}",
+                my_order.uuid,
+                connect_msg.taker_order_uuid,
+                e
+            );
+            // remove the refused match to unlock the reserved amount
+            my_order.matches.remove(&connect_msg.taker_order_uuid);
+            save_my_maker_order(&ctx, my_order);
+            let refused = new_protocol::MakerConnectRefused {
+                taker_order_uuid: connect_msg.taker_order_uuid.into(),
+                maker_order_uuid: connect_msg.maker_order_uuid.into(),
+                reason: e.to_string(),
+            };
+            broadcast_ordermatch_message(
+                &ctx,
+                vec![my_order.orderbook_topic()],
+                new_protocol::OrdermatchMessage::MakerConnectRefused(refused),
+            );
+            return;
+        }
+        // the match is still there since `my_maker_orders` is locked
+        let order_match = my_order.matches.get_mut(&connect_msg.taker_order_uuid).unwrap();
         let connected = MakerConnected {
             sender_pubkey: our_public_id.bytes.into(),
             dest_pub_key: connect_msg.sender_pubkey.clone(),
@@ -3097,7 +3806,6 @@ async fn process_taker_connect(ctx: MmArc, sender_pubkey: H256Json, connect_msg:
         order_match.connect = Some(connect_msg);
         order_match.connected = Some(connected.clone());
         my_order.started_swaps.push(order_match.request.uuid);
-        lp_connect_start_bob(ctx.clone(), order_match.clone(), my_order.clone());
         let topic = my_order.orderbook_topic();
         broadcast_ordermatch_message(&ctx, vec![topic.clone()], connected.into());
 
@@ -3137,6 +3845,11 @@ pub struct AutoBuyInput {
     min_volume: Option<MmNumber>,
     #[serde(default = "get_true")]
     save_in_history: bool,
+    /// Splits the order across several maker orders of the orderbook.
+    #[serde(default)]
+    fill_across_makers: bool,
+    /// The max deviation of the maker prices from the best one when the order is split across makers, e.g. 0.01 for 1%.
+    max_slippage: Option<MmNumber>,
 }
 
 pub async fn buy(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
@@ -3271,6 +3984,30 @@ pub struct TakerRequestForRpc<'a> {
     conf_settings: &'a Option<OrderConfirmationsSettings>,
 }
 
+/// Returns the confirmations settings of the taker order of `base_amount` for `rel_amount`.
+fn taker_order_conf_settings(
+    ctx: &MmArc,
+    base_coin: &MmCoinEnum,
+    rel_coin: &MmCoinEnum,
+    input: &AutoBuyInput,
+    action: &TakerAction,
+    base_amount: &MmNumber,
+    rel_amount: &MmNumber,
+) -> OrderConfirmationsSettings {
+    let conf_settings = OrderConfirmationsSettings {
+        base_confs: input.base_confs.unwrap_or_else(|| base_coin.required_confirmations()),
+        base_nota: input.base_nota.unwrap_or_else(|| base_coin.requires_notarization()),
+        rel_confs: input.rel_confs.unwrap_or_else(|| rel_coin.required_confirmations()),
+        rel_nota: input.rel_nota.unwrap_or_else(|| rel_coin.requires_notarization()),
+    };
+    // the maker pays the base coin on buy and the rel coin on sell
+    let confs_policy = ConfirmationsPolicy::from_ctx(ctx);
+    match action {
+        TakerAction::Buy => confs_policy.apply_to_base(conf_settings, base_coin.ticker(), base_amount),
+        TakerAction::Sell => confs_policy.apply_to_rel(conf_settings, rel_coin.ticker(), rel_amount),
+    }
+}
+
 pub async fn lp_auto_buy(
     ctx: &MmArc,
     base_coin: &MmCoinEnum,
@@ -3287,15 +4024,20 @@ pub async fn lp_auto_buy(
         _ => return ERR!("Auto buy must be called only from buy/sell RPC methods"),
     };
     let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let (spent_coin, spent_volume) = match action {
+        TakerAction::Buy => (rel_coin.ticker(), &input.volume * &input.price),
+        TakerAction::Sell => (base_coin.ticker(), input.volume.clone()),
+    };
+    try_s!(risk_limits::check_new_order_open_volume(ctx, &ordermatch_ctx, spent_coin, &spent_volume, |_| false).await);
+    try_s!(risk_limits::check_daily_notional(ctx, &ordermatch_ctx, spent_coin, &spent_volume).await);
+    if input.fill_across_makers {
+        return lp_multi_maker_order(ctx, base_coin, rel_coin, input, action).await;
+    }
     let mut my_taker_orders = ordermatch_ctx.my_taker_orders.lock().await;
     let our_public_id = try_s!(ctx.public_id());
     let rel_volume = &input.volume * &input.price;
-    let conf_settings = OrderConfirmationsSettings {
-        base_confs: input.base_confs.unwrap_or_else(|| base_coin.required_confirmations()),
-        base_nota: input.base_nota.unwrap_or_else(|| base_coin.requires_notarization()),
-        rel_confs: input.rel_confs.unwrap_or_else(|| rel_coin.required_confirmations()),
-        rel_nota: input.rel_nota.unwrap_or_else(|| rel_coin.requires_notarization()),
-    };
+    let conf_settings =
+        taker_order_conf_settings(ctx, base_coin, rel_coin, &input, &action, &input.volume, &rel_volume);
     let mut order_builder = TakerOrderBuilder::new(base_coin, rel_coin)
         .with_base_amount(input.volume)
         .with_rel_amount(rel_volume)
@@ -3383,6 +4125,7 @@ impl OrderbookP2PItem {
             base_min_volume,
             rel_max_volume,
             rel_min_volume,
+            order_signature: None,
         }
     }
 
@@ -3417,6 +4160,7 @@ impl OrderbookP2PItem {
             base_min_volume,
             rel_max_volume,
             rel_min_volume,
+            order_signature: None,
         }
     }
 }
@@ -3460,7 +4204,7 @@ impl From<OrderbookItem> for OrderbookP2PItem {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 struct OrderbookItem {
     pubkey: String,
     base: String,
@@ -3472,6 +4216,12 @@ struct OrderbookItem {
     created_at: u64,
     base_protocol_info: Vec<u8>,
     rel_protocol_info: Vec<u8>,
+    /// It isn't a part of the trie state, so it's synced in `OrderSyncInfo`
+    expires_at: Option<u64>,
+    /// The sequence number of the latest signed message of the order, it isn't a part of the trie state
+    nonce: u64,
+    /// The maker signature of the order state with the `nonce`, empty if the order is placed by an older node
+    signature: Vec<u8>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -3517,8 +4267,17 @@ impl OrderbookItem {
         if let Some(new_min_volume) = msg.new_min_volume() {
             self.min_volume = new_min_volume.into();
         }
+
+        if msg.nonce() > self.nonce {
+            self.nonce = msg.nonce();
+            self.signature = msg.signature().to_vec();
+        }
     }
 
+    /// Whether the signed message with the `nonce` is older than the latest known message of the order.
+    /// The unsigned messages of the older nodes have the zero nonce and are never considered stale.
+    fn is_stale_nonce(&self, nonce: u64) -> bool { nonce != 0 && nonce <= self.nonce }
+
     fn as_rpc_entry_ask(&self, address: String, is_mine: bool) -> RpcOrderbookEntry {
         let price_mm = MmNumber::from(self.price.clone());
         let max_vol_mm = MmNumber::from(self.max_volume.clone());
@@ -3550,6 +4309,7 @@ impl OrderbookItem {
             base_min_volume,
             rel_max_volume,
             rel_min_volume,
+            order_signature: self.rpc_signature(),
         }
     }
 
@@ -3584,10 +4344,11 @@ impl OrderbookItem {
             base_min_volume,
             rel_max_volume,
             rel_min_volume,
+            order_signature: self.rpc_signature(),
         }
     }
 
-    fn from_p2p_and_proto_info(o: OrderbookP2PItem, info: BaseRelProtocolInfo) -> Self {
+    fn from_p2p_and_info(o: OrderbookP2PItem, info: BaseRelProtocolInfo, sync_info: OrderSyncInfo) -> Self {
         OrderbookItem {
             pubkey: o.pubkey,
             base: o.base,
@@ -3599,9 +4360,47 @@ impl OrderbookItem {
             created_at: o.created_at,
             base_protocol_info: info.base,
             rel_protocol_info: info.rel,
+            expires_at: sync_info.expires_at,
+            nonce: sync_info.nonce,
+            signature: sync_info.signature,
+        }
+    }
+
+    fn sync_info(&self) -> OrderSyncInfo {
+        OrderSyncInfo {
+            expires_at: self.expires_at,
+            nonce: self.nonce,
+            signature: self.signature.clone(),
         }
     }
 
+    /// Returns the order state signed by the maker, see `new_protocol::SignedOrderState`.
+    fn signing_payload(&self) -> Vec<u8> {
+        new_protocol::SignedOrderState {
+            uuid: self.uuid.into(),
+            base: &self.base,
+            rel: &self.rel,
+            price: &self.price,
+            max_volume: &self.max_volume,
+            min_volume: &self.min_volume,
+            created_at: self.created_at,
+            nonce: self.nonce,
+        }
+        .signing_payload()
+    }
+
+    fn rpc_signature(&self) -> Option<RpcOrderSignature> {
+        if self.signature.is_empty() {
+            return None;
+        }
+        Some(RpcOrderSignature {
+            nonce: self.nonce,
+            signature: hex::encode(&self.signature),
+        })
+    }
+
+    fn is_expired(&self, now: u64) -> bool { self.expires_at.map_or(false, |expires_at| expires_at <= now) }
+
     fn base_rel_proto_info(&self) -> BaseRelProtocolInfo {
         BaseRelProtocolInfo {
             base: self.base_protocol_info.clone(),
@@ -3645,6 +4444,7 @@ fn get_true() -> bool { true }
 struct SetPriceReq {
     base: String,
     rel: String,
+    /// The anchor price the `max_deviation_bps` of the pegged order is counted from
     price: MmNumber,
     #[serde(default)]
     max: bool,
@@ -3659,6 +4459,25 @@ struct SetPriceReq {
     rel_nota: Option<bool>,
     #[serde(default = "get_true")]
     save_in_history: bool,
+    /// The timestamp in seconds the order expires at
+    expires_at: Option<u64>,
+    /// The order lifetime in seconds, mutually exclusive with `expires_at`
+    expires_in: Option<u64>,
+    /// The price of the order follows the reference price if set
+    price_peg: Option<PricePeg>,
+    /// The order is offered privately to the takers with these pubkeys if set
+    private_for: Option<Vec<String>>,
+}
+
+impl SetPriceReq {
+    fn expires_at(&self) -> Result<Option<u64>, String> {
+        match (self.expires_at, self.expires_in) {
+            (Some(_), Some(_)) => ERR!("'expires_at' and 'expires_in' can't be set both"),
+            (Some(expires_at), None) => Ok(Some(expires_at)),
+            (None, Some(expires_in)) => Ok(Some(now_ms() / 1000 + expires_in)),
+            (None, None) => Ok(None),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -3792,6 +4611,12 @@ struct MakerOrderForRpc<'a> {
     changes_history: &'a Option<Vec<HistoricalOrder>>,
     base_orderbook_ticker: &'a Option<String>,
     rel_orderbook_ticker: &'a Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price_peg: &'a Option<PricePeg>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    private_for: &'a Option<Vec<String>>,
 }
 
 impl<'a> From<&'a MakerOrder> for MakerOrderForRpc<'a> {
@@ -3818,6 +4643,9 @@ impl<'a> From<&'a MakerOrder> for MakerOrderForRpc<'a> {
             changes_history: &order.changes_history,
             base_orderbook_ticker: &order.base_orderbook_ticker,
             rel_orderbook_ticker: &order.rel_orderbook_ticker,
+            expires_at: order.expires_at,
+            price_peg: &order.price_peg,
+            private_for: &order.private_for,
         }
     }
 }
@@ -3873,6 +4701,7 @@ async fn get_max_volume(ctx: &MmArc, my_coin: &MmCoinEnum, other_coin: &MmCoinEn
 
 pub async fn set_price(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
     let req: SetPriceReq = try_s!(json::from_value(req));
+    let expires_at = try_s!(req.expires_at());
 
     let base_coin: MmCoinEnum = match try_s!(lp_coinfind(&ctx, &req.base).await) {
         Some(coin) => coin,
@@ -3891,6 +4720,17 @@ pub async fn set_price(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, Strin
         return ERR!("Rel coin {} is wallet only", req.rel);
     }
 
+    let (price, price_peg) = match &req.price_peg {
+        Some(price_peg) => {
+            try_s!(price_peg.validate());
+            let mut price_peg = price_peg.clone();
+            price_peg.set_anchor_price(req.price.clone());
+            let price = try_s!(initial_pegged_price(&ctx, &req.base, &req.rel, &price_peg).await);
+            (price, Some(price_peg))
+        },
+        None => (req.price.clone(), None),
+    };
+
     let volume = if req.max {
         try_s!(
             get_max_volume(&ctx, &base_coin, &rel_coin)
@@ -3915,6 +4755,13 @@ pub async fn set_price(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, Strin
     };
 
     let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(&ctx));
+    // the previous orders of the pair are replaced by the new one, check the limits before cancelling them
+    try_s!(
+        risk_limits::check_new_order_open_volume(&ctx, &ordermatch_ctx, &req.base, &volume, |order| {
+            req.cancel_previous && order.base == req.base && order.rel == req.rel
+        })
+        .await
+    );
     let mut my_orders = ordermatch_ctx.my_maker_orders.lock().await;
 
     if req.cancel_previous {
@@ -3950,11 +4797,14 @@ pub async fn set_price(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, Strin
     let builder = MakerOrderBuilder::new(&base_coin, &rel_coin)
         .with_max_base_vol(volume)
         .with_min_base_vol(req.min_volume)
-        .with_price(req.price)
+        .with_price(price)
         .with_conf_settings(conf_settings)
         .with_save_in_history(req.save_in_history)
         .with_base_orderbook_ticker(ordermatch_ctx.orderbook_ticker(base_coin.ticker()))
-        .with_rel_orderbook_ticker(ordermatch_ctx.orderbook_ticker(rel_coin.ticker()));
+        .with_rel_orderbook_ticker(ordermatch_ctx.orderbook_ticker(rel_coin.ticker()))
+        .with_expires_at(expires_at)
+        .with_price_peg(price_peg)
+        .with_private_for(req.private_for.clone());
 
     let new_order = try_s!(builder.build());
 
@@ -4163,12 +5013,20 @@ pub async fn order_status(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, St
 
     let req: OrderStatusReq = try_s!(json::from_value(req));
 
+    if let Some(res) = try_s!(multi_maker_order_status(&ctx, &req.uuid).await) {
+        return Response::builder()
+            .body(json::to_vec(&res).expect("Serialization failed"))
+            .map_err(|e| ERRL!("{}", e));
+    }
+
     let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(&ctx));
     let maker_orders = ordermatch_ctx.my_maker_orders.lock().await;
     if let Some(order) = maker_orders.get(&req.uuid) {
+        let risk_limits_state = ordermatch_ctx.risk_limits_state.lock().await;
         let res = json!({
             "type": "Maker",
             "order": MakerOrderForMyOrdersRpc::from(order),
+            "risk_limit_rejection": risk_limits_state.rejection(&req.uuid),
         });
         return Response::builder()
             .body(json::to_vec(&res).expect("Serialization failed"))
@@ -4213,6 +5071,7 @@ enum MakerOrderCancellationReason {
     Fulfilled,
     InsufficientBalance,
     Cancelled,
+    Expired,
 }
 
 #[derive(Display)]
@@ -4221,6 +5080,7 @@ enum TakerOrderCancellationReason {
     ToMaker,
     TimedOut,
     Cancelled,
+    Unmatched,
 }
 
 #[derive(Debug, Deserialize)]
@@ -4350,6 +5210,20 @@ struct CancelOrderReq {
 pub async fn cancel_order(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
     let req: CancelOrderReq = try_s!(json::from_value(req));
 
+    // the multi-maker order is cancelled with all its child taker orders
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if let Some(currently_matching) = try_s!(cancel_multi_maker_order(&ctx, &req.uuid).await) {
+            let res = json!({
+                "result": "success",
+                "currently_matching": currently_matching,
+            });
+            return Response::builder()
+                .body(json::to_vec(&res).expect("Serialization failed"))
+                .map_err(|e| ERRL!("{}", e));
+        }
+    }
+
     let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(&ctx));
     let mut maker_orders = ordermatch_ctx.my_maker_orders.lock().await;
     match maker_orders.entry(req.uuid) {
@@ -4699,6 +5573,11 @@ pub async fn orders_kick_start(ctx: &MmArc) -> Result<HashSet<String>, String> {
 
     maker_entries.iter().for_each(|entry| {
         if let Ok(order) = json::from_slice::<MakerOrder>(&slurp(&entry.path())) {
+            // the order could expire while MM2 was stopped
+            if order.is_expired(now_ms() / 1000) {
+                delete_my_maker_order(ctx, &order, MakerOrderCancellationReason::Expired);
+                return;
+            }
             coins.insert(order.base.clone());
             coins.insert(order.rel.clone());
             maker_orders.insert(order.uuid, order);
@@ -4718,6 +5597,39 @@ pub async fn orders_kick_start(ctx: &MmArc) -> Result<HashSet<String>, String> {
     Ok(coins)
 }
 
+/// Loads my active maker and taker orders from their files to put them into a swaps backup.
+pub fn my_orders_for_backup(ctx: &MmArc) -> Result<(Vec<MakerOrder>, Vec<TakerOrder>), String> {
+    let maker_orders = try_s!(json_dir_entries(&my_maker_orders_dir(ctx)))
+        .iter()
+        .filter_map(|entry| json::from_slice::<MakerOrder>(&slurp(&entry.path())).ok())
+        .collect();
+    let taker_orders = try_s!(json_dir_entries(&my_taker_orders_dir(ctx)))
+        .iter()
+        .filter_map(|entry| json::from_slice::<TakerOrder>(&slurp(&entry.path())).ok())
+        .collect();
+    Ok((maker_orders, taker_orders))
+}
+
+/// Saves the maker order restored from a swaps backup.
+/// The order is loaded by `orders_kick_start` on the next start.
+pub fn restore_my_maker_order(ctx: &MmArc, order: &MakerOrder) -> Result<(), String> {
+    if my_maker_order_file_path(ctx, &order.uuid).exists() {
+        return ERR!("Maker order {} exists already", order.uuid);
+    }
+    save_my_new_maker_order(ctx, order);
+    Ok(())
+}
+
+/// Saves the taker order restored from a swaps backup.
+/// The order is loaded by `orders_kick_start` on the next start.
+pub fn restore_my_taker_order(ctx: &MmArc, order: &TakerOrder) -> Result<(), String> {
+    if my_taker_order_file_path(ctx, &order.request.uuid).exists() {
+        return ERR!("Taker order {} exists already", order.request.uuid);
+    }
+    save_my_new_taker_order(ctx, order);
+    Ok(())
+}
+
 #[derive(Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum CancelBy {
@@ -4851,8 +5763,15 @@ pub(self) async fn subscribe_to_orderbook_topic(
     let is_orderbook_filled = {
         let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
         let mut orderbook = ordermatch_ctx.orderbook.lock().await;
+        let restored = orderbook.restored_topics.remove(&topic);
 
         match orderbook.topics_subscribed_to.entry(topic.clone()) {
+            Entry::Vacant(e) if restored => {
+                // we weren't subscribed to the topic yet, but the orderbook is restored from the snapshot
+                e.insert(OrderbookRequestingState::Requested);
+                subscribe_to_topic(ctx, topic.clone()).await;
+                true
+            },
             Entry::Vacant(e) => {
                 // we weren't subscribed to the topic yet
                 e.insert(OrderbookRequestingState::NotRequested {
@@ -4922,6 +5841,16 @@ pub struct RpcOrderbookEntry {
     rel_max_volume: DetailedRelMaxVolume,
     #[serde(flatten)]
     rel_min_volume: DetailedRelMinVolume,
+    /// Is not set if the order is placed by an older node
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order_signature: Option<RpcOrderSignature>,
+}
+
+/// The maker signature of the order state, see `new_protocol::SignedOrderState`.
+#[derive(Debug, Serialize)]
+pub struct RpcOrderSignature {
+    nonce: u64,
+    signature: String,
 }
 
 fn choose_maker_confs_and_notas(
@@ -0,0 +1,173 @@
+//! Value-dependent confirmations of the counterparty payments.
+//!
+//! The policy is set by the `swap_confirmations_policy` field of the MM2 config, e.g.
+//! `{"BTC": {"price": "40000", "tiers": [{"min_value": "1000", "confs": 2}, {"min_value": "10000", "confs": 4, "nota": true}]}}`.
+//! The tiers thresholds are in the unit of the coin `price` (fiat or the base coin of the user choice),
+//! or in the coin units if the price is not set.
+//! The policy never lowers the confirmations and notarization settings of the order.
+
+use super::OrderConfirmationsSettings;
+use common::log::error;
+use common::mm_ctx::MmArc;
+use common::mm_number::MmNumber;
+use serde_json as json;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ConfirmationsTier {
+    /// The minimum value of the payment the tier applies to.
+    min_value: MmNumber,
+    confs: u64,
+    #[serde(default)]
+    nota: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct CoinConfirmationsPolicy {
+    /// The price of the coin in the unit of the tiers thresholds.
+    #[serde(default)]
+    price: Option<MmNumber>,
+    tiers: Vec<ConfirmationsTier>,
+}
+
+impl CoinConfirmationsPolicy {
+    /// Returns the tier with the greatest threshold not exceeding the value of the `amount` payment.
+    fn tier_for_amount(&self, amount: &MmNumber) -> Option<&ConfirmationsTier> {
+        let value = match &self.price {
+            Some(price) => amount * price,
+            None => amount.clone(),
+        };
+        self.tiers
+            .iter()
+            .filter(|tier| tier.min_value <= value)
+            .max_by(|a, b| a.min_value.cmp(&b.min_value))
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct ConfirmationsPolicy(HashMap<String, CoinConfirmationsPolicy>);
+
+impl ConfirmationsPolicy {
+    /// Reads the policy from the MM2 config, the invalid policy is ignored.
+    pub fn from_ctx(ctx: &MmArc) -> ConfirmationsPolicy {
+        let policy = &ctx.conf["swap_confirmations_policy"];
+        if policy.is_null() {
+            return ConfirmationsPolicy::default();
+        }
+        match json::from_value(policy.clone()) {
+            Ok(policy) => policy,
+            Err(e) => {
+                error!("Error {} parsing 'swap_confirmations_policy', the policy is ignored", e);
+                ConfirmationsPolicy::default()
+            },
+        }
+    }
+
+    /// Returns the confirmations and notarization required for the `amount` payment of the `coin`.
+    pub fn required_confs_and_nota(&self, coin: &str, amount: &MmNumber, confs: u64, nota: bool) -> (u64, bool) {
+        match self.0.get(coin).and_then(|policy| policy.tier_for_amount(amount)) {
+            Some(tier) => (confs.max(tier.confs), nota || tier.nota),
+            None => (confs, nota),
+        }
+    }
+
+    /// Applies the policy to the base coin settings, used when the counterparty pays the `base_amount` of the `base` coin.
+    pub fn apply_to_base(
+        &self,
+        settings: OrderConfirmationsSettings,
+        base: &str,
+        base_amount: &MmNumber,
+    ) -> OrderConfirmationsSettings {
+        let (base_confs, base_nota) =
+            self.required_confs_and_nota(base, base_amount, settings.base_confs, settings.base_nota);
+        OrderConfirmationsSettings {
+            base_confs,
+            base_nota,
+            ..settings
+        }
+    }
+
+    /// Applies the policy to the rel coin settings, used when the counterparty pays the `rel_amount` of the `rel` coin.
+    pub fn apply_to_rel(
+        &self,
+        settings: OrderConfirmationsSettings,
+        rel: &str,
+        rel_amount: &MmNumber,
+    ) -> OrderConfirmationsSettings {
+        let (rel_confs, rel_nota) =
+            self.required_confs_and_nota(rel, rel_amount, settings.rel_confs, settings.rel_nota);
+        OrderConfirmationsSettings {
+            rel_confs,
+            rel_nota,
+            ..settings
+        }
+    }
+}
+
+#[cfg(test)]
+mod confirmations_policy_tests {
+    use super::*;
+
+    fn test_policy() -> ConfirmationsPolicy {
+        json::from_value(json!({
+            "BTC": {
+                "price": "40000",
+                "tiers": [
+                    { "min_value": "10000", "confs": 4, "nota": true },
+                    { "min_value": "1000", "confs": 2 }
+                ]
+            },
+            "KMD": {
+                "tiers": [{ "min_value": "500", "confs": 3 }]
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_required_confs_and_nota_fiat_value() {
+        let policy = test_policy();
+        // 400 in fiat
+        let actual = policy.required_confs_and_nota("BTC", &MmNumber::from("0.01"), 1, false);
+        assert_eq!(actual, (1, false));
+        // 2000 in fiat
+        let actual = policy.required_confs_and_nota("BTC", &MmNumber::from("0.05"), 1, false);
+        assert_eq!(actual, (2, false));
+        // 20000 in fiat
+        let actual = policy.required_confs_and_nota("BTC", &MmNumber::from("0.5"), 1, false);
+        assert_eq!(actual, (4, true));
+        // the policy doesn't lower the order settings
+        let actual = policy.required_confs_and_nota("BTC", &MmNumber::from("0.05"), 6, true);
+        assert_eq!(actual, (6, true));
+    }
+
+    #[test]
+    fn test_required_confs_and_nota_coin_value() {
+        let policy = test_policy();
+        let actual = policy.required_confs_and_nota("KMD", &MmNumber::from("499"), 1, false);
+        assert_eq!(actual, (1, false));
+        let actual = policy.required_confs_and_nota("KMD", &MmNumber::from("500"), 1, false);
+        assert_eq!(actual, (3, false));
+        let actual = policy.required_confs_and_nota("ETH", &MmNumber::from("1000000"), 1, false);
+        assert_eq!(actual, (1, false));
+    }
+
+    #[test]
+    fn test_apply_to_rel() {
+        let policy = test_policy();
+        let settings = OrderConfirmationsSettings {
+            base_confs: 1,
+            base_nota: false,
+            rel_confs: 1,
+            rel_nota: false,
+        };
+        let expected = OrderConfirmationsSettings {
+            base_confs: 1,
+            base_nota: false,
+            rel_confs: 3,
+            rel_nota: false,
+        };
+        assert_eq!(policy.apply_to_rel(settings, "KMD", &MmNumber::from("1000")), expected);
+        assert_eq!(policy.apply_to_base(settings, "KMD", &MmNumber::from("1")), settings);
+    }
+}
@@ -0,0 +1,663 @@
+//! Grid and ladder market making strategies.
+//!
+//! The strategy splits the configured price range into evenly spaced levels and keeps a maker order at every level.
+//! The grid sells above the start price and buys below it. Once the swap of a level order finishes successfully,
+//! the grid posts the opposite side at the adjacent level, so every round trip earns the distance between the levels.
+//! The ladder places all the levels on the one side and doesn't re-post the filled levels.
+//! The level which order was cancelled bypassing the strategy, e.g. with the `cancel_order` RPC, is not maintained
+//! anymore.
+//!
+//! The maker orders are always sells, so the buy level is the maker order of the reversed pair:
+//! buying `volume_per_level` of the base at the price `p` is selling `volume_per_level * p` of the rel at `1 / p`.
+
+use super::{check_balance_for_maker_swap, delete_my_maker_order, maker_order_cancelled_p2p_notify,
+            maker_order_created_p2p_notify, my_order_history_file_path, save_my_new_maker_order,
+            subscribe_to_orderbook_topic, MakerOrderBuilder, MakerOrderCancellationReason, Order,
+            OrderConfirmationsSettings, OrdermatchContext};
+use crate::mm2::lp_swap::SavedSwap;
+use coins::{lp_coinfind, FeeApproxStage, MmCoinEnum};
+#[cfg(not(target_arch = "wasm32"))] use common::executor::Timer;
+#[cfg(not(target_arch = "wasm32"))] use common::json_dir_entries;
+use common::log::{error, warn};
+use common::mm_ctx::MmArc;
+use common::mm_number::MmNumber;
+use common::{new_uuid, now_ms, remove_file, write};
+use gstuff::slurp;
+use http::Response;
+use serde_json::{self as json, Value as Json};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// The default interval of the strategies maintenance in seconds.
+#[cfg(not(target_arch = "wasm32"))]
+const GRID_STRATEGY_INTERVAL: f64 = 30.;
+
+fn get_true() -> bool { true }
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum GridSide {
+    Buy,
+    Sell,
+}
+
+impl GridSide {
+    fn opposite(self) -> GridSide {
+        match self {
+            GridSide::Buy => GridSide::Sell,
+            GridSide::Sell => GridSide::Buy,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", content = "data")]
+pub enum StrategyKind {
+    /// Sells above the start price, buys below it and re-posts the opposite side after the fills
+    Grid,
+    /// Places all the levels on the one side
+    Ladder { side: GridSide },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GridStrategyConf {
+    base: String,
+    rel: String,
+    kind: StrategyKind,
+    /// The price of the lowest level in the rel coin per 1 base coin
+    min_price: MmNumber,
+    /// The price of the highest level in the rel coin per 1 base coin
+    max_price: MmNumber,
+    /// The number of the levels including the range bounds
+    levels: usize,
+    /// The base coin volume of every level order
+    volume_per_level: MmNumber,
+    /// The grid sells above and buys below this price, the middle of the range by default
+    #[serde(default)]
+    start_price: Option<MmNumber>,
+    /// The max base coin volume available in the sell orders of the strategy
+    #[serde(default)]
+    max_base_exposure: Option<MmNumber>,
+    /// The max rel coin volume available in the buy orders of the strategy
+    #[serde(default)]
+    max_rel_exposure: Option<MmNumber>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct GridLevel {
+    price: MmNumber,
+    /// The side of the level order, `None` if the level is empty
+    side: Option<GridSide>,
+    order_uuid: Option<Uuid>,
+    /// The swaps started by the current level order
+    order_swaps: Vec<Uuid>,
+    /// The swaps not finished yet with the side of the order started them
+    pending_swaps: Vec<(Uuid, GridSide)>,
+    /// The number of the successfully finished swaps of the level
+    fills: u64,
+    /// The level order was cancelled with no swaps started, the level is not maintained anymore
+    #[serde(default)]
+    cancelled: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GridStrategy {
+    uuid: Uuid,
+    conf: GridStrategyConf,
+    levels: Vec<GridLevel>,
+    created_at: u64,
+    /// The last error of the strategy maintenance
+    #[serde(default)]
+    last_error: Option<String>,
+}
+
+fn my_strategies_dir(ctx: &MmArc) -> PathBuf { ctx.dbdir().join("ORDERS").join("MY").join("STRATEGIES") }
+
+fn my_strategy_file_path(ctx: &MmArc, uuid: &Uuid) -> PathBuf { my_strategies_dir(ctx).join(format!("{}.json", uuid)) }
+
+fn save_strategy(ctx: &MmArc, strategy: &GridStrategy) {
+    let content = json::to_vec(strategy).expect("Serialization failed");
+    if let Err(e) = write(&my_strategy_file_path(ctx, &strategy.uuid), &content) {
+        error!("Error {} on saving the strategy {}", e, strategy.uuid);
+    }
+}
+
+/// Calculates the evenly spaced levels of the strategy with their initial sides.
+fn grid_levels(conf: &GridStrategyConf) -> Result<Vec<GridLevel>, String> {
+    let zero = MmNumber::from(0);
+    if conf.levels < 2 {
+        return ERR!("The strategy requires at least 2 levels, {} given", conf.levels);
+    }
+    if conf.min_price <= zero || conf.min_price >= conf.max_price {
+        return ERR!(
+            "Invalid price range {} - {}",
+            conf.min_price.to_decimal(),
+            conf.max_price.to_decimal()
+        );
+    }
+    if conf.volume_per_level <= zero {
+        return ERR!("The volume per level must be positive");
+    }
+    if conf.base == conf.rel {
+        return ERR!("Base and rel must be different coins");
+    }
+
+    let start_price = match &conf.start_price {
+        Some(price) if *price < conf.min_price || *price > conf.max_price => {
+            return ERR!("The start price {} is out of the range", price.to_decimal())
+        },
+        Some(price) => price.clone(),
+        None => (&conf.min_price + &conf.max_price) / MmNumber::from(2),
+    };
+
+    let step = (&conf.max_price - &conf.min_price) / MmNumber::from((conf.levels - 1) as u64);
+    let levels = (0..conf.levels)
+        .map(|i| {
+            let price = &conf.min_price + &(&step * &MmNumber::from(i as u64));
+            let side = match &conf.kind {
+                StrategyKind::Ladder { side } => Some(*side),
+                StrategyKind::Grid if price > start_price => Some(GridSide::Sell),
+                StrategyKind::Grid if price < start_price => Some(GridSide::Buy),
+                StrategyKind::Grid => None,
+            };
+            GridLevel {
+                price,
+                side,
+                order_uuid: None,
+                order_swaps: Vec::new(),
+                pending_swaps: Vec::new(),
+                fills: 0,
+                cancelled: false,
+            }
+        })
+        .collect();
+    Ok(levels)
+}
+
+/// Returns the swaps started by the order removed from `my_maker_orders` saved in the orders history.
+fn finished_order_swaps(ctx: &MmArc, order_uuid: &Uuid) -> Vec<Uuid> {
+    let content = slurp(&my_order_history_file_path(ctx, order_uuid));
+    match json::from_slice::<Order>(&content) {
+        Ok(Order::Maker(order)) => order.started_swaps,
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the index of the level getting the opposite order after the fill of the `side` order at the `index`.
+fn opposite_level(kind: &StrategyKind, index: usize, side: GridSide, levels_len: usize) -> Option<usize> {
+    match (kind, side) {
+        (StrategyKind::Ladder { .. }, _) => None,
+        (StrategyKind::Grid, GridSide::Sell) => index.checked_sub(1),
+        (StrategyKind::Grid, GridSide::Buy) if index + 1 < levels_len => Some(index + 1),
+        (StrategyKind::Grid, GridSide::Buy) => None,
+    }
+}
+
+/// Returns the volume of the level order in the coin it sells.
+fn level_order_volume(price: &MmNumber, side: GridSide, volume_per_level: &MmNumber) -> MmNumber {
+    match side {
+        GridSide::Sell => volume_per_level.clone(),
+        GridSide::Buy => volume_per_level * price,
+    }
+}
+
+/// Returns the maker order price of the level.
+fn level_order_price(price: &MmNumber, side: GridSide) -> MmNumber {
+    match side {
+        GridSide::Sell => price.clone(),
+        GridSide::Buy => MmNumber::from(1) / price.clone(),
+    }
+}
+
+async fn place_level_order(
+    ctx: &MmArc,
+    base_coin: &MmCoinEnum,
+    rel_coin: &MmCoinEnum,
+    price: MmNumber,
+    volume: MmNumber,
+) -> Result<Uuid, String> {
+    try_s!(
+        check_balance_for_maker_swap(
+            ctx,
+            base_coin,
+            rel_coin,
+            volume.clone(),
+            None,
+            None,
+            FeeApproxStage::OrderIssue
+        )
+        .await
+    );
+
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let conf_settings = OrderConfirmationsSettings {
+        base_confs: base_coin.required_confirmations(),
+        base_nota: base_coin.requires_notarization(),
+        rel_confs: rel_coin.required_confirmations(),
+        rel_nota: rel_coin.requires_notarization(),
+    };
+    let new_order = try_s!(MakerOrderBuilder::new(base_coin, rel_coin)
+        .with_max_base_vol(volume)
+        .with_price(price)
+        .with_conf_settings(conf_settings)
+        .with_base_orderbook_ticker(ordermatch_ctx.orderbook_ticker(base_coin.ticker()))
+        .with_rel_orderbook_ticker(ordermatch_ctx.orderbook_ticker(rel_coin.ticker()))
+        .build());
+
+    let request_orderbook = false;
+    try_s!(
+        subscribe_to_orderbook_topic(
+            ctx,
+            &new_order.base_orderbook_ticker(),
+            &new_order.rel_orderbook_ticker(),
+            request_orderbook
+        )
+        .await
+    );
+    save_my_new_maker_order(ctx, &new_order);
+    maker_order_created_p2p_notify(
+        ctx.clone(),
+        &new_order,
+        base_coin.coin_protocol_info(),
+        rel_coin.coin_protocol_info(),
+    )
+    .await;
+    let uuid = new_order.uuid;
+    ordermatch_ctx.my_maker_orders.lock().await.insert(uuid, new_order);
+    Ok(uuid)
+}
+
+/// Syncs the strategy levels with the current orders and swaps and places the missing level orders.
+async fn maintain_strategy(ctx: &MmArc, strategy: &mut GridStrategy) -> Result<(), String> {
+    let conf = strategy.conf.clone();
+    let base_coin = match try_s!(lp_coinfind(ctx, &conf.base).await) {
+        Some(coin) => coin,
+        None => return ERR!("Base coin {} is not activated", conf.base),
+    };
+    let rel_coin = match try_s!(lp_coinfind(ctx, &conf.rel).await) {
+        Some(coin) => coin,
+        None => return ERR!("Rel coin {} is not activated", conf.rel),
+    };
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+
+    let zero = MmNumber::from(0);
+    let mut base_exposure = zero.clone();
+    let mut rel_exposure = zero.clone();
+    {
+        let my_maker_orders = ordermatch_ctx.my_maker_orders.lock().await;
+        for level in strategy.levels.iter_mut() {
+            let (order_uuid, side) = match (level.order_uuid, level.side) {
+                (Some(order_uuid), Some(side)) => (order_uuid, side),
+                _ => continue,
+            };
+            match my_maker_orders.get(&order_uuid) {
+                Some(order) => {
+                    for swap_uuid in order.started_swaps.iter() {
+                        if !level.order_swaps.contains(swap_uuid) {
+                            level.order_swaps.push(*swap_uuid);
+                            level.pending_swaps.push((*swap_uuid, side));
+                        }
+                    }
+                    match side {
+                        GridSide::Sell => base_exposure = &base_exposure + &order.available_amount(),
+                        GridSide::Buy => rel_exposure = &rel_exposure + &order.available_amount(),
+                    }
+                },
+                None => {
+                    // the order may have started the swaps after the previous maintenance
+                    for swap_uuid in finished_order_swaps(ctx, &order_uuid) {
+                        if !level.order_swaps.contains(&swap_uuid) {
+                            level.order_swaps.push(swap_uuid);
+                            level.pending_swaps.push((swap_uuid, side));
+                        }
+                    }
+                    // the order is either fully matched or cancelled,
+                    // the matched level waits for the swaps to finish before the opposite side is posted
+                    // and the cancelled one is not re-posted
+                    if level.order_swaps.is_empty() {
+                        level.cancelled = true;
+                    }
+                    level.side = None;
+                    level.order_uuid = None;
+                    level.order_swaps.clear();
+                },
+            }
+        }
+    }
+
+    let levels_len = strategy.levels.len();
+    let mut opposite_orders = Vec::new();
+    for (index, level) in strategy.levels.iter_mut().enumerate() {
+        let mut pending_swaps = Vec::with_capacity(level.pending_swaps.len());
+        for (swap_uuid, side) in level.pending_swaps.drain(..) {
+            let swap = match SavedSwap::load_my_swap(ctx, &swap_uuid) {
+                Ok(Some(swap)) if swap.is_finished() => swap,
+                Ok(_) => {
+                    pending_swaps.push((swap_uuid, side));
+                    continue;
+                },
+                Err(e) => {
+                    warn!("Error {} on loading the swap {}", e, swap_uuid);
+                    pending_swaps.push((swap_uuid, side));
+                    continue;
+                },
+            };
+            if let Ok(true) = swap.is_success() {
+                level.fills += 1;
+                if let Some(opposite) = opposite_level(&conf.kind, index, side, levels_len) {
+                    opposite_orders.push((opposite, side.opposite()));
+                }
+            }
+        }
+        level.pending_swaps = pending_swaps;
+    }
+    for (index, side) in opposite_orders {
+        let level = &mut strategy.levels[index];
+        // the level may hold the order posted after the fill of another adjacent level
+        if level.side.is_none() && level.order_uuid.is_none() && !level.cancelled {
+            level.side = Some(side);
+        }
+    }
+
+    for level in strategy.levels.iter_mut() {
+        let side = match (level.side, level.order_uuid) {
+            (Some(side), None) if level.pending_swaps.is_empty() => side,
+            _ => continue,
+        };
+        let volume = level_order_volume(&level.price, side, &conf.volume_per_level);
+        let (my_coin, other_coin, exposure, max_exposure) = match side {
+            GridSide::Sell => (&base_coin, &rel_coin, &mut base_exposure, &conf.max_base_exposure),
+            GridSide::Buy => (&rel_coin, &base_coin, &mut rel_exposure, &conf.max_rel_exposure),
+        };
+        let new_exposure = &*exposure + &volume;
+        if let Some(max_exposure) = max_exposure {
+            if new_exposure > *max_exposure {
+                continue;
+            }
+        }
+
+        let price = level_order_price(&level.price, side);
+        match place_level_order(ctx, my_coin, other_coin, price, volume).await {
+            Ok(order_uuid) => {
+                level.order_uuid = Some(order_uuid);
+                *exposure = new_exposure;
+            },
+            Err(e) => {
+                warn!(
+                    "Couldn't place the {:?} order of the strategy {} at {}: {}",
+                    side,
+                    strategy.uuid,
+                    level.price.to_decimal(),
+                    e
+                );
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Cancels the strategy orders that are not being matched now, returns the cancelled and the matching ones.
+async fn cancel_strategy_orders(
+    ctx: &MmArc,
+    order_uuids: impl Iterator<Item = Uuid>,
+) -> Result<(Vec<Uuid>, Vec<Uuid>), String> {
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let mut cancelled = Vec::new();
+    let mut not_cancelled = Vec::new();
+    {
+        let mut my_maker_orders = ordermatch_ctx.my_maker_orders.lock().await;
+        for order_uuid in order_uuids {
+            match my_maker_orders.get(&order_uuid) {
+                Some(order) if order.is_cancellable() => {
+                    let order = my_maker_orders.remove(&order_uuid).expect("Order must exist");
+                    delete_my_maker_order(ctx, &order, MakerOrderCancellationReason::Cancelled);
+                    cancelled.push(order);
+                },
+                Some(_) => not_cancelled.push(order_uuid),
+                None => (),
+            }
+        }
+    }
+
+    let cancelled_uuids = cancelled.iter().map(|order| order.uuid).collect();
+    for order in cancelled {
+        maker_order_cancelled_p2p_notify(ctx.clone(), &order).await;
+    }
+    Ok((cancelled_uuids, not_cancelled))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn maintain_strategies(ctx: &MmArc) -> Result<(), String> {
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    // the strategies are not locked while the orders are placed, so the RPCs are not blocked by the network
+    let snapshot: Vec<GridStrategy> = ordermatch_ctx.grid_strategies.lock().await.values().cloned().collect();
+    for mut strategy in snapshot {
+        strategy.last_error = maintain_strategy(ctx, &mut strategy).await.err();
+
+        let mut strategies = ordermatch_ctx.grid_strategies.lock().await;
+        match strategies.get_mut(&strategy.uuid) {
+            Some(current) => {
+                save_strategy(ctx, &strategy);
+                *current = strategy;
+            },
+            None => {
+                // the strategy has been stopped meanwhile, cancel the orders placed by this maintenance
+                drop(strategies);
+                let order_uuids = strategy.levels.iter().filter_map(|level| level.order_uuid);
+                if let Err(e) = cancel_strategy_orders(ctx, order_uuids).await {
+                    error!(
+                        "Error {} on cancelling the orders of the stopped strategy {}",
+                        e, strategy.uuid
+                    );
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load_strategies(ctx: &MmArc) -> Result<Vec<GridStrategy>, String> {
+    let strategies = try_s!(json_dir_entries(&my_strategies_dir(ctx)))
+        .iter()
+        .filter_map(|entry| json::from_slice::<GridStrategy>(&slurp(&entry.path())).ok())
+        .collect();
+    Ok(strategies)
+}
+
+/// Loads the saved strategies and maintains them every `grid_strategy_interval` seconds.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn lp_grid_strategy_loop(ctx: MmArc) {
+    match load_strategies(&ctx) {
+        Ok(loaded) => match OrdermatchContext::from_ctx(&ctx) {
+            Ok(ordermatch_ctx) => {
+                let mut strategies = ordermatch_ctx.grid_strategies.lock().await;
+                strategies.extend(loaded.into_iter().map(|strategy| (strategy.uuid, strategy)));
+            },
+            Err(e) => error!("Error {} on getting the ordermatch context", e),
+        },
+        Err(e) => error!("Error {} on loading the strategies", e),
+    }
+
+    let interval = ctx.conf["grid_strategy_interval"]
+        .as_f64()
+        .unwrap_or(GRID_STRATEGY_INTERVAL);
+    loop {
+        if ctx.is_stopping() {
+            break;
+        }
+        if let Err(e) = maintain_strategies(&ctx).await {
+            error!("Error maintaining the strategies: {}", e);
+        }
+        Timer::sleep(interval).await;
+    }
+}
+
+/// Starts the grid or ladder strategy and places its initial orders.
+pub async fn start_grid_strategy(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
+    let conf: GridStrategyConf = try_s!(json::from_value(req));
+    let levels = try_s!(grid_levels(&conf));
+    for ticker in [&conf.base, &conf.rel].iter() {
+        match try_s!(lp_coinfind(&ctx, ticker).await) {
+            Some(coin) if coin.wallet_only(&ctx) => return ERR!("Coin {} is wallet only", ticker),
+            Some(_) => (),
+            None => return ERR!("Coin {} is not found", ticker),
+        }
+    }
+
+    let mut strategy = GridStrategy {
+        uuid: new_uuid(),
+        conf,
+        levels,
+        created_at: now_ms() / 1000,
+        last_error: None,
+    };
+    strategy.last_error = maintain_strategy(&ctx, &mut strategy).await.err();
+    save_strategy(&ctx, &strategy);
+
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(&ctx));
+    let res = try_s!(json::to_vec(&json!({ "result": strategy })));
+    ordermatch_ctx
+        .grid_strategies
+        .lock()
+        .await
+        .insert(strategy.uuid, strategy);
+    Ok(try_s!(Response::builder().body(res)))
+}
+
+#[derive(Deserialize)]
+struct StopGridStrategyReq {
+    uuid: Uuid,
+    #[serde(default = "get_true")]
+    cancel_orders: bool,
+}
+
+/// Stops the strategy and cancels its orders that are not being matched now.
+pub async fn stop_grid_strategy(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
+    let req: StopGridStrategyReq = try_s!(json::from_value(req));
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(&ctx));
+    let strategy = match ordermatch_ctx.grid_strategies.lock().await.remove(&req.uuid) {
+        Some(strategy) => strategy,
+        None => return ERR!("Strategy {} is not found", req.uuid),
+    };
+    try_s!(remove_file(&my_strategy_file_path(&ctx, &req.uuid)));
+
+    let (cancelled_uuids, not_cancelled) = if req.cancel_orders {
+        let order_uuids = strategy.levels.iter().filter_map(|level| level.order_uuid);
+        try_s!(cancel_strategy_orders(&ctx, order_uuids).await)
+    } else {
+        (Vec::new(), Vec::new())
+    };
+
+    let res = try_s!(json::to_vec(&json!({
+        "result": {
+            "cancelled": cancelled_uuids,
+            "currently_matching": not_cancelled,
+        }
+    })));
+    Ok(try_s!(Response::builder().body(res)))
+}
+
+#[derive(Deserialize)]
+struct GridStrategyStatusReq {
+    #[serde(default)]
+    uuid: Option<Uuid>,
+}
+
+/// Returns the state of the strategy with the `uuid` or all the running strategies.
+pub async fn grid_strategy_status(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
+    let req: GridStrategyStatusReq = try_s!(json::from_value(req));
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(&ctx));
+    let strategies = ordermatch_ctx.grid_strategies.lock().await;
+    let result: Vec<&GridStrategy> = match req.uuid {
+        Some(uuid) => match strategies.get(&uuid) {
+            Some(strategy) => vec![strategy],
+            None => return ERR!("Strategy {} is not found", uuid),
+        },
+        None => strategies.values().collect(),
+    };
+    let res = try_s!(json::to_vec(&json!({ "result": result })));
+    Ok(try_s!(Response::builder().body(res)))
+}
+
+#[cfg(test)]
+mod grid_strategy_tests {
+    use super::*;
+
+    fn conf(kind: StrategyKind, start_price: Option<MmNumber>) -> GridStrategyConf {
+        GridStrategyConf {
+            base: "RICK".into(),
+            rel: "MORTY".into(),
+            kind,
+            min_price: MmNumber::from(1),
+            max_price: MmNumber::from(2),
+            levels: 5,
+            volume_per_level: MmNumber::from(10),
+            start_price,
+            max_base_exposure: None,
+            max_rel_exposure: None,
+        }
+    }
+
+    fn prices_and_sides(levels: &[GridLevel]) -> Vec<(MmNumber, Option<GridSide>)> {
+        levels.iter().map(|level| (level.price.clone(), level.side)).collect()
+    }
+
+    #[test]
+    fn test_grid_levels() {
+        let levels = grid_levels(&conf(StrategyKind::Grid, None)).unwrap();
+        assert_eq!(prices_and_sides(&levels), vec![
+            (MmNumber::from(1), Some(GridSide::Buy)),
+            (MmNumber::from("1.25"), Some(GridSide::Buy)),
+            (MmNumber::from("1.5"), None),
+            (MmNumber::from("1.75"), Some(GridSide::Sell)),
+            (MmNumber::from(2), Some(GridSide::Sell)),
+        ]);
+
+        let levels = grid_levels(&conf(StrategyKind::Grid, Some(MmNumber::from("1.6")))).unwrap();
+        let sides: Vec<_> = levels.iter().map(|level| level.side).collect();
+        assert_eq!(sides, vec![
+            Some(GridSide::Buy),
+            Some(GridSide::Buy),
+            Some(GridSide::Buy),
+            Some(GridSide::Sell),
+            Some(GridSide::Sell),
+        ]);
+
+        let levels = grid_levels(&conf(StrategyKind::Ladder { side: GridSide::Sell }, None)).unwrap();
+        assert!(levels.iter().all(|level| level.side == Some(GridSide::Sell)));
+    }
+
+    #[test]
+    fn test_grid_levels_invalid_conf() {
+        let mut invalid = conf(StrategyKind::Grid, None);
+        invalid.levels = 1;
+        assert!(grid_levels(&invalid).is_err());
+
+        let mut invalid = conf(StrategyKind::Grid, None);
+        invalid.min_price = MmNumber::from(3);
+        assert!(grid_levels(&invalid).is_err());
+
+        let invalid = conf(StrategyKind::Grid, Some(MmNumber::from(3)));
+        assert!(grid_levels(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_opposite_level() {
+        assert_eq!(opposite_level(&StrategyKind::Grid, 3, GridSide::Sell, 5), Some(2));
+        assert_eq!(opposite_level(&StrategyKind::Grid, 0, GridSide::Sell, 5), None);
+        assert_eq!(opposite_level(&StrategyKind::Grid, 1, GridSide::Buy, 5), Some(2));
+        assert_eq!(opposite_level(&StrategyKind::Grid, 4, GridSide::Buy, 5), None);
+        let ladder = StrategyKind::Ladder { side: GridSide::Buy };
+        assert_eq!(opposite_level(&ladder, 1, GridSide::Buy, 5), None);
+    }
+
+    #[test]
+    fn test_buy_level_order() {
+        let price = MmNumber::from(4);
+        let volume = MmNumber::from(10);
+        assert_eq!(level_order_volume(&price, GridSide::Sell, &volume), MmNumber::from(10));
+        assert_eq!(level_order_price(&price, GridSide::Sell), MmNumber::from(4));
+        assert_eq!(level_order_volume(&price, GridSide::Buy, &volume), MmNumber::from(40));
+        assert_eq!(level_order_price(&price, GridSide::Buy), MmNumber::from("0.25"));
+    }
+}
@@ -0,0 +1,455 @@
+//! The market data of the pairs built from the successful swaps indexed in the stats DB:
+//! the trade tape, OHLCV candles and 24h ticker.
+
+use crate::mm2::database::stats_swaps::{select_pair_trades, StatsSwapTrade};
+use bigdecimal::BigDecimal;
+use common::log::warn;
+use common::mm_ctx::MmArc;
+use common::mm_number::MmNumber;
+use common::now_ms;
+use http::Response;
+use serde_json::{self as json, Value as Json};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+const DEFAULT_TRADE_TAPE_LIMIT: usize = 100;
+const MAX_TRADE_TAPE_LIMIT: usize = 1000;
+const DEFAULT_CANDLE_INTERVAL: u64 = 3600;
+const MIN_CANDLE_INTERVAL: u64 = 60;
+const MAX_CANDLES: u64 = 1000;
+const TICKER_PERIOD: u64 = 24 * 3600;
+
+fn default_trade_tape_limit() -> usize { DEFAULT_TRADE_TAPE_LIMIT }
+
+fn default_candle_interval() -> u64 { DEFAULT_CANDLE_INTERVAL }
+
+/// The side of the trade from the taker's point of view, `Buy` means the taker bought the base coin.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum TradeSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct PairTrade {
+    uuid: String,
+    side: TradeSide,
+    price: MmNumber,
+    base_amount: MmNumber,
+    rel_amount: MmNumber,
+    timestamp: u64,
+}
+
+impl PairTrade {
+    /// Converts the swap to the trade of the `base/rel` pair. Returns `None` if the amounts can't be parsed or are zero.
+    fn from_stats_swap(swap: StatsSwapTrade, base: &str) -> Option<PairTrade> {
+        let maker_amount: MmNumber = BigDecimal::from_str(&swap.maker_amount).ok()?.into();
+        let taker_amount: MmNumber = BigDecimal::from_str(&swap.taker_amount).ok()?.into();
+        let (side, base_amount, rel_amount) = if swap.maker_coin == base {
+            (TradeSide::Buy, maker_amount, taker_amount)
+        } else {
+            (TradeSide::Sell, taker_amount, maker_amount)
+        };
+        if base_amount.is_zero() || rel_amount.is_zero() {
+            return None;
+        }
+        Some(PairTrade {
+            uuid: swap.uuid,
+            side,
+            price: &rel_amount / &base_amount,
+            base_amount,
+            rel_amount,
+            timestamp: swap.finished_at,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PairTradeForRpc<'a> {
+    uuid: &'a str,
+    side: TradeSide,
+    price: BigDecimal,
+    base_amount: BigDecimal,
+    rel_amount: BigDecimal,
+    timestamp: u64,
+}
+
+impl<'a> From<&'a PairTrade> for PairTradeForRpc<'a> {
+    fn from(trade: &'a PairTrade) -> Self {
+        PairTradeForRpc {
+            uuid: &trade.uuid,
+            side: trade.side,
+            price: trade.price.to_decimal(),
+            base_amount: trade.base_amount.to_decimal(),
+            rel_amount: trade.rel_amount.to_decimal(),
+            timestamp: trade.timestamp,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Candle {
+    timestamp: u64,
+    open: MmNumber,
+    high: MmNumber,
+    low: MmNumber,
+    close: MmNumber,
+    base_volume: MmNumber,
+    rel_volume: MmNumber,
+    trades_count: usize,
+}
+
+impl Candle {
+    fn new(timestamp: u64, trade: &PairTrade) -> Candle {
+        Candle {
+            timestamp,
+            open: trade.price.clone(),
+            high: trade.price.clone(),
+            low: trade.price.clone(),
+            close: trade.price.clone(),
+            base_volume: trade.base_amount.clone(),
+            rel_volume: trade.rel_amount.clone(),
+            trades_count: 1,
+        }
+    }
+
+    /// Expects the trades to be applied in the chronological order.
+    fn apply(&mut self, trade: &PairTrade) {
+        if trade.price > self.high {
+            self.high = trade.price.clone();
+        }
+        if trade.price < self.low {
+            self.low = trade.price.clone();
+        }
+        self.close = trade.price.clone();
+        self.base_volume += &trade.base_amount;
+        self.rel_volume += &trade.rel_amount;
+        self.trades_count += 1;
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CandleForRpc {
+    timestamp: u64,
+    open: BigDecimal,
+    high: BigDecimal,
+    low: BigDecimal,
+    close: BigDecimal,
+    base_volume: BigDecimal,
+    rel_volume: BigDecimal,
+    trades_count: usize,
+}
+
+impl From<&Candle> for CandleForRpc {
+    fn from(candle: &Candle) -> Self {
+        CandleForRpc {
+            timestamp: candle.timestamp,
+            open: candle.open.to_decimal(),
+            high: candle.high.to_decimal(),
+            low: candle.low.to_decimal(),
+            close: candle.close.to_decimal(),
+            base_volume: candle.base_volume.to_decimal(),
+            rel_volume: candle.rel_volume.to_decimal(),
+            trades_count: candle.trades_count,
+        }
+    }
+}
+
+/// Groups the trades to the candles of `interval` seconds, the candles without trades are omitted.
+/// Expects the trades to be sorted in the chronological order.
+fn build_candles(trades: &[PairTrade], interval: u64) -> Vec<Candle> {
+    let mut candles: BTreeMap<u64, Candle> = BTreeMap::new();
+    for trade in trades {
+        let timestamp = trade.timestamp - trade.timestamp % interval;
+        candles
+            .entry(timestamp)
+            .and_modify(|candle| candle.apply(trade))
+            .or_insert_with(|| Candle::new(timestamp, trade));
+    }
+    candles.into_iter().map(|(_, candle)| candle).collect()
+}
+
+#[derive(Debug, Serialize)]
+struct Ticker {
+    base: String,
+    rel: String,
+    last_price: Option<BigDecimal>,
+    open_price: Option<BigDecimal>,
+    high_price: Option<BigDecimal>,
+    low_price: Option<BigDecimal>,
+    /// The change of the last price relatively to the open price, in percents
+    price_change_percent: Option<BigDecimal>,
+    base_volume: BigDecimal,
+    rel_volume: BigDecimal,
+    trades_count: usize,
+}
+
+/// Builds the ticker of the trades sorted in the chronological order.
+fn build_ticker(base: String, rel: String, trades: &[PairTrade]) -> Ticker {
+    let candle = trades.split_first().map(|(first, rest)| {
+        let mut candle = Candle::new(first.timestamp, first);
+        for trade in rest {
+            candle.apply(trade);
+        }
+        candle
+    });
+    match candle {
+        Some(candle) => {
+            let change = (&candle.close - &candle.open) / candle.open.clone() * MmNumber::from(100);
+            Ticker {
+                base,
+                rel,
+                last_price: Some(candle.close.to_decimal()),
+                open_price: Some(candle.open.to_decimal()),
+                high_price: Some(candle.high.to_decimal()),
+                low_price: Some(candle.low.to_decimal()),
+                price_change_percent: Some(change.to_decimal()),
+                base_volume: candle.base_volume.to_decimal(),
+                rel_volume: candle.rel_volume.to_decimal(),
+                trades_count: candle.trades_count,
+            }
+        },
+        None => Ticker {
+            base,
+            rel,
+            last_price: None,
+            open_price: None,
+            high_price: None,
+            low_price: None,
+            price_change_percent: None,
+            base_volume: BigDecimal::from(0),
+            rel_volume: BigDecimal::from(0),
+            trades_count: 0,
+        },
+    }
+}
+
+/// Selects the trades of the pair in the chronological order.
+fn pair_trades(
+    ctx: &MmArc,
+    base: &str,
+    rel: &str,
+    from: u64,
+    to: u64,
+    limit: Option<usize>,
+) -> Result<Vec<PairTrade>, String> {
+    let swaps = try_s!(select_pair_trades(&ctx.sqlite_connection(), base, rel, from, to, limit));
+    let mut trades: Vec<_> = swaps
+        .into_iter()
+        .filter_map(|swap| {
+            let uuid = swap.uuid.clone();
+            let trade = PairTrade::from_stats_swap(swap, base);
+            if trade.is_none() {
+                warn!("Swap {} has invalid amounts, skipping", uuid);
+            }
+            trade
+        })
+        .collect();
+    trades.reverse();
+    Ok(trades)
+}
+
+#[derive(Debug, Deserialize)]
+struct TradeTapeReq {
+    base: String,
+    rel: String,
+    #[serde(default)]
+    from_timestamp: u64,
+    to_timestamp: Option<u64>,
+    #[serde(default = "default_trade_tape_limit")]
+    limit: usize,
+}
+
+/// Returns the most recent trades of the pair, the newest first.
+pub async fn trade_tape(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
+    let req: TradeTapeReq = try_s!(json::from_value(req));
+    if req.base == req.rel {
+        return ERR!("Base and rel must be different coins");
+    }
+    if req.limit > MAX_TRADE_TAPE_LIMIT {
+        return ERR!("The limit {} exceeds the max {}", req.limit, MAX_TRADE_TAPE_LIMIT);
+    }
+    let to = req.to_timestamp.unwrap_or_else(|| now_ms() / 1000 + 1);
+    let trades = try_s!(pair_trades(
+        &ctx,
+        &req.base,
+        &req.rel,
+        req.from_timestamp,
+        to,
+        Some(req.limit)
+    ));
+    let trades: Vec<PairTradeForRpc> = trades.iter().rev().map(PairTradeForRpc::from).collect();
+    let res = json!({
+        "result": {
+            "base": req.base,
+            "rel": req.rel,
+            "trades": trades,
+        }
+    });
+    Response::builder()
+        .body(json::to_vec(&res).expect("Serialization failed"))
+        .map_err(|e| ERRL!("{}", e))
+}
+
+#[derive(Debug, Deserialize)]
+struct OhlcvReq {
+    base: String,
+    rel: String,
+    /// The candle duration in seconds
+    #[serde(default = "default_candle_interval")]
+    interval: u64,
+    from_timestamp: Option<u64>,
+    to_timestamp: Option<u64>,
+}
+
+/// Returns the OHLCV candles of the pair in the chronological order.
+/// The default range is the last 100 candles.
+pub async fn ohlcv(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
+    let req: OhlcvReq = try_s!(json::from_value(req));
+    if req.base == req.rel {
+        return ERR!("Base and rel must be different coins");
+    }
+    if req.interval < MIN_CANDLE_INTERVAL {
+        return ERR!(
+            "The interval {} is less than the min {}",
+            req.interval,
+            MIN_CANDLE_INTERVAL
+        );
+    }
+    let to = req.to_timestamp.unwrap_or_else(|| now_ms() / 1000 + 1);
+    let from = req
+        .from_timestamp
+        .unwrap_or_else(|| to.saturating_sub(req.interval.saturating_mul(100)));
+    // align the range start to the candle start
+    let from = from - from % req.interval;
+    if from >= to {
+        return ERR!("from_timestamp {} must be less than to_timestamp {}", from, to);
+    }
+    if (to - from) / req.interval > MAX_CANDLES {
+        return ERR!("The range exceeds the max {} candles", MAX_CANDLES);
+    }
+
+    let trades = try_s!(pair_trades(&ctx, &req.base, &req.rel, from, to, None));
+    let candles: Vec<CandleForRpc> = build_candles(&trades, req.interval)
+        .iter()
+        .map(CandleForRpc::from)
+        .collect();
+    let res = json!({
+        "result": {
+            "base": req.base,
+            "rel": req.rel,
+            "interval": req.interval,
+            "candles": candles,
+        }
+    });
+    Response::builder()
+        .body(json::to_vec(&res).expect("Serialization failed"))
+        .map_err(|e| ERRL!("{}", e))
+}
+
+#[derive(Debug, Deserialize)]
+struct Ticker24hReq {
+    pairs: Vec<(String, String)>,
+}
+
+/// Returns the 24h statistics of the requested pairs.
+pub async fn ticker_24h(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
+    let req: Ticker24hReq = try_s!(json::from_value(req));
+    let to = now_ms() / 1000 + 1;
+    let from = to.saturating_sub(TICKER_PERIOD);
+    let mut tickers = Vec::with_capacity(req.pairs.len());
+    for (base, rel) in req.pairs {
+        if base == rel {
+            return ERR!("Base and rel must be different coins, got {}/{}", base, rel);
+        }
+        let trades = try_s!(pair_trades(&ctx, &base, &rel, from, to, None));
+        tickers.push(build_ticker(base, rel, &trades));
+    }
+    let res = json!({ "result": tickers });
+    Response::builder()
+        .body(json::to_vec(&res).expect("Serialization failed"))
+        .map_err(|e| ERRL!("{}", e))
+}
+
+#[cfg(test)]
+mod market_data_tests {
+    use super::*;
+
+    fn stats_swap(
+        maker_coin: &str,
+        taker_coin: &str,
+        maker_amount: &str,
+        taker_amount: &str,
+        ts: u64,
+    ) -> StatsSwapTrade {
+        StatsSwapTrade {
+            uuid: ts.to_string(),
+            maker_coin: maker_coin.into(),
+            taker_coin: taker_coin.into(),
+            maker_amount: maker_amount.into(),
+            taker_amount: taker_amount.into(),
+            finished_at: ts,
+        }
+    }
+
+    fn trades() -> Vec<PairTrade> {
+        vec![
+            stats_swap("RICK", "MORTY", "1", "2", 100),
+            stats_swap("MORTY", "RICK", "3", "1", 150),
+            stats_swap("RICK", "MORTY", "2", "3", 200),
+            stats_swap("RICK", "MORTY", "0", "3", 210),
+            stats_swap("RICK", "MORTY", "1", "2.5", 250),
+        ]
+        .into_iter()
+        .filter_map(|swap| PairTrade::from_stats_swap(swap, "RICK"))
+        .collect()
+    }
+
+    #[test]
+    fn test_pair_trade_from_stats_swap() {
+        let trades = trades();
+        assert_eq!(trades.len(), 4);
+        assert_eq!(trades[0].side, TradeSide::Buy);
+        assert_eq!(trades[0].price, MmNumber::from(2));
+        assert_eq!(trades[1].side, TradeSide::Sell);
+        assert_eq!(trades[1].price, MmNumber::from(3));
+        assert_eq!(trades[1].base_amount, MmNumber::from(1));
+        assert_eq!(trades[1].rel_amount, MmNumber::from(3));
+    }
+
+    #[test]
+    fn test_build_candles() {
+        let candles = build_candles(&trades(), 120);
+        assert_eq!(candles.len(), 2);
+
+        assert_eq!(candles[0].timestamp, 0);
+        assert_eq!(candles[0].open, MmNumber::from(2));
+        assert_eq!(candles[0].close, MmNumber::from(2));
+        assert_eq!(candles[0].trades_count, 1);
+
+        assert_eq!(candles[1].timestamp, 120);
+        assert_eq!(candles[1].open, MmNumber::from(3));
+        assert_eq!(candles[1].high, MmNumber::from(3));
+        assert_eq!(candles[1].low, MmNumber::from("1.5"));
+        assert_eq!(candles[1].close, MmNumber::from("2.5"));
+        assert_eq!(candles[1].base_volume, MmNumber::from(4));
+        assert_eq!(candles[1].rel_volume, MmNumber::from("8.5"));
+        assert_eq!(candles[1].trades_count, 3);
+    }
+
+    #[test]
+    fn test_build_ticker() {
+        let ticker = build_ticker("RICK".into(), "MORTY".into(), &trades());
+        assert_eq!(ticker.last_price, Some(MmNumber::from("2.5").to_decimal()));
+        assert_eq!(ticker.open_price, Some(MmNumber::from(2).to_decimal()));
+        assert_eq!(ticker.high_price, Some(MmNumber::from(3).to_decimal()));
+        assert_eq!(ticker.low_price, Some(MmNumber::from("1.5").to_decimal()));
+        assert_eq!(ticker.price_change_percent, Some(MmNumber::from(25).to_decimal()));
+        assert_eq!(ticker.trades_count, 4);
+
+        let empty = build_ticker("RICK".into(), "MORTY".into(), &[]);
+        assert_eq!(empty.last_price, None);
+        assert_eq!(empty.trades_count, 0);
+    }
+}
@@ -0,0 +1,525 @@
+//! Taker orders filled across several maker orders.
+//!
+//! The order walks the orderbook of the pair from the best price and splits its volume into the child taker orders
+//! targeting the particular maker orders with `MatchBy::Orders`. Every child has its own uuid, so the resulting swaps
+//! are independent and run in parallel. The makers are selected while their price deviates from the best one
+//! by no more than `max_slippage` and doesn't exceed the price limit of the order.
+//! The order is removed once all its children are matched or cancelled, or when it's cancelled as a whole.
+
+use super::{broadcast_ordermatch_message, save_my_new_taker_order, subscribe_to_orderbook_topic,
+            taker_order_conf_settings, AutoBuyInput, MatchBy, OrderType, OrdermatchContext, TakerAction, TakerOrder,
+            TakerOrderBuilder};
+#[cfg(not(target_arch = "wasm32"))]
+use super::{delete_my_taker_order, TakerOrderCancellationReason};
+use coins::MmCoinEnum;
+use common::mm_ctx::MmArc;
+use common::mm_number::MmNumber;
+use common::{new_uuid, now_ms, write};
+use rpc::v1::types::H256 as H256Json;
+use serde_json as json;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+#[cfg(not(target_arch = "wasm32"))] use common::log::error;
+#[cfg(not(target_arch = "wasm32"))] use common::read_dir;
+#[cfg(not(target_arch = "wasm32"))] use gstuff::slurp;
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::hash_map::{Entry, HashMap};
+
+/// The interval in milliseconds the finished multi-maker orders are removed with.
+#[cfg(not(target_arch = "wasm32"))]
+pub const MULTI_MAKER_CLEANUP_INTERVAL: u64 = 30000;
+
+/// The maker order as seen from the taker side of the pair.
+#[derive(Clone, Debug, PartialEq)]
+struct MakerQuote {
+    uuid: Uuid,
+    /// The price in the rel coin per 1 base coin.
+    price: MmNumber,
+    /// The max volume in the base coin.
+    max_base_vol: MmNumber,
+    /// The min volume in the base coin.
+    min_base_vol: MmNumber,
+}
+
+/// The part of the order filled by the particular maker order.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+struct MultiMakerChild {
+    /// The uuid of the child taker order and the resulting swap.
+    uuid: Uuid,
+    maker_order_uuid: Uuid,
+    base_amount: MmNumber,
+    rel_amount: MmNumber,
+    price: MmNumber,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct MultiMakerOrder {
+    uuid: Uuid,
+    base: String,
+    rel: String,
+    action: TakerAction,
+    volume: MmNumber,
+    /// The worst price acceptable by the order.
+    price_limit: MmNumber,
+    created_at: u64,
+    children: Vec<MultiMakerChild>,
+}
+
+fn my_multi_maker_orders_dir(ctx: &MmArc) -> PathBuf { ctx.dbdir().join("ORDERS").join("MY").join("MULTI_MAKER") }
+
+fn my_multi_maker_order_file_path(ctx: &MmArc, uuid: &Uuid) -> PathBuf {
+    my_multi_maker_orders_dir(ctx).join(format!("{}.json", uuid))
+}
+
+/// The slippage is relative to the best price, so it can't be negative,
+/// and the price of the sold coin can't drop more than to zero.
+fn validate_max_slippage(action: &TakerAction, max_slippage: &MmNumber) -> Result<(), String> {
+    if *max_slippage < MmNumber::from(0) {
+        return ERR!("'max_slippage' {} can't be negative", max_slippage.to_decimal());
+    }
+    if matches!(action, TakerAction::Sell) && *max_slippage > MmNumber::from(1) {
+        return ERR!(
+            "'max_slippage' {} can't exceed 1 for the sell order",
+            max_slippage.to_decimal()
+        );
+    }
+    Ok(())
+}
+
+/// Returns the worst price within the `max_slippage` from the `best_price` and the `price_limit`.
+fn worst_acceptable_price(
+    action: &TakerAction,
+    best_price: &MmNumber,
+    price_limit: &MmNumber,
+    max_slippage: &MmNumber,
+) -> MmNumber {
+    let one = MmNumber::from(1);
+    match action {
+        TakerAction::Buy => (best_price * &(&one + max_slippage)).min(price_limit.clone()),
+        TakerAction::Sell => (best_price * &(&one - max_slippage)).max(price_limit.clone()),
+    }
+}
+
+/// Splits the `volume` across the `quotes` sorted from the best price, returns the maker order uuid,
+/// base amount and price of every fill.
+fn plan_fills(
+    action: &TakerAction,
+    quotes: &[MakerQuote],
+    volume: &MmNumber,
+    price_limit: &MmNumber,
+    max_slippage: &MmNumber,
+) -> Result<Vec<(Uuid, MmNumber, MmNumber)>, String> {
+    let best_price = match quotes.first() {
+        Some(quote) => &quote.price,
+        None => return ERR!("There are no maker orders for the pair"),
+    };
+    let worst_price = worst_acceptable_price(action, best_price, price_limit, max_slippage);
+
+    let zero = MmNumber::from(0);
+    let mut remaining = volume.clone();
+    let mut fills = Vec::new();
+    for quote in quotes {
+        if remaining == zero {
+            break;
+        }
+        let acceptable = match action {
+            TakerAction::Buy => quote.price <= worst_price,
+            TakerAction::Sell => quote.price >= worst_price,
+        };
+        if !acceptable {
+            break;
+        }
+        let amount = remaining.clone().min(quote.max_base_vol.clone());
+        if amount < quote.min_base_vol {
+            continue;
+        }
+        remaining = &remaining - &amount;
+        fills.push((quote.uuid, amount, quote.price.clone()));
+    }
+
+    if remaining > zero {
+        return ERR!(
+            "Not enough liquidity up to the price {}: {} of {} can be filled",
+            worst_price.to_decimal(),
+            (volume - &remaining).to_decimal(),
+            volume.to_decimal()
+        );
+    }
+    Ok(fills)
+}
+
+/// Collects the maker orders of the pair from the local orderbook sorted from the best price for the taker.
+async fn maker_quotes(ctx: &MmArc, base: &str, rel: &str, action: &TakerAction) -> Result<Vec<MakerQuote>, String> {
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let request_orderbook = true;
+    try_s!(subscribe_to_orderbook_topic(ctx, base, rel, request_orderbook).await);
+
+    let my_pubsecp = hex::encode(&**ctx.secp256k1_key_pair().public());
+    let orderbook = ordermatch_ctx.orderbook.lock().await;
+    // the asks are sold for the rel coin, the bids are the orders of the reversed pair
+    let pair = match action {
+        TakerAction::Buy => (base.to_owned(), rel.to_owned()),
+        TakerAction::Sell => (rel.to_owned(), base.to_owned()),
+    };
+    let ordered = match orderbook.ordered.get(&pair) {
+        Some(ordered) => ordered,
+        None => return Ok(Vec::new()),
+    };
+
+    let quotes = ordered
+        .iter()
+        .filter_map(|ordered| orderbook.order_set.get(&ordered.uuid))
+        .filter(|order| order.pubkey != my_pubsecp)
+        .map(|order| {
+            let price = MmNumber::from(order.price.clone());
+            let max_volume = MmNumber::from(order.max_volume.clone());
+            let min_volume = MmNumber::from(order.min_volume.clone());
+            match action {
+                TakerAction::Buy => MakerQuote {
+                    uuid: order.uuid,
+                    price,
+                    max_base_vol: max_volume,
+                    min_base_vol: min_volume,
+                },
+                // the bid volumes are in the rel coin and the price is in the base coin per 1 rel coin
+                TakerAction::Sell => MakerQuote {
+                    uuid: order.uuid,
+                    max_base_vol: &max_volume * &price,
+                    min_base_vol: &min_volume * &price,
+                    price: MmNumber::from(1) / price,
+                },
+            }
+        })
+        .collect();
+    Ok(quotes)
+}
+
+/// Places the child taker orders filling the `input.volume` across the best maker orders.
+pub async fn lp_multi_maker_order(
+    ctx: &MmArc,
+    base_coin: &MmCoinEnum,
+    rel_coin: &MmCoinEnum,
+    input: AutoBuyInput,
+    action: TakerAction,
+) -> Result<String, String> {
+    if !matches!(input.match_by, MatchBy::Any) {
+        return ERR!("'match_by' can't be used with 'fill_across_makers'");
+    }
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let base_orderbook_ticker = ordermatch_ctx.orderbook_ticker(base_coin.ticker());
+    let rel_orderbook_ticker = ordermatch_ctx.orderbook_ticker(rel_coin.ticker());
+    let base = ordermatch_ctx.orderbook_ticker_bypass(base_coin.ticker());
+    let rel = ordermatch_ctx.orderbook_ticker_bypass(rel_coin.ticker());
+
+    let quotes = try_s!(maker_quotes(ctx, &base, &rel, &action).await);
+    let max_slippage = input.max_slippage.clone().unwrap_or_else(|| MmNumber::from(0));
+    try_s!(validate_max_slippage(&action, &max_slippage));
+    let fills = try_s!(plan_fills(&action, &quotes, &input.volume, &input.price, &max_slippage));
+
+    let our_public_id = try_s!(ctx.public_id());
+    let mut children = Vec::with_capacity(fills.len());
+    let mut orders = Vec::with_capacity(fills.len());
+    for (maker_order_uuid, base_amount, price) in fills {
+        let rel_amount = &base_amount * &price;
+        let conf_settings =
+            taker_order_conf_settings(ctx, base_coin, rel_coin, &input, &action, &base_amount, &rel_amount);
+        let match_by = MatchBy::Orders(std::iter::once(maker_order_uuid).collect::<HashSet<_>>());
+        let mut order_builder = TakerOrderBuilder::new(base_coin, rel_coin)
+            .with_base_amount(base_amount.clone())
+            .with_rel_amount(rel_amount.clone())
+            .with_action(action.clone())
+            .with_match_by(match_by)
+            // the child order targets the particular maker order, so it must not become a maker order itself
+            .with_order_type(OrderType::FillOrKill)
+            .with_conf_settings(conf_settings)
+            .with_sender_pubkey(H256Json::from(our_public_id.bytes))
+            .with_save_in_history(input.save_in_history)
+            .with_base_orderbook_ticker(base_orderbook_ticker.clone())
+            .with_rel_orderbook_ticker(rel_orderbook_ticker.clone());
+        if let Some(timeout) = input.timeout {
+            order_builder = order_builder.with_timeout(timeout);
+        }
+        let order: TakerOrder = try_s!(order_builder.build());
+        children.push(MultiMakerChild {
+            uuid: order.request.uuid,
+            maker_order_uuid,
+            base_amount,
+            rel_amount,
+            price,
+        });
+        orders.push(order);
+    }
+
+    let multi_maker_order = MultiMakerOrder {
+        uuid: new_uuid(),
+        base: input.base.clone(),
+        rel: input.rel.clone(),
+        action,
+        volume: input.volume.clone(),
+        price_limit: input.price.clone(),
+        created_at: now_ms(),
+        children,
+    };
+    // the order is saved under the lock, so it's not removed as finished before the children are placed
+    let mut my_taker_orders = ordermatch_ctx.my_taker_orders.lock().await;
+    let path = my_multi_maker_order_file_path(ctx, &multi_maker_order.uuid);
+    try_s!(write(&path, &try_s!(json::to_vec(&multi_maker_order))));
+    for order in orders {
+        broadcast_ordermatch_message(ctx, vec![order.orderbook_topic()], order.clone().into());
+        save_my_new_taker_order(ctx, &order);
+        my_taker_orders.insert(order.request.uuid, order);
+    }
+
+    Ok(json!({ "result": multi_maker_order }).to_string())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Serialize)]
+struct MultiMakerChildStatus<'a> {
+    #[serde(flatten)]
+    child: &'a MultiMakerChild,
+    /// `Pending` and `Matched` for the active child orders, the cancellation reason of the finished ones.
+    state: String,
+}
+
+/// Returns the aggregated status of the multi-maker order if the `uuid` is of such order.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn multi_maker_order_status(ctx: &MmArc, uuid: &Uuid) -> Result<Option<json::Value>, String> {
+    use crate::mm2::database::my_orders::select_status_by_uuid;
+
+    let order = match try_s!(load_multi_maker_order(&my_multi_maker_order_file_path(ctx, uuid))) {
+        Some(order) => order,
+        None => return Ok(None),
+    };
+
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let my_taker_orders = ordermatch_ctx.my_taker_orders.lock().await;
+    let mut fulfilled_base_amount = MmNumber::from(0);
+    let mut pending_base_amount = MmNumber::from(0);
+    let mut children = Vec::with_capacity(order.children.len());
+    for child in order.children.iter() {
+        let state = match my_taker_orders.get(&child.uuid) {
+            Some(child_order) if child_order.matches.is_empty() => "Pending".to_owned(),
+            Some(_) => "Matched".to_owned(),
+            None => match select_status_by_uuid(&ctx.sqlite_connection(), &child.uuid) {
+                Ok(status) => status,
+                Err(e) => {
+                    error!("Error {} getting the status of the order {}", e, child.uuid);
+                    "Unknown".to_owned()
+                },
+            },
+        };
+        match state.as_str() {
+            "Pending" | "Matched" => pending_base_amount = &pending_base_amount + &child.base_amount,
+            "Fulfilled" => fulfilled_base_amount = &fulfilled_base_amount + &child.base_amount,
+            _ => (),
+        }
+        children.push(MultiMakerChildStatus { child, state });
+    }
+
+    Ok(Some(json!({
+        "type": "MultiMaker",
+        "order": order,
+        "children": children,
+        "fulfilled_base_amount": fulfilled_base_amount.to_decimal(),
+        "pending_base_amount": pending_base_amount.to_decimal(),
+    })))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn load_multi_maker_order(path: &Path) -> Result<Option<MultiMakerOrder>, String> {
+    let content = slurp(&path);
+    if content.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(try_s!(json::from_slice(&content))))
+}
+
+/// Cancels the child orders of the multi-maker order that are not being matched now.
+/// The order is removed if all its children are cancelled, returns the children being matched
+/// or `None` if the `uuid` is not of a multi-maker order.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn cancel_multi_maker_order(ctx: &MmArc, uuid: &Uuid) -> Result<Option<Vec<Uuid>>, String> {
+    let path = my_multi_maker_order_file_path(ctx, uuid);
+    let order = match try_s!(load_multi_maker_order(&path)) {
+        Some(order) => order,
+        None => return Ok(None),
+    };
+
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let mut my_taker_orders = ordermatch_ctx.my_taker_orders.lock().await;
+    let mut currently_matching = Vec::new();
+    for child in order.children.iter() {
+        match my_taker_orders.entry(child.uuid) {
+            Entry::Occupied(entry) if entry.get().is_cancellable() => {
+                let child_order = entry.remove();
+                delete_my_taker_order(ctx, &child_order, TakerOrderCancellationReason::Cancelled);
+            },
+            Entry::Occupied(_) => currently_matching.push(child.uuid),
+            Entry::Vacant(_) => (),
+        }
+    }
+    if currently_matching.is_empty() {
+        try_s!(std::fs::remove_file(&path));
+    }
+    Ok(Some(currently_matching))
+}
+
+/// Removes the multi-maker orders whose children are all matched or cancelled.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn remove_finished_multi_maker_orders(ctx: &MmArc, my_taker_orders: &HashMap<Uuid, TakerOrder>) {
+    let entries = match read_dir(&my_multi_maker_orders_dir(ctx)) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Error {} reading the multi-maker orders", e);
+            return;
+        },
+    };
+    for (_, path) in entries {
+        let order = match load_multi_maker_order(&path) {
+            Ok(Some(order)) => order,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Error {} loading the multi-maker order {}", e, path.display());
+                continue;
+            },
+        };
+        if order
+            .children
+            .iter()
+            .all(|child| !my_taker_orders.contains_key(&child.uuid))
+        {
+            if let Err(e) = std::fs::remove_file(&path) {
+                error!("Error {} removing the multi-maker order {}", e, order.uuid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod multi_maker_tests {
+    use super::*;
+
+    fn quote(price: &'static str, max_base_vol: &'static str, min_base_vol: &'static str) -> MakerQuote {
+        MakerQuote {
+            uuid: new_uuid(),
+            price: MmNumber::from(price),
+            max_base_vol: MmNumber::from(max_base_vol),
+            min_base_vol: MmNumber::from(min_base_vol),
+        }
+    }
+
+    fn amounts(fills: &[(Uuid, MmNumber, MmNumber)]) -> Vec<(MmNumber, MmNumber)> {
+        fills
+            .iter()
+            .map(|(_, amount, price)| (amount.clone(), price.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_fills_buy_across_makers() {
+        let quotes = vec![
+            quote("1", "2", "0.1"),
+            quote("1.005", "2", "0.1"),
+            quote("1.02", "10", "0.1"),
+        ];
+        let fills = plan_fills(
+            &TakerAction::Buy,
+            &quotes,
+            &MmNumber::from(3),
+            &MmNumber::from(2),
+            &MmNumber::from("0.01"),
+        )
+        .unwrap();
+        let expected = vec![
+            (MmNumber::from(2), MmNumber::from(1)),
+            (MmNumber::from(1), MmNumber::from("1.005")),
+        ];
+        assert_eq!(amounts(&fills), expected);
+        assert_eq!(fills[0].0, quotes[0].uuid);
+        assert_eq!(fills[1].0, quotes[1].uuid);
+
+        // the third maker is out of the slippage bound
+        let err = plan_fills(
+            &TakerAction::Buy,
+            &quotes,
+            &MmNumber::from(5),
+            &MmNumber::from(2),
+            &MmNumber::from("0.01"),
+        )
+        .unwrap_err();
+        assert!(err.contains("Not enough liquidity"));
+
+        // the price limit is stricter than the slippage bound
+        plan_fills(
+            &TakerAction::Buy,
+            &quotes,
+            &MmNumber::from(3),
+            &MmNumber::from(1),
+            &MmNumber::from("0.01"),
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_plan_fills_skips_maker_min_volume() {
+        let quotes = vec![quote("1", "1", "0.1"), quote("1", "5", "1"), quote("1", "5", "0.1")];
+        let fills = plan_fills(
+            &TakerAction::Buy,
+            &quotes,
+            &MmNumber::from("1.5"),
+            &MmNumber::from(1),
+            &MmNumber::from(0),
+        )
+        .unwrap();
+        assert_eq!(fills.len(), 2);
+        assert_eq!(fills[0].0, quotes[0].uuid);
+        assert_eq!(fills[1].0, quotes[2].uuid);
+        assert_eq!(fills[1].1, MmNumber::from("0.5"));
+    }
+
+    #[test]
+    fn test_validate_max_slippage() {
+        validate_max_slippage(&TakerAction::Buy, &MmNumber::from("0.01")).unwrap();
+        validate_max_slippage(&TakerAction::Buy, &MmNumber::from(2)).unwrap();
+        validate_max_slippage(&TakerAction::Sell, &MmNumber::from(1)).unwrap();
+        validate_max_slippage(&TakerAction::Buy, &MmNumber::from("-0.01")).unwrap_err();
+        validate_max_slippage(&TakerAction::Sell, &MmNumber::from("-0.01")).unwrap_err();
+        validate_max_slippage(&TakerAction::Sell, &MmNumber::from("1.01")).unwrap_err();
+    }
+
+    #[test]
+    fn test_plan_fills_sell() {
+        // the bids are sorted from the highest price
+        let quotes = vec![
+            quote("2", "1", "0.1"),
+            quote("1.99", "1", "0.1"),
+            quote("1.9", "1", "0.1"),
+        ];
+        let fills = plan_fills(
+            &TakerAction::Sell,
+            &quotes,
+            &MmNumber::from("1.5"),
+            &MmNumber::from("1.5"),
+            &MmNumber::from("0.01"),
+        )
+        .unwrap();
+        let expected = vec![
+            (MmNumber::from(1), MmNumber::from(2)),
+            (MmNumber::from("0.5"), MmNumber::from("1.99")),
+        ];
+        assert_eq!(amounts(&fills), expected);
+
+        let err = plan_fills(
+            &TakerAction::Sell,
+            &quotes,
+            &MmNumber::from("2.5"),
+            &MmNumber::from("1.5"),
+            &MmNumber::from("0.01"),
+        )
+        .unwrap_err();
+        assert!(err.contains("Not enough liquidity"));
+    }
+}
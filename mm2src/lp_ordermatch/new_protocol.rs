@@ -2,6 +2,7 @@ use super::{MatchBy as SuperMatchBy, TakerAction};
 use crate::mm2::lp_ordermatch::{AlbOrderedOrderbookPair, OrderConfirmationsSettings, H64};
 use common::{mm_number::MmNumber, now_ms};
 use compact_uuid::CompactUuid;
+use mm2_libp2p::encode_message;
 use num_rational::BigRational;
 use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
@@ -17,6 +18,7 @@ pub enum OrdermatchMessage {
     MakerReserved(MakerReserved),
     TakerConnect(TakerConnect),
     MakerConnected(MakerConnected),
+    MakerConnectRefused(MakerConnectRefused),
 }
 
 impl From<PubkeyKeepAlive> for OrdermatchMessage {
@@ -126,6 +128,52 @@ pub struct MakerOrderCreated {
     pub base_protocol_info: Vec<u8>,
     #[serde(default)]
     pub rel_protocol_info: Vec<u8>,
+    /// The timestamp the order expires at, peers drop the order from the orderbook after it
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// The sequence number of the order message, increases with every message of the maker
+    #[serde(default)]
+    pub nonce: u64,
+    /// The maker signature of the `signing_payload`, empty if the message is sent by an older node
+    #[serde(default)]
+    pub signature: Vec<u8>,
+}
+
+impl MakerOrderCreated {
+    pub fn signing_payload(&self) -> Vec<u8> {
+        SignedOrderState {
+            uuid: self.uuid,
+            base: &self.base,
+            rel: &self.rel,
+            price: &self.price,
+            max_volume: &self.max_volume,
+            min_volume: &self.min_volume,
+            created_at: self.created_at,
+            nonce: self.nonce,
+        }
+        .signing_payload()
+    }
+}
+
+/// The order state signed by the maker on every order message, so the order stored in the orderbook
+/// can be verified independently of the transport by anyone knowing the maker pubkey.
+/// It consists of the trie state fields, so the peers which state has diverged resync the order by the trie.
+#[derive(Serialize)]
+pub struct SignedOrderState<'a> {
+    pub uuid: CompactUuid,
+    pub base: &'a str,
+    pub rel: &'a str,
+    pub price: &'a BigRational,
+    pub max_volume: &'a BigRational,
+    pub min_volume: &'a BigRational,
+    pub created_at: u64,
+    pub nonce: u64,
+}
+
+impl SignedOrderState<'_> {
+    pub fn signing_payload(&self) -> Vec<u8> {
+        encode_message(&("order", self)).expect("Serialization should never fail")
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -139,6 +187,16 @@ pub struct MakerOrderCancelled {
     pub uuid: CompactUuid,
     pub timestamp: u64,
     pub pair_trie_root: H64,
+    #[serde(default)]
+    pub nonce: u64,
+    #[serde(default)]
+    pub signature: Vec<u8>,
+}
+
+impl MakerOrderCancelled {
+    pub fn signing_payload(&self) -> Vec<u8> {
+        encode_message(&("cancelled", &self.uuid, self.nonce)).expect("Serialization should never fail")
+    }
 }
 
 #[derive(Clone, Debug, Eq, Deserialize, PartialEq, Serialize)]
@@ -162,16 +220,34 @@ pub struct MakerOrderUpdatedV2 {
     pub conf_settings: Option<OrderConfirmationsSettings>,
 }
 
+/// The update signed by the maker with the sequence number to protect the order from the replayed updates.
+/// The nodes not aware of this version drop the message, so the maker sends the unsigned `MakerOrderUpdatedV2` copy
+/// along with it.
+#[derive(Clone, Debug, Eq, Deserialize, PartialEq, Serialize)]
+pub struct MakerOrderUpdatedV3 {
+    uuid: CompactUuid,
+    pub new_price: Option<BigRational>,
+    pub new_max_volume: Option<BigRational>,
+    pub new_min_volume: Option<BigRational>,
+    timestamp: u64,
+    pair_trie_root: H64,
+    pub conf_settings: Option<OrderConfirmationsSettings>,
+    pub nonce: u64,
+    /// The maker signature of the `SignedOrderState` resulting from the update
+    pub signature: Vec<u8>,
+}
+
 #[derive(Clone, Debug, Eq, Deserialize, PartialEq, Serialize)]
 #[serde(untagged)]
 pub enum MakerOrderUpdated {
     V1(MakerOrderUpdatedV1),
     V2(MakerOrderUpdatedV2),
+    V3(MakerOrderUpdatedV3),
 }
 
 impl MakerOrderUpdated {
     pub fn new(uuid: Uuid) -> Self {
-        MakerOrderUpdated::V2(MakerOrderUpdatedV2 {
+        MakerOrderUpdated::V3(MakerOrderUpdatedV3 {
             uuid: uuid.into(),
             new_price: None,
             new_max_volume: None,
@@ -179,6 +255,8 @@ impl MakerOrderUpdated {
             conf_settings: None,
             timestamp: now_ms() / 1000,
             pair_trie_root: H64::default(),
+            nonce: 0,
+            signature: Vec::new(),
         })
     }
 
@@ -186,6 +264,7 @@ impl MakerOrderUpdated {
         match self {
             MakerOrderUpdated::V1(v1) => v1.new_price = Some(new_price),
             MakerOrderUpdated::V2(v2) => v2.new_price = Some(new_price),
+            MakerOrderUpdated::V3(v3) => v3.new_price = Some(new_price),
         }
     }
 
@@ -193,6 +272,7 @@ impl MakerOrderUpdated {
         match self {
             MakerOrderUpdated::V1(v1) => v1.new_max_volume = Some(new_max_volume),
             MakerOrderUpdated::V2(v2) => v2.new_max_volume = Some(new_max_volume),
+            MakerOrderUpdated::V3(v3) => v3.new_max_volume = Some(new_max_volume),
         }
     }
 
@@ -200,6 +280,7 @@ impl MakerOrderUpdated {
         match self {
             MakerOrderUpdated::V1(v1) => v1.new_min_volume = Some(new_min_volume),
             MakerOrderUpdated::V2(v2) => v2.new_min_volume = Some(new_min_volume),
+            MakerOrderUpdated::V3(v3) => v3.new_min_volume = Some(new_min_volume),
         }
     }
 
@@ -209,6 +290,9 @@ impl MakerOrderUpdated {
             MakerOrderUpdated::V2(v2) => {
                 v2.conf_settings = Some(conf_settings);
             },
+            MakerOrderUpdated::V3(v3) => {
+                v3.conf_settings = Some(conf_settings);
+            },
         }
     }
 
@@ -216,6 +300,7 @@ impl MakerOrderUpdated {
         match self {
             MakerOrderUpdated::V1(v1) => v1.new_price.as_ref().map(|num| num.clone().into()),
             MakerOrderUpdated::V2(v2) => v2.new_price.as_ref().map(|num| num.clone().into()),
+            MakerOrderUpdated::V3(v3) => v3.new_price.as_ref().map(|num| num.clone().into()),
         }
     }
 
@@ -223,6 +308,7 @@ impl MakerOrderUpdated {
         match self {
             MakerOrderUpdated::V1(v1) => v1.new_max_volume.as_ref().map(|num| num.clone().into()),
             MakerOrderUpdated::V2(v2) => v2.new_max_volume.as_ref().map(|num| num.clone().into()),
+            MakerOrderUpdated::V3(v3) => v3.new_max_volume.as_ref().map(|num| num.clone().into()),
         }
     }
 
@@ -230,6 +316,7 @@ impl MakerOrderUpdated {
         match self {
             MakerOrderUpdated::V1(v1) => v1.new_min_volume.as_ref().map(|num| num.clone().into()),
             MakerOrderUpdated::V2(v2) => v2.new_min_volume.as_ref().map(|num| num.clone().into()),
+            MakerOrderUpdated::V3(v3) => v3.new_min_volume.as_ref().map(|num| num.clone().into()),
         }
     }
 
@@ -237,6 +324,7 @@ impl MakerOrderUpdated {
         match self {
             MakerOrderUpdated::V1(_) => None,
             MakerOrderUpdated::V2(v2) => v2.conf_settings,
+            MakerOrderUpdated::V3(v3) => v3.conf_settings,
         }
     }
 
@@ -244,6 +332,51 @@ impl MakerOrderUpdated {
         match self {
             MakerOrderUpdated::V1(v1) => v1.uuid.into(),
             MakerOrderUpdated::V2(v2) => v2.uuid.into(),
+            MakerOrderUpdated::V3(v3) => v3.uuid.into(),
+        }
+    }
+
+    /// Returns the sequence number of the update, 0 if it's sent by an older node.
+    pub fn nonce(&self) -> u64 {
+        match self {
+            MakerOrderUpdated::V1(_) | MakerOrderUpdated::V2(_) => 0,
+            MakerOrderUpdated::V3(v3) => v3.nonce,
+        }
+    }
+
+    /// Returns the maker signature of the updated order state, empty if the update is sent by an older node.
+    pub fn signature(&self) -> &[u8] {
+        match self {
+            MakerOrderUpdated::V1(_) | MakerOrderUpdated::V2(_) => &[],
+            MakerOrderUpdated::V3(v3) => &v3.signature,
+        }
+    }
+
+    pub fn with_nonce(&mut self, nonce: u64) {
+        if let MakerOrderUpdated::V3(v3) = self {
+            v3.nonce = nonce;
+        }
+    }
+
+    pub fn with_signature(&mut self, signature: Vec<u8>) {
+        if let MakerOrderUpdated::V3(v3) = self {
+            v3.signature = signature;
+        }
+    }
+
+    /// Returns the unsigned copy of the update for the nodes not aware of `MakerOrderUpdatedV3`.
+    pub fn to_v2(&self) -> MakerOrderUpdated {
+        match self {
+            MakerOrderUpdated::V3(v3) => MakerOrderUpdated::V2(MakerOrderUpdatedV2 {
+                uuid: v3.uuid,
+                new_price: v3.new_price.clone(),
+                new_max_volume: v3.new_max_volume.clone(),
+                new_min_volume: v3.new_min_volume.clone(),
+                timestamp: v3.timestamp,
+                pair_trie_root: v3.pair_trie_root,
+                conf_settings: v3.conf_settings,
+            }),
+            _ => self.clone(),
         }
     }
 }
@@ -264,6 +397,10 @@ pub struct TakerRequest {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rel_protocol_info: Option<Vec<u8>>,
+    /// Whether the sender accepts the swap messages encrypted to its pubkey, absent if sent by an older node
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap_msg_encryption: Option<bool>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -281,6 +418,10 @@ pub struct MakerReserved {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rel_protocol_info: Option<Vec<u8>>,
+    /// Whether the sender accepts the swap messages encrypted to its pubkey, absent if sent by an older node
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap_msg_encryption: Option<bool>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -295,6 +436,15 @@ pub struct MakerConnected {
     pub maker_order_uuid: CompactUuid,
 }
 
+/// Sent by the maker instead of `MakerConnected` when the match is refused, e.g. by the risk limits.
+/// The older nodes can't decode it and keep waiting for the match to time out.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MakerConnectRefused {
+    pub taker_order_uuid: CompactUuid,
+    pub maker_order_uuid: CompactUuid,
+    pub reason: String,
+}
+
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod new_protocol_tests {
     use common::new_uuid;
@@ -378,6 +528,20 @@ mod new_protocol_tests {
         let deserialized: MakerOrderUpdated = rmp_serde::from_read_ref(serialized.as_slice()).unwrap();
 
         assert_eq!(deserialized, v2);
+
+        // signed format should be deserialized to V3
+        let mut v3 = MakerOrderUpdated::new(uuid.into());
+        v3.with_new_price(BigRational::from_integer(2.into()));
+        v3.with_nonce(1);
+        v3.with_signature(vec![1, 2, 3]);
+
+        let serialized = rmp_serde::to_vec(&v3).unwrap();
+
+        let deserialized: MakerOrderUpdated = rmp_serde::from_read_ref(serialized.as_slice()).unwrap();
+
+        assert_eq!(deserialized, v3);
+        assert_eq!(deserialized.nonce(), 1);
+        assert_eq!(deserialized.signature(), &[1, 2, 3]);
     }
 
     #[test]
@@ -417,6 +581,9 @@ mod new_protocol_tests {
 
         new.base_protocol_info = vec![1, 2, 3];
         new.rel_protocol_info = vec![1, 2, 3, 4];
+        new.expires_at = Some(1);
+        new.nonce = 1;
+        new.signature = vec![1, 2, 3];
 
         let new_serialized = rmp_serde::to_vec(&new).unwrap();
         let _old_from_new: MakerOrderCreatedV1 = rmp_serde::from_read_ref(&new_serialized).unwrap();
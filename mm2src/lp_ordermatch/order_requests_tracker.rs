@@ -1,15 +1,35 @@
-#![allow(dead_code)]
 use std::{collections::hash_map::{HashMap, RawEntryMut},
           num::NonZeroUsize,
           time::Duration};
 use wasm_timer::Instant;
 
 const ONE_SECOND: Duration = Duration::from_secs(1);
+/// The window the dropped requests are counted within to decide whether the peer should be banned
+const VIOLATIONS_WINDOW: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_VIOLATIONS: usize = 30;
+const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(300);
+
+#[derive(Debug, PartialEq)]
+pub enum PeerRequestStatus {
+    Allowed,
+    /// The request exceeds the limit and must be dropped
+    LimitReached,
+    /// The request exceeds the limit and the peer is banned from now on
+    PeerBanned,
+    /// The peer is banned, the request must be dropped
+    Banned,
+}
 
 /// Stores the timestamps of order requests sent to specific peer
 pub struct OrderRequestsTracker {
     requested_at: HashMap<String, Vec<Instant>>,
     limit_per_sec: NonZeroUsize,
+    /// The timestamps of the dropped requests of the peers
+    violations: HashMap<String, Vec<Instant>>,
+    /// The number of the dropped requests within `VIOLATIONS_WINDOW` leading to the peer ban
+    max_violations: usize,
+    banned_until: HashMap<String, Instant>,
+    ban_duration: Duration,
 }
 
 impl Default for OrderRequestsTracker {
@@ -22,7 +42,60 @@ impl OrderRequestsTracker {
         OrderRequestsTracker {
             requested_at: HashMap::new(),
             limit_per_sec,
+            violations: HashMap::new(),
+            max_violations: DEFAULT_MAX_VIOLATIONS,
+            banned_until: HashMap::new(),
+            ban_duration: DEFAULT_BAN_DURATION,
+        }
+    }
+
+    /// Bans the peer for `ban_duration` once it has `max_violations` requests dropped within a minute
+    pub fn with_ban_policy(mut self, max_violations: usize, ban_duration: Duration) -> OrderRequestsTracker {
+        self.max_violations = max_violations;
+        self.ban_duration = ban_duration;
+        self
+    }
+
+    /// Checks whether the request of the `peer` should be served and records it.
+    pub fn check_peer_request(&mut self, peer: &str) -> PeerRequestStatus {
+        let now = Instant::now();
+        match self.banned_until.get(peer) {
+            Some(until) if *until > now => return PeerRequestStatus::Banned,
+            Some(_) => {
+                self.banned_until.remove(peer);
+            },
+            None => (),
+        }
+
+        if !self.limit_reached(peer) {
+            self.peer_requested(peer);
+            return PeerRequestStatus::Allowed;
+        }
+
+        let violations = self.violations.entry(peer.to_string()).or_insert_with(Vec::new);
+        violations.retain(|dropped_at| now.duration_since(*dropped_at) < VIOLATIONS_WINDOW);
+        violations.push(now);
+        if violations.len() < self.max_violations {
+            return PeerRequestStatus::LimitReached;
         }
+
+        self.violations.remove(peer);
+        self.banned_until.insert(peer.to_string(), now + self.ban_duration);
+        PeerRequestStatus::PeerBanned
+    }
+
+    /// Forgets the peers that haven't requested anything recently and the expired bans.
+    pub fn remove_inactive_peers(&mut self) {
+        let now = Instant::now();
+        self.requested_at.retain(|_, requested| match requested.first() {
+            Some(latest) => now.duration_since(*latest) < ONE_SECOND,
+            None => false,
+        });
+        self.violations.retain(|_, violations| match violations.last() {
+            Some(last) => now.duration_since(*last) < VIOLATIONS_WINDOW,
+            None => false,
+        });
+        self.banned_until.retain(|_, until| *until > now);
     }
 
     pub fn peer_requested(&mut self, peer: &str) {
@@ -88,4 +161,20 @@ mod order_requests_tracker_tests {
 
         assert!(!tracker.limit_reached(peer));
     }
+
+    #[test]
+    fn test_check_peer_request_ban() {
+        let limit = NonZeroUsize::new(2).unwrap();
+        let mut tracker = OrderRequestsTracker::new(limit).with_ban_policy(3, Duration::from_secs(60));
+        let peer = "peer";
+        assert_eq!(tracker.check_peer_request(peer), PeerRequestStatus::Allowed);
+        assert_eq!(tracker.check_peer_request(peer), PeerRequestStatus::Allowed);
+        assert_eq!(tracker.check_peer_request(peer), PeerRequestStatus::LimitReached);
+        assert_eq!(tracker.check_peer_request(peer), PeerRequestStatus::LimitReached);
+        assert_eq!(tracker.check_peer_request(peer), PeerRequestStatus::PeerBanned);
+        assert_eq!(tracker.check_peer_request(peer), PeerRequestStatus::Banned);
+
+        // other peers are not affected
+        assert_eq!(tracker.check_peer_request("other"), PeerRequestStatus::Allowed);
+    }
 }
@@ -0,0 +1,240 @@
+//! The orderbook snapshot persisted to disk, so the restarted node doesn't wait for the full orderbook download.
+//!
+//! The snapshot keeps the orders of the other pubkeys, their latest keep alive timestamps and the pair trie diffs.
+//! The pair tries are rebuilt from the orders on load, the trie of the same orders always has the same root,
+//! so the next `PubkeyKeepAlive` of the maker either confirms the restored state or triggers the delta sync
+//! by `SyncPubkeyOrderbookState`. The pubkeys that don't send the keep alive in time are pruned by `lp_ordermatch_loop`.
+//! The restored diffs let the node keep answering `SyncPubkeyOrderbookState` with the deltas after the restart.
+
+use super::{is_pubkey_banned, pair_history_mut, AlbOrderedOrderbookPair, Orderbook, OrderbookItem,
+            OrderbookRequestingState, OrdermatchContext, TrieDiff, H64};
+use common::executor::Timer;
+use common::log::{error, info};
+use common::mm_ctx::MmArc;
+use common::{now_ms, write};
+use gstuff::slurp;
+use mm2_libp2p::{decode_message, encode_message};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// The default interval of the snapshot saving in seconds.
+const ORDERBOOK_SNAPSHOT_INTERVAL: f64 = 60.;
+/// The snapshot older than this age in seconds is ignored on load.
+const ORDERBOOK_SNAPSHOT_MAX_AGE: u64 = 600;
+
+/// The pair trie diffs by the roots they are applied to.
+type PairHistorySnapshot = Vec<(H64, TrieDiff<Uuid, OrderbookItem>)>;
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct PubkeySnapshot {
+    last_keep_alive: u64,
+    orders: Vec<OrderbookItem>,
+    #[serde(default)]
+    pair_history: HashMap<AlbOrderedOrderbookPair, PairHistorySnapshot>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct OrderbookSnapshot {
+    saved_at: u64,
+    pubkeys: HashMap<String, PubkeySnapshot>,
+    /// The topics the orderbook was requested for
+    topics: Vec<String>,
+}
+
+fn orderbook_snapshot_path(ctx: &MmArc) -> PathBuf { ctx.dbdir().join("ORDERBOOK_SNAPSHOT") }
+
+impl OrderbookSnapshot {
+    fn from_orderbook(orderbook: &Orderbook, my_pubsecp: &str) -> OrderbookSnapshot {
+        let mut pubkeys: HashMap<String, PubkeySnapshot> = orderbook
+            .pubkeys_state
+            .iter()
+            .filter(|(pubkey, _)| pubkey.as_str() != my_pubsecp)
+            .map(|(pubkey, state)| {
+                let pair_history = state
+                    .order_pairs_trie_state_history
+                    .iter()
+                    .map(|(pair, history)| {
+                        let diffs = history
+                            .get_element()
+                            .inner
+                            .iter()
+                            .map(|(root, diff)| (*root, diff.get_element().clone()))
+                            .collect();
+                        (pair.clone(), diffs)
+                    })
+                    .collect();
+                (pubkey.clone(), PubkeySnapshot {
+                    last_keep_alive: state.last_keep_alive,
+                    orders: Vec::new(),
+                    pair_history,
+                })
+            })
+            .collect();
+        for order in orderbook.order_set.values() {
+            if let Some(snapshot) = pubkeys.get_mut(&order.pubkey) {
+                snapshot.orders.push(order.clone());
+            }
+        }
+        pubkeys.retain(|_, snapshot| !snapshot.orders.is_empty());
+
+        let topics = orderbook
+            .topics_subscribed_to
+            .iter()
+            .filter(|(_, state)| **state == OrderbookRequestingState::Requested)
+            .map(|(topic, _)| topic.clone())
+            .collect();
+
+        OrderbookSnapshot {
+            saved_at: now_ms() / 1000,
+            pubkeys,
+            topics,
+        }
+    }
+
+    /// Fills the orderbook with the orders of the pubkeys that sent the keep alive not later than `max_age` ago.
+    fn restore(self, ctx: &MmArc, orderbook: &mut Orderbook, max_age: u64) -> usize {
+        let now = now_ms() / 1000;
+        let mut restored = 0;
+        for (pubkey, snapshot) in self.pubkeys {
+            if snapshot.last_keep_alive + max_age < now {
+                continue;
+            }
+            match hex::decode(&pubkey) {
+                Ok(bytes) if bytes.len() == 33 && !is_pubkey_banned(ctx, &bytes[1..].into()) => (),
+                _ => continue,
+            }
+            for order in snapshot.orders {
+                if order.pubkey != pubkey || order.is_expired(now) {
+                    continue;
+                }
+                orderbook.insert_or_update_order_update_trie(order);
+                restored += 1;
+            }
+            // give the maker the whole keep alive timeout to confirm the restored state
+            if let Some(state) = orderbook.pubkeys_state.get_mut(&pubkey) {
+                state.last_keep_alive = now;
+                for (pair, diffs) in snapshot.pair_history {
+                    let history = pair_history_mut(&mut state.order_pairs_trie_state_history, &pair);
+                    for (root, diff) in diffs {
+                        history.inner.insert(root, diff);
+                    }
+                }
+            }
+        }
+        orderbook.restored_topics.extend(self.topics);
+        restored
+    }
+}
+
+async fn save_orderbook_snapshot(ctx: &MmArc) -> Result<(), String> {
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let my_pubsecp = hex::encode(&**ctx.secp256k1_key_pair().public());
+    let snapshot = {
+        let orderbook = ordermatch_ctx.orderbook.lock().await;
+        OrderbookSnapshot::from_orderbook(&orderbook, &my_pubsecp)
+    };
+    let encoded = try_s!(encode_message(&snapshot));
+    // the snapshot is replaced at once, so the node stopped while saving doesn't leave the truncated file
+    let path = orderbook_snapshot_path(ctx);
+    let tmp_path = format!("{}.tmp", path.display());
+    try_s!(write(&tmp_path, &encoded));
+    try_s!(fs::rename(&tmp_path, &path));
+    Ok(())
+}
+
+/// Loads the orderbook snapshot saved before the restart.
+pub async fn load_orderbook_snapshot(ctx: &MmArc) -> Result<(), String> {
+    let content = slurp(&orderbook_snapshot_path(ctx));
+    if content.is_empty() {
+        return Ok(());
+    }
+    let snapshot: OrderbookSnapshot = try_s!(decode_message(&content));
+    let max_age = ctx.conf["orderbook_snapshot_max_age"]
+        .as_u64()
+        .unwrap_or(ORDERBOOK_SNAPSHOT_MAX_AGE);
+    if snapshot.saved_at + max_age < now_ms() / 1000 {
+        info!("The orderbook snapshot is too old, skipping");
+        return Ok(());
+    }
+
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let mut orderbook = ordermatch_ctx.orderbook.lock().await;
+    let restored = snapshot.restore(ctx, &mut orderbook, max_age);
+    info!("Restored {} orders from the orderbook snapshot", restored);
+    Ok(())
+}
+
+/// Saves the orderbook snapshot every `orderbook_snapshot_interval` seconds.
+pub async fn lp_orderbook_snapshot_loop(ctx: MmArc) {
+    let interval = ctx.conf["orderbook_snapshot_interval"]
+        .as_f64()
+        .unwrap_or(ORDERBOOK_SNAPSHOT_INTERVAL);
+    loop {
+        Timer::sleep(interval).await;
+        if ctx.is_stopping() {
+            break;
+        }
+        if let Err(e) = save_orderbook_snapshot(&ctx).await {
+            error!("Error saving the orderbook snapshot: {}", e);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod orderbook_snapshot_tests {
+    use super::*;
+    use crate::mm2::lp_ordermatch::ordermatch_tests::make_random_orders;
+    use common::mm_ctx::MmCtxBuilder;
+    use common::privkey::key_pair_from_seed;
+
+    #[test]
+    fn test_orderbook_snapshot_restore() {
+        let ctx = MmCtxBuilder::default()
+            .with_secp256k1_key_pair(key_pair_from_seed("passphrase").unwrap())
+            .into_mm_arc();
+        let maker = key_pair_from_seed("maker").unwrap();
+        let maker_pubkey = hex::encode(&**maker.public());
+        let stale = key_pair_from_seed("stale").unwrap();
+        let stale_pubkey = hex::encode(&**stale.public());
+
+        let mut orderbook = Orderbook::default();
+        let orders = make_random_orders(maker_pubkey.clone(), &[0; 32], "RICK".into(), "MORTY".into(), 3);
+        for order in orders {
+            orderbook.insert_or_update_order_update_trie(order);
+        }
+        let stale_orders = make_random_orders(stale_pubkey.clone(), &[0; 32], "RICK".into(), "MORTY".into(), 2);
+        for order in stale_orders {
+            orderbook.insert_or_update_order_update_trie(order);
+        }
+        orderbook.pubkeys_state.get_mut(&stale_pubkey).unwrap().last_keep_alive = 0;
+        orderbook
+            .topics_subscribed_to
+            .insert("orbk/MORTY:RICK".into(), OrderbookRequestingState::Requested);
+
+        let snapshot = OrderbookSnapshot::from_orderbook(&orderbook, "");
+        let encoded = encode_message(&snapshot).unwrap();
+        let decoded: OrderbookSnapshot = decode_message(&encoded).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        let mut restored = Orderbook::default();
+        assert_eq!(decoded.restore(&ctx, &mut restored, ORDERBOOK_SNAPSHOT_MAX_AGE), 3);
+        assert!(!restored.pubkeys_state.contains_key(&stale_pubkey));
+        assert_eq!(
+            restored.pubkeys_state[&maker_pubkey].trie_roots,
+            orderbook.pubkeys_state[&maker_pubkey].trie_roots
+        );
+        assert!(restored.restored_topics.contains("orbk/MORTY:RICK"));
+
+        // the node answers the delta sync requests from the roots known before the restart
+        let history = &orderbook.pubkeys_state[&maker_pubkey].order_pairs_trie_state_history;
+        let restored_history = &restored.pubkeys_state[&maker_pubkey].order_pairs_trie_state_history;
+        for (pair, diffs) in history.iter() {
+            let restored_diffs = restored_history.get(pair).unwrap();
+            for (root, diff) in diffs.get_element().inner.iter() {
+                assert_eq!(restored_diffs.get(root), Some(diff.get_element()));
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Maker orders with the price following a reference price.
+//!
+//! The pegged order price is the reference price shifted by `offset_bps` basis points. The reference is one of:
+//! * the JSON price feed polled by the node,
+//! * the price pushed by the local oracle with the `push_oracle_price` RPC,
+//! * the mid price of the best ask and bid of the orderbook excluding our own orders.
+//!
+//! The node reprices the order only when the new price differs from the current one by `reprice_threshold_bps`
+//! or more, so the external price jitter doesn't flood the network with `MakerOrderUpdated` messages.
+
+use super::{maker_order_updated_p2p_notify, new_protocol, save_maker_order_on_update, validate_price,
+            OrdermatchContext};
+use common::executor::Timer;
+use common::log::{error, warn};
+use common::mm_ctx::MmArc;
+use common::mm_number::MmNumber;
+use common::{fetch_json, now_ms};
+use http::Response;
+use num_rational::BigRational;
+use serde_json::{self as json, Value as Json};
+use uuid::Uuid;
+
+/// The default interval of the pegged orders repricing in seconds.
+const PRICE_PEG_INTERVAL: f64 = 10.;
+/// The price pushed by the oracle is considered stale after this timeout in seconds.
+const ORACLE_PRICE_TIMEOUT: u64 = 300;
+const BPS_PER_UNIT: i64 = 10_000;
+
+fn default_reprice_threshold_bps() -> u64 { 10 }
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", content = "data")]
+pub enum PriceReference {
+    /// The JSON price feed, `pointer` is the JSON pointer to the price in the response, e.g. `/komodo/btc`
+    PriceFeed { url: String, pointer: String },
+    /// The price pushed with the `push_oracle_price` RPC
+    Oracle,
+    /// The mid price of the best ask and bid of the orderbook
+    OrderbookMid,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PricePeg {
+    reference: PriceReference,
+    /// The offset of the order price from the reference price, may be negative
+    #[serde(default)]
+    offset_bps: i64,
+    /// The max deviation of the order price from the price the order was placed at
+    max_deviation_bps: Option<u64>,
+    /// The min change of the price required to reprice the order
+    #[serde(default = "default_reprice_threshold_bps")]
+    reprice_threshold_bps: u64,
+    /// The `price` of the `setprice` request, set by the node on the order creation
+    #[serde(default)]
+    anchor_price: Option<MmNumber>,
+}
+
+/// Returns `price * (1 + bps / 10000)`.
+fn apply_bps(price: &MmNumber, bps: i64) -> MmNumber {
+    let ratio = MmNumber::from(BigRational::new((BPS_PER_UNIT + bps).into(), BPS_PER_UNIT.into()));
+    price * &ratio
+}
+
+impl PricePeg {
+    /// Checks that the pegged price and its deviation bounds are positive for any positive reference price.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.offset_bps <= -BPS_PER_UNIT {
+            return ERR!("offset_bps {} must be greater than {}", self.offset_bps, -BPS_PER_UNIT);
+        }
+        if let Some(max_deviation) = self.max_deviation_bps {
+            if max_deviation >= BPS_PER_UNIT as u64 {
+                return ERR!("max_deviation_bps {} must be less than {}", max_deviation, BPS_PER_UNIT);
+            }
+        }
+        Ok(())
+    }
+
+    /// Calculates the order price from the `reference_price` respecting the deviation bounds.
+    pub fn pegged_price(&self, reference_price: &MmNumber) -> MmNumber {
+        let price = apply_bps(reference_price, self.offset_bps);
+        match (&self.anchor_price, self.max_deviation_bps) {
+            (Some(anchor), Some(max_deviation)) => {
+                let max_deviation = max_deviation as i64;
+                let lower = apply_bps(anchor, -max_deviation);
+                let upper = apply_bps(anchor, max_deviation);
+                price.max(lower).min(upper)
+            },
+            _ => price,
+        }
+    }
+
+    /// Whether the change from the `current` to the `new` price is enough to reprice the order.
+    pub fn needs_reprice(&self, current: &MmNumber, new: &MmNumber) -> bool {
+        let diff = if new > current { new - current } else { current - new };
+        let threshold = current
+            * &MmNumber::from(BigRational::new(
+                (self.reprice_threshold_bps as i64).into(),
+                BPS_PER_UNIT.into(),
+            ));
+        diff > MmNumber::from(0) && diff >= threshold
+    }
+
+    pub fn set_anchor_price(&mut self, price: MmNumber) { self.anchor_price = Some(price); }
+}
+
+/// Returns the mid price of the `base/rel` orderbook excluding the orders of `my_pubsecp`.
+async fn orderbook_mid_price(ctx: &MmArc, base: &str, rel: &str) -> Result<MmNumber, String> {
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let my_pubsecp = hex::encode(&**ctx.secp256k1_key_pair().public());
+    let base = ordermatch_ctx.orderbook_ticker_bypass(base);
+    let rel = ordermatch_ctx.orderbook_ticker_bypass(rel);
+    let orderbook = ordermatch_ctx.orderbook.lock().await;
+
+    // the orders are sorted by the price ascending, the bids are the asks of the reversed pair
+    let best_price = |pair: (String, String)| -> Option<MmNumber> {
+        orderbook
+            .ordered
+            .get(&pair)?
+            .iter()
+            .filter_map(|ordered| orderbook.order_set.get(&ordered.uuid))
+            .find(|order| order.pubkey != my_pubsecp)
+            .map(|order| MmNumber::from(order.price.clone()))
+    };
+    let best_ask = try_s!(best_price((base.clone(), rel.clone())).ok_or("No asks in the orderbook"));
+    let best_bid = try_s!(best_price((rel, base)).ok_or("No bids in the orderbook"));
+    let best_bid = MmNumber::from(1) / best_bid;
+    Ok((best_ask + best_bid) / MmNumber::from(2))
+}
+
+async fn reference_price(ctx: &MmArc, base: &str, rel: &str, reference: &PriceReference) -> Result<MmNumber, String> {
+    match reference {
+        PriceReference::PriceFeed { url, pointer } => {
+            let response: Json = try_s!(fetch_json(url).await);
+            let price = match response.pointer(pointer) {
+                Some(price) => price.clone(),
+                None => return ERR!("No price at {} in the {} response", pointer, url),
+            };
+            Ok(try_s!(json::from_value(price)))
+        },
+        PriceReference::Oracle => {
+            let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+            let oracle_prices = ordermatch_ctx.oracle_prices.lock().await;
+            match oracle_prices.get(&(base.to_owned(), rel.to_owned())) {
+                Some((price, pushed_at)) if pushed_at + ORACLE_PRICE_TIMEOUT > now_ms() / 1000 => Ok(price.clone()),
+                Some(_) => ERR!("The oracle price of {}/{} is stale", base, rel),
+                None => ERR!("The oracle price of {}/{} has not been pushed", base, rel),
+            }
+        },
+        PriceReference::OrderbookMid => orderbook_mid_price(ctx, base, rel).await,
+    }
+}
+
+/// Calculates the initial price of the order pegged to the reference respecting the deviation bounds.
+pub async fn initial_pegged_price(ctx: &MmArc, base: &str, rel: &str, peg: &PricePeg) -> Result<MmNumber, String> {
+    let reference = try_s!(reference_price(ctx, base, rel, &peg.reference).await);
+    let price = peg.pegged_price(&reference);
+    try_s!(validate_price(price.clone()));
+    Ok(price)
+}
+
+async fn reprice_pegged_orders(ctx: &MmArc) -> Result<(), String> {
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let pegged: Vec<(Uuid, String, String, PricePeg)> = ordermatch_ctx
+        .my_maker_orders
+        .lock()
+        .await
+        .values()
+        .filter_map(|order| {
+            let peg = order.price_peg.clone()?;
+            Some((order.uuid, order.base.clone(), order.rel.clone(), peg))
+        })
+        .collect();
+
+    for (uuid, base, rel, peg) in pegged {
+        // the reference price may be fetched from the network, the orders are not locked meanwhile
+        let reference = match reference_price(ctx, &base, &rel, &peg.reference).await {
+            Ok(price) => price,
+            Err(e) => {
+                warn!("Couldn't get the reference price of the order {}: {}", uuid, e);
+                continue;
+            },
+        };
+        let new_price = peg.pegged_price(&reference);
+        if let Err(e) = validate_price(new_price.clone()) {
+            warn!("Pegged price of the order {} is invalid: {}", uuid, e);
+            continue;
+        }
+
+        let mut my_maker_orders = ordermatch_ctx.my_maker_orders.lock().await;
+        let order = match my_maker_orders.get_mut(&uuid) {
+            Some(order) => order,
+            None => continue,
+        };
+        if order.has_ongoing_matches() || !peg.needs_reprice(&order.price, &new_price) {
+            continue;
+        }
+
+        let mut update_msg = new_protocol::MakerOrderUpdated::new(uuid);
+        update_msg.with_new_price(new_price.to_ratio());
+        order.apply_updated(&update_msg);
+        save_maker_order_on_update(ctx, order);
+        let topic = order.orderbook_topic();
+        drop(my_maker_orders);
+        maker_order_updated_p2p_notify(ctx.clone(), topic, update_msg).await;
+    }
+    Ok(())
+}
+
+/// Reprices the pegged maker orders every `price_peg_interval` seconds.
+pub async fn lp_price_peg_loop(ctx: MmArc) {
+    let interval = ctx.conf["price_peg_interval"].as_f64().unwrap_or(PRICE_PEG_INTERVAL);
+    loop {
+        if ctx.is_stopping() {
+            break;
+        }
+        if let Err(e) = reprice_pegged_orders(&ctx).await {
+            error!("Error repricing the pegged orders: {}", e);
+        }
+        Timer::sleep(interval).await;
+    }
+}
+
+#[derive(Deserialize)]
+struct PushOraclePriceReq {
+    base: String,
+    rel: String,
+    price: MmNumber,
+}
+
+/// Sets the reference price of the orders pegged to the `Oracle`.
+pub async fn push_oracle_price(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
+    let req: PushOraclePriceReq = try_s!(json::from_value(req));
+    try_s!(validate_price(req.price.clone()));
+
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(&ctx));
+    ordermatch_ctx
+        .oracle_prices
+        .lock()
+        .await
+        .insert((req.base, req.rel), (req.price, now_ms() / 1000));
+
+    let res = try_s!(json::to_vec(&json!({ "result": "success" })));
+    Ok(try_s!(Response::builder().body(res)))
+}
+
+#[cfg(test)]
+mod price_peg_tests {
+    use super::*;
+
+    fn peg(offset_bps: i64, max_deviation_bps: Option<u64>, anchor_price: Option<MmNumber>) -> PricePeg {
+        PricePeg {
+            reference: PriceReference::OrderbookMid,
+            offset_bps,
+            max_deviation_bps,
+            reprice_threshold_bps: 10,
+            anchor_price,
+        }
+    }
+
+    #[test]
+    fn test_pegged_price_offset() {
+        let reference = MmNumber::from(100);
+        assert_eq!(peg(0, None, None).pegged_price(&reference), MmNumber::from(100));
+        assert_eq!(peg(150, None, None).pegged_price(&reference), MmNumber::from("101.5"));
+        assert_eq!(peg(-50, None, None).pegged_price(&reference), MmNumber::from("99.5"));
+    }
+
+    #[test]
+    fn test_pegged_price_bounds() {
+        let peg = peg(100, Some(200), Some(MmNumber::from(100)));
+        assert_eq!(peg.pegged_price(&MmNumber::from(100)), MmNumber::from(101));
+        assert_eq!(peg.pegged_price(&MmNumber::from(110)), MmNumber::from(102));
+        assert_eq!(peg.pegged_price(&MmNumber::from(90)), MmNumber::from(98));
+    }
+
+    #[test]
+    fn test_price_peg_validate() {
+        assert!(peg(-9999, None, None).validate().is_ok());
+        assert!(peg(-10000, None, None).validate().is_err());
+        assert!(peg(-20000, None, None).validate().is_err());
+        assert!(peg(0, Some(9999), None).validate().is_ok());
+        assert!(peg(0, Some(10000), None).validate().is_err());
+    }
+
+    #[test]
+    fn test_needs_reprice() {
+        let peg = peg(0, None, None);
+        let current = MmNumber::from(100);
+        assert!(!peg.needs_reprice(&current, &MmNumber::from(100)));
+        assert!(!peg.needs_reprice(&current, &MmNumber::from("100.09")));
+        assert!(peg.needs_reprice(&current, &MmNumber::from("100.1")));
+        assert!(peg.needs_reprice(&current, &MmNumber::from("99.9")));
+    }
+
+    #[test]
+    fn test_price_peg_deserialize() {
+        let peg: PricePeg = json::from_value(json!({
+            "reference": { "type": "PriceFeed", "data": { "url": "https://prices.example/kmd", "pointer": "/komodo/btc" } },
+            "offset_bps": -25,
+        }))
+        .unwrap();
+        assert_eq!(peg.reference, PriceReference::PriceFeed {
+            url: "https://prices.example/kmd".into(),
+            pointer: "/komodo/btc".into(),
+        });
+        assert_eq!(peg.offset_bps, -25);
+        assert_eq!(peg.reprice_threshold_bps, 10);
+        assert_eq!(peg.anchor_price, None);
+    }
+}
@@ -0,0 +1,439 @@
+//! Private (OTC) maker orders offered to the allow-listed taker pubkeys only.
+//!
+//! The private orders never enter the orderbook trie and are never published to the gossip topics.
+//! The maker encrypts the signed `MakerOrderCreated` and `MakerOrderCancelled` messages of the order to every allowed
+//! taker pubkey and sends them by the direct request to the taker peer. The taker peer id is derived from its pubkey,
+//! so the takers accepting the private offers use the secp256k1 key as the libp2p identity (`accept_private_offers`).
+//! The maker resends the offers every `PRIVATE_OFFER_RESEND_INTERVAL`, the takers drop the offers that aren't
+//! refreshed in time.
+//! The taker matches the private offer as usual, e.g. by the taker request with `MatchBy::Orders`,
+//! and the maker checks the taker pubkey against the allow-list on matching.
+
+use super::{is_order_signature_valid, is_pubkey_banned, new_protocol, OrderbookItem, OrdermatchContext,
+            OrdermatchRequest};
+use crate::mm2::lp_network::{request_one_peer, P2PRequest};
+use bigdecimal::BigDecimal;
+use common::executor::{spawn, Timer};
+use common::log;
+use common::mm_ctx::MmArc;
+use common::mm_number::MmNumber;
+use common::now_ms;
+use http::Response;
+use mm2_libp2p::{decrypt_and_decode_signed, encode_sign_and_encrypt, is_encrypted, peer_id_from_secp256k1_pubkey,
+                 PublicKey};
+use serde_json::{self as json, Value as Json};
+use uuid::Uuid;
+
+/// The interval in seconds the maker resends its private offers at.
+const PRIVATE_OFFER_RESEND_INTERVAL: u64 = 30;
+/// The taker drops the offer that isn't refreshed during this time in seconds.
+const PRIVATE_OFFER_TIMEOUT: u64 = PRIVATE_OFFER_RESEND_INTERVAL * 3;
+
+/// The private offer of this node.
+pub struct MyPrivateOffer {
+    /// The compressed pubkeys of the takers the offer is sent to
+    allowed: Vec<String>,
+    /// The latest signed state of the order
+    message: new_protocol::MakerOrderCreated,
+}
+
+/// The private offer received from the maker.
+pub struct ReceivedPrivateOffer {
+    order: OrderbookItem,
+    received_at: u64,
+    /// The cancelled offers are kept until the timeout to reject the replayed messages
+    cancelled: bool,
+}
+
+/// Encrypts the signed ordermatch message to every of the `allowed` takers and sends it to their peers.
+/// The message is still encrypted since the request can be forwarded by the relay.
+fn send_to_allowed_takers(ctx: &MmArc, allowed: Vec<String>, msg: new_protocol::OrdermatchMessage) {
+    let key_pair = ctx.secp256k1_key_pair.or(&&|| panic!());
+    for pubkey in allowed {
+        // the pubkeys are validated on the order creation
+        let bytes = match hex::decode(&pubkey) {
+            Ok(bytes) if bytes.len() == 33 => bytes,
+            _ => continue,
+        };
+        let peer = match peer_id_from_secp256k1_pubkey(&bytes) {
+            Ok(peer) => peer,
+            Err(e) => {
+                log::error!("Error {} on getting the peer of the taker {}", e, pubkey);
+                continue;
+            },
+        };
+        let mut recipient = [0; 32];
+        recipient.copy_from_slice(&bytes[1..]);
+        let msg = match encode_sign_and_encrypt(&msg, &*key_pair.private().secret, &recipient) {
+            Ok(encrypted) => encrypted,
+            Err(e) => {
+                log::error!("Error {} on encrypting the private offer to {}", e, pubkey);
+                continue;
+            },
+        };
+        let req = P2PRequest::Ordermatch(OrdermatchRequest::PrivateOffer { msg });
+        let ctx = ctx.clone();
+        spawn(async move {
+            if let Err(e) = request_one_peer::<()>(ctx, req, peer.to_string()).await {
+                log::debug!("Error {} on sending the private offer to {}", e, pubkey);
+            }
+        });
+    }
+}
+
+/// Stores the new private offer and sends it to the allowed takers.
+pub(super) async fn publish_private_offer(ctx: &MmArc, allowed: Vec<String>, message: new_protocol::MakerOrderCreated) {
+    let ordermatch_ctx = OrdermatchContext::from_ctx(ctx).expect("from_ctx failed");
+    let uuid = Uuid::from(message.uuid);
+    send_to_allowed_takers(
+        ctx,
+        allowed.clone(),
+        new_protocol::OrdermatchMessage::MakerOrderCreated(message.clone()),
+    );
+    let mut my_offers = ordermatch_ctx.my_private_offers.lock().await;
+    my_offers.insert(uuid, MyPrivateOffer { allowed, message });
+}
+
+/// Applies the update to the private offer and resends it to the allowed takers.
+/// Returns false if the order isn't a private offer of this node.
+pub(super) async fn update_private_offer(ctx: &MmArc, updated: &new_protocol::MakerOrderUpdated) -> bool {
+    let ordermatch_ctx = OrdermatchContext::from_ctx(ctx).expect("from_ctx failed");
+    let mut my_offers = ordermatch_ctx.my_private_offers.lock().await;
+    let offer = match my_offers.get_mut(&updated.uuid()) {
+        Some(offer) => offer,
+        None => return false,
+    };
+
+    if let Some(new_price) = updated.new_price() {
+        offer.message.price = new_price.to_ratio();
+    }
+    if let Some(new_max_volume) = updated.new_max_volume() {
+        offer.message.max_volume = new_max_volume.to_ratio();
+    }
+    if let Some(new_min_volume) = updated.new_min_volume() {
+        offer.message.min_volume = new_min_volume.to_ratio();
+    }
+    if let Some(conf_settings) = updated.new_conf_settings() {
+        offer.message.conf_settings = conf_settings;
+    }
+    offer.message.timestamp = now_ms() / 1000;
+    offer.message.nonce = super::next_order_nonce(ctx);
+    offer.message.signature = super::sign_order_payload(ctx, &offer.message.signing_payload());
+
+    send_to_allowed_takers(
+        ctx,
+        offer.allowed.clone(),
+        new_protocol::OrdermatchMessage::MakerOrderCreated(offer.message.clone()),
+    );
+    true
+}
+
+/// Forgets the private offer and notifies the allowed takers about the cancellation.
+pub(super) async fn cancel_private_offer(ctx: &MmArc, cancelled: new_protocol::MakerOrderCancelled) {
+    let ordermatch_ctx = OrdermatchContext::from_ctx(ctx).expect("from_ctx failed");
+    let offer = ordermatch_ctx
+        .my_private_offers
+        .lock()
+        .await
+        .remove(&Uuid::from(cancelled.uuid));
+    if let Some(offer) = offer {
+        send_to_allowed_takers(
+            ctx,
+            offer.allowed,
+            new_protocol::OrdermatchMessage::MakerOrderCancelled(cancelled),
+        );
+    }
+}
+
+/// Whether the private offer of this node is sent to the takers.
+pub(super) async fn is_private_offer_published(ctx: &MmArc, uuid: &Uuid) -> bool {
+    let ordermatch_ctx = OrdermatchContext::from_ctx(ctx).expect("from_ctx failed");
+    let my_offers = ordermatch_ctx.my_private_offers.lock().await;
+    my_offers.contains_key(uuid)
+}
+
+/// Processes the private offer message the maker sent to this node by the direct request.
+pub(super) async fn process_private_offer_request(ctx: &MmArc, msg: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    if !is_encrypted(msg) {
+        return ERR!("The private offer message is not encrypted");
+    }
+    let key_pair = ctx.secp256k1_key_pair.or(&&|| panic!());
+    let (msg, _sig, pubkey) = try_s!(decrypt_and_decode_signed::<new_protocol::OrdermatchMessage>(
+        msg,
+        &*key_pair.private().secret
+    ));
+    try_s!(process_private_offer(ctx, msg, &pubkey).await);
+    Ok(None)
+}
+
+/// Processes the signed private offer message the maker sent to this node.
+async fn process_private_offer(
+    ctx: &MmArc,
+    msg: new_protocol::OrdermatchMessage,
+    pubkey: &PublicKey,
+) -> Result<(), String> {
+    if is_pubkey_banned(ctx, &pubkey.unprefixed().into()) {
+        return ERR!("Pubkey {} is banned", pubkey.to_hex());
+    }
+    let pubkey_bytes = pubkey.to_bytes();
+    let pubkey_hex = hex::encode(pubkey_bytes.as_slice());
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(ctx));
+    let now = now_ms() / 1000;
+
+    match msg {
+        new_protocol::OrdermatchMessage::MakerOrderCreated(created) => {
+            if !is_order_signature_valid(&pubkey_bytes, &created.signing_payload(), &created.signature) {
+                return ERR!("Invalid signature of the offer {}", Uuid::from(created.uuid));
+            }
+            let order: OrderbookItem = (created, pubkey_hex).into();
+            if order.is_expired(now) {
+                return ERR!("The offer {} is expired", order.uuid);
+            }
+            let mut offers = ordermatch_ctx.private_offers.lock().await;
+            if let Some(known) = offers.get(&order.uuid) {
+                // the maker resends the same message periodically, so the equal nonce just refreshes the offer
+                let is_replayed = if known.cancelled {
+                    known.order.is_stale_nonce(order.nonce)
+                } else {
+                    order.nonce < known.order.nonce
+                };
+                if known.order.pubkey != order.pubkey || is_replayed {
+                    return ERR!("The offer {} message is outdated", order.uuid);
+                }
+            }
+            offers.insert(order.uuid, ReceivedPrivateOffer {
+                order,
+                received_at: now,
+                cancelled: false,
+            });
+        },
+        new_protocol::OrdermatchMessage::MakerOrderCancelled(cancelled) => {
+            let uuid = Uuid::from(cancelled.uuid);
+            if !is_order_signature_valid(&pubkey_bytes, &cancelled.signing_payload(), &cancelled.signature) {
+                return ERR!("Invalid signature of the offer {} cancellation", uuid);
+            }
+            let mut offers = ordermatch_ctx.private_offers.lock().await;
+            if let Some(known) = offers.get_mut(&uuid) {
+                if known.order.pubkey == pubkey_hex && !known.order.is_stale_nonce(cancelled.nonce) {
+                    known.order.nonce = cancelled.nonce;
+                    known.cancelled = true;
+                }
+            }
+        },
+        _ => return ERR!("Unexpected private offer message"),
+    }
+    Ok(())
+}
+
+/// Resends the private offers of this node and drops the offers of the makers that weren't refreshed in time.
+pub async fn lp_private_offers_loop(ctx: MmArc) {
+    while !ctx.is_stopping() {
+        Timer::sleep(PRIVATE_OFFER_RESEND_INTERVAL as f64).await;
+        let ordermatch_ctx = OrdermatchContext::from_ctx(&ctx).expect("from_ctx failed");
+        let to_resend: Vec<_> = {
+            let my_offers = ordermatch_ctx.my_private_offers.lock().await;
+            my_offers
+                .values()
+                .map(|offer| (offer.allowed.clone(), offer.message.clone()))
+                .collect()
+        };
+        for (allowed, message) in to_resend {
+            send_to_allowed_takers(
+                &ctx,
+                allowed,
+                new_protocol::OrdermatchMessage::MakerOrderCreated(message),
+            );
+        }
+
+        let now = now_ms() / 1000;
+        ordermatch_ctx
+            .private_offers
+            .lock()
+            .await
+            .retain(|_, offer| offer.received_at + PRIVATE_OFFER_TIMEOUT > now && !offer.order.is_expired(now));
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PrivateOffersReq {
+    base: Option<String>,
+    rel: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct PrivateOfferForRpc<'a> {
+    uuid: Uuid,
+    pubkey: &'a str,
+    base: &'a str,
+    rel: &'a str,
+    price: BigDecimal,
+    max_volume: BigDecimal,
+    min_volume: BigDecimal,
+    created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+}
+
+impl<'a> From<&'a OrderbookItem> for PrivateOfferForRpc<'a> {
+    fn from(order: &'a OrderbookItem) -> Self {
+        PrivateOfferForRpc {
+            uuid: order.uuid,
+            pubkey: &order.pubkey,
+            base: &order.base,
+            rel: &order.rel,
+            price: MmNumber::from(order.price.clone()).to_decimal(),
+            max_volume: MmNumber::from(order.max_volume.clone()).to_decimal(),
+            min_volume: MmNumber::from(order.min_volume.clone()).to_decimal(),
+            created_at: order.created_at,
+            expires_at: order.expires_at,
+        }
+    }
+}
+
+/// Returns the private offers the makers sent to this node, optionally filtered by the base and rel coins.
+pub async fn private_offers(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
+    let req: PrivateOffersReq = try_s!(json::from_value(req));
+    let ordermatch_ctx = try_s!(OrdermatchContext::from_ctx(&ctx));
+    let base = req.base.map(|base| ordermatch_ctx.orderbook_ticker_bypass(&base));
+    let rel = req.rel.map(|rel| ordermatch_ctx.orderbook_ticker_bypass(&rel));
+
+    let now = now_ms() / 1000;
+    let offers = ordermatch_ctx.private_offers.lock().await;
+    let result: Vec<PrivateOfferForRpc> = offers
+        .values()
+        .filter(|offer| !offer.cancelled && !offer.order.is_expired(now))
+        .filter(|offer| base.as_ref().map_or(true, |base| *base == offer.order.base))
+        .filter(|offer| rel.as_ref().map_or(true, |rel| *rel == offer.order.rel))
+        .map(|offer| PrivateOfferForRpc::from(&offer.order))
+        .collect();
+    let res = try_s!(json::to_vec(&json!({ "result": result })));
+    Ok(try_s!(Response::builder().body(res)))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod private_offers_tests {
+    use super::*;
+    use crate::mm2::lp_ordermatch::{sign_order_payload, OrderConfirmationsSettings, H64};
+    use common::block_on;
+    use common::mm_ctx::MmCtxBuilder;
+    use common::privkey::key_pair_from_seed;
+    use num_rational::BigRational;
+
+    fn signed_offer(maker_ctx: &MmArc, uuid: Uuid, nonce: u64) -> new_protocol::MakerOrderCreated {
+        let mut created = new_protocol::MakerOrderCreated {
+            uuid: uuid.into(),
+            base: "RICK".into(),
+            rel: "MORTY".into(),
+            price: BigRational::from_integer(1.into()),
+            max_volume: BigRational::from_integer(2.into()),
+            min_volume: BigRational::from_integer(1.into()),
+            created_at: now_ms() / 1000,
+            conf_settings: OrderConfirmationsSettings::default(),
+            timestamp: now_ms() / 1000,
+            pair_trie_root: H64::default(),
+            base_protocol_info: vec![],
+            rel_protocol_info: vec![],
+            expires_at: None,
+            nonce,
+            signature: vec![],
+        };
+        created.signature = sign_order_payload(maker_ctx, &created.signing_payload());
+        created
+    }
+
+    fn encrypt(maker_ctx: &MmArc, taker_ctx: &MmArc, msg: new_protocol::OrdermatchMessage) -> Vec<u8> {
+        let mut recipient = [0; 32];
+        recipient.copy_from_slice(&taker_ctx.secp256k1_key_pair().public()[1..]);
+        let key_pair = maker_ctx.secp256k1_key_pair();
+        encode_sign_and_encrypt(&msg, &*key_pair.private().secret, &recipient).unwrap()
+    }
+
+    #[test]
+    fn test_process_private_offer() {
+        let maker_ctx = MmCtxBuilder::default()
+            .with_secp256k1_key_pair(key_pair_from_seed("maker").unwrap())
+            .into_mm_arc();
+        let taker_ctx = MmCtxBuilder::default()
+            .with_secp256k1_key_pair(key_pair_from_seed("taker").unwrap())
+            .into_mm_arc();
+        let other_ctx = MmCtxBuilder::default()
+            .with_secp256k1_key_pair(key_pair_from_seed("other").unwrap())
+            .into_mm_arc();
+        let uuid = Uuid::new_v4();
+        let taker_ordermatch_ctx = OrdermatchContext::from_ctx(&taker_ctx).unwrap();
+        let offers_num = || block_on(taker_ordermatch_ctx.private_offers.lock()).len();
+
+        let created = signed_offer(&maker_ctx, uuid, 2);
+        let msg = encrypt(
+            &maker_ctx,
+            &taker_ctx,
+            new_protocol::OrdermatchMessage::MakerOrderCreated(created),
+        );
+        // the offer to the other taker can't be decrypted
+        block_on(process_private_offer_request(&other_ctx, &msg)).unwrap_err();
+        let other_ordermatch_ctx = OrdermatchContext::from_ctx(&other_ctx).unwrap();
+        assert!(block_on(other_ordermatch_ctx.private_offers.lock()).is_empty());
+        // the plain message is rejected
+        block_on(process_private_offer_request(&taker_ctx, b"not encrypted")).unwrap_err();
+
+        assert_eq!(block_on(process_private_offer_request(&taker_ctx, &msg)).unwrap(), None);
+        assert_eq!(offers_num(), 1);
+        // the resent offer is accepted
+        block_on(process_private_offer_request(&taker_ctx, &msg)).unwrap();
+        // the older message is skipped
+        let older = signed_offer(&maker_ctx, uuid, 1);
+        let older = encrypt(
+            &maker_ctx,
+            &taker_ctx,
+            new_protocol::OrdermatchMessage::MakerOrderCreated(older),
+        );
+        block_on(process_private_offer_request(&taker_ctx, &older)).unwrap_err();
+
+        {
+            let offers = block_on(taker_ordermatch_ctx.private_offers.lock());
+            let offer = &offers[&uuid];
+            assert_eq!(
+                offer.order.pubkey,
+                hex::encode(&**maker_ctx.secp256k1_key_pair().public())
+            );
+            assert_eq!(offer.order.nonce, 2);
+            assert!(!offer.cancelled);
+        }
+
+        let mut cancelled = new_protocol::MakerOrderCancelled {
+            uuid: uuid.into(),
+            timestamp: now_ms() / 1000,
+            pair_trie_root: H64::default(),
+            nonce: 3,
+            signature: vec![],
+        };
+        cancelled.signature = sign_order_payload(&maker_ctx, &cancelled.signing_payload());
+        let cancel_msg = encrypt(
+            &maker_ctx,
+            &taker_ctx,
+            new_protocol::OrdermatchMessage::MakerOrderCancelled(cancelled),
+        );
+        block_on(process_private_offer_request(&taker_ctx, &cancel_msg)).unwrap();
+        assert!(block_on(taker_ordermatch_ctx.private_offers.lock())[&uuid].cancelled);
+
+        // the replayed offer can't restore the cancelled one
+        block_on(process_private_offer_request(&taker_ctx, &msg)).unwrap_err();
+        assert!(block_on(taker_ordermatch_ctx.private_offers.lock())[&uuid].cancelled);
+        // the offer of the other pubkey with the same uuid is skipped
+        let other = signed_offer(&other_ctx, uuid, 10);
+        let other = encrypt(
+            &other_ctx,
+            &taker_ctx,
+            new_protocol::OrdermatchMessage::MakerOrderCreated(other),
+        );
+        block_on(process_private_offer_request(&taker_ctx, &other)).unwrap_err();
+        {
+            let offers = block_on(taker_ordermatch_ctx.private_offers.lock());
+            assert_eq!(
+                offers[&uuid].order.pubkey,
+                hex::encode(&**maker_ctx.secp256k1_key_pair().public())
+            );
+            assert!(offers[&uuid].cancelled);
+        }
+    }
+}
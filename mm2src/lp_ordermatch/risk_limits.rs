@@ -0,0 +1,433 @@
+//! The order-level risk limits configured by the `risk_limits` object of the MM2 config, e.g.
+//! `{"max_open_volume": {"RICK": "100"}, "max_swaps_per_counterparty": 2, "daily_notional_cap": {"RICK": "500"}}`.
+//!
+//! The volumes are measured in the units of the coin this node spends: the base coin of the maker orders
+//! and the taker coin of the taker orders.
+//! The open volume of the coin is the available volume of the maker orders, the volume of the taker orders
+//! and the amount locked by the running swaps.
+//! The daily notional is the volume spent by the swaps started within the last 24 hours,
+//! the swaps started before the node restart are loaded from the saved swaps.
+
+use super::{MakerOrder, OrdermatchContext, TakerAction, TakerOrder};
+use crate::mm2::lp_swap::{get_locked_amount, my_swaps_started_since, running_swaps_num_with_pubkey};
+use bigdecimal::BigDecimal;
+use common::mm_ctx::MmArc;
+use common::mm_number::MmNumber;
+use common::{bits256, log, now_ms};
+use derive_more::Display;
+use futures::lock::MutexGuard as AsyncMutexGuard;
+use rpc::v1::types::H256 as H256Json;
+use serde_json::{self as json};
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+
+const DAILY_NOTIONAL_PERIOD: u64 = 24 * 3600;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RiskLimits {
+    /// The max open volume by the coin tickers
+    #[serde(default)]
+    max_open_volume: HashMap<String, MmNumber>,
+    /// The max number of the concurrent swaps with the same counterparty pubkey
+    max_swaps_per_counterparty: Option<usize>,
+    /// The max volume spent by the swaps within 24 hours by the coin tickers
+    #[serde(default)]
+    daily_notional_cap: HashMap<String, MmNumber>,
+}
+
+impl RiskLimits {
+    pub fn from_conf(ctx: &MmArc) -> Result<RiskLimits, String> {
+        if ctx.conf["risk_limits"].is_null() {
+            return Ok(RiskLimits::default());
+        }
+        let limits: RiskLimits = try_s!(json::from_value(ctx.conf["risk_limits"].clone()));
+        if limits.max_swaps_per_counterparty == Some(0) {
+            return ERR!("risk_limits.max_swaps_per_counterparty must be positive");
+        }
+        Ok(limits)
+    }
+
+    fn check_open_volume(
+        &self,
+        coin: &str,
+        open_volume: &MmNumber,
+        volume: &MmNumber,
+    ) -> Result<(), RiskLimitViolation> {
+        let limit = match self.max_open_volume.get(coin) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let total = open_volume + volume;
+        if &total > limit {
+            return Err(RiskLimitViolation::MaxOpenVolume {
+                coin: coin.to_owned(),
+                volume: total.to_decimal(),
+                limit: limit.to_decimal(),
+            });
+        }
+        Ok(())
+    }
+
+    fn check_daily_notional(&self, coin: &str, spent: &MmNumber, volume: &MmNumber) -> Result<(), RiskLimitViolation> {
+        let limit = match self.daily_notional_cap.get(coin) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let total = spent + volume;
+        if &total > limit {
+            return Err(RiskLimitViolation::DailyNotionalCap {
+                coin: coin.to_owned(),
+                volume: total.to_decimal(),
+                limit: limit.to_decimal(),
+            });
+        }
+        Ok(())
+    }
+
+    fn check_counterparty_swaps(&self, pubkey: &H256Json, swaps: usize) -> Result<(), RiskLimitViolation> {
+        match self.max_swaps_per_counterparty {
+            Some(limit) if swaps >= limit => Err(RiskLimitViolation::MaxSwapsPerCounterparty {
+                pubkey: hex::encode(&pubkey.0),
+                swaps,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Display, PartialEq)]
+pub enum RiskLimitViolation {
+    #[display(fmt = "Open volume {} {} exceeds the max_open_volume limit {}", volume, coin, limit)]
+    MaxOpenVolume {
+        coin: String,
+        volume: BigDecimal,
+        limit: BigDecimal,
+    },
+    #[display(
+        fmt = "{} swaps are already running with the counterparty {}, the max_swaps_per_counterparty limit is {}",
+        swaps,
+        pubkey,
+        limit
+    )]
+    MaxSwapsPerCounterparty { pubkey: String, swaps: usize, limit: usize },
+    #[display(
+        fmt = "Daily volume {} {} exceeds the daily_notional_cap limit {}",
+        volume,
+        coin,
+        limit
+    )]
+    DailyNotionalCap {
+        coin: String,
+        volume: BigDecimal,
+        limit: BigDecimal,
+    },
+}
+
+/// The match refused by the risk limits, displayed by `order_status`
+#[derive(Clone, Debug, Serialize)]
+pub struct RiskLimitRejection {
+    pub taker_order_uuid: Uuid,
+    pub reason: String,
+    pub rejected_at: u64,
+}
+
+#[derive(Default)]
+pub struct RiskLimitsState {
+    /// The started swaps as the (started_at, spent coin, spent volume)
+    started_swaps: VecDeque<(u64, String, MmNumber)>,
+    /// The latest rejected match by the maker order uuid
+    rejections: HashMap<Uuid, RiskLimitRejection>,
+    /// Whether the swaps started before the node start are loaded to `started_swaps`
+    saved_swaps_loaded: bool,
+}
+
+impl RiskLimitsState {
+    fn remove_outdated(&mut self, now: u64) {
+        while let Some((started_at, _, _)) = self.started_swaps.front() {
+            if started_at + DAILY_NOTIONAL_PERIOD > now {
+                break;
+            }
+            self.started_swaps.pop_front();
+        }
+        self.rejections
+            .retain(|_, rejection| rejection.rejected_at + DAILY_NOTIONAL_PERIOD > now);
+    }
+
+    fn spent_within_day(&self, coin: &str) -> MmNumber {
+        self.started_swaps
+            .iter()
+            .filter(|(_, spent_coin, _)| spent_coin == coin)
+            .fold(MmNumber::from(0), |mut total, (_, _, volume)| {
+                total += volume;
+                total
+            })
+    }
+
+    pub fn rejection(&self, order_uuid: &Uuid) -> Option<&RiskLimitRejection> { self.rejections.get(order_uuid) }
+}
+
+/// Locks the state removing the outdated entries.
+/// The swaps started within the last 24 hours are loaded from the saved swaps on the first call,
+/// so the node restart doesn't reset the `daily_notional_cap`.
+async fn lock_state<'a>(
+    ctx: &MmArc,
+    ordermatch_ctx: &'a OrdermatchContext,
+    now: u64,
+) -> AsyncMutexGuard<'a, RiskLimitsState> {
+    let mut state = ordermatch_ctx.risk_limits_state.lock().await;
+    if !state.saved_swaps_loaded {
+        match my_swaps_started_since(ctx, now.saturating_sub(DAILY_NOTIONAL_PERIOD)) {
+            Ok(swaps) => {
+                let mut started_swaps: Vec<_> = swaps
+                    .into_iter()
+                    .map(|info| (info.started_at, info.my_coin, MmNumber::from(info.my_amount)))
+                    .collect();
+                started_swaps.sort_by_key(|(started_at, _, _)| *started_at);
+                // the swaps started after the node start are recorded after the saved ones are loaded
+                started_swaps.extend(state.started_swaps.drain(..));
+                state.started_swaps = started_swaps.into();
+            },
+            Err(e) => log::error!("Error {} loading the saved swaps for the risk limits", e),
+        }
+        state.saved_swaps_loaded = true;
+    }
+    state.remove_outdated(now);
+    state
+}
+
+fn taker_order_volume(order: &TakerOrder) -> &MmNumber {
+    match order.request.action {
+        TakerAction::Buy => &order.request.rel_amount,
+        TakerAction::Sell => &order.request.base_amount,
+    }
+}
+
+fn maker_orders_open_volume<'a>(coin: &str, orders: impl Iterator<Item = &'a MakerOrder>) -> MmNumber {
+    orders
+        .filter(|order| order.base == coin)
+        .fold(MmNumber::from(0), |mut total, order| {
+            total += &order.available_amount();
+            total
+        })
+}
+
+fn taker_orders_open_volume<'a>(coin: &str, orders: impl Iterator<Item = &'a TakerOrder>) -> MmNumber {
+    orders
+        .filter(|order| order.taker_coin_ticker() == coin)
+        .fold(MmNumber::from(0), |mut total, order| {
+            total += taker_order_volume(order);
+            total
+        })
+}
+
+/// Checks that the new order spending the `volume` of the `coin` doesn't exceed the `max_open_volume` limit.
+/// The maker orders matching `replaced` are about to be cancelled and aren't accounted.
+/// Locks `my_taker_orders` and `my_maker_orders` one after another, must not be called with any of them locked.
+pub async fn check_new_order_open_volume(
+    ctx: &MmArc,
+    ordermatch_ctx: &OrdermatchContext,
+    coin: &str,
+    volume: &MmNumber,
+    replaced: impl Fn(&MakerOrder) -> bool,
+) -> Result<(), RiskLimitViolation> {
+    if !ordermatch_ctx.risk_limits.max_open_volume.contains_key(coin) {
+        return Ok(());
+    }
+    let mut open_volume = {
+        let my_taker_orders = ordermatch_ctx.my_taker_orders.lock().await;
+        taker_orders_open_volume(coin, my_taker_orders.values())
+    };
+    {
+        let my_maker_orders = ordermatch_ctx.my_maker_orders.lock().await;
+        open_volume += &maker_orders_open_volume(coin, my_maker_orders.values().filter(|order| !replaced(order)));
+    }
+    open_volume += &get_locked_amount(ctx, coin);
+    ordermatch_ctx.risk_limits.check_open_volume(coin, &open_volume, volume)
+}
+
+/// Checks that one more swap with the counterparty `pubkey` doesn't exceed the `max_swaps_per_counterparty` limit.
+pub fn check_counterparty(
+    ctx: &MmArc,
+    ordermatch_ctx: &OrdermatchContext,
+    pubkey: &H256Json,
+) -> Result<(), RiskLimitViolation> {
+    let swaps = running_swaps_num_with_pubkey(ctx, &bits256::from(pubkey.0));
+    ordermatch_ctx.risk_limits.check_counterparty_swaps(pubkey, swaps)
+}
+
+/// Checks that the swaps spending the `volume` of the `coin` don't exceed the `daily_notional_cap` limit.
+pub async fn check_daily_notional(
+    ctx: &MmArc,
+    ordermatch_ctx: &OrdermatchContext,
+    coin: &str,
+    volume: &MmNumber,
+) -> Result<(), RiskLimitViolation> {
+    let state = lock_state(ctx, ordermatch_ctx, now_ms() / 1000).await;
+    ordermatch_ctx
+        .risk_limits
+        .check_daily_notional(coin, &state.spent_within_day(coin), volume)
+}
+
+/// Checks the limits of the maker swap about to start and records its volume on success.
+/// The violation is remembered as the rejection of the maker order.
+pub async fn check_maker_swap_start(
+    ctx: &MmArc,
+    ordermatch_ctx: &OrdermatchContext,
+    maker_order: &MakerOrder,
+    taker_order_uuid: Uuid,
+    taker_pubkey: &H256Json,
+    volume: &MmNumber,
+) -> Result<(), RiskLimitViolation> {
+    let limits = &ordermatch_ctx.risk_limits;
+    let now = now_ms() / 1000;
+    let mut state = lock_state(ctx, ordermatch_ctx, now).await;
+
+    let swaps = running_swaps_num_with_pubkey(ctx, &bits256::from(taker_pubkey.0));
+    let result = limits
+        .check_counterparty_swaps(taker_pubkey, swaps)
+        .and_then(|_| limits.check_open_volume(&maker_order.base, &get_locked_amount(ctx, &maker_order.base), volume))
+        .and_then(|_| {
+            limits.check_daily_notional(&maker_order.base, &state.spent_within_day(&maker_order.base), volume)
+        });
+    match &result {
+        Ok(_) => state
+            .started_swaps
+            .push_back((now, maker_order.base.clone(), volume.clone())),
+        Err(e) => {
+            state.rejections.insert(maker_order.uuid, RiskLimitRejection {
+                taker_order_uuid,
+                reason: e.to_string(),
+                rejected_at: now,
+            });
+        },
+    }
+    result
+}
+
+/// Records the volume of the started taker swap to account it in the `daily_notional_cap`.
+pub async fn record_taker_swap_start(ctx: &MmArc, ordermatch_ctx: &OrdermatchContext, coin: &str, volume: &MmNumber) {
+    let now = now_ms() / 1000;
+    let mut state = lock_state(ctx, ordermatch_ctx, now).await;
+    state.started_swaps.push_back((now, coin.to_owned(), volume.clone()));
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod risk_limits_tests {
+    use super::*;
+    use crate::mm2::lp_swap::{my_swap_file_path, my_swaps_dir};
+    use common::mm_ctx::MmCtxBuilder;
+
+    #[test]
+    fn test_risk_limits_checks() {
+        let limits: RiskLimits = json::from_value(json!({
+            "max_open_volume": {"RICK": "10"},
+            "max_swaps_per_counterparty": 2,
+            "daily_notional_cap": {"RICK": "5"},
+        }))
+        .unwrap();
+
+        assert!(limits
+            .check_open_volume("RICK", &MmNumber::from(7), &MmNumber::from(3))
+            .is_ok());
+        let err = limits
+            .check_open_volume("RICK", &MmNumber::from(7), &MmNumber::from(4))
+            .unwrap_err();
+        assert_eq!(err, RiskLimitViolation::MaxOpenVolume {
+            coin: "RICK".into(),
+            volume: BigDecimal::from(11),
+            limit: BigDecimal::from(10),
+        });
+        assert!(limits
+            .check_open_volume("MORTY", &MmNumber::from(1000), &MmNumber::from(1))
+            .is_ok());
+
+        let pubkey = H256Json::default();
+        assert!(limits.check_counterparty_swaps(&pubkey, 1).is_ok());
+        assert!(limits.check_counterparty_swaps(&pubkey, 2).is_err());
+
+        let mut state = RiskLimitsState::default();
+        state.started_swaps.push_back((0, "RICK".into(), MmNumber::from(4)));
+        state
+            .started_swaps
+            .push_back((100_000, "RICK".into(), MmNumber::from(2)));
+        state
+            .started_swaps
+            .push_back((100_000, "MORTY".into(), MmNumber::from(2)));
+        state.remove_outdated(100_001);
+        assert_eq!(state.spent_within_day("RICK"), MmNumber::from(2));
+        assert!(limits
+            .check_daily_notional("RICK", &state.spent_within_day("RICK"), &MmNumber::from(3))
+            .is_ok());
+        assert!(limits
+            .check_daily_notional("RICK", &state.spent_within_day("RICK"), &MmNumber::from(4))
+            .is_err());
+    }
+
+    fn saved_maker_swap(uuid: Uuid, maker_coin: &str, maker_amount: &str, started_at: u64) -> json::Value {
+        json!({
+            "type": "Maker",
+            "uuid": uuid,
+            "events": [{
+                "event": {
+                    "type": "Started",
+                    "data": {
+                        "lock_duration": 7800,
+                        "maker_amount": maker_amount,
+                        "maker_coin": maker_coin,
+                        "maker_coin_start_block": 1,
+                        "maker_payment_confirmations": 1,
+                        "maker_payment_lock": started_at + 15600,
+                        "my_persistent_pub": "02631dcf1d4b1b693aa8c2751afc68e4794b1e5996566cfc701a663f8b7bbbe640",
+                        "secret": "e1c9bd12a83f810813dc078ac398069b63d56bf1e94657def995c43cd1975302",
+                        "started_at": started_at,
+                        "taker": "031d4256c4bc9f99ac88bf3dba21773132281f65f9bf23a59928bce08961e2f3",
+                        "taker_amount": "1",
+                        "taker_coin": "MORTY",
+                        "taker_coin_start_block": 1,
+                        "taker_payment_confirmations": 1,
+                        "uuid": uuid,
+                    },
+                },
+                "timestamp": started_at * 1000,
+            }],
+            "success_events": [],
+            "error_events": [],
+        })
+    }
+
+    #[test]
+    fn test_daily_notional_loaded_from_saved_swaps() {
+        let dbdir = std::env::temp_dir().join(format!("risk_limits_{}", Uuid::new_v4()));
+        let ctx = MmCtxBuilder::default()
+            .with_conf(json!({ "dbdir": dbdir.display().to_string() }))
+            .into_mm_arc();
+        std::fs::create_dir_all(my_swaps_dir(&ctx)).unwrap();
+
+        let now = now_ms() / 1000;
+        let swaps = vec![
+            (now - 100, "RICK", "3"),
+            (now - 50, "RICK", "1"),
+            (now - 50, "MORTY", "7"),
+            (now - DAILY_NOTIONAL_PERIOD - 100, "RICK", "5"),
+        ];
+        for (started_at, coin, amount) in swaps {
+            let uuid = Uuid::new_v4();
+            let swap = saved_maker_swap(uuid, coin, amount, started_at);
+            std::fs::write(my_swap_file_path(&ctx, &uuid), json::to_vec(&swap).unwrap()).unwrap();
+        }
+
+        let ordermatch_ctx = OrdermatchContext::default();
+        common::block_on(record_taker_swap_start(
+            &ctx,
+            &ordermatch_ctx,
+            "RICK",
+            &MmNumber::from(2),
+        ));
+        let state = common::block_on(lock_state(&ctx, &ordermatch_ctx, now));
+        assert_eq!(state.spent_within_day("RICK"), MmNumber::from(6));
+        assert_eq!(state.spent_within_day("MORTY"), MmNumber::from(7));
+        drop(state);
+        std::fs::remove_dir_all(&dbdir).unwrap();
+    }
+}
@@ -57,19 +57,21 @@
 
 #[cfg(not(target_arch = "wasm32"))]
 use crate::mm2::database::database_common::PagingOptions;
-use crate::mm2::lp_network::broadcast_p2p_msg;
+use crate::mm2::lp_network::{broadcast_p2p_msg, request_one_peer, P2PMessageError, P2PRequest};
 use async_std::sync as async_std_sync;
 use bigdecimal::BigDecimal;
 use coins::{lp_coinfind, MmCoinEnum, TradeFee, TransactionEnum};
 use common::{bits256, block_on, calc_total_pages,
              executor::{spawn, Timer},
-             log::{error, info},
+             log::{debug, error, info},
              mm_ctx::{from_ctx, MmArc},
              mm_number::MmNumber,
              now_ms, read_dir, rpc_response, slurp, var, write, HyRes};
 use futures::future::{abortable, AbortHandle, TryFutureExt};
 use http::Response;
-use mm2_libp2p::{decode_signed, encode_and_sign, pub_sub_topic, TopicPrefix};
+use mm2_libp2p::{decode_signed, decrypt_and_decode_signed, encode_and_sign, encode_sign_and_encrypt, is_encrypted,
+                 pub_sub_topic, PeerId, PublicKey, TopicPrefix};
+#[cfg(test)] use mocktopus::macros::*;
 use num_rational::BigRational;
 use primitives::hash::{H160, H264};
 use rpc::v1::types::{Bytes as BytesJson, H256 as H256Json};
@@ -89,15 +91,33 @@ use uuid::Uuid;
 
 #[path = "lp_swap/pubkey_banning.rs"] mod pubkey_banning;
 
+#[cfg(not(target_arch = "wasm32"))]
+#[path = "lp_swap/refund_manager.rs"]
+mod refund_manager;
+
 #[path = "lp_swap/check_balance.rs"] mod check_balance;
+#[cfg(all(test, feature = "sim_coin", not(target_arch = "wasm32")))]
+#[path = "lp_swap/sim_swap_tests.rs"]
+mod sim_swap_tests;
+#[cfg(not(target_arch = "wasm32"))]
+#[path = "lp_swap/swaps_backup.rs"]
+mod swaps_backup;
 #[path = "lp_swap/trade_preimage.rs"] mod trade_preimage;
 
 pub use check_balance::{check_other_coin_balance_for_swap, CheckBalanceError};
 pub use maker_swap::{calc_max_maker_vol, check_balance_for_maker_swap, maker_swap_trade_preimage, run_maker_swap,
                      stats_maker_swap_dir, MakerSavedSwap, MakerSwap, MakerTradePreimage, RunMakerSwapInput};
 use maker_swap::{stats_maker_swap_file_path, MakerSwapEvent};
+#[cfg(not(target_arch = "wasm32"))]
+pub use pubkey_banning::load_banned_pubkeys;
 use pubkey_banning::BanReason;
 pub use pubkey_banning::{ban_pubkey_rpc, is_pubkey_banned, list_banned_pubkeys_rpc, unban_pubkeys_rpc};
+#[cfg(not(target_arch = "wasm32"))]
+use refund_manager::SwapRefundState;
+#[cfg(not(target_arch = "wasm32"))]
+pub use refund_manager::{swap_refund_manager_loop, swap_refund_manager_status};
+#[cfg(not(target_arch = "wasm32"))]
+pub use swaps_backup::{export_swaps, restore_swaps};
 pub use taker_swap::{calc_max_taker_vol, check_balance_for_taker_swap, max_taker_vol, max_taker_vol_from_available,
                      run_taker_swap, stats_taker_swap_dir, taker_swap_trade_preimage, RunTakerSwapInput,
                      TakerSavedSwap, TakerSwap, TakerSwapPreparedParams, TakerTradePreimage};
@@ -116,6 +136,14 @@ pub enum SwapMsg {
     TakerPayment(Vec<u8>),
 }
 
+/// The swap message with the peer of the sender, it's encrypted to the counterparty.
+/// The peer is learned from the signed payload since the source of the message can be replayed by anyone.
+#[derive(Debug, Deserialize, Serialize)]
+struct EncryptedSwapMsg {
+    msg: SwapMsg,
+    sender_peer: String,
+}
+
 #[derive(Debug, Default)]
 pub struct SwapMsgStore {
     negotiation: Option<NegotiationDataMsg>,
@@ -125,15 +153,47 @@ pub struct SwapMsgStore {
     maker_payment: Option<Vec<u8>>,
     taker_payment: Option<Vec<u8>>,
     accept_only_from: bits256,
+    /// Whether the counterparty accepts the encrypted messages,
+    /// it's advertised on the order matching or proved by an encrypted message from the counterparty
+    encryption_supported: bool,
+    /// The peer signed by the counterparty in its encrypted messages, the next messages are also sent to it directly
+    counterparty_peer: Option<PeerId>,
 }
 
 impl SwapMsgStore {
-    pub fn new(accept_only_from: bits256) -> Self {
+    pub fn new(accept_only_from: bits256, encryption_supported: bool) -> Self {
         SwapMsgStore {
             accept_only_from,
+            encryption_supported,
             ..Default::default()
         }
     }
+
+    fn accept_msg(&mut self, msg: SwapMsg, from_pubkey: &PublicKey) -> bool {
+        if self.accept_only_from.bytes != from_pubkey.unprefixed() {
+            return false;
+        }
+        match msg {
+            SwapMsg::Negotiation(data) => self.negotiation = Some(data),
+            SwapMsg::NegotiationReply(data) => self.negotiation_reply = Some(data),
+            SwapMsg::Negotiated(negotiated) => self.negotiated = Some(negotiated),
+            SwapMsg::TakerFee(taker_fee) => self.taker_fee = Some(taker_fee),
+            SwapMsg::MakerPayment(maker_payment) => self.maker_payment = Some(maker_payment),
+            SwapMsg::TakerPayment(taker_payment) => self.taker_payment = Some(taker_payment),
+        }
+        true
+    }
+
+    fn accept_encrypted_msg(&mut self, msg: EncryptedSwapMsg, from_pubkey: &PublicKey) -> bool {
+        if !self.accept_msg(msg.msg, from_pubkey) {
+            return false;
+        }
+        self.encryption_supported = true;
+        if let Ok(peer) = PeerId::from_str(&msg.sender_peer) {
+            self.counterparty_peer = Some(peer);
+        }
+        true
+    }
 }
 
 /// The AbortHandle that aborts on drop
@@ -143,12 +203,24 @@ impl Drop for AbortOnDropHandle {
     fn drop(&mut self) { self.0.abort(); }
 }
 
-/// Spawns the loop that broadcasts message every `interval` seconds returning the AbortOnDropHandle
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SwapRequest {
+    /// The swap message encrypted to the counterparty pubkey
+    Msg { uuid: Uuid, msg: Vec<u8> },
+}
+
+/// Spawns the loop that sends message to the counterparty every `interval` seconds returning the AbortOnDropHandle
 /// to stop it
-pub fn broadcast_swap_message_every(ctx: MmArc, topic: String, msg: SwapMsg, interval: f64) -> AbortOnDropHandle {
+pub fn send_swap_message_every(
+    ctx: MmArc,
+    uuid: Uuid,
+    counterparty: bits256,
+    msg: SwapMsg,
+    interval: f64,
+) -> AbortOnDropHandle {
     let fut = async move {
         loop {
-            broadcast_swap_message(&ctx, topic.clone(), msg.clone());
+            send_swap_message(&ctx, uuid, &counterparty, &msg).await;
             Timer::sleep(interval).await;
         }
     };
@@ -157,44 +229,116 @@ pub fn broadcast_swap_message_every(ctx: MmArc, topic: String, msg: SwapMsg, int
     AbortOnDropHandle(abort_handle)
 }
 
-/// Broadcast the swap message once
-pub fn broadcast_swap_message(ctx: &MmArc, topic: String, msg: SwapMsg) {
+/// Sends the swap message to the `counterparty` once.
+/// The message is encrypted to the counterparty pubkey if the counterparty supports it, otherwise it's only signed
+/// to be readable by the nodes of previous versions.
+/// The encrypted message is also sent directly to the counterparty peer if it's known,
+/// the message is broadcasted to the swap topic anyway in case the peer is unreachable.
+pub async fn send_swap_message(ctx: &MmArc, uuid: Uuid, counterparty: &bits256, msg: &SwapMsg) {
     let key_pair = ctx.secp256k1_key_pair.or(&&|| panic!());
-    let encoded_msg = encode_and_sign(&msg, &*key_pair.private().secret).unwrap();
-    broadcast_p2p_msg(ctx, vec![topic], encoded_msg);
+    let (encryption_supported, counterparty_peer) = {
+        let swap_ctx = SwapsContext::from_ctx(ctx).unwrap();
+        let msgs = swap_ctx.swap_msgs.lock().unwrap();
+        match msgs.get(&uuid) {
+            Some(store) => (store.encryption_supported, store.counterparty_peer),
+            None => (false, None),
+        }
+    };
+    if !encryption_supported {
+        let encoded_msg = encode_and_sign(msg, &*key_pair.private().secret).unwrap();
+        broadcast_p2p_msg(ctx, vec![swap_topic(&uuid)], encoded_msg);
+        return;
+    }
+
+    let msg = EncryptedSwapMsg {
+        msg: msg.clone(),
+        sender_peer: ctx.peer_id.or(&&|| panic!()).clone(),
+    };
+    let encrypted = match encode_sign_and_encrypt(&msg, &*key_pair.private().secret, &counterparty.bytes) {
+        Ok(encrypted) => encrypted,
+        Err(e) => {
+            error!("Error encrypting the swap {} message: {}", uuid, e);
+            return;
+        },
+    };
+    if let Some(peer) = counterparty_peer {
+        let req = P2PRequest::Swap(SwapRequest::Msg {
+            uuid,
+            msg: encrypted.clone(),
+        });
+        if let Err(e) = request_one_peer::<()>(ctx.clone(), req, peer.to_string()).await {
+            debug!("Couldn't send the swap {} message to {} directly: {}", uuid, peer, e);
+        }
+    }
+    broadcast_p2p_msg(ctx, vec![swap_topic(&uuid)], encrypted);
 }
 
-pub fn process_msg(ctx: MmArc, topic: &str, msg: &[u8]) {
-    let uuid = match Uuid::from_str(topic) {
-        Ok(u) => u,
-        Err(_) => return,
+/// Stores the message if it's sent by the counterparty of the running swap, returns whether the message is accepted.
+/// The message is encrypted, so it's decrypted only if the swap is ours.
+fn store_encrypted_swap_msg(ctx: &MmArc, uuid: &Uuid, msg: &[u8]) -> bool {
+    let swap_ctx = SwapsContext::from_ctx(ctx).unwrap();
+    let mut msgs = swap_ctx.swap_msgs.lock().unwrap();
+    let msg_store = match msgs.get_mut(uuid) {
+        Some(store) => store,
+        None => return false,
     };
+    let key_pair = ctx.secp256k1_key_pair.or(&&|| panic!());
+    let (msg, _, pubkey) = match decrypt_and_decode_signed::<EncryptedSwapMsg>(msg, &*key_pair.private().secret) {
+        Ok(decrypted) => decrypted,
+        Err(_) => return false,
+    };
+    msg_store.accept_encrypted_msg(msg, &pubkey)
+}
+
+/// Returns an error if the message can't be decoded, so the message is rejected.
+/// The well-formed messages encrypted to other nodes are accepted to be propagated by the relays.
+pub fn process_msg(ctx: MmArc, topic: &str, msg: &[u8]) -> Result<(), P2PMessageError> {
+    let uuid = Uuid::from_str(topic).map_err(|e| P2PMessageError::Invalid(format!("Invalid swap topic: {}", e)))?;
+    if is_encrypted(msg) {
+        store_encrypted_swap_msg(&ctx, &uuid, msg);
+        return Ok(());
+    }
+
+    // the plain signed messages sent by the nodes of previous versions
     let msg = match decode_signed::<SwapMsg>(msg) {
         Ok(m) => m,
         Err(swap_msg_err) => {
-            match json::from_slice::<SwapStatus>(msg) {
-                Ok(status) => save_stats_swap(&ctx, &status.data).unwrap(),
-                Err(swap_status_err) => {
-                    error!("Couldn't deserialize 'SwapMsg': {:?}", swap_msg_err);
-                    error!("Couldn't deserialize 'SwapStatus': {:?}", swap_status_err);
+            return match json::from_slice::<SwapStatus>(msg) {
+                Ok(status) => {
+                    save_stats_swap(&ctx, &status.data).unwrap();
+                    Ok(())
                 },
+                Err(swap_status_err) => Err(P2PMessageError::from_decode_signed_error(
+                    msg,
+                    format!(
+                        "Couldn't deserialize 'SwapMsg': {:?}, 'SwapStatus': {:?}",
+                        swap_msg_err, swap_status_err
+                    ),
+                )),
             };
-            return;
         },
     };
     let swap_ctx = SwapsContext::from_ctx(&ctx).unwrap();
     let mut msgs = swap_ctx.swap_msgs.lock().unwrap();
     if let Some(msg_store) = msgs.get_mut(&uuid) {
-        if msg_store.accept_only_from.bytes == msg.2.unprefixed() {
-            match msg.0 {
-                SwapMsg::Negotiation(data) => msg_store.negotiation = Some(data),
-                SwapMsg::NegotiationReply(data) => msg_store.negotiation_reply = Some(data),
-                SwapMsg::Negotiated(negotiated) => msg_store.negotiated = Some(negotiated),
-                SwapMsg::TakerFee(taker_fee) => msg_store.taker_fee = Some(taker_fee),
-                SwapMsg::MakerPayment(maker_payment) => msg_store.maker_payment = Some(maker_payment),
-                SwapMsg::TakerPayment(taker_payment) => msg_store.taker_payment = Some(taker_payment),
+        msg_store.accept_msg(msg.0, &msg.2);
+    }
+    Ok(())
+}
+
+/// Processes the swap message sent directly by the counterparty.
+pub async fn process_peer_request(
+    ctx: MmArc,
+    from_peer: PeerId,
+    request: SwapRequest,
+) -> Result<Option<Vec<u8>>, String> {
+    match request {
+        SwapRequest::Msg { uuid, msg } => {
+            if !is_encrypted(&msg) || !store_encrypted_swap_msg(&ctx, &uuid, &msg) {
+                return ERR!("Unexpected message of the swap {} from {}", uuid, from_peer);
             }
-        }
+            Ok(None)
+        },
     }
 }
 
@@ -206,7 +350,7 @@ async fn recv_swap_msg<T>(
     uuid: &Uuid,
     timeout: u64,
 ) -> Result<T, String> {
-    let started = now_ms() / 1000;
+    let started = swap_now();
     let timeout = BASIC_COMM_TIMEOUT + timeout;
     let wait_until = started + timeout;
     loop {
@@ -218,7 +362,7 @@ async fn recv_swap_msg<T>(
                 return Ok(msg);
             }
         }
-        let now = now_ms() / 1000;
+        let now = swap_now();
         if now > wait_until {
             return ERR!("Timeout ({} > {})", now - started, timeout);
         }
@@ -237,8 +381,11 @@ const _SWAP_DEFAULT_NUM_CONFIRMS: u32 = 1;
 const _SWAP_DEFAULT_MAX_CONFIRMS: u32 = 6;
 /// MM2 checks that swap payment is confirmed every WAIT_CONFIRM_INTERVAL seconds
 const WAIT_CONFIRM_INTERVAL: u64 = 15;
+/// The payment can be refunded when the median time past of the chain reaches the locktime,
+/// it lags behind the current time for about an hour.
+const REFUND_LOCKTIME_DELAY: u64 = 3700;
 
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum RecoveredSwapAction {
     RefundedMyPayment,
     SpentOtherPayment,
@@ -267,9 +414,11 @@ pub trait AtomicSwap: Send + Sync {
     fn maker_coin(&self) -> &str;
 
     fn taker_coin(&self) -> &str;
+
+    fn other_pubkey(&self) -> bits256;
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(tag = "type", content = "event")]
 pub enum SwapEvent {
     Maker(MakerSwapEvent),
@@ -294,6 +443,9 @@ struct SwapsContext {
     /// Very unpleasant consequences
     shutdown_rx: async_std_sync::Receiver<()>,
     swap_msgs: Mutex<HashMap<Uuid, SwapMsgStore>>,
+    /// The states of the finished swaps handled by the refund manager.
+    #[cfg(not(target_arch = "wasm32"))]
+    refund_states: Mutex<HashMap<Uuid, SwapRefundState>>,
 }
 
 impl SwapsContext {
@@ -318,13 +470,15 @@ impl SwapsContext {
                 running_swaps: Mutex::new(vec![]),
                 banned_pubkeys: Mutex::new(HashMap::new()),
                 swap_msgs: Mutex::new(HashMap::new()),
+                #[cfg(not(target_arch = "wasm32"))]
+                refund_states: Mutex::new(HashMap::new()),
                 shutdown_rx,
             })
         })))
     }
 
-    pub fn init_msg_store(&self, uuid: Uuid, accept_only_from: bits256) {
-        let store = SwapMsgStore::new(accept_only_from);
+    pub fn init_msg_store(&self, uuid: Uuid, accept_only_from: bits256, encryption_supported: bool) {
+        let store = SwapMsgStore::new(accept_only_from, encryption_supported);
         self.swap_msgs.lock().unwrap().insert(uuid, store);
     }
 }
@@ -362,6 +516,17 @@ pub fn running_swaps_num(ctx: &MmArc) -> u64 {
     })
 }
 
+/// Get number of currently running swaps with the selected counterparty
+pub fn running_swaps_num_with_pubkey(ctx: &MmArc, pubkey: &bits256) -> usize {
+    let swap_ctx = SwapsContext::from_ctx(ctx).unwrap();
+    let swaps = swap_ctx.running_swaps.lock().unwrap();
+    swaps
+        .iter()
+        .filter_map(|swap| swap.upgrade())
+        .filter(|swap| swap.other_pubkey() == *pubkey)
+        .count()
+}
+
 /// Get total amount of selected coin locked by all currently ongoing swaps except the one with selected uuid
 fn get_locked_amount_by_other_swaps(ctx: &MmArc, except_uuid: &Uuid, coin: &str) -> MmNumber {
     let swap_ctx = SwapsContext::from_ctx(ctx).unwrap();
@@ -603,10 +768,29 @@ pub struct TransactionIdentifier {
     tx_hash: BytesJson,
 }
 
+/// The current time in seconds the swap deadlines are checked against.
+/// The simulated swaps replace it with the time of the simulated chain.
+#[cfg_attr(test, mockable)]
+fn swap_now() -> u64 { now_ms() / 1000 }
+
 pub fn my_swaps_dir(ctx: &MmArc) -> PathBuf { ctx.dbdir().join("SWAPS").join("MY") }
 
 pub fn my_swap_file_path(ctx: &MmArc, uuid: &Uuid) -> PathBuf { my_swaps_dir(ctx).join(format!("{}.json", uuid)) }
 
+/// Returns the info of my saved swaps started at `since` (in seconds) or later.
+pub fn my_swaps_started_since(ctx: &MmArc, since: u64) -> Result<Vec<MySwapInfo>, String> {
+    let entries = try_s!(read_dir(&my_swaps_dir(ctx)));
+    let infos = entries
+        .into_iter()
+        // the swap file is modified on every swap event, so the file modified earlier can't belong to the swap started later
+        .filter(|(last_modified, path)| *last_modified / 1000 >= since && path.extension() == Some(OsStr::new("json")))
+        .filter_map(|(_, path)| json::from_slice::<SavedSwap>(&slurp(&path).ok()?).ok())
+        .filter_map(|swap| swap.get_my_info())
+        .filter(|info| info.started_at >= since)
+        .collect();
+    Ok(infos)
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub fn insert_new_swap_to_db(
     ctx: &MmArc,
@@ -667,19 +851,35 @@ pub enum SavedSwap {
 pub struct MySwapInfo {
     pub my_coin: String,
     pub other_coin: String,
-    my_amount: BigDecimal,
+    pub my_amount: BigDecimal,
     other_amount: BigDecimal,
     pub started_at: u64,
 }
 
 impl SavedSwap {
-    fn is_finished(&self) -> bool {
+    /// Loads my swap with the `uuid`, returns `None` if there is no such swap.
+    pub fn load_my_swap(ctx: &MmArc, uuid: &Uuid) -> Result<Option<SavedSwap>, String> {
+        let content = try_s!(slurp(&my_swap_file_path(ctx, uuid)));
+        if content.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(try_s!(json::from_slice(&content))))
+    }
+
+    pub fn is_finished(&self) -> bool {
         match self {
             SavedSwap::Maker(swap) => swap.is_finished(),
             SavedSwap::Taker(swap) => swap.is_finished(),
         }
     }
 
+    pub fn is_success(&self) -> Result<bool, String> {
+        match self {
+            SavedSwap::Maker(swap) => swap.is_success(),
+            SavedSwap::Taker(swap) => swap.is_success(),
+        }
+    }
+
     pub fn uuid(&self) -> &Uuid {
         match self {
             SavedSwap::Maker(swap) => &swap.uuid,
@@ -708,22 +908,35 @@ impl SavedSwap {
         }
     }
 
-    fn recover_funds(self, ctx: MmArc) -> Result<RecoveredSwap, String> {
+    /// Returns the timestamp after which my payment can be refunded.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn wait_refund_until(&self) -> Result<u64, String> {
+        match self {
+            SavedSwap::Maker(swap) => swap.wait_refund_until(),
+            SavedSwap::Taker(swap) => swap.wait_refund_until(),
+        }
+    }
+
+    /// Finds the activated maker and taker coins of the swap.
+    async fn find_coins(&self, ctx: &MmArc) -> Result<(MmCoinEnum, MmCoinEnum), String> {
         let maker_ticker = try_s!(self.maker_coin_ticker());
-        // Should remove `block_on` when recover_funds is async.
-        let maker_coin = match block_on(lp_coinfind(&ctx, &maker_ticker)) {
+        let maker_coin = match lp_coinfind(ctx, &maker_ticker).await {
             Ok(Some(c)) => c,
             Ok(None) => return ERR!("Coin {} is not activated", maker_ticker),
             Err(e) => return ERR!("Error {} on {} coin find attempt", e, maker_ticker),
         };
 
         let taker_ticker = try_s!(self.taker_coin_ticker());
-        // Should remove `block_on` when recover_funds is async.
-        let taker_coin = match block_on(lp_coinfind(&ctx, &taker_ticker)) {
+        let taker_coin = match lp_coinfind(ctx, &taker_ticker).await {
             Ok(Some(c)) => c,
             Ok(None) => return ERR!("Coin {} is not activated", taker_ticker),
             Err(e) => return ERR!("Error {} on {} coin find attempt", e, taker_ticker),
         };
+        Ok((maker_coin, taker_coin))
+    }
+
+    async fn recover_funds(self, ctx: MmArc) -> Result<RecoveredSwap, String> {
+        let (maker_coin, taker_coin) = try_s!(self.find_coins(&ctx).await);
         match self {
             SavedSwap::Maker(saved) => {
                 let (maker_swap, _) = try_s!(MakerSwap::load_from_saved(ctx, maker_coin, taker_coin, saved));
@@ -752,6 +965,23 @@ impl SavedSwap {
         try_s!(std::fs::write(path, &content));
         Ok(())
     }
+
+    /// Saves the swap received from outside (e.g. `import_swaps` or a swaps backup) and adds it to the swaps index.
+    fn import_to_db(&self, ctx: &MmArc) -> Result<(), String> {
+        try_s!(self.save_to_db(ctx));
+        if let Some(info) = self.get_my_info() {
+            if let Err(e) = insert_new_swap_to_db(
+                ctx,
+                &info.my_coin,
+                &info.other_coin,
+                &self.uuid().to_string(),
+                &info.started_at.to_string(),
+            ) {
+                error!("Error {} on new swap insertion", e);
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
@@ -1126,7 +1356,7 @@ pub async fn recover_funds_of_swap(ctx: MmArc, req: Json) -> Result<Response<Vec
 
     let swap: SavedSwap = try_s!(json::from_slice(&content));
 
-    let recover_data = try_s!(swap.recover_funds(ctx));
+    let recover_data = try_s!(swap.recover_funds(ctx).await);
     let res = try_s!(json::to_vec(&json!({
         "result": {
             "action": recover_data.action,
@@ -1143,21 +1373,8 @@ pub async fn import_swaps(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, St
     let mut imported = vec![];
     let mut skipped = HashMap::new();
     for swap in swaps {
-        match swap.save_to_db(&ctx) {
-            Ok(_) => {
-                if let Some(info) = swap.get_my_info() {
-                    if let Err(e) = insert_new_swap_to_db(
-                        &ctx,
-                        &info.my_coin,
-                        &info.other_coin,
-                        &swap.uuid().to_string(),
-                        &info.started_at.to_string(),
-                    ) {
-                        error!("Error {} on new swap insertion", e);
-                    }
-                }
-                imported.push(swap.uuid().to_owned());
-            },
+        match swap.import_to_db(&ctx) {
+            Ok(_) => imported.push(swap.uuid().to_owned()),
             Err(e) => {
                 skipped.insert(swap.uuid().to_owned(), e);
             },
@@ -1498,4 +1715,29 @@ mod lp_swap_tests {
 
         assert_eq!(deserialized, v2);
     }
+
+    #[test]
+    fn test_swap_msg_store_encrypted_msg() {
+        let pubkey_of = |secret: &[u8; 32]| {
+            let signed = encode_and_sign(&(), secret).unwrap();
+            decode_signed::<()>(&signed).unwrap().2
+        };
+        let counterparty = pubkey_of(&[1; 32]);
+        let other = pubkey_of(&[2; 32]);
+        let peer = PeerId::random();
+        let encrypted_msg = || EncryptedSwapMsg {
+            msg: SwapMsg::Negotiated(true),
+            sender_peer: peer.to_string(),
+        };
+
+        let mut store = SwapMsgStore::new(counterparty.unprefixed().into(), false);
+        assert!(!store.accept_encrypted_msg(encrypted_msg(), &other));
+        assert!(!store.encryption_supported);
+        assert_eq!(store.counterparty_peer, None);
+
+        assert!(store.accept_encrypted_msg(encrypted_msg(), &counterparty));
+        assert!(store.encryption_supported);
+        assert_eq!(store.counterparty_peer, Some(peer));
+        assert_eq!(store.negotiated, Some(true));
+    }
 }
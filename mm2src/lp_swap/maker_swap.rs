@@ -2,11 +2,11 @@ use super::check_balance::{check_base_coin_balance_for_swap, check_my_coin_balan
                            CheckBalanceResult};
 use super::pubkey_banning::ban_pubkey_on_failed_swap;
 use super::trade_preimage::{TradePreimageRequest, TradePreimageRpcError, TradePreimageRpcResult};
-use super::{broadcast_my_swap_status, broadcast_swap_message_every, check_other_coin_balance_for_swap,
-            dex_fee_amount_from_taker_coin, get_locked_amount, my_swap_file_path, my_swaps_dir, recv_swap_msg,
+use super::{broadcast_my_swap_status, check_other_coin_balance_for_swap, dex_fee_amount_from_taker_coin,
+            get_locked_amount, my_swap_file_path, my_swaps_dir, recv_swap_msg, send_swap_message_every, swap_now,
             swap_topic, AtomicSwap, LockedAmount, MySwapInfo, NegotiationDataMsg, NegotiationDataV2, RecoveredSwap,
             RecoveredSwapAction, SavedSwap, SavedTradeFee, SwapConfirmationsSettings, SwapError, SwapMsg,
-            SwapsContext, TransactionIdentifier, WAIT_CONFIRM_INTERVAL};
+            SwapsContext, TransactionIdentifier, REFUND_LOCKTIME_DELAY, WAIT_CONFIRM_INTERVAL};
 
 use crate::mm2::lp_network::subscribe_to_topic;
 use crate::mm2::lp_ordermatch::{MakerOrderBuilder, OrderConfirmationsSettings};
@@ -163,6 +163,8 @@ pub struct MakerSwap {
     mutable: RwLock<MakerSwapMut>,
     conf_settings: SwapConfirmationsSettings,
     payment_locktime: u64,
+    /// Whether the taker accepts the swap messages encrypted to its pubkey
+    swap_msg_encryption: bool,
 }
 
 impl MakerSwap {
@@ -174,7 +176,7 @@ impl MakerSwap {
         rng.gen()
     }
 
-    fn wait_refund_until(&self) -> u64 { self.r().data.maker_payment_lock + 3700 }
+    fn wait_refund_until(&self) -> u64 { self.r().data.maker_payment_lock + REFUND_LOCKTIME_DELAY }
 
     fn apply_event(&self, event: MakerSwapEvent) {
         match event {
@@ -249,6 +251,7 @@ impl MakerSwap {
         maker_coin: MmCoinEnum,
         taker_coin: MmCoinEnum,
         payment_locktime: u64,
+        swap_msg_encryption: bool,
     ) -> Self {
         MakerSwap {
             ctx,
@@ -266,6 +269,7 @@ impl MakerSwap {
             taker_payment_confirmed: AtomicBool::new(false),
             conf_settings,
             payment_locktime,
+            swap_msg_encryption,
             mutable: RwLock::new(MakerSwapMut {
                 data: MakerSwapData::default(),
                 other_persistent_pub: H264::default(),
@@ -326,7 +330,7 @@ impl MakerSwap {
         };
 
         let secret = self.generate_secret();
-        let started_at = now_ms() / 1000;
+        let started_at = swap_now();
         let maker_coin_start_block = match self.maker_coin.current_block().compat().await {
             Ok(b) => b,
             Err(e) => {
@@ -387,9 +391,10 @@ impl MakerSwap {
         }));
         const NEGOTIATION_TIMEOUT: u64 = 90;
 
-        let send_abort_handle = broadcast_swap_message_every(
+        let send_abort_handle = send_swap_message_every(
             self.ctx.clone(),
-            swap_topic(&self.uuid),
+            self.uuid,
+            self.taker,
             maker_negotiation_data,
             NEGOTIATION_TIMEOUT as f64 / 6.,
         );
@@ -464,9 +469,10 @@ impl MakerSwap {
     async fn wait_taker_fee(&self) -> Result<(Option<MakerSwapCommand>, Vec<MakerSwapEvent>), String> {
         const TAKER_FEE_RECV_TIMEOUT: u64 = 180;
         let negotiated = SwapMsg::Negotiated(true);
-        let send_abort_handle = broadcast_swap_message_every(
+        let send_abort_handle = send_swap_message_every(
             self.ctx.clone(),
-            swap_topic(&self.uuid),
+            self.uuid,
+            self.taker,
             negotiated,
             TAKER_FEE_RECV_TIMEOUT as f64 / 6.,
         );
@@ -543,7 +549,7 @@ impl MakerSwap {
 
     async fn maker_payment(&self) -> Result<(Option<MakerSwapCommand>, Vec<MakerSwapEvent>), String> {
         let timeout = self.r().data.started_at + self.r().data.lock_duration / 3;
-        let now = now_ms() / 1000;
+        let now = swap_now();
         if now > timeout {
             return Ok((Some(MakerSwapCommand::Finish), vec![
                 MakerSwapEvent::MakerPaymentTransactionFailed(ERRL!("Timeout {} > {}", now, timeout).into()),
@@ -618,7 +624,7 @@ impl MakerSwap {
     async fn wait_for_taker_payment(&self) -> Result<(Option<MakerSwapCommand>, Vec<MakerSwapEvent>), String> {
         let maker_payment_hex = self.r().maker_payment.as_ref().unwrap().tx_hex.0.clone();
         let msg = SwapMsg::MakerPayment(maker_payment_hex);
-        let abort_send_handle = broadcast_swap_message_every(self.ctx.clone(), swap_topic(&self.uuid), msg, 600.);
+        let abort_send_handle = send_swap_message_every(self.ctx.clone(), self.uuid, self.taker, msg, 600.);
 
         let maker_payment_wait_confirm = self.r().data.started_at + (self.r().data.lock_duration * 2) / 5;
         let f = self.maker_coin.wait_for_confirmations(
@@ -740,7 +746,7 @@ impl MakerSwap {
         let duration = (self.r().data.lock_duration * 4) / 5;
         let timeout = self.r().data.started_at + duration;
 
-        let now = now_ms() / 1000;
+        let now = swap_now();
         if now > timeout {
             return Ok((Some(MakerSwapCommand::RefundMakerPayment), vec![
                 MakerSwapEvent::TakerPaymentSpendFailed(ERRL!("Timeout {} > {}", now, timeout).into()),
@@ -918,6 +924,8 @@ impl MakerSwap {
             maker_coin,
             taker_coin,
             data.lock_duration,
+            // the restarted swap switches to the encryption once the taker sends an encrypted message
+            false,
         );
         let command = saved.events.last().unwrap().get_command();
         for saved_event in saved.events {
@@ -926,7 +934,36 @@ impl MakerSwap {
         Ok((swap, command))
     }
 
-    pub fn recover_funds(&self) -> Result<RecoveredSwap, String> {
+    /// Returns the transaction that refunded the maker payment if it has been refunded already.
+    pub fn find_maker_payment_refund(&self) -> Result<Option<TransactionEnum>, String> {
+        let maker_payment = match &self.r().maker_payment {
+            Some(tx) => tx.tx_hex.0.clone(),
+            None => return Ok(None),
+        };
+        let secret_hash = self
+            .r()
+            .data
+            .secret_hash
+            .clone()
+            .unwrap_or_else(|| dhash160(&self.r().data.secret.0).into());
+
+        match try_s!(self.maker_coin.search_for_swap_tx_spend_my(
+            self.r().data.maker_payment_lock as u32,
+            &*self.r().other_persistent_pub,
+            &secret_hash.0,
+            &maker_payment,
+            self.r().data.maker_coin_start_block,
+            &self.r().data.maker_coin_swap_contract_address,
+        )) {
+            Some(FoundSwapTxSpend::Refunded(tx)) => Ok(Some(tx)),
+            Some(FoundSwapTxSpend::Spent(_)) | None => Ok(None),
+        }
+    }
+
+    pub fn recover_funds(&self) -> Result<RecoveredSwap, String> { self.recover_funds_with_fee_multiplier(1) }
+
+    /// Recovers the funds of the finished swap paying `fee_multiplier` times the regular fee if the refund is required.
+    pub fn recover_funds_with_fee_multiplier(&self, fee_multiplier: u64) -> Result<RecoveredSwap, String> {
         fn try_spend_taker_payment(selfi: &MakerSwap, secret_hash: &[u8]) -> Result<TransactionEnum, String> {
             let taker_payment_hex = &selfi
                 .r()
@@ -1042,20 +1079,21 @@ impl MakerSwap {
             Ok(None) => {
                 // our payment is not spent, try to refund
                 log!("Trying to refund MakerPayment");
-                if now_ms() / 1000 < self.r().data.maker_payment_lock + 3700 {
+                if swap_now() < self.r().data.maker_payment_lock + REFUND_LOCKTIME_DELAY {
                     return ERR!(
                         "Too early to refund, wait until {}",
-                        self.r().data.maker_payment_lock + 3700
+                        self.r().data.maker_payment_lock + REFUND_LOCKTIME_DELAY
                     );
                 }
                 let transaction = try_s!(self
                     .maker_coin
-                    .send_maker_refunds_payment(
+                    .send_maker_refunds_payment_with_fee_multiplier(
                         &maker_payment,
                         self.r().data.maker_payment_lock as u32,
                         &*self.r().other_persistent_pub,
                         &secret_hash.0,
                         &self.r().data.maker_coin_swap_contract_address,
+                        fee_multiplier,
                     )
                     .wait());
 
@@ -1101,6 +1139,8 @@ impl AtomicSwap for MakerSwap {
     fn maker_coin(&self) -> &str { self.maker_coin.ticker() }
 
     fn taker_coin(&self) -> &str { self.taker_coin.ticker() }
+
+    fn other_pubkey(&self) -> bits256 { self.taker }
 }
 
 #[derive(Debug)]
@@ -1334,6 +1374,12 @@ impl MakerSavedSwap {
         true
     }
 
+    /// Returns the timestamp after which the maker payment can be refunded.
+    pub fn wait_refund_until(&self) -> Result<u64, String> {
+        let data = try_s!(self.swap_data());
+        Ok(data.maker_payment_lock + REFUND_LOCKTIME_DELAY)
+    }
+
     pub fn swap_data(&self) -> Result<&MakerSwapData, String> {
         match self.events.first() {
             Some(event) => match &event.event {
@@ -1464,7 +1510,7 @@ pub async fn run_maker_swap(swap: RunMakerSwapInput, ctx: MmArc) {
     let running_swap = Arc::new(swap);
     let weak_ref = Arc::downgrade(&running_swap);
     let swap_ctx = SwapsContext::from_ctx(&ctx).unwrap();
-    swap_ctx.init_msg_store(running_swap.uuid, running_swap.taker);
+    swap_ctx.init_msg_store(running_swap.uuid, running_swap.taker, running_swap.swap_msg_encryption);
     swap_ctx.running_swaps.lock().unwrap().push(weak_ref);
     let shutdown_rx = swap_ctx.shutdown_rx.clone();
     let swap_for_log = running_swap.clone();
@@ -1,13 +1,15 @@
 use super::{SwapEvent, SwapsContext};
 use chain::hash::H256;
+#[cfg(not(target_arch = "wasm32"))] use common::log::error;
 use common::mm_ctx::MmArc;
 use http::Response;
 use rpc::v1::types::H256 as H256Json;
 use serde_json::{self as json, Value as Json};
 use std::collections::hash_map::{Entry, HashMap};
+#[cfg(not(target_arch = "wasm32"))] use std::path::PathBuf;
 use uuid::Uuid;
 
-#[derive(Serialize)]
+#[derive(Deserialize, Serialize)]
 #[serde(tag = "type")]
 #[allow(clippy::large_enum_variant)]
 pub enum BanReason {
@@ -20,13 +22,42 @@ pub enum BanReason {
     },
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn banned_pubkeys_file_path(ctx: &MmArc) -> PathBuf { ctx.dbdir().join("BANNED_PUBKEYS.json") }
+
+/// Saves the banned pubkeys, so the bans survive the restart.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_banned_pubkeys(ctx: &MmArc, banned: &HashMap<H256Json, BanReason>) {
+    let content = json::to_vec(banned).expect("Serialization failed");
+    if let Err(e) = common::write(&banned_pubkeys_file_path(ctx), &content) {
+        error!("Error {} on saving the banned pubkeys", e);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn save_banned_pubkeys(_ctx: &MmArc, _banned: &HashMap<H256Json, BanReason>) {}
+
+/// Loads the pubkeys banned on the previous runs.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_banned_pubkeys(ctx: &MmArc) -> Result<(), String> {
+    let content = gstuff::slurp(&banned_pubkeys_file_path(ctx));
+    if content.is_empty() {
+        return Ok(());
+    }
+    let loaded: HashMap<H256Json, BanReason> = try_s!(json::from_slice(&content));
+    let ctx = try_s!(SwapsContext::from_ctx(ctx));
+    try_s!(ctx.banned_pubkeys.lock()).extend(loaded);
+    Ok(())
+}
+
 pub fn ban_pubkey_on_failed_swap(ctx: &MmArc, pubkey: H256, swap_uuid: &Uuid, event: SwapEvent) {
-    let ctx = SwapsContext::from_ctx(ctx).unwrap();
-    let mut banned = ctx.banned_pubkeys.lock().unwrap();
+    let swap_ctx = SwapsContext::from_ctx(ctx).unwrap();
+    let mut banned = swap_ctx.banned_pubkeys.lock().unwrap();
     banned.insert(pubkey.into(), BanReason::FailedSwap {
         caused_by_swap: *swap_uuid,
         caused_by_event: event,
     });
+    save_banned_pubkeys(ctx, &banned);
 }
 
 pub fn is_pubkey_banned(ctx: &MmArc, pubkey: &H256Json) -> bool {
@@ -51,13 +82,14 @@ struct BanPubkeysReq {
 
 pub async fn ban_pubkey_rpc(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
     let req: BanPubkeysReq = try_s!(json::from_value(req));
-    let ctx = try_s!(SwapsContext::from_ctx(&ctx));
-    let mut banned_pubs = try_s!(ctx.banned_pubkeys.lock());
+    let swap_ctx = try_s!(SwapsContext::from_ctx(&ctx));
+    let mut banned_pubs = try_s!(swap_ctx.banned_pubkeys.lock());
 
     match banned_pubs.entry(req.pubkey) {
         Entry::Occupied(_) => ERR!("Pubkey is banned already"),
         Entry::Vacant(entry) => {
             entry.insert(BanReason::Manual { reason: req.reason });
+            save_banned_pubkeys(&ctx, &banned_pubs);
             let res = try_s!(json::to_vec(&json!({
                 "result": "success",
             })));
@@ -75,8 +107,8 @@ enum UnbanPubkeysReq {
 
 pub async fn unban_pubkeys_rpc(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>>, String> {
     let req: UnbanPubkeysReq = try_s!(json::from_value(req["unban_by"].clone()));
-    let ctx = try_s!(SwapsContext::from_ctx(&ctx));
-    let mut banned_pubs = try_s!(ctx.banned_pubkeys.lock());
+    let swap_ctx = try_s!(SwapsContext::from_ctx(&ctx));
+    let mut banned_pubs = try_s!(swap_ctx.banned_pubkeys.lock());
     let mut unbanned = HashMap::new();
     let mut were_not_banned = vec![];
     match req {
@@ -94,6 +126,9 @@ pub async fn unban_pubkeys_rpc(ctx: MmArc, req: Json) -> Result<Response<Vec<u8>
             }
         },
     }
+    if !unbanned.is_empty() {
+        save_banned_pubkeys(&ctx, &banned_pubs);
+    }
     let res = try_s!(json::to_vec(&json!({
         "result": {
             "still_banned": *banned_pubs,
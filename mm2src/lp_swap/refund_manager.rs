@@ -0,0 +1,386 @@
+//! The background manager recovering the funds of the finished swaps which payments were not refunded.
+//!
+//! The manager checks the finished recoverable swaps on startup and then every `check_interval` seconds.
+//! Each failed refund attempt increases the fee of the next one up to `max_fee_multiplier` times the regular fee.
+//! The manager gives up after `max_attempts` failed attempts, such swaps have to be recovered manually.
+//! The refund states are saved to the `SWAPS/REFUND_STATES.json` file to survive the restarts.
+
+use super::{my_swaps_dir, MakerSwap, RecoveredSwap, RecoveredSwapAction, SavedSwap, SwapsContext, TakerSwap};
+use coins::{MmCoinEnum, TransactionEnum};
+use common::executor::Timer;
+use common::log::{error, info, warn};
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::wio::CORE;
+use common::{json_dir_entries, now_ms, write, HttpStatusCode};
+use derive_more::Display;
+use gstuff::slurp;
+use http::StatusCode;
+use rpc::v1::types::Bytes as BytesJson;
+use serde_json::{self as json, Value as Json};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+pub type RefundManagerResult<T> = Result<T, MmError<RefundManagerError>>;
+
+const DEFAULT_CHECK_INTERVAL: f64 = 600.;
+const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+const DEFAULT_MAX_FEE_MULTIPLIER: u64 = 4;
+
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum RefundManagerError {
+    #[display(fmt = "Invalid 'swap_refund_manager' config: {}", _0)]
+    InvalidConfig(String),
+    #[display(fmt = "Internal error: {}", _0)]
+    Internal(String),
+}
+
+impl HttpStatusCode for RefundManagerError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RefundManagerError::InvalidConfig(_) | RefundManagerError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// The refund manager settings set by the `swap_refund_manager` field of the MM2 config.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RefundManagerConf {
+    #[serde(default = "get_true")]
+    enabled: bool,
+    /// The interval between the checks of the finished swaps in seconds.
+    #[serde(default = "default_check_interval")]
+    check_interval: f64,
+    /// The number of failed refund attempts after which the manager gives up.
+    #[serde(default = "default_max_attempts")]
+    max_attempts: u32,
+    /// The upper limit of the refund fee in the regular refund fees.
+    #[serde(default = "default_max_fee_multiplier")]
+    max_fee_multiplier: u64,
+}
+
+impl Default for RefundManagerConf {
+    fn default() -> Self {
+        RefundManagerConf {
+            enabled: true,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            max_fee_multiplier: DEFAULT_MAX_FEE_MULTIPLIER,
+        }
+    }
+}
+
+impl RefundManagerConf {
+    fn from_ctx(ctx: &MmArc) -> Result<RefundManagerConf, String> {
+        let conf = &ctx.conf["swap_refund_manager"];
+        if conf.is_null() {
+            return Ok(RefundManagerConf::default());
+        }
+        Ok(try_s!(json::from_value(conf.clone())))
+    }
+
+    /// The `attempt` refund pays `attempt` times the regular fee, but no more than `max_fee_multiplier` times.
+    fn fee_multiplier(&self, attempt: u32) -> u64 { (attempt as u64).min(self.max_fee_multiplier).max(1) }
+}
+
+fn get_true() -> bool { true }
+
+fn default_check_interval() -> f64 { DEFAULT_CHECK_INTERVAL }
+
+fn default_max_attempts() -> u32 { DEFAULT_MAX_ATTEMPTS }
+
+fn default_max_fee_multiplier() -> u64 { DEFAULT_MAX_FEE_MULTIPLIER }
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "state")]
+pub enum SwapRefundState {
+    /// The funds are not recovered yet, the manager will retry on the next check.
+    Pending {
+        attempts: u32,
+        last_attempt_at: u64,
+        last_error: String,
+    },
+    /// The funds have been recovered by the manager.
+    Recovered {
+        attempts: u32,
+        action: RecoveredSwapAction,
+        coin: String,
+        tx_hash: BytesJson,
+        tx_hex: BytesJson,
+    },
+    /// My payment had been refunded before the manager found it.
+    AlreadyRefunded { tx_hash: BytesJson },
+    /// The manager has given up, the funds have to be recovered manually.
+    GaveUp { attempts: u32, last_error: String },
+}
+
+impl SwapRefundState {
+    fn is_final(&self) -> bool { !matches!(self, SwapRefundState::Pending { .. }) }
+
+    fn attempts(&self) -> u32 {
+        match self {
+            SwapRefundState::Pending { attempts, .. }
+            | SwapRefundState::Recovered { attempts, .. }
+            | SwapRefundState::GaveUp { attempts, .. } => *attempts,
+            SwapRefundState::AlreadyRefunded { .. } => 0,
+        }
+    }
+
+    fn pending(attempts: u32, last_error: String) -> SwapRefundState {
+        SwapRefundState::Pending {
+            attempts,
+            last_attempt_at: now_ms() / 1000,
+            last_error,
+        }
+    }
+}
+
+enum RecoveryAttempt {
+    AlreadyRefunded(TransactionEnum),
+    Recovered(RecoveredSwap),
+}
+
+fn refund_states_file_path(ctx: &MmArc) -> PathBuf { ctx.dbdir().join("SWAPS").join("REFUND_STATES.json") }
+
+fn load_refund_states(ctx: &MmArc) -> HashMap<Uuid, SwapRefundState> {
+    let content = slurp(&refund_states_file_path(ctx));
+    if content.is_empty() {
+        return HashMap::new();
+    }
+    match json::from_slice(&content) {
+        Ok(states) => states,
+        Err(e) => {
+            error!("Error {} parsing the swap refund states, starting from scratch", e);
+            HashMap::new()
+        },
+    }
+}
+
+fn save_refund_states(ctx: &MmArc, states: &HashMap<Uuid, SwapRefundState>) {
+    let content = match json::to_vec(states) {
+        Ok(content) => content,
+        Err(e) => {
+            error!("Error {} serializing the swap refund states", e);
+            return;
+        },
+    };
+    if let Err(e) = write(&refund_states_file_path(ctx), &content) {
+        error!("Error {} saving the swap refund states", e);
+    }
+}
+
+fn my_recoverable_swaps(ctx: &MmArc) -> Vec<SavedSwap> {
+    let entries = match json_dir_entries(&my_swaps_dir(ctx)) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Error {} reading my swaps dir", e);
+            return Vec::new();
+        },
+    };
+    entries
+        .iter()
+        .filter_map(|entry| json::from_slice::<SavedSwap>(&slurp(&entry.path())).ok())
+        .filter(|swap| swap.is_recoverable())
+        .collect()
+}
+
+/// Checks whether my payment has been refunded already and tries to recover the funds otherwise.
+/// The swap recovery waits for the coin RPC responses, so it's run on the blocking thread not to stall the executor.
+async fn try_recover_funds(
+    ctx: MmArc,
+    maker_coin: MmCoinEnum,
+    taker_coin: MmCoinEnum,
+    swap: SavedSwap,
+    fee_multiplier: u64,
+) -> Result<RecoveryAttempt, String> {
+    try_s!(
+        CORE.0
+            .spawn_blocking(move || recover_funds_blocking(ctx, maker_coin, taker_coin, swap, fee_multiplier))
+            .await
+    )
+}
+
+fn recover_funds_blocking(
+    ctx: MmArc,
+    maker_coin: MmCoinEnum,
+    taker_coin: MmCoinEnum,
+    swap: SavedSwap,
+    fee_multiplier: u64,
+) -> Result<RecoveryAttempt, String> {
+    match swap {
+        SavedSwap::Maker(saved) => {
+            let (maker_swap, _) = try_s!(MakerSwap::load_from_saved(ctx, maker_coin, taker_coin, saved));
+            if let Some(tx) = try_s!(maker_swap.find_maker_payment_refund()) {
+                return Ok(RecoveryAttempt::AlreadyRefunded(tx));
+            }
+            let recovered = try_s!(maker_swap.recover_funds_with_fee_multiplier(fee_multiplier));
+            Ok(RecoveryAttempt::Recovered(recovered))
+        },
+        SavedSwap::Taker(saved) => {
+            let (taker_swap, _) = try_s!(TakerSwap::load_from_saved(ctx, maker_coin, taker_coin, saved));
+            if let Some(tx) = try_s!(taker_swap.find_taker_payment_refund()) {
+                return Ok(RecoveryAttempt::AlreadyRefunded(tx));
+            }
+            let recovered = try_s!(taker_swap.recover_funds_with_fee_multiplier(fee_multiplier));
+            Ok(RecoveryAttempt::Recovered(recovered))
+        },
+    }
+}
+
+/// Makes the next recovery attempt of the swap that has failed `attempts` times already.
+async fn process_swap(ctx: &MmArc, conf: &RefundManagerConf, swap: SavedSwap, attempts: u32) -> SwapRefundState {
+    let uuid = *swap.uuid();
+    let refund_available = match swap.wait_refund_until() {
+        Ok(until) => now_ms() / 1000 >= until,
+        Err(e) => {
+            return SwapRefundState::GaveUp {
+                attempts,
+                last_error: e,
+            }
+        },
+    };
+    // the coins can be activated later, so the attempt is not counted
+    let (maker_coin, taker_coin) = match swap.find_coins(ctx).await {
+        Ok(coins) => coins,
+        Err(e) => return SwapRefundState::pending(attempts, e),
+    };
+
+    let fee_multiplier = conf.fee_multiplier(attempts + 1);
+    match try_recover_funds(ctx.clone(), maker_coin, taker_coin, swap, fee_multiplier).await {
+        Ok(RecoveryAttempt::AlreadyRefunded(tx)) => SwapRefundState::AlreadyRefunded { tx_hash: tx.tx_hash() },
+        Ok(RecoveryAttempt::Recovered(recovered)) => {
+            info!(
+                "Swap {} funds are recovered by {} tx {:02x}",
+                uuid,
+                recovered.coin,
+                recovered.transaction.tx_hash()
+            );
+            SwapRefundState::Recovered {
+                attempts: attempts + 1,
+                action: recovered.action,
+                coin: recovered.coin,
+                tx_hash: recovered.transaction.tx_hash(),
+                tx_hex: recovered.transaction.tx_hex().into(),
+            }
+        },
+        // it's too early to refund, the attempt is not counted to not escalate the fee
+        Err(e) if !refund_available => SwapRefundState::pending(attempts, e),
+        Err(e) => {
+            let attempts = attempts + 1;
+            if attempts >= conf.max_attempts {
+                warn!(
+                    "Giving up the swap {} funds recovery after {} attempts, last error: {}",
+                    uuid, attempts, e
+                );
+                SwapRefundState::GaveUp {
+                    attempts,
+                    last_error: e,
+                }
+            } else {
+                SwapRefundState::pending(attempts, e)
+            }
+        },
+    }
+}
+
+pub async fn swap_refund_manager_loop(ctx: MmArc) {
+    let conf = match RefundManagerConf::from_ctx(&ctx) {
+        Ok(conf) => conf,
+        Err(e) => {
+            error!(
+                "Error {} parsing 'swap_refund_manager' config, the refund manager is disabled",
+                e
+            );
+            return;
+        },
+    };
+    if !conf.enabled {
+        info!("Swap refund manager is disabled");
+        return;
+    }
+    let swaps_ctx = SwapsContext::from_ctx(&ctx).expect("from_ctx failed");
+    swaps_ctx.refund_states.lock().unwrap().extend(load_refund_states(&ctx));
+
+    while !ctx.is_stopping() {
+        let mut updated = false;
+        for swap in my_recoverable_swaps(&ctx) {
+            let uuid = *swap.uuid();
+            let attempts = match swaps_ctx.refund_states.lock().unwrap().get(&uuid) {
+                Some(state) if state.is_final() => continue,
+                Some(state) => state.attempts(),
+                None => 0,
+            };
+            let state = process_swap(&ctx, &conf, swap, attempts).await;
+            swaps_ctx.refund_states.lock().unwrap().insert(uuid, state);
+            updated = true;
+        }
+        if updated {
+            let states = swaps_ctx.refund_states.lock().unwrap().clone();
+            save_refund_states(&ctx, &states);
+        }
+        Timer::sleep(conf.check_interval).await;
+    }
+}
+
+#[derive(Serialize)]
+pub struct RefundManagerStatusResponse {
+    conf: RefundManagerConf,
+    swaps: HashMap<Uuid, SwapRefundState>,
+}
+
+pub async fn swap_refund_manager_status(ctx: MmArc, _req: Json) -> RefundManagerResult<RefundManagerStatusResponse> {
+    let conf = RefundManagerConf::from_ctx(&ctx).map_to_mm(RefundManagerError::InvalidConfig)?;
+    let swaps_ctx = SwapsContext::from_ctx(&ctx).map_to_mm(RefundManagerError::Internal)?;
+    let swaps = swaps_ctx.refund_states.lock().unwrap().clone();
+    Ok(RefundManagerStatusResponse { conf, swaps })
+}
+
+#[cfg(test)]
+mod refund_manager_tests {
+    use super::*;
+
+    #[test]
+    fn test_refund_manager_conf_defaults() {
+        let conf: RefundManagerConf = json::from_value(json!({ "max_attempts": 3 })).unwrap();
+        let expected = RefundManagerConf {
+            max_attempts: 3,
+            ..RefundManagerConf::default()
+        };
+        assert_eq!(conf, expected);
+    }
+
+    #[test]
+    fn test_swap_refund_state_serde() {
+        let state = SwapRefundState::pending(2, "Transaction is not mined".into());
+        let json = json::to_value(&state).unwrap();
+        assert_eq!(json["state"], "Pending");
+        let actual: SwapRefundState = json::from_value(json).unwrap();
+        assert_eq!(actual.attempts(), 2);
+        assert!(!actual.is_final());
+
+        let state = SwapRefundState::GaveUp {
+            attempts: 10,
+            last_error: "Refund fee escalation is not supported".into(),
+        };
+        let actual: SwapRefundState = json::from_value(json::to_value(&state).unwrap()).unwrap();
+        assert_eq!(actual.attempts(), 10);
+        assert!(actual.is_final());
+    }
+
+    #[test]
+    fn test_refund_fee_multiplier() {
+        let conf = RefundManagerConf::default();
+        assert_eq!(conf.fee_multiplier(0), 1);
+        assert_eq!(conf.fee_multiplier(1), 1);
+        assert_eq!(conf.fee_multiplier(2), 2);
+        assert_eq!(conf.fee_multiplier(DEFAULT_MAX_ATTEMPTS), DEFAULT_MAX_FEE_MULTIPLIER);
+
+        let conf = RefundManagerConf {
+            max_fee_multiplier: 0,
+            ..RefundManagerConf::default()
+        };
+        assert_eq!(conf.fee_multiplier(5), 1);
+    }
+}
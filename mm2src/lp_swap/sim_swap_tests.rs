@@ -0,0 +1,311 @@
+//! The swaps driven end to end on the simulated chains.
+//!
+//! The maker and taker nodes have their own contexts connected by the in-memory router instead of libp2p,
+//! `swap_now` is mocked to return the time of the simulated chain, so the test moves the swap deadlines forward.
+
+use super::*;
+use crate::mm2::database::init_and_migrate_db;
+use crate::mm2::lp_network::{P2PContext, P2PRequest};
+use coins::utxo::{compressed_pub_key_from_priv_raw, ChecksumType};
+use coins::{SimChain, SimCoin, SimMisbehaviour};
+use common::mm_ctx::MmCtxBuilder;
+use common::mm_metrics::MetricsOps;
+use common::privkey::key_pair_from_seed;
+use common::rusqlite::Connection;
+use futures::channel::mpsc;
+use futures::future::join3;
+use futures::StreamExt;
+use mm2_libp2p::atomicdex_behaviour::{AdexBehaviourCmd, AdexResponse};
+use mm2_libp2p::{decode_message, TOPIC_SEPARATOR};
+use mocktopus::mocking::*;
+use std::future::Future;
+use std::path::Path;
+
+const MAKER_SEED: &str = "spice describe gravity federal blast come thank unfair canal monkey style afraid";
+const TAKER_SEED: &str = "also shoot benefit prefer juice shell elder veteran woman mimic image kidney";
+const PAYMENT_LOCKTIME: u64 = 900;
+
+struct SimNode {
+    ctx: MmArc,
+    peer: PeerId,
+    pubkey: Vec<u8>,
+    cmd_rx: mpsc::Receiver<AdexBehaviourCmd>,
+}
+
+impl SimNode {
+    fn new(seed: &str, dbdir: &Path) -> SimNode {
+        let key_pair = key_pair_from_seed(seed).unwrap();
+        let pubkey = key_pair.public().to_vec();
+        let ctx = MmCtxBuilder::default()
+            .with_conf(json!({ "dbdir": dbdir.display().to_string() }))
+            .with_secp256k1_key_pair(key_pair)
+            .into_mm_arc();
+        ctx.metrics.init().unwrap();
+        std::fs::create_dir_all(my_swaps_dir(&ctx)).unwrap();
+        std::fs::create_dir_all(stats_maker_swap_dir(&ctx)).unwrap();
+        std::fs::create_dir_all(stats_taker_swap_dir(&ctx)).unwrap();
+        let connection = Connection::open_in_memory().unwrap();
+        init_and_migrate_db(&ctx, &connection).unwrap();
+        ctx.sqlite_connection.pin(Mutex::new(connection)).unwrap();
+
+        let (cmd_tx, cmd_rx) = mpsc::channel(1024);
+        P2PContext::new(cmd_tx).store_to_mm_arc(&ctx);
+        let peer = PeerId::random();
+        ctx.peer_id.pin(peer.to_string()).unwrap();
+        SimNode {
+            ctx,
+            peer,
+            pubkey,
+            cmd_rx,
+        }
+    }
+
+    fn bits256(&self) -> bits256 {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&self.pubkey[1..33]);
+        bits256::from(bytes)
+    }
+
+    fn persistent_pub(&self) -> H264 {
+        let privkey = &self.ctx.secp256k1_key_pair().private().secret;
+        compressed_pub_key_from_priv_raw(&privkey[..], ChecksumType::DSHA256).unwrap()
+    }
+}
+
+/// Delivers the swap messages published or requested by the node to the `other` node.
+fn spawn_router(mut cmd_rx: mpsc::Receiver<AdexBehaviourCmd>, my_peer: PeerId, other: MmArc, other_peer: PeerId) {
+    spawn(async move {
+        while let Some(cmd) = cmd_rx.next().await {
+            match cmd {
+                AdexBehaviourCmd::PublishMsg { topics, msg } => {
+                    for topic in topics {
+                        let mut split = topic.split(TOPIC_SEPARATOR);
+                        if let (Some(SWAP_PREFIX), Some(uuid)) = (split.next(), split.next()) {
+                            let _ = process_msg(other.clone(), uuid, &msg);
+                        }
+                    }
+                },
+                AdexBehaviourCmd::RequestPeers { req, response_tx, .. } => {
+                    let response = match decode_message::<P2PRequest>(&req) {
+                        Ok(P2PRequest::Swap(req)) => match process_peer_request(other.clone(), my_peer, req).await {
+                            Ok(_) => AdexResponse::None,
+                            Err(error) => AdexResponse::Err { error },
+                        },
+                        _ => AdexResponse::Err {
+                            error: "Unexpected request".into(),
+                        },
+                    };
+                    let _ = response_tx.send(vec![(other_peer, response)]);
+                },
+                _ => (),
+            }
+        }
+    });
+}
+
+struct SimSwap {
+    maker: SimNode,
+    taker: SimNode,
+    maker_chain: SimChain,
+    taker_chain: SimChain,
+    _dbdir: TempDir,
+}
+
+impl SimSwap {
+    fn new() -> SimSwap {
+        let dbdir = TempDir::new();
+        let maker = SimNode::new(MAKER_SEED, &dbdir.path().join("maker"));
+        let taker = SimNode::new(TAKER_SEED, &dbdir.path().join("taker"));
+        let maker_chain = SimChain::new("SIMA");
+        let taker_chain = SimChain::new("SIMB");
+        maker_chain.fund(&maker.pubkey, 10.into());
+        taker_chain.fund(&taker.pubkey, 10.into());
+
+        // the swap deadlines follow the chain time, the test sets the same time on both chains
+        let clock = maker_chain.clone();
+        swap_now.mock_safe(move || MockResult::Return(clock.now()));
+        SimSwap {
+            maker,
+            taker,
+            maker_chain,
+            taker_chain,
+            _dbdir: dbdir,
+        }
+    }
+
+    /// Runs both sides of the swap to the end returning the saved maker and taker swaps.
+    fn run(self, taker_misbehaviour: SimMisbehaviour, driver: impl Future<Output = ()>) -> (SavedSwap, SavedSwap) {
+        let SimSwap {
+            maker,
+            taker,
+            maker_chain,
+            taker_chain,
+            _dbdir,
+        } = self;
+        spawn_router(maker.cmd_rx, maker.peer, taker.ctx.clone(), taker.peer);
+        spawn_router(taker.cmd_rx, taker.peer, maker.ctx.clone(), maker.peer);
+
+        let uuid = Uuid::new_v4();
+        let conf_settings = SwapConfirmationsSettings {
+            maker_coin_confs: 1,
+            maker_coin_nota: false,
+            taker_coin_confs: 1,
+            taker_coin_nota: false,
+        };
+        let maker_swap = MakerSwap::new(
+            maker.ctx.clone(),
+            taker.bits256(),
+            1.into(),
+            1.into(),
+            maker.persistent_pub(),
+            uuid,
+            Some(uuid),
+            conf_settings,
+            SimCoin::new(maker_chain.clone(), &maker.pubkey).into(),
+            SimCoin::new(taker_chain.clone(), &maker.pubkey).into(),
+            PAYMENT_LOCKTIME,
+            true,
+        );
+        let taker_swap = TakerSwap::new(
+            taker.ctx.clone(),
+            maker.bits256(),
+            1.into(),
+            1.into(),
+            taker.persistent_pub(),
+            uuid,
+            Some(uuid),
+            conf_settings,
+            SimCoin::new(maker_chain, &taker.pubkey).into(),
+            SimCoin::new(taker_chain, &taker.pubkey)
+                .with_misbehaviour(taker_misbehaviour)
+                .into(),
+            PAYMENT_LOCKTIME,
+            true,
+        );
+
+        block_on(join3(
+            run_maker_swap(RunMakerSwapInput::StartNew(maker_swap), maker.ctx.clone()),
+            run_taker_swap(RunTakerSwapInput::StartNew(taker_swap), taker.ctx.clone()),
+            driver,
+        ));
+        let maker_saved = SavedSwap::load_my_swap(&maker.ctx, &uuid).unwrap().unwrap();
+        let taker_saved = SavedSwap::load_my_swap(&taker.ctx, &uuid).unwrap().unwrap();
+        (maker_saved, taker_saved)
+    }
+}
+
+/// The temporary directory removed on drop.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> TempDir {
+        let path = std::env::temp_dir().join(format!("sim_swap_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn path(&self) -> &Path { &self.0 }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) { let _ = std::fs::remove_dir_all(&self.0); }
+}
+
+#[test]
+fn test_sim_swap_success() {
+    let swap = SimSwap::new();
+    let maker_pub = swap.maker.pubkey.clone();
+    let taker_pub = swap.taker.pubkey.clone();
+    let (maker_chain, taker_chain) = (swap.maker_chain.clone(), swap.taker_chain.clone());
+
+    let (maker_saved, taker_saved) = swap.run(SimMisbehaviour::default(), futures::future::ready(()));
+    assert!(maker_saved.is_finished());
+    assert!(maker_saved.is_success().unwrap());
+    assert!(taker_saved.is_finished());
+    assert!(taker_saved.is_success().unwrap());
+
+    // both payments are spent by the counterparties paying the spend fee
+    let fee = maker_chain.tx_fee();
+    assert!(maker_chain.unspent_payment_locks().is_empty());
+    assert!(taker_chain.unspent_payment_locks().is_empty());
+    assert_eq!(maker_chain.balance(&taker_pub), BigDecimal::from(1) - &fee);
+    assert_eq!(taker_chain.balance(&maker_pub), BigDecimal::from(1) - &fee);
+    assert_eq!(maker_chain.balance(&maker_pub), BigDecimal::from(9) - &fee);
+}
+
+#[test]
+fn test_sim_swap_refunds_after_locktime() {
+    let swap = SimSwap::new();
+    let maker_pub = swap.maker.pubkey.clone();
+    let (maker_chain, taker_chain) = (swap.maker_chain.clone(), swap.taker_chain.clone());
+
+    // the maker can't spend the taker payment locked with the wrong secret hash,
+    // so both payments stay on the chains until the time moves past their locktimes
+    let misbehaviour = SimMisbehaviour {
+        wrong_secret_hash: true,
+        ..Default::default()
+    };
+    let driver = {
+        let (maker_chain, taker_chain) = (maker_chain.clone(), taker_chain.clone());
+        async move {
+            let mut attempts = 0;
+            while taker_chain.unspent_payment_locks().is_empty() {
+                attempts += 1;
+                assert!(attempts < 1200, "The taker payment wasn't sent");
+                Timer::sleep(0.1).await;
+            }
+            let max_lock = maker_chain
+                .unspent_payment_locks()
+                .into_iter()
+                .chain(taker_chain.unspent_payment_locks())
+                .max()
+                .unwrap();
+            let time = max_lock as u64 + REFUND_LOCKTIME_DELAY;
+            maker_chain.set_time(time);
+            taker_chain.set_time(time);
+        }
+    };
+
+    let (maker_saved, taker_saved) = swap.run(misbehaviour, driver);
+    assert!(maker_saved.is_finished());
+    assert!(!maker_saved.is_success().unwrap());
+    assert!(taker_saved.is_finished());
+    assert!(!taker_saved.is_success().unwrap());
+
+    // both payments are refunded to the senders paying the refund fee
+    let fee = maker_chain.tx_fee();
+    assert!(maker_chain.unspent_payment_locks().is_empty());
+    assert!(taker_chain.unspent_payment_locks().is_empty());
+    assert_eq!(
+        maker_chain.balance(&maker_pub),
+        BigDecimal::from(10) - &fee * BigDecimal::from(2)
+    );
+    assert_eq!(taker_chain.balance(&maker_pub), BigDecimal::from(0));
+}
+
+#[test]
+fn test_replay_maker_payment_refund_errored_on_sim_chain() {
+    // the swap of test_recover_funds_maker_payment_refund_errored replayed on the simulated chains
+    let maker_saved_json = r#"{"error_events":["StartFailed","NegotiateFailed","TakerFeeValidateFailed","MakerPaymentTransactionFailed","MakerPaymentDataSendFailed","TakerPaymentValidateFailed","TakerPaymentSpendFailed","TakerPaymentSpendConfirmFailed","MakerPaymentRefunded","MakerPaymentRefundFailed"],"events":[{"event":{"data":{"lock_duration":7800,"maker_amount":"0.58610590","maker_coin":"KMD","maker_coin_start_block":1450923,"maker_payment_confirmations":1,"maker_payment_lock":1563636475,"my_persistent_pub":"031bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8","secret":"0000000000000000000000000000000000000000000000000000000000000000","started_at":1563620875,"taker":"14a96292bfcd7762ece8eb08ead915da927c2619277363853572f30880d5155e","taker_amount":"0.0077700000552410000000000","taker_coin":"LTC","taker_coin_start_block":1670837,"taker_payment_confirmations":1,"uuid":"9db641f5-4300-4527-9fa6-f1c391d42c35"},"type":"Started"},"timestamp":1563620875062},{"event":{"data":{"taker_payment_locktime":1563628675,"taker_pubkey":"02713015d3fa4d30259e90be5f131beb593bf0131f3af2dcdb304e3322d8d52b91"},"type":"Negotiated"},"timestamp":1563620915497},{"event":{"data":{"tx_hash":"6740136eaaa615d9d231969e3a9599d0fc59e53989237a8d31cd6fc86c160013","tx_hex":"0100000001a2586ea8294cedc55741bef625ba72c646399903391a7f6c604a58c6263135f2000000006b4830450221009c78c8ba4a7accab6b09f9a95da5bc59c81f4fc1e60b288ec3c5462b4d02ef01022056b63be1629cf17751d3cc5ffec51bcb1d7f9396e9ce9ca254d0f34104f7263a012102713015d3fa4d30259e90be5f131beb593bf0131f3af2dcdb304e3322d8d52b91ffffffff0210270000000000001976a914ca1e04745e8ca0c60d8c5881531d51bec470743f88ac78aa1900000000001976a91406ccabfd5f9075ecd5e8d0d31c0e973a54d51e8288ac5bf6325d"},"type":"TakerFeeValidated"},"timestamp":1563620976060},{"event":{"data":{"tx_hash":"d0f6e664cea9d89fe7b5cf8005fdca070d1ab1d05a482aaef95c08cdaecddf0a","tx_hex":"0400008085202f89019f1cbda354342cdf982046b331bbd3791f53b692efc6e4becc36be495b2977d9000000006b483045022100fa9d4557394141f6a8b9bfb8cd594a521fd8bcd1965dbf8bc4e04abc849ac66e0220589f521814c10a7561abfd5e432f7a2ee60d4875fe4604618af3207dae531ac00121031bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8ffffffff029e537e030000000017a9145534898009f1467191065f6890b96914b39a1c018791857702000000001976a914c3f710deb7320b0efa6edb14e3ebeeb9155fa90d88ac72ee325d000000000000000000000000000000"},"type":"MakerPaymentSent"},"timestamp":1563620976189},{"event":{"data":{"tx_hash":"1e883eb2f3991e84ba27f53651f89b7dda708678a5b9813d043577f222b9ca30","tx_hex":"01000000011300166cc86fcd318d7a238939e559fcd099953a9e9631d2d915a6aa6e134067010000006a47304402206781d5f2db2ff13d2ec7e266f774ea5630cc2dba4019e18e9716131b8b026051022006ebb33857b6d180f13aa6be2fc532f9734abde9d00ae14757e7d7ba3741c08c012102713015d3fa4d30259e90be5f131beb593bf0131f3af2dcdb304e3322d8d52b91ffffffff0228db0b000000000017a91483818667161bf94adda3964a81a231cbf6f5338187b0480c00000000001976a91406ccabfd5f9075ecd5e8d0d31c0e973a54d51e8288ac7cf7325d"},"type":"TakerPaymentReceived"},"timestamp":1563621268320},{"event":{"type":"TakerPaymentWaitConfirmStarted"},"timestamp":1563621268321},{"event":{"type":"TakerPaymentValidatedAndConfirmed"},"timestamp":1563621778471},{"event":{"data":{"error":"lp_swap:2025] utxo:938] rpc_clients:719] JsonRpcError { request: JsonRpcRequest { jsonrpc: \"2.0\", id: \"9\", method: \"blockchain.transaction.broadcast\", params: [String(\"010000000130cab922f27735043d81b9a5788670da7d9bf85136f527ba841e99f3b23e881e00000000b6473044022058a0c1da6bcf8c1418899ff8475f3ab6dddbff918528451c1fe71c2f7dad176302204c2e0bcf8f9b5f09e02ccfeb9256e9b34fb355ea655a5704a8a3fa920079b91501514c6b63048314335db1752102713015d3fa4d30259e90be5f131beb593bf0131f3af2dcdb304e3322d8d52b91ac6782012088a9147ed38daab6085c1a1e4426e61dc87a3c2c081a958821031bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8ac68feffffff0188540a00000000001976a91406ccabfd5f9075ecd5e8d0d31c0e973a54d51e8288ac1c2b335d\")] }, error: Response(Object({\"code\": Number(1), \"message\": String(\"the transaction was rejected by network rules.\\n\\nMissing inputs\\n[010000000130cab922f27735043d81b9a5788670da7d9bf85136f527ba841e99f3b23e881e00000000b6473044022058a0c1da6bcf8c1418899ff8475f3ab6dddbff918528451c1fe71c2f7dad176302204c2e0bcf8f9b5f09e02ccfeb9256e9b34fb355ea655a5704a8a3fa920079b91501514c6b63048314335db1752102713015d3fa4d30259e90be5f131beb593bf0131f3af2dcdb304e3322d8d52b91ac6782012088a9147ed38daab6085c1a1e4426e61dc87a3c2c081a958821031bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8ac68feffffff0188540a00000000001976a91406ccabfd5f9075ecd5e8d0d31c0e973a54d51e8288ac1c2b335d]\")})) }"},"type":"TakerPaymentSpendFailed"},"timestamp":1563638060583},{"event":{"data":{"error":"lp_swap:2025] utxo:938] rpc_clients:719] JsonRpcError { request: JsonRpcRequest { jsonrpc: \"2.0\", id: \"9\", method: \"blockchain.transaction.broadcast\", params: [String(\"010000000130cab922f27735043d81b9a5788670da7d9bf85136f527ba841e99f3b23e881e00000000b6473044022058a0c1da6bcf8c1418899ff8475f3ab6dddbff918528451c1fe71c2f7dad176302204c2e0bcf8f9b5f09e02ccfeb9256e9b34fb355ea655a5704a8a3fa920079b91501514c6b63048314335db1752102713015d3fa4d30259e90be5f131beb593bf0131f3af2dcdb304e3322d8d52b91ac6782012088a9147ed38daab6085c1a1e4426e61dc87a3c2c081a958821031bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8ac68feffffff0188540a00000000001976a91406ccabfd5f9075ecd5e8d0d31c0e973a54d51e8288ac1c2b335d\")] }, error: Response(Object({\"code\": Number(1), \"message\": String(\"the transaction was rejected by network rules.\\n\\nMissing inputs\\n[010000000130cab922f27735043d81b9a5788670da7d9bf85136f527ba841e99f3b23e881e00000000b6473044022058a0c1da6bcf8c1418899ff8475f3ab6dddbff918528451c1fe71c2f7dad176302204c2e0bcf8f9b5f09e02ccfeb9256e9b34fb355ea655a5704a8a3fa920079b91501514c6b63048314335db1752102713015d3fa4d30259e90be5f131beb593bf0131f3af2dcdb304e3322d8d52b91ac6782012088a9147ed38daab6085c1a1e4426e61dc87a3c2c081a958821031bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8ac68feffffff0188540a00000000001976a91406ccabfd5f9075ecd5e8d0d31c0e973a54d51e8288ac1c2b335d]\")})) }"},"type":"MakerPaymentRefundFailed"},"timestamp":1563638060583},{"event":{"type":"Finished"},"timestamp":1563621778483}],"success_events":["Started","Negotiated","TakerFeeValidated","MakerPaymentSent","TakerPaymentReceived","TakerPaymentWaitConfirmStarted","TakerPaymentValidatedAndConfirmed","TakerPaymentSpent","TakerPaymentSpendConfirmStarted","TakerPaymentSpendConfirmed","Finished"],"uuid":"9db641f5-4300-4527-9fa6-f1c391d42c35"}"#;
+    let maker_saved_swap: MakerSavedSwap = json::from_str(maker_saved_json).unwrap();
+    let key_pair = key_pair_from_seed(MAKER_SEED).unwrap();
+    let ctx = MmCtxBuilder::default().with_secp256k1_key_pair(key_pair).into_mm_arc();
+    let my_pub = ctx.secp256k1_key_pair().public().to_vec();
+
+    let maker_chain = SimChain::new("KMD");
+    let maker_payment = hex::decode("0400008085202f89019f1cbda354342cdf982046b331bbd3791f53b692efc6e4becc36be495b2977d9000000006b483045022100fa9d4557394141f6a8b9bfb8cd594a521fd8bcd1965dbf8bc4e04abc849ac66e0220589f521814c10a7561abfd5e432f7a2ee60d4875fe4604618af3207dae531ac00121031bb83b58ec130e28e0a6d5d2acf2eb01b0d3f1670e021d47d31db8a858219da8ffffffff029e537e030000000017a9145534898009f1467191065f6890b96914b39a1c018791857702000000001976a914c3f710deb7320b0efa6edb14e3ebeeb9155fa90d88ac72ee325d000000000000000000000000000000").unwrap();
+    maker_chain.import_payment(&maker_payment, "0.5861059".parse().unwrap());
+    let maker_coin = SimCoin::new(maker_chain.clone(), &my_pub).into();
+    let taker_coin = SimCoin::new(SimChain::new("LTC"), &my_pub).into();
+
+    let (maker_swap, _) = MakerSwap::load_from_saved(ctx, maker_coin, taker_coin, maker_saved_swap).unwrap();
+    let recovered = maker_swap.recover_funds().unwrap();
+    assert_eq!(recovered.action, RecoveredSwapAction::RefundedMyPayment);
+    assert_eq!(recovered.coin, "KMD");
+    assert_eq!(maker_chain.confirmations(&recovered.transaction.tx_hash()), Some(1));
+
+    // the refund is on the chain now, so the funds can't be recovered twice
+    let refund = maker_swap.find_maker_payment_refund().unwrap();
+    assert_eq!(refund, Some(recovered.transaction));
+    maker_swap.recover_funds().unwrap_err();
+}
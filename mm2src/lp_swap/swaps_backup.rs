@@ -0,0 +1,427 @@
+//! Encrypted backups of the swaps, orders and banned pubkeys of `my` node.
+//!
+//! The backup bundle is encrypted with ChaCha20-Poly1305,
+//! the key is derived from the node passphrase using PBKDF2-HMAC-SHA256.
+//! The bundle can be restored by any node started with the same passphrase.
+
+use super::pubkey_banning::{save_banned_pubkeys, BanReason};
+use super::{my_swaps_dir, SavedSwap, SwapsContext};
+use crate::mm2::lp_ordermatch::{my_orders_for_backup, restore_my_maker_order, restore_my_taker_order, MakerOrder,
+                                TakerOrder};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use common::mm_ctx::MmArc;
+use common::mm_error::prelude::*;
+use common::wio::CORE;
+use common::{json_dir_entries, now_ms, HttpStatusCode};
+use derive_more::Display;
+use gstuff::slurp;
+use hmac::Hmac;
+use http::StatusCode;
+use pbkdf2::pbkdf2;
+use rpc::v1::types::{Bytes as BytesJson, H256 as H256Json};
+use serde_json::{self as json, Value as Json};
+use sha2::Sha256;
+use std::collections::hash_map::{Entry, HashMap};
+use uuid::Uuid;
+
+pub type SwapsBackupResult<T> = Result<T, MmError<SwapsBackupError>>;
+
+/// The version of the backup bundle format produced by `export_swaps`.
+const SWAPS_BACKUP_VERSION: u32 = 1;
+const SWAPS_BACKUP_KDF_ITERATIONS: u32 = 100_000;
+/// The bundles are untrusted input, so the KDF work they can request is limited.
+const SWAPS_BACKUP_MAX_KDF_ITERATIONS: u32 = 1_000_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+#[derive(Debug, Display, Serialize, SerializeErrorType)]
+#[serde(tag = "error_type", content = "error_data")]
+pub enum SwapsBackupError {
+    #[display(fmt = "Invalid request: {}", _0)]
+    InvalidRequest(String),
+    #[display(fmt = "Passphrase is not available")]
+    PassphraseNotAvailable,
+    #[display(
+        fmt = "Backup version {} is not supported, the latest supported version is {}",
+        found,
+        latest
+    )]
+    UnsupportedVersion { found: u32, latest: u32 },
+    #[display(fmt = "Invalid backup: {}", _0)]
+    InvalidBackup(String),
+    #[display(fmt = "Error decrypting the backup, probably it was created with another passphrase")]
+    DecryptionError,
+    #[display(fmt = "Internal error: {}", _0)]
+    Internal(String),
+}
+
+impl HttpStatusCode for SwapsBackupError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SwapsBackupError::InvalidRequest(_)
+            | SwapsBackupError::UnsupportedVersion { .. }
+            | SwapsBackupError::InvalidBackup(_)
+            | SwapsBackupError::DecryptionError => StatusCode::BAD_REQUEST,
+            SwapsBackupError::PassphraseNotAvailable | SwapsBackupError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            },
+        }
+    }
+}
+
+impl From<serde_json::Error> for SwapsBackupError {
+    fn from(e: serde_json::Error) -> Self { SwapsBackupError::InvalidRequest(e.to_string()) }
+}
+
+/// The encrypted bundle returned by `export_swaps` and accepted by `restore_swaps`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EncryptedSwapsBackup {
+    version: u32,
+    created_at: u64,
+    kdf_iterations: u32,
+    salt: BytesJson,
+    nonce: BytesJson,
+    ciphertext: BytesJson,
+}
+
+#[derive(Deserialize, Serialize)]
+struct SwapsBackupPayload {
+    swaps: Vec<SavedSwap>,
+    maker_orders: Vec<MakerOrder>,
+    taker_orders: Vec<TakerOrder>,
+    /// Serialized `BanReason`s by the banned pubkeys.
+    banned_pubkeys: HashMap<H256Json, Json>,
+}
+
+#[derive(Deserialize)]
+pub struct ExportSwapsRequest {
+    /// Export only the swaps with these uuids. All swaps are exported if not set.
+    #[serde(default)]
+    uuids: Option<Vec<Uuid>>,
+    #[serde(default = "get_true")]
+    include_orders: bool,
+    #[serde(default = "get_true")]
+    include_banned_pubkeys: bool,
+}
+
+#[derive(Serialize)]
+pub struct ExportSwapsResponse {
+    backup: EncryptedSwapsBackup,
+    swaps: usize,
+    maker_orders: usize,
+    taker_orders: usize,
+    banned_pubkeys: usize,
+}
+
+#[derive(Deserialize)]
+pub struct RestoreSwapsRequest {
+    backup: EncryptedSwapsBackup,
+}
+
+#[derive(Serialize)]
+pub struct RestoredItems<T> {
+    restored: Vec<T>,
+    skipped: HashMap<String, String>,
+}
+
+impl<T> Default for RestoredItems<T> {
+    fn default() -> Self {
+        RestoredItems {
+            restored: Vec::new(),
+            skipped: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RestoreSwapsResponse {
+    swaps: RestoredItems<Uuid>,
+    maker_orders: RestoredItems<Uuid>,
+    taker_orders: RestoredItems<Uuid>,
+    banned_pubkeys: RestoredItems<H256Json>,
+}
+
+fn get_true() -> bool { true }
+
+/// Derives the 32-byte key using PBKDF2-HMAC-SHA256.
+/// The derivation is CPU-bound, so it's run on the blocking thread not to stall the RPC executor.
+async fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> SwapsBackupResult<[u8; 32]> {
+    let passphrase = passphrase.to_owned();
+    let salt = salt.to_vec();
+    CORE.0
+        .spawn_blocking(move || {
+            let mut key = [0; 32];
+            pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), &salt, iterations, &mut key);
+            key
+        })
+        .await
+        .map_to_mm(|e| SwapsBackupError::Internal(e.to_string()))
+}
+
+fn node_passphrase(ctx: &MmArc) -> SwapsBackupResult<&str> {
+    ctx.conf["passphrase"]
+        .as_str()
+        .or_mm_err(|| SwapsBackupError::PassphraseNotAvailable)
+}
+
+async fn encrypt_backup(passphrase: &str, payload: &SwapsBackupPayload) -> SwapsBackupResult<EncryptedSwapsBackup> {
+    let plaintext = json::to_vec(payload).map_to_mm(|e| SwapsBackupError::Internal(e.to_string()))?;
+    let salt: [u8; SALT_LEN] = rand::random();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+
+    let key = derive_key(passphrase, &salt, SWAPS_BACKUP_KDF_ITERATIONS).await?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+        .map_to_mm(|e| SwapsBackupError::Internal(format!("{:?}", e)))?;
+
+    Ok(EncryptedSwapsBackup {
+        version: SWAPS_BACKUP_VERSION,
+        created_at: now_ms() / 1000,
+        kdf_iterations: SWAPS_BACKUP_KDF_ITERATIONS,
+        salt: salt.to_vec().into(),
+        nonce: nonce.to_vec().into(),
+        ciphertext: ciphertext.into(),
+    })
+}
+
+async fn decrypt_backup(passphrase: &str, backup: &EncryptedSwapsBackup) -> SwapsBackupResult<SwapsBackupPayload> {
+    if backup.version > SWAPS_BACKUP_VERSION {
+        return MmError::err(SwapsBackupError::UnsupportedVersion {
+            found: backup.version,
+            latest: SWAPS_BACKUP_VERSION,
+        });
+    }
+    if backup.kdf_iterations == 0 || backup.kdf_iterations > SWAPS_BACKUP_MAX_KDF_ITERATIONS {
+        return MmError::err(SwapsBackupError::InvalidBackup(format!(
+            "'kdf_iterations' must be in the range 1..={}",
+            SWAPS_BACKUP_MAX_KDF_ITERATIONS
+        )));
+    }
+    if backup.salt.len() != SALT_LEN {
+        return MmError::err(SwapsBackupError::InvalidBackup(format!(
+            "'salt' must be {} bytes long",
+            SALT_LEN
+        )));
+    }
+    if backup.nonce.len() != NONCE_LEN {
+        return MmError::err(SwapsBackupError::InvalidBackup(format!(
+            "'nonce' must be {} bytes long",
+            NONCE_LEN
+        )));
+    }
+
+    let key = derive_key(passphrase, &backup.salt, backup.kdf_iterations).await?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&backup.nonce), backup.ciphertext.as_slice())
+        .map_to_mm(|_| SwapsBackupError::DecryptionError)?;
+    json::from_slice(&plaintext).map_to_mm(|e| SwapsBackupError::InvalidBackup(e.to_string()))
+}
+
+fn my_saved_swaps(ctx: &MmArc) -> SwapsBackupResult<Vec<SavedSwap>> {
+    let entries = json_dir_entries(&my_swaps_dir(ctx)).map_to_mm(SwapsBackupError::Internal)?;
+    Ok(entries
+        .iter()
+        .filter_map(|entry| json::from_slice::<SavedSwap>(&slurp(&entry.path())).ok())
+        .collect())
+}
+
+fn banned_pubkeys_for_backup(ctx: &MmArc) -> SwapsBackupResult<HashMap<H256Json, Json>> {
+    let swap_ctx = SwapsContext::from_ctx(ctx).map_to_mm(SwapsBackupError::Internal)?;
+    let banned = swap_ctx.banned_pubkeys.lock().unwrap();
+    banned
+        .iter()
+        .map(|(pubkey, reason)| {
+            let reason = json::to_value(reason).map_to_mm(|e| SwapsBackupError::Internal(e.to_string()))?;
+            Ok((pubkey.clone(), reason))
+        })
+        .collect()
+}
+
+/// Exports the swaps, orders and banned pubkeys of `my` node to the encrypted bundle.
+pub async fn export_swaps(ctx: MmArc, req: ExportSwapsRequest) -> SwapsBackupResult<ExportSwapsResponse> {
+    let passphrase = node_passphrase(&ctx)?;
+
+    let mut swaps = my_saved_swaps(&ctx)?;
+    if let Some(uuids) = req.uuids {
+        swaps.retain(|swap| uuids.contains(swap.uuid()));
+    }
+
+    let (maker_orders, taker_orders) = if req.include_orders {
+        my_orders_for_backup(&ctx).map_to_mm(SwapsBackupError::Internal)?
+    } else {
+        (Vec::new(), Vec::new())
+    };
+
+    let banned_pubkeys = if req.include_banned_pubkeys {
+        banned_pubkeys_for_backup(&ctx)?
+    } else {
+        HashMap::new()
+    };
+
+    let payload = SwapsBackupPayload {
+        swaps,
+        maker_orders,
+        taker_orders,
+        banned_pubkeys,
+    };
+    let backup = encrypt_backup(passphrase, &payload).await?;
+    Ok(ExportSwapsResponse {
+        backup,
+        swaps: payload.swaps.len(),
+        maker_orders: payload.maker_orders.len(),
+        taker_orders: payload.taker_orders.len(),
+        banned_pubkeys: payload.banned_pubkeys.len(),
+    })
+}
+
+/// Decrypts the bundle produced by `export_swaps` and merges it into the DB of `my` node.
+/// The swaps and orders existing on the node already are skipped.
+pub async fn restore_swaps(ctx: MmArc, req: RestoreSwapsRequest) -> SwapsBackupResult<RestoreSwapsResponse> {
+    let passphrase = node_passphrase(&ctx)?;
+    let payload = decrypt_backup(passphrase, &req.backup).await?;
+
+    let mut swaps = RestoredItems::default();
+    for swap in payload.swaps {
+        match swap.import_to_db(&ctx) {
+            Ok(_) => swaps.restored.push(*swap.uuid()),
+            Err(e) => {
+                swaps.skipped.insert(swap.uuid().to_string(), e);
+            },
+        }
+    }
+
+    let mut maker_orders = RestoredItems::default();
+    for order in payload.maker_orders {
+        match restore_my_maker_order(&ctx, &order) {
+            Ok(_) => maker_orders.restored.push(*order.uuid()),
+            Err(e) => {
+                maker_orders.skipped.insert(order.uuid().to_string(), e);
+            },
+        }
+    }
+
+    let mut taker_orders = RestoredItems::default();
+    for order in payload.taker_orders {
+        match restore_my_taker_order(&ctx, &order) {
+            Ok(_) => taker_orders.restored.push(*order.uuid()),
+            Err(e) => {
+                taker_orders.skipped.insert(order.uuid().to_string(), e);
+            },
+        }
+    }
+
+    let mut banned_pubkeys = RestoredItems::default();
+    let swap_ctx = SwapsContext::from_ctx(&ctx).map_to_mm(SwapsBackupError::Internal)?;
+    let mut banned = swap_ctx.banned_pubkeys.lock().unwrap();
+    for (pubkey, reason) in payload.banned_pubkeys {
+        let reason: BanReason = match json::from_value(reason) {
+            Ok(reason) => reason,
+            Err(e) => {
+                banned_pubkeys
+                    .skipped
+                    .insert(format!("{:?}", pubkey), format!("Invalid ban reason: {}", e));
+                continue;
+            },
+        };
+        match banned.entry(pubkey.clone()) {
+            Entry::Occupied(_) => {
+                banned_pubkeys
+                    .skipped
+                    .insert(format!("{:?}", pubkey), "Pubkey is banned already".to_owned());
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(reason);
+                banned_pubkeys.restored.push(pubkey);
+            },
+        }
+    }
+    if !banned_pubkeys.restored.is_empty() {
+        save_banned_pubkeys(&ctx, &banned);
+    }
+
+    Ok(RestoreSwapsResponse {
+        swaps,
+        maker_orders,
+        taker_orders,
+        banned_pubkeys,
+    })
+}
+
+#[cfg(test)]
+mod swaps_backup_tests {
+    use super::*;
+    use common::block_on;
+
+    fn empty_payload() -> SwapsBackupPayload {
+        SwapsBackupPayload {
+            swaps: Vec::new(),
+            maker_orders: Vec::new(),
+            taker_orders: Vec::new(),
+            banned_pubkeys: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_derive_key() {
+        // RFC 7914, section 11
+        let actual = block_on(derive_key("passwd", b"salt", 1)).unwrap();
+        let expected = hex::decode("55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc").unwrap();
+        assert_eq!(actual.to_vec(), expected);
+    }
+
+    #[test]
+    fn test_swaps_backup_encrypt_decrypt() {
+        let mut payload = empty_payload();
+        payload
+            .banned_pubkeys
+            .insert(H256Json::default(), json!({"type": "Manual", "reason": "test"}));
+
+        let backup = block_on(encrypt_backup("passphrase", &payload)).unwrap();
+        let decrypted = block_on(decrypt_backup("passphrase", &backup)).unwrap();
+        assert_eq!(decrypted.banned_pubkeys, payload.banned_pubkeys);
+
+        let error = block_on(decrypt_backup("another passphrase", &backup)).unwrap_err();
+        match error.into_inner() {
+            SwapsBackupError::DecryptionError => (),
+            e => panic!("Expected DecryptionError, found {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_swaps_backup_unsupported_version() {
+        let mut backup = block_on(encrypt_backup("passphrase", &empty_payload())).unwrap();
+        backup.version = SWAPS_BACKUP_VERSION + 1;
+        let error = block_on(decrypt_backup("passphrase", &backup)).unwrap_err();
+        match error.into_inner() {
+            SwapsBackupError::UnsupportedVersion { found, latest } => {
+                assert_eq!(found, SWAPS_BACKUP_VERSION + 1);
+                assert_eq!(latest, SWAPS_BACKUP_VERSION);
+            },
+            e => panic!("Expected UnsupportedVersion, found {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_swaps_backup_invalid_kdf_params() {
+        let backup = block_on(encrypt_backup("passphrase", &empty_payload())).unwrap();
+
+        let mut too_many_iterations = backup.clone();
+        too_many_iterations.kdf_iterations = u32::MAX;
+        let error = block_on(decrypt_backup("passphrase", &too_many_iterations)).unwrap_err();
+        match error.into_inner() {
+            SwapsBackupError::InvalidBackup(_) => (),
+            e => panic!("Expected InvalidBackup, found {:?}", e),
+        }
+
+        let mut short_salt = backup;
+        short_salt.salt = vec![0; 4].into();
+        let error = block_on(decrypt_backup("passphrase", &short_salt)).unwrap_err();
+        match error.into_inner() {
+            SwapsBackupError::InvalidBackup(_) => (),
+            e => panic!("Expected InvalidBackup, found {:?}", e),
+        }
+    }
+}
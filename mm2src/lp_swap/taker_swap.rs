@@ -2,16 +2,18 @@ use super::check_balance::{check_my_coin_balance_for_swap, CheckBalanceError, Ch
                            TakerFeeAdditionalInfo};
 use super::pubkey_banning::ban_pubkey_on_failed_swap;
 use super::trade_preimage::{TradePreimageRequest, TradePreimageRpcError, TradePreimageRpcResult};
-use super::{broadcast_my_swap_status, broadcast_swap_message_every, check_other_coin_balance_for_swap,
-            dex_fee_amount_from_taker_coin, dex_fee_rate, dex_fee_threshold, get_locked_amount, my_swap_file_path,
-            my_swaps_dir, recv_swap_msg, swap_topic, AtomicSwap, LockedAmount, MySwapInfo, NegotiationDataMsg,
+use super::{broadcast_my_swap_status, check_other_coin_balance_for_swap, dex_fee_amount_from_taker_coin, dex_fee_rate,
+            dex_fee_threshold, get_locked_amount, my_swap_file_path, my_swaps_dir, recv_swap_msg,
+            send_swap_message_every, swap_now, swap_topic, AtomicSwap, LockedAmount, MySwapInfo, NegotiationDataMsg,
             NegotiationDataV2, RecoveredSwap, RecoveredSwapAction, SavedSwap, SavedTradeFee,
-            SwapConfirmationsSettings, SwapError, SwapMsg, SwapsContext, TransactionIdentifier, WAIT_CONFIRM_INTERVAL};
+            SwapConfirmationsSettings, SwapError, SwapMsg, SwapsContext, TransactionIdentifier, REFUND_LOCKTIME_DELAY,
+            WAIT_CONFIRM_INTERVAL};
 use crate::mm2::lp_network::subscribe_to_topic;
 use crate::mm2::lp_ordermatch::{MatchBy, OrderConfirmationsSettings, TakerAction, TakerOrderBuilder};
 use crate::mm2::MM_VERSION;
 use bigdecimal::BigDecimal;
-use coins::{lp_coinfind, CanRefundHtlc, FeeApproxStage, FoundSwapTxSpend, MmCoinEnum, TradeFee, TradePreimageValue};
+use coins::{lp_coinfind, CanRefundHtlc, FeeApproxStage, FoundSwapTxSpend, MmCoinEnum, TradeFee, TradePreimageValue,
+            TransactionEnum};
 use common::executor::Timer;
 use common::log::{debug, error, warn};
 use common::mm_ctx::MmArc;
@@ -210,6 +212,12 @@ impl TakerSavedSwap {
         true
     }
 
+    /// Returns the timestamp after which the taker payment can be refunded.
+    pub fn wait_refund_until(&self) -> Result<u64, String> {
+        let data = try_s!(self.swap_data());
+        Ok(data.taker_payment_lock + REFUND_LOCKTIME_DELAY)
+    }
+
     pub fn swap_data(&self) -> Result<&TakerSwapData, String> {
         match self.events.first() {
             Some(event) => match &event.event {
@@ -335,7 +343,7 @@ pub async fn run_taker_swap(swap: RunTakerSwapInput, ctx: MmArc) {
     let running_swap = Arc::new(swap);
     let weak_ref = Arc::downgrade(&running_swap);
     let swap_ctx = SwapsContext::from_ctx(&ctx).unwrap();
-    swap_ctx.init_msg_store(running_swap.uuid, running_swap.maker);
+    swap_ctx.init_msg_store(running_swap.uuid, running_swap.maker, running_swap.swap_msg_encryption);
     swap_ctx.running_swaps.lock().unwrap().push(weak_ref);
     let shutdown_rx = swap_ctx.shutdown_rx.clone();
     let swap_for_log = running_swap.clone();
@@ -453,6 +461,8 @@ pub struct TakerSwap {
     mutable: RwLock<TakerSwapMut>,
     conf_settings: SwapConfirmationsSettings,
     payment_locktime: u64,
+    /// Whether the maker accepts the swap messages encrypted to its pubkey
+    swap_msg_encryption: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -578,7 +588,7 @@ impl TakerSwap {
     fn w(&self) -> RwLockWriteGuard<TakerSwapMut> { self.mutable.write().unwrap() }
     fn r(&self) -> RwLockReadGuard<TakerSwapMut> { self.mutable.read().unwrap() }
 
-    fn wait_refund_until(&self) -> u64 { self.r().data.taker_payment_lock + 3700 }
+    fn wait_refund_until(&self) -> u64 { self.r().data.taker_payment_lock + REFUND_LOCKTIME_DELAY }
 
     fn apply_event(&self, event: TakerSwapEvent) {
         match event {
@@ -657,6 +667,7 @@ impl TakerSwap {
         maker_coin: MmCoinEnum,
         taker_coin: MmCoinEnum,
         payment_locktime: u64,
+        swap_msg_encryption: bool,
     ) -> Self {
         TakerSwap {
             ctx,
@@ -674,6 +685,7 @@ impl TakerSwap {
             errors: PaMutex::new(Vec::new()),
             conf_settings,
             payment_locktime,
+            swap_msg_encryption,
             mutable: RwLock::new(TakerSwapMut {
                 data: TakerSwapData::default(),
                 other_persistent_pub: H264::default(),
@@ -746,7 +758,7 @@ impl TakerSwap {
             )]));
         }
 
-        let started_at = now_ms() / 1000;
+        let started_at = swap_now();
 
         let maker_coin_start_block = match self.maker_coin.current_block().compat().await {
             Ok(b) => b,
@@ -866,9 +878,10 @@ impl TakerSwap {
             maker_coin_swap_contract: maker_coin_swap_contract_addr.clone().map_or(vec![], |bytes| bytes.0),
             taker_coin_swap_contract: taker_coin_swap_contract_addr.clone().map_or(vec![], |bytes| bytes.0),
         }));
-        let send_abort_handle = broadcast_swap_message_every(
+        let send_abort_handle = send_swap_message_every(
             self.ctx.clone(),
-            swap_topic(&self.uuid),
+            self.uuid,
+            self.maker,
             taker_data,
             NEGOTIATE_TIMEOUT as f64 / 6.,
         );
@@ -907,7 +920,7 @@ impl TakerSwap {
 
     async fn send_taker_fee(&self) -> Result<(Option<TakerSwapCommand>, Vec<TakerSwapEvent>), String> {
         let timeout = self.r().data.started_at + self.r().data.lock_duration / 3;
-        let now = now_ms() / 1000;
+        let now = swap_now();
         if now > timeout {
             return Ok((Some(TakerSwapCommand::Finish), vec![
                 TakerSwapEvent::TakerFeeSendFailed(ERRL!("Timeout {} > {}", now, timeout).into()),
@@ -946,9 +959,10 @@ impl TakerSwap {
         const MAKER_PAYMENT_WAIT_TIMEOUT: u64 = 180;
         let tx_hex = self.r().taker_fee.as_ref().unwrap().tx_hex.0.clone();
         let msg = SwapMsg::TakerFee(tx_hex);
-        let abort_send_handle = broadcast_swap_message_every(
+        let abort_send_handle = send_swap_message_every(
             self.ctx.clone(),
-            swap_topic(&self.uuid),
+            self.uuid,
+            self.maker,
             msg,
             MAKER_PAYMENT_WAIT_TIMEOUT as f64 / 6.,
         );
@@ -1035,7 +1049,7 @@ impl TakerSwap {
 
     async fn send_taker_payment(&self) -> Result<(Option<TakerSwapCommand>, Vec<TakerSwapEvent>), String> {
         let timeout = self.r().data.started_at + self.r().data.lock_duration / 3;
-        let now = now_ms() / 1000;
+        let now = swap_now();
         if now > timeout {
             return Ok((Some(TakerSwapCommand::Finish), vec![
                 TakerSwapEvent::TakerPaymentTransactionFailed(ERRL!("Timeout {} > {}", now, timeout).into()),
@@ -1093,7 +1107,7 @@ impl TakerSwap {
     async fn wait_for_taker_payment_spend(&self) -> Result<(Option<TakerSwapCommand>, Vec<TakerSwapEvent>), String> {
         let tx_hex = self.r().taker_payment.as_ref().unwrap().tx_hex.0.clone();
         let msg = SwapMsg::TakerPayment(tx_hex);
-        let send_abort_handle = broadcast_swap_message_every(self.ctx.clone(), swap_topic(&self.uuid), msg, 600.);
+        let send_abort_handle = send_swap_message_every(self.ctx.clone(), self.uuid, self.maker, msg, 600.);
 
         let wait_duration = (self.r().data.lock_duration * 4) / 5;
         let wait_taker_payment = self.r().data.started_at + wait_duration;
@@ -1297,6 +1311,8 @@ impl TakerSwap {
             maker_coin,
             taker_coin,
             data.lock_duration,
+            // the restarted swap switches to the encryption once the maker sends an encrypted message
+            false,
         );
         let command = saved.events.last().unwrap().get_command();
         for saved_event in saved.events {
@@ -1305,7 +1321,30 @@ impl TakerSwap {
         Ok((swap, command))
     }
 
-    pub fn recover_funds(&self) -> Result<RecoveredSwap, String> {
+    /// Returns the transaction that refunded the taker payment if it has been refunded already.
+    pub fn find_taker_payment_refund(&self) -> Result<Option<TransactionEnum>, String> {
+        let taker_payment = match &self.r().taker_payment {
+            Some(tx) => tx.tx_hex.0.clone(),
+            None => return Ok(None),
+        };
+
+        match try_s!(self.taker_coin.search_for_swap_tx_spend_my(
+            self.r().data.taker_payment_lock as u32,
+            &*self.r().other_persistent_pub,
+            &self.r().secret_hash.0,
+            &taker_payment,
+            self.r().data.taker_coin_start_block,
+            &self.r().data.taker_coin_swap_contract_address,
+        )) {
+            Some(FoundSwapTxSpend::Refunded(tx)) => Ok(Some(tx)),
+            Some(FoundSwapTxSpend::Spent(_)) | None => Ok(None),
+        }
+    }
+
+    pub fn recover_funds(&self) -> Result<RecoveredSwap, String> { self.recover_funds_with_fee_multiplier(1) }
+
+    /// Recovers the funds of the finished swap paying `fee_multiplier` times the regular fee if the refund is required.
+    pub fn recover_funds_with_fee_multiplier(&self, fee_multiplier: u64) -> Result<RecoveredSwap, String> {
         if self.finished_at.load(Ordering::Relaxed) == 0 {
             return ERR!("Swap must be finished before recover funds attempt");
         }
@@ -1431,21 +1470,22 @@ impl TakerSwap {
                 ),
             },
             None => {
-                if now_ms() / 1000 < self.r().data.taker_payment_lock + 3700 {
+                if swap_now() < self.r().data.taker_payment_lock + REFUND_LOCKTIME_DELAY {
                     return ERR!(
                         "Too early to refund, wait until {}",
-                        self.r().data.taker_payment_lock + 3700
+                        self.r().data.taker_payment_lock + REFUND_LOCKTIME_DELAY
                     );
                 }
 
                 let transaction = try_s!(self
                     .taker_coin
-                    .send_taker_refunds_payment(
+                    .send_taker_refunds_payment_with_fee_multiplier(
                         &taker_payment,
                         self.r().data.taker_payment_lock as u32,
                         &*self.r().other_persistent_pub,
                         &self.r().secret_hash.0,
                         &self.r().data.taker_coin_swap_contract_address,
+                        fee_multiplier,
                     )
                     .wait());
 
@@ -1503,6 +1543,8 @@ impl AtomicSwap for TakerSwap {
     fn maker_coin(&self) -> &str { self.maker_coin.ticker() }
 
     fn taker_coin(&self) -> &str { self.taker_coin.ticker() }
+
+    fn other_pubkey(&self) -> bits256 { self.maker }
 }
 
 pub struct TakerSwapPreparedParams {
@@ -1,10 +1,11 @@
-use libp2p::{ping::{Ping, PingConfig, PingEvent},
+use libp2p::{ping::{Ping, PingConfig, PingEvent, PingSuccess},
              swarm::{CloseConnection, NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters},
-             NetworkBehaviour};
+             NetworkBehaviour, PeerId};
 use log::error;
-use std::{collections::VecDeque,
+use std::{collections::{HashMap, VecDeque},
           num::NonZeroU32,
-          task::{Context, Poll}};
+          task::{Context, Poll},
+          time::Duration};
 use void::Void;
 
 /// Wrapper around libp2p Ping behaviour that forcefully disconnects a peer using NetworkBehaviourAction::DisconnectPeer
@@ -18,16 +19,26 @@ pub struct AdexPing {
     ping: Ping,
     #[behaviour(ignore)]
     events: VecDeque<NetworkBehaviourAction<Void, Void>>,
+    /// The latest round-trip time of the outbound pings by the peers
+    #[behaviour(ignore)]
+    rtts: HashMap<PeerId, Duration>,
 }
 
 impl NetworkBehaviourEventProcess<PingEvent> for AdexPing {
     fn inject_event(&mut self, event: PingEvent) {
-        if let Err(e) = event.result {
-            error!("Ping error {}. Disconnecting peer {}", e, event.peer);
-            self.events.push_back(NetworkBehaviourAction::CloseConnection {
-                peer_id: event.peer,
-                connection: CloseConnection::All,
-            });
+        match event.result {
+            Ok(PingSuccess::Ping { rtt }) => {
+                self.rtts.insert(event.peer, rtt);
+            },
+            Ok(PingSuccess::Pong) => (),
+            Err(e) => {
+                error!("Ping error {}. Disconnecting peer {}", e, event.peer);
+                self.rtts.remove(&event.peer);
+                self.events.push_back(NetworkBehaviourAction::CloseConnection {
+                    peer_id: event.peer,
+                    connection: CloseConnection::All,
+                });
+            },
         }
     }
 }
@@ -38,9 +49,18 @@ impl AdexPing {
         AdexPing {
             ping: Ping::new(PingConfig::new().with_max_failures(unsafe { NonZeroU32::new_unchecked(2) })),
             events: VecDeque::new(),
+            rtts: HashMap::new(),
         }
     }
 
+    /// The latest ping round-trip time of the peer, is None if the peer hasn't been pinged yet.
+    pub fn rtt(&self, peer: &PeerId) -> Option<Duration> { self.rtts.get(peer).copied() }
+
+    /// Forgets the round-trip times of the peers that are not `connected` anymore.
+    pub fn retain_connected(&mut self, connected: impl Fn(&PeerId) -> bool) {
+        self.rtts.retain(|peer, _| connected(peer));
+    }
+
     fn poll_event(
         &mut self,
         _cx: &mut Context,
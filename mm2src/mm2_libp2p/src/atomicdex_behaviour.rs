@@ -1,30 +1,39 @@
 use crate::{adex_ping::AdexPing,
-            peers_exchange::{PeerAddresses, PeersExchange},
+            netid_check::{NetidCheck, NetidCheckEvent},
+            peers_exchange::{PeerAddresses, PeerStore, PeersExchange},
             request_response::{build_request_response_behaviour, PeerRequest, PeerResponse, RequestResponseBehaviour,
-                               RequestResponseBehaviourEvent, RequestResponseSender},
+                               RequestResponseBehaviourEvent, RequestResponseSender, RequestResponseStats},
             runtime::{SwarmRuntimeOps, SWARM_RUNTIME}};
-use atomicdex_gossipsub::{Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, MessageId, Topic,
-                          TopicHash};
+use atomicdex_gossipsub::{Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, MessageId,
+                          PeerScoreParams, Topic, TopicHash};
 use futures::{channel::{mpsc::{channel, Receiver, Sender},
                         oneshot},
               future::{abortable, join_all, poll_fn, AbortHandle},
               Future, SinkExt, StreamExt};
-use libp2p::swarm::{IntoProtocolsHandler, NetworkBehaviour, ProtocolsHandler};
-use libp2p::{core::{ConnectedPoint, Multiaddr, Transport},
+#[cfg(not(target_arch = "wasm32"))]
+use libp2p::mdns::{Mdns, MdnsConfig, MdnsEvent};
+#[cfg(target_arch = "wasm32")] use libp2p::swarm::DummyBehaviour;
+use libp2p::swarm::{toggle::Toggle, IntoProtocolsHandler, NetworkBehaviour, ProtocolsHandler, SwarmEvent};
+use libp2p::{core::{connection::ListenerId, network::ConnectionLimits, upgrade::SelectUpgrade, ConnectedPoint,
+                    Multiaddr, Transport},
              identity,
              multiaddr::Protocol,
              noise,
+             relay::{Relay, RelayConfig},
              request_response::ResponseChannel,
              swarm::{ExpandedSwarm, NetworkBehaviourEventProcess, Swarm},
              NetworkBehaviour, PeerId};
 use libp2p_floodsub::{Floodsub, FloodsubEvent, Topic as FloodsubTopic};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use rand::seq::SliceRandom;
 use rand::Rng;
-use std::{collections::hash_map::{DefaultHasher, HashMap},
+use serde::{Deserialize, Serialize};
+use socks5::Socks5ProxyConfig;
+use std::{collections::{hash_map::{DefaultHasher, HashMap},
+                        HashSet},
           hash::{Hash, Hasher},
           iter,
-          net::IpAddr,
+          net::{IpAddr, SocketAddr},
           str::FromStr,
           task::{Context, Poll},
           time::Duration};
@@ -37,11 +46,23 @@ pub type AdexEventRx = Receiver<AdexBehaviourEvent>;
 #[cfg(test)] mod tests;
 
 pub const PEERS_TOPIC: &str = "PEERS";
+/// The light nodes announce their circuit addresses on the separate topic,
+/// so the nodes of the previous versions don't take them for the relay addresses announced on the [`PEERS_TOPIC`].
+pub const CIRCUITS_TOPIC: &str = "CIRCUITS";
 const CONNECTED_RELAYS_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(600);
 const ANNOUNCE_INITIAL_DELAY: Duration = Duration::from_secs(60);
 const CHANNEL_BUF_SIZE: usize = 1024 * 8;
 const NETID_7777: u16 = 7777;
+/// The number of the relays the light node listens through.
+const MAX_CIRCUIT_RELAYS: usize = 2;
+const RELAY_CONNECTION_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// mDNS is not available in the browser.
+#[cfg(not(target_arch = "wasm32"))]
+type AdexMdns = Toggle<Mdns>;
+#[cfg(target_arch = "wasm32")]
+type AdexMdns = Toggle<DummyBehaviour>;
 
 /// Returns info about connected peers
 pub async fn get_peers_info(mut cmd_tx: AdexCmdTx) -> HashMap<String, Vec<String>> {
@@ -73,6 +94,30 @@ pub async fn get_gossip_topic_peers(mut cmd_tx: AdexCmdTx) -> HashMap<String, Ve
     rx.await.expect("Tx should be present")
 }
 
+/// Returns the reachability of the node
+pub async fn get_reachability(mut cmd_tx: AdexCmdTx) -> Reachability {
+    let (result_tx, rx) = oneshot::channel();
+    let cmd = AdexBehaviourCmd::GetReachability { result_tx };
+    cmd_tx.send(cmd).await.expect("Rx should be present");
+    rx.await.expect("Tx should be present")
+}
+
+/// Returns the known peers with their connection statistics to be persisted
+pub async fn get_peer_store(mut cmd_tx: AdexCmdTx) -> PeerStore {
+    let (result_tx, rx) = oneshot::channel();
+    let cmd = AdexBehaviourCmd::GetPeerStore { result_tx };
+    cmd_tx.send(cmd).await.expect("Rx should be present");
+    rx.await.expect("Tx should be present")
+}
+
+/// Returns the gossipsub scores of the known peers
+pub async fn get_gossip_peer_scores(mut cmd_tx: AdexCmdTx) -> HashMap<String, GossipPeerScore> {
+    let (result_tx, rx) = oneshot::channel();
+    let cmd = AdexBehaviourCmd::GetGossipPeerScores { result_tx };
+    cmd_tx.send(cmd).await.expect("Rx should be present");
+    rx.await.expect("Tx should be present")
+}
+
 pub async fn get_relay_mesh(mut cmd_tx: AdexCmdTx) -> Vec<String> {
     let (result_tx, rx) = oneshot::channel();
     let cmd = AdexBehaviourCmd::GetRelayMesh { result_tx };
@@ -80,6 +125,62 @@ pub async fn get_relay_mesh(mut cmd_tx: AdexCmdTx) -> Vec<String> {
     rx.await.expect("Tx should be present")
 }
 
+/// Returns the ping, gossip and request-response statistics of the node
+pub async fn get_network_diagnostics(mut cmd_tx: AdexCmdTx) -> NetworkDiagnostics {
+    let (result_tx, rx) = oneshot::channel();
+    let cmd = AdexBehaviourCmd::GetNetworkDiagnostics { result_tx };
+    cmd_tx.send(cmd).await.expect("Rx should be present");
+    rx.await.expect("Tx should be present")
+}
+
+/// How the other nodes can reach this node, derived from its listeners.
+/// The light nodes don't listen directly and are reached through the relay circuits only.
+/// The NAT detection (AutoNAT) and the hole punching (DCUtR) require the newer libp2p with the relay v2 protocol
+/// and are out of scope until the libp2p is upgraded.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reachability {
+    /// The node doesn't listen yet
+    Unknown,
+    /// The node listens on a global address directly
+    Direct,
+    /// The node listens through the circuits of the relays only
+    Relayed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GossipTopicScore {
+    pub first_message_deliveries: f64,
+    pub invalid_message_deliveries: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GossipPeerScore {
+    pub score: f64,
+    /// All RPCs of the graylisted peer are ignored
+    pub graylisted: bool,
+    pub topics: HashMap<String, GossipTopicScore>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct GossipTopicStats {
+    pub published: u64,
+    pub received: u64,
+    pub received_bytes: u64,
+    pub duplicates: u64,
+    pub invalid: u64,
+    /// The received messages per second within the latest check interval
+    pub received_per_sec: f64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct NetworkDiagnostics {
+    /// The latest ping round-trip times of the connected peers in milliseconds
+    pub peers_rtt_ms: HashMap<String, u64>,
+    pub topics: HashMap<String, GossipTopicStats>,
+    pub requests: RequestResponseStats,
+}
+
 #[derive(Debug)]
 pub struct AdexResponseChannel(ResponseChannel<PeerResponse>);
 
@@ -136,9 +237,21 @@ pub enum AdexBehaviourCmd {
     GetGossipTopicPeers {
         result_tx: oneshot::Sender<HashMap<String, Vec<String>>>,
     },
+    GetGossipPeerScores {
+        result_tx: oneshot::Sender<HashMap<String, GossipPeerScore>>,
+    },
     GetRelayMesh {
         result_tx: oneshot::Sender<Vec<String>>,
     },
+    GetPeerStore {
+        result_tx: oneshot::Sender<PeerStore>,
+    },
+    GetReachability {
+        result_tx: oneshot::Sender<Reachability>,
+    },
+    GetNetworkDiagnostics {
+        result_tx: oneshot::Sender<NetworkDiagnostics>,
+    },
     /// Add a reserved peer to the peer exchange.
     AddReservedPeer {
         peer: PeerId,
@@ -148,6 +261,14 @@ pub enum AdexBehaviourCmd {
         message_id: MessageId,
         propagation_source: PeerId,
     },
+    /// Drop the message failed the validation and penalize the peers delivered it.
+    RejectMessage {
+        message_id: MessageId,
+    },
+    /// Drop the message that can't be processed but isn't provably invalid without penalizing anyone.
+    IgnoreMessage {
+        message_id: MessageId,
+    },
 }
 
 /// The structure is the same as `PeerResponse`,
@@ -241,6 +362,34 @@ pub struct AtomicDexBehaviour {
     request_response: RequestResponseBehaviour,
     peers_exchange: PeersExchange,
     ping: AdexPing,
+    relay: Relay,
+    mdns: AdexMdns,
+    netid_check: NetidCheck,
+    /// The peers discovered by mDNS in the local network, waiting to be asked for their netid
+    #[behaviour(ignore)]
+    discovered_local_peers: Vec<(PeerId, Multiaddr)>,
+    /// The local peers of other netids, they are not dialed again
+    #[behaviour(ignore)]
+    rejected_local_peers: HashSet<PeerId>,
+    /// The rejected local peers waiting to be disconnected
+    #[behaviour(ignore)]
+    local_peers_to_disconnect: Vec<PeerId>,
+    /// The circuit listeners of the light node by the relays
+    #[behaviour(ignore)]
+    circuit_listeners: HashMap<PeerId, ListenerId>,
+    #[behaviour(ignore)]
+    reachability: Reachability,
+    #[behaviour(ignore)]
+    connection_limits: ConnectionLimitsConfig,
+    /// The peers that are neither relays nor subscribed to any topic since the instant
+    #[behaviour(ignore)]
+    idle_peers: HashMap<PeerId, Instant>,
+    /// The received messages counters of the topics at the instant of the latest rates update
+    #[behaviour(ignore)]
+    topics_received_snapshot: (Instant, HashMap<TopicHash, u64>),
+    /// The received messages per second by the topics
+    #[behaviour(ignore)]
+    topics_received_rates: HashMap<TopicHash, f64>,
 }
 
 impl AtomicDexBehaviour {
@@ -362,6 +511,35 @@ impl AtomicDexBehaviour {
                     error!("Result rx is dropped");
                 }
             },
+            AdexBehaviourCmd::GetGossipPeerScores { result_tx } => {
+                let result = self
+                    .gossipsub
+                    .get_peer_scores()
+                    .into_iter()
+                    .map(|(peer, info)| {
+                        let topics = info
+                            .topics
+                            .into_iter()
+                            .map(|(topic, stats)| {
+                                let score = GossipTopicScore {
+                                    first_message_deliveries: stats.first_message_deliveries,
+                                    invalid_message_deliveries: stats.invalid_message_deliveries,
+                                };
+                                (topic.to_string(), score)
+                            })
+                            .collect();
+                        let score = GossipPeerScore {
+                            score: info.score,
+                            graylisted: info.graylisted,
+                            topics,
+                        };
+                        (peer.to_string(), score)
+                    })
+                    .collect();
+                if result_tx.send(result).is_err() {
+                    error!("Result rx is dropped");
+                }
+            },
             AdexBehaviourCmd::GetRelayMesh { result_tx } => {
                 let result = self
                     .gossipsub
@@ -373,6 +551,21 @@ impl AtomicDexBehaviour {
                     error!("Result rx is dropped");
                 }
             },
+            AdexBehaviourCmd::GetReachability { result_tx } => {
+                if result_tx.send(self.reachability).is_err() {
+                    error!("Result rx is dropped");
+                }
+            },
+            AdexBehaviourCmd::GetNetworkDiagnostics { result_tx } => {
+                if result_tx.send(self.network_diagnostics()).is_err() {
+                    error!("Result rx is dropped");
+                }
+            },
+            AdexBehaviourCmd::GetPeerStore { result_tx } => {
+                if result_tx.send(self.peers_exchange.peer_store()).is_err() {
+                    error!("Result rx is dropped");
+                }
+            },
             AdexBehaviourCmd::AddReservedPeer { peer, addresses } => {
                 self.peers_exchange
                     .add_peer_addresses_to_reserved_peers(&peer, addresses);
@@ -383,12 +576,18 @@ impl AtomicDexBehaviour {
             } => {
                 self.gossipsub.propagate_message(&message_id, &propagation_source);
             },
+            AdexBehaviourCmd::RejectMessage { message_id } => {
+                self.gossipsub.reject_message(&message_id);
+            },
+            AdexBehaviourCmd::IgnoreMessage { message_id } => {
+                self.gossipsub.ignore_message(&message_id);
+            },
         }
     }
 
-    fn announce_listeners(&mut self, listeners: PeerAddresses) {
+    fn announce_listeners(&mut self, topic: &str, listeners: PeerAddresses) {
         let serialized = rmp_serde::to_vec(&listeners).expect("PeerAddresses serialization should never fail");
-        self.floodsub.publish(FloodsubTopic::new(PEERS_TOPIC), serialized);
+        self.floodsub.publish(FloodsubTopic::new(topic), serialized);
     }
 
     pub fn connected_relays_len(&self) -> usize { self.gossipsub.connected_relays_len() }
@@ -398,6 +597,64 @@ impl AtomicDexBehaviour {
     pub fn received_messages_in_period(&self) -> (Duration, usize) { self.gossipsub.get_received_messages_in_period() }
 
     pub fn connected_peers_len(&self) -> usize { self.gossipsub.get_num_peers() }
+
+    pub fn connection_limits(&self) -> &ConnectionLimitsConfig { &self.connection_limits }
+
+    pub fn network_diagnostics(&self) -> NetworkDiagnostics {
+        let peers_rtt_ms = self
+            .gossipsub
+            .get_peers_connections()
+            .into_iter()
+            .filter_map(|(peer, _)| {
+                let rtt = self.ping.rtt(&peer)?;
+                Some((peer.to_string(), rtt.as_millis() as u64))
+            })
+            .collect();
+        let topics = self
+            .gossipsub
+            .get_topic_stats()
+            .iter()
+            .map(|(topic, stats)| {
+                let stats = GossipTopicStats {
+                    published: stats.published,
+                    received: stats.received,
+                    received_bytes: stats.received_bytes,
+                    duplicates: stats.duplicates,
+                    invalid: stats.invalid,
+                    received_per_sec: self.topics_received_rates.get(topic).copied().unwrap_or_default(),
+                };
+                (topic.to_string(), stats)
+            })
+            .collect();
+        NetworkDiagnostics {
+            peers_rtt_ms,
+            topics,
+            requests: self.request_response.stats(),
+        }
+    }
+
+    /// Updates the received messages rates of the topics since the previous update.
+    fn update_topics_received_rates(&mut self) {
+        let now = Instant::now();
+        let (updated_at, snapshot) = &self.topics_received_snapshot;
+        let elapsed = now.duration_since(*updated_at).as_secs_f64();
+        let received: HashMap<_, _> = self
+            .gossipsub
+            .get_topic_stats()
+            .iter()
+            .map(|(topic, stats)| (topic.clone(), stats.received))
+            .collect();
+        if elapsed > 0. {
+            self.topics_received_rates = received
+                .iter()
+                .map(|(topic, received)| {
+                    let previous = snapshot.get(topic).copied().unwrap_or_default();
+                    (topic.clone(), (received - previous) as f64 / elapsed)
+                })
+                .collect();
+        }
+        self.topics_received_snapshot = (now, received);
+    }
 }
 
 impl NetworkBehaviourEventProcess<GossipsubEvent> for AtomicDexBehaviour {
@@ -410,11 +667,29 @@ impl NetworkBehaviourEventProcess<FloodsubEvent> for AtomicDexBehaviour {
         if self.netid != NETID_7777 {
             if let FloodsubEvent::Message(message) = &event {
                 for topic in &message.topics {
-                    if topic == &FloodsubTopic::new(PEERS_TOPIC) {
-                        let addresses: PeerAddresses = match rmp_serde::from_read_ref(&message.data) {
-                            Ok(a) => a,
-                            Err(_) => return,
-                        };
+                    let is_circuits_topic = if topic == &FloodsubTopic::new(PEERS_TOPIC) {
+                        false
+                    } else if topic == &FloodsubTopic::new(CIRCUITS_TOPIC) {
+                        true
+                    } else {
+                        continue;
+                    };
+                    let addresses: PeerAddresses = match rmp_serde::from_read_ref(&message.data) {
+                        Ok(a) => a,
+                        Err(_) => return,
+                    };
+                    // the circuit addresses are not dialed as relays, the relay addresses are not listened through
+                    let addresses: PeerAddresses = addresses
+                        .into_iter()
+                        .filter(|address| is_circuit_address(address) == is_circuits_topic)
+                        .collect();
+                    if addresses.is_empty() {
+                        continue;
+                    }
+                    if is_circuits_topic {
+                        self.peers_exchange
+                            .add_relayed_peer_addresses(&message.source, addresses);
+                    } else {
                         self.peers_exchange
                             .add_peer_addresses_to_known_peers(&message.source, addresses);
                     }
@@ -432,6 +707,41 @@ impl NetworkBehaviourEventProcess<()> for AtomicDexBehaviour {
     fn inject_event(&mut self, _event: ()) {}
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl NetworkBehaviourEventProcess<MdnsEvent> for AtomicDexBehaviour {
+    fn inject_event(&mut self, event: MdnsEvent) {
+        if let MdnsEvent::Discovered(discovered) = event {
+            let network_port = self.peers_exchange.netid_port();
+            for (peer, address) in discovered {
+                // the nodes of other netids usually listen on other ports, the netid is checked on dial
+                if !is_local_netid_address(&address, network_port) || self.rejected_local_peers.contains(&peer) {
+                    continue;
+                }
+                debug!("Discovered local peer {} at {}", peer, address);
+                if !self.discovered_local_peers.contains(&(peer, address.clone())) {
+                    self.discovered_local_peers.push((peer, address));
+                }
+            }
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<NetidCheckEvent> for AtomicDexBehaviour {
+    fn inject_event(&mut self, event: NetidCheckEvent) {
+        match event {
+            NetidCheckEvent::Verified(peer) => info!("Local peer {} runs our netid", peer),
+            NetidCheckEvent::Rejected(peer) => {
+                warn!(
+                    "Local peer {} runs another netid or doesn't support the netid check",
+                    peer
+                );
+                self.rejected_local_peers.insert(peer);
+                self.local_peers_to_disconnect.push(peer);
+            },
+        }
+    }
+}
+
 impl NetworkBehaviourEventProcess<RequestResponseBehaviourEvent> for AtomicDexBehaviour {
     fn inject_event(&mut self, event: RequestResponseBehaviourEvent) {
         match event {
@@ -461,6 +771,40 @@ type AtomicDexSwarm = ExpandedSwarm<
     <AtomicDexBehaviour as NetworkBehaviour>::ProtocolsHandler,
 >;
 
+/// Checks that the address discovered by mDNS is a private IPv4 TCP address on the port of our netid.
+/// The port doesn't prove the netid, so the peer is asked for its netid on dial.
+#[cfg(not(target_arch = "wasm32"))]
+fn is_local_netid_address(address: &Multiaddr, network_port: u16) -> bool {
+    let mut components = address.iter();
+    match (components.next(), components.next()) {
+        (Some(Protocol::Ip4(ip)), Some(Protocol::Tcp(port))) => {
+            (ip.is_private() || ip.is_loopback()) && port == network_port && components.next().is_none()
+        },
+        _ => false,
+    }
+}
+
+fn dial_discovered_local_peers(swarm: &mut AtomicDexSwarm) {
+    let discovered = std::mem::take(&mut swarm.behaviour_mut().discovered_local_peers);
+    for (peer, address) in discovered {
+        if swarm.behaviour().gossipsub.is_connected_to_addr(&address) || Swarm::is_connected(swarm, &peer) {
+            continue;
+        }
+        info!("Checking the netid of local peer {} at {}", peer, address);
+        swarm.behaviour_mut().netid_check.check_peer(peer, address);
+    }
+}
+
+fn disconnect_rejected_local_peers(swarm: &mut AtomicDexSwarm) {
+    let rejected = std::mem::take(&mut swarm.behaviour_mut().local_peers_to_disconnect);
+    for peer in rejected {
+        info!("Disconnecting local peer {}", peer);
+        if Swarm::disconnect_peer_id(swarm, peer).is_err() {
+            debug!("Local peer {} is already disconnected", peer);
+        }
+    }
+}
+
 fn maintain_connection_to_relays(swarm: &mut AtomicDexSwarm, bootstrap_addresses: &[Multiaddr]) {
     let behaviour = swarm.behaviour();
     let connected_relays = behaviour.gossipsub.connected_relays();
@@ -492,13 +836,15 @@ fn maintain_connection_to_relays(swarm: &mut AtomicDexSwarm, bootstrap_addresses
             }
         }
         for (peer, addresses) in to_connect {
-            for addr in addresses {
-                if swarm.behaviour().gossipsub.is_connected_to_addr(&addr) {
-                    continue;
-                }
-                if let Err(e) = libp2p::Swarm::dial_addr(swarm, addr.clone()) {
-                    error!("Peer {} address {} dial error {}", peer, addr, e);
-                }
+            if addresses
+                .iter()
+                .any(|addr| swarm.behaviour().gossipsub.is_connected_to_addr(addr))
+            {
+                continue;
+            }
+            // dial by the peer id to attribute the dial failures to the peer
+            if let Err(e) = libp2p::Swarm::dial(swarm, &peer) {
+                error!("Peer {} dial error {:?}", peer, e);
             }
         }
     }
@@ -527,21 +873,152 @@ fn maintain_connection_to_relays(swarm: &mut AtomicDexSwarm, bootstrap_addresses
     }
 }
 
+/// Updates the connection statistics of the peers exchange.
+fn process_swarm_event<E, H>(swarm: &mut AtomicDexSwarm, event: SwarmEvent<E, H>) {
+    match event {
+        SwarmEvent::ConnectionEstablished {
+            peer_id,
+            endpoint: ConnectedPoint::Dialer { address },
+            ..
+        } => swarm
+            .behaviour_mut()
+            .peers_exchange
+            .on_connection_established(&peer_id, address),
+        SwarmEvent::UnreachableAddr {
+            peer_id,
+            attempts_remaining: 0,
+            ..
+        } => swarm.behaviour_mut().peers_exchange.on_dial_failure(&peer_id),
+        _ => (),
+    }
+}
+
+/// Closes the connections to the peers that are neither relays nor subscribed to any topic
+/// for longer than `idle_connection_timeout`.
+fn prune_idle_connections(swarm: &mut AtomicDexSwarm) {
+    let now = Instant::now();
+    let idle_timeout = Duration::from_secs(swarm.behaviour().connection_limits.idle_connection_timeout);
+    let behaviour = swarm.behaviour();
+    let connected_relays = behaviour.gossipsub.connected_relays();
+    let peer_topics = behaviour.gossipsub.get_all_peer_topics();
+    let idle_now: Vec<_> = behaviour
+        .gossipsub
+        .get_peers_connections()
+        .into_iter()
+        .map(|(peer, _)| peer)
+        .filter(|peer| {
+            !connected_relays.contains(peer)
+                && !behaviour.peers_exchange.is_reserved_peer(peer)
+                && peer_topics.get(peer).map_or(true, |topics| topics.is_empty())
+        })
+        .collect();
+
+    let idle_peers = &mut swarm.behaviour_mut().idle_peers;
+    idle_peers.retain(|peer, _| idle_now.contains(peer));
+    let mut to_disconnect = Vec::new();
+    for peer in idle_now {
+        let idle_since = *idle_peers.entry(peer).or_insert(now);
+        if now.duration_since(idle_since) >= idle_timeout {
+            to_disconnect.push(peer);
+        }
+    }
+
+    for peer in to_disconnect {
+        info!("Disconnecting idle peer {}", peer);
+        swarm.behaviour_mut().idle_peers.remove(&peer);
+        if Swarm::disconnect_peer_id(swarm, peer).is_err() {
+            error!("Peer {} disconnect error", peer);
+        }
+    }
+}
+
+fn is_circuit_address(address: &Multiaddr) -> bool { address.iter().any(|p| p == Protocol::P2pCircuit) }
+
+fn is_global_address(address: &Multiaddr) -> bool {
+    for protocol in address.iter() {
+        if let Protocol::Ip4(ip) = protocol {
+            return ip.is_global();
+        }
+    }
+    false
+}
+
+/// Listens through up to `MAX_CIRCUIT_RELAYS` connected relays, so the light node behind NAT
+/// can be reached by the other nodes.
+fn maintain_circuit_listeners(swarm: &mut AtomicDexSwarm) {
+    let connected_relays = swarm.behaviour().gossipsub.connected_relays();
+    let disconnected: Vec<_> = swarm
+        .behaviour()
+        .circuit_listeners
+        .keys()
+        .filter(|relay| !connected_relays.contains(relay))
+        .cloned()
+        .collect();
+    for relay in disconnected {
+        if let Some(listener_id) = swarm.behaviour_mut().circuit_listeners.remove(&relay) {
+            if Swarm::remove_listener(swarm, listener_id).is_err() {
+                debug!("Circuit listener through relay {} is already closed", relay);
+            }
+        }
+    }
+
+    let required = MAX_CIRCUIT_RELAYS.saturating_sub(swarm.behaviour().circuit_listeners.len());
+    let peers_connections = swarm.behaviour().gossipsub.get_peers_connections();
+    let mut to_listen = Vec::new();
+    for relay in connected_relays
+        .iter()
+        .filter(|relay| !swarm.behaviour().circuit_listeners.contains_key(*relay))
+    {
+        if to_listen.len() >= required {
+            break;
+        }
+        let dialed_address = peers_connections.get(relay).and_then(|connections| {
+            connections.iter().find_map(|(_, point)| match point {
+                ConnectedPoint::Dialer { address } if !is_circuit_address(address) => Some(address.clone()),
+                _ => None,
+            })
+        });
+        if let Some(mut address) = dialed_address {
+            if !matches!(address.iter().last(), Some(Protocol::P2p(_))) {
+                address.push(Protocol::P2p((*relay).into()));
+            }
+            address.push(Protocol::P2pCircuit);
+            to_listen.push((*relay, address));
+        }
+    }
+    for (relay, address) in to_listen {
+        match Swarm::listen_on(swarm, address.clone()) {
+            Ok(listener_id) => {
+                info!("Listening on {}", address);
+                swarm.behaviour_mut().circuit_listeners.insert(relay, listener_id);
+            },
+            Err(e) => error!("Error listening on {}: {}", address, e),
+        }
+    }
+}
+
+fn update_reachability(swarm: &mut AtomicDexSwarm) {
+    let reachability = if Swarm::listeners(swarm).any(|l| is_global_address(l) && !is_circuit_address(l)) {
+        Reachability::Direct
+    } else if Swarm::listeners(swarm).any(is_circuit_address) {
+        Reachability::Relayed
+    } else {
+        Reachability::Unknown
+    };
+    swarm.behaviour_mut().reachability = reachability;
+}
+
 fn announce_my_addresses(swarm: &mut AtomicDexSwarm) {
+    // the relays announce their global address, the light nodes announce their circuit addresses on the separate topic
+    let i_am_relay = swarm.behaviour().gossipsub.is_relay();
     let global_listeners: PeerAddresses = Swarm::listeners(swarm)
-        .filter(|listener| {
-            for protocol in listener.iter() {
-                if let Protocol::Ip4(ip) = protocol {
-                    return ip.is_global();
-                }
-            }
-            false
-        })
-        .take(1)
+        .filter(|listener| is_global_address(listener) && is_circuit_address(listener) != i_am_relay)
+        .take(if i_am_relay { 1 } else { MAX_CIRCUIT_RELAYS })
         .cloned()
         .collect();
     if !global_listeners.is_empty() {
-        swarm.behaviour_mut().announce_listeners(global_listeners);
+        let topic = if i_am_relay { PEERS_TOPIC } else { CIRCUITS_TOPIC };
+        swarm.behaviour_mut().announce_listeners(topic, global_listeners);
     }
 }
 
@@ -611,23 +1088,167 @@ impl NodeType {
     }
 }
 
+/// The connection limits of the swarm configured by the `p2p_connection_limits` object of the MM2 config,
+/// e.g. `{"max_established_incoming": 256, "max_established_per_peer": 1, "idle_connection_timeout": 300}`.
+/// The `null` limit disables the corresponding check.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConnectionLimitsConfig {
+    pub max_established_incoming: Option<u32>,
+    pub max_established_outgoing: Option<u32>,
+    pub max_established_per_peer: Option<u32>,
+    pub max_pending_incoming: Option<u32>,
+    pub max_pending_outgoing: Option<u32>,
+    /// The connection to a peer that is neither a relay nor subscribed to any topic
+    /// is closed after this number of seconds
+    pub idle_connection_timeout: u64,
+}
+
+impl Default for ConnectionLimitsConfig {
+    fn default() -> Self {
+        ConnectionLimitsConfig {
+            max_established_incoming: Some(512),
+            max_established_outgoing: Some(128),
+            max_established_per_peer: Some(2),
+            max_pending_incoming: Some(128),
+            max_pending_outgoing: Some(64),
+            idle_connection_timeout: 600,
+        }
+    }
+}
+
+impl ConnectionLimitsConfig {
+    fn to_connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits::default()
+            .with_max_established_incoming(self.max_established_incoming)
+            .with_max_established_outgoing(self.max_established_outgoing)
+            .with_max_established_per_peer(self.max_established_per_peer)
+            .with_max_pending_incoming(self.max_pending_incoming)
+            .with_max_pending_outgoing(self.max_pending_outgoing)
+    }
+}
+
+/// The DNS resolution of the `/dns*` multiaddrs by the TCP transport, is not used in WASM.
+/// Is deserialized from `"system"`, `"disabled"`, `{"nameservers": ["1.1.1.1:53"]}`
+/// or `{"https": {"ips": ["1.1.1.1"], "tls_dns_name": "cloudflare-dns.com"}}`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsConfig {
+    /// The system configuration, e.g. `/etc/resolv.conf` on Unix.
+    /// Google DNS is used if the system configuration can't be read.
+    System,
+    /// The custom nameservers queried over UDP and TCP.
+    Nameservers(Vec<SocketAddr>),
+    /// The DNS-over-HTTPS nameservers.
+    Https {
+        ips: Vec<IpAddr>,
+        #[serde(default = "default_https_port")]
+        port: u16,
+        tls_dns_name: String,
+    },
+    /// Only the IP multiaddrs can be dialed.
+    Disabled,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self { DnsConfig::System }
+}
+
+fn default_https_port() -> u16 { 443 }
+
+/// Builds the TCP transport resolving the DNS multiaddrs according to the `dns_config`.
+/// If the `socks5_proxy` is set, the TCP multiaddrs are dialed and resolved through the proxy instead.
+#[cfg(not(target_arch = "wasm32"))]
+fn build_dns_tcp_transport(
+    dns_config: DnsConfig,
+    socks5_proxy: Option<Socks5ProxyConfig>,
+) -> libp2p::core::transport::Boxed<libp2p::tcp::tokio::TcpStream> {
+    let dns_tcp = build_dns_tcp_transport_direct(dns_config, socks5_proxy.clone());
+    match socks5_proxy {
+        Some(proxy) => crate::socks5_transport::Socks5TcpTransport::new(proxy, dns_tcp).boxed(),
+        None => dns_tcp,
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn build_dns_tcp_transport_direct(
+    dns_config: DnsConfig,
+    socks5_proxy: Option<Socks5ProxyConfig>,
+) -> libp2p::core::transport::Boxed<libp2p::tcp::tokio::TcpStream> {
+    use libp2p::dns::{ResolverConfig, ResolverOpts, TokioDnsConfig};
+    use trust_dns_resolver::config::NameServerConfigGroup;
+
+    let tcp = libp2p::tcp::TokioTcpConfig::new().nodelay(true);
+    let resolver_config = match dns_config {
+        DnsConfig::System => match TokioDnsConfig::system(tcp.clone()) {
+            Ok(dns_tcp) => return dns_tcp.boxed(),
+            Err(e) => {
+                log::warn!("Error reading the system DNS configuration: {}, using Google DNS", e);
+                ResolverConfig::google()
+            },
+        },
+        DnsConfig::Nameservers(nameservers) => {
+            let mut group = NameServerConfigGroup::new();
+            for nameserver in nameservers {
+                group.merge(NameServerConfigGroup::from_ips_clear(
+                    &[nameserver.ip()],
+                    nameserver.port(),
+                    true,
+                ));
+            }
+            ResolverConfig::from_parts(None, Vec::new(), group)
+        },
+        DnsConfig::Https {
+            ips,
+            port,
+            tls_dns_name,
+        } => {
+            // the DoH nameservers are connected through the proxy as well
+            return crate::doh_transport::DohTcpTransport::new(ips, port, &tls_dns_name, socks5_proxy, tcp.boxed())
+                .boxed();
+        },
+        DnsConfig::Disabled => return tcp.boxed(),
+    };
+    TokioDnsConfig::custom(tcp, resolver_config, ResolverOpts::default())
+        .expect("TokioDnsConfig::custom never fails")
+        .boxed()
+}
+
 /// Creates and spawns new AdexBehaviour Swarm returning:
 /// 1. tx to send control commands
 /// 2. rx emitting gossip events to processing side
 /// 3. our peer_id
 /// 4. abort handle to stop the P2P processing fut.
+#[allow(clippy::too_many_arguments)]
 pub async fn spawn_gossipsub(
     netid: u16,
-    force_key: Option<[u8; 32]>,
+    node_key: NodeKey,
     spawn_fn: fn(Box<dyn Future<Output = ()> + Send + Unpin + 'static>) -> (),
     to_dial: Vec<String>,
+    stored_peers: PeerStore,
     node_type: NodeType,
+    dns_config: DnsConfig,
+    mdns_enabled: bool,
+    connection_limits: ConnectionLimitsConfig,
+    socks5_proxy: Option<Socks5ProxyConfig>,
     on_poll: impl Fn(&AtomicDexSwarm) + Send + 'static,
 ) -> (Sender<AdexBehaviourCmd>, AdexEventRx, PeerId, AbortHandle) {
     let (result_tx, result_rx) = futures::channel::oneshot::channel();
     let fut = async move {
-        let (cmd_tx, event_rx, peer_id, p2p_abort) =
-            start_gossipsub(netid, force_key, spawn_fn, to_dial, node_type, on_poll);
+        let (cmd_tx, event_rx, peer_id, p2p_abort) = start_gossipsub(
+            netid,
+            node_key,
+            spawn_fn,
+            to_dial,
+            stored_peers,
+            node_type,
+            dns_config,
+            mdns_enabled,
+            connection_limits,
+            socks5_proxy,
+            on_poll,
+        )
+        .await;
         result_tx.send((cmd_tx, event_rx, peer_id, p2p_abort)).unwrap();
     };
 
@@ -642,21 +1263,31 @@ pub async fn spawn_gossipsub(
 /// 3. our peer_id
 /// 4. abort handle to stop the P2P processing fut
 ///
+/// The `stored_peers` saved on the previous run are dialed along with `to_dial`,
+/// so the node joins the network even if the seednodes are unreachable.
+/// If `mdns_enabled`, the relays of the same netid are also discovered in the local network.
+/// If the `socks5_proxy` is set, the peers are dialed through the proxy (not supported in WASM).
+///
 /// Prefer using [`spawn_gossipsub`] to make sure the Swarm is initialized and spawned on the same runtime.
 /// Otherwise, you can face the following error:
 /// `panicked at 'there is no reactor running, must be called from the context of a Tokio 1.x runtime'`.
 #[allow(clippy::too_many_arguments)]
-fn start_gossipsub(
+async fn start_gossipsub(
     netid: u16,
-    force_key: Option<[u8; 32]>,
+    node_key: NodeKey,
     spawn_fn: fn(Box<dyn Future<Output = ()> + Send + Unpin + 'static>) -> (),
     to_dial: Vec<String>,
+    stored_peers: PeerStore,
     node_type: NodeType,
+    #[cfg_attr(target_arch = "wasm32", allow(unused_variables))] dns_config: DnsConfig,
+    #[cfg_attr(target_arch = "wasm32", allow(unused_variables))] mdns_enabled: bool,
+    connection_limits: ConnectionLimitsConfig,
+    #[cfg_attr(target_arch = "wasm32", allow(unused_variables))] socks5_proxy: Option<Socks5ProxyConfig>,
     on_poll: impl Fn(&AtomicDexSwarm) + Send + 'static,
 ) -> (Sender<AdexBehaviourCmd>, AdexEventRx, PeerId, AbortHandle) {
     let i_am_relay = node_type.is_relay();
     let mut rng = rand::thread_rng();
-    let local_key = generate_ed25519_keypair(&mut rng, force_key);
+    let local_key = generate_keypair(&mut rng, node_key);
     let local_peer_id = PeerId::from(local_key.public());
     info!("Local peer id: {:?}", local_peer_id);
     let network_port = node_type.network_port();
@@ -669,10 +1300,7 @@ fn start_gossipsub(
 
     #[cfg(not(target_arch = "wasm32"))]
     let transport = {
-        let tcp = libp2p::tcp::TokioTcpConfig::new().nodelay(true);
-        let dns_tcp =
-            libp2p::dns::TokioDnsConfig::custom(tcp, libp2p::dns::ResolverConfig::google(), Default::default())
-                .unwrap();
+        let dns_tcp = build_dns_tcp_transport(dns_config, socks5_proxy);
         let ws_dns_tcp = libp2p::websocket::WsConfig::new(dns_tcp.clone());
         dns_tcp.or_transport(ws_dns_tcp)
     };
@@ -681,11 +1309,22 @@ fn start_gossipsub(
         .into_authentic(&local_key)
         .expect("Signing libp2p-noise static DH keypair failed.");
 
-    // Set up an encrypted Transport over the Mplex protocol
+    // the nodes listening through the relays can be reached by the circuits,
+    // the relays don't connect to the destination nodes themselves
+    let relay_config = RelayConfig {
+        connection_idle_timeout: RELAY_CONNECTION_IDLE_TIMEOUT,
+        actively_connect_to_dst_nodes: false,
+    };
+    let (transport, relay) = libp2p::relay::new_transport_and_behaviour(relay_config, transport);
+
+    // Set up an encrypted Transport over the Yamux or Mplex protocol, Yamux is preferred
     let transport = transport
         .upgrade(libp2p::core::upgrade::Version::V1)
         .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
-        .multiplex(libp2p::mplex::MplexConfig::default())
+        .multiplex(SelectUpgrade::new(
+            libp2p::yamux::YamuxConfig::default(),
+            libp2p::mplex::MplexConfig::default(),
+        ))
         .timeout(std::time::Duration::from_secs(20))
         .map(|(peer, muxer), _| (peer, libp2p::core::muxing::StreamMuxerBox::new(muxer)))
         .boxed();
@@ -722,6 +1361,7 @@ fn start_gossipsub(
             .mesh_n_high(mesh_n_high)
             .manual_propagation()
             .max_transmit_size(1024 * 1024 - 100)
+            .peer_score_params(PeerScoreParams::default())
             .build();
         // build a gossipsub network behaviour
         let mut gossipsub = Gossipsub::new(local_peer_id, gossipsub_config);
@@ -737,6 +1377,7 @@ fn start_gossipsub(
                 gossipsub.add_explicit_relay(peer_id);
             }
         }
+        peers_exchange.load_peer_store(stored_peers);
 
         // build a request-response network behaviour
         let request_response = build_request_response_behaviour();
@@ -744,6 +1385,21 @@ fn start_gossipsub(
         // use default ping config with 15s interval, 20s timeout and 1 max failure
         let ping = AdexPing::new();
 
+        #[cfg(not(target_arch = "wasm32"))]
+        let mdns = if mdns_enabled {
+            match Mdns::new(MdnsConfig::default()).await {
+                Ok(mdns) => Some(mdns),
+                Err(e) => {
+                    error!("Error starting mDNS discovery: {}", e);
+                    None
+                },
+            }
+        } else {
+            None
+        };
+        #[cfg(target_arch = "wasm32")]
+        let mdns: Option<DummyBehaviour> = None;
+
         let adex_behavior = AtomicDexBehaviour {
             event_tx,
             spawn_fn,
@@ -754,15 +1410,32 @@ fn start_gossipsub(
             request_response,
             peers_exchange,
             ping,
+            relay,
+            mdns: mdns.into(),
+            netid_check: NetidCheck::new(netid),
+            discovered_local_peers: Vec::new(),
+            rejected_local_peers: HashSet::new(),
+            local_peers_to_disconnect: Vec::new(),
+            circuit_listeners: HashMap::new(),
+            reachability: Reachability::Unknown,
+            connection_limits: connection_limits.clone(),
+            idle_peers: HashMap::new(),
+            topics_received_snapshot: (Instant::now(), HashMap::new()),
+            topics_received_rates: HashMap::new(),
         };
         libp2p::swarm::SwarmBuilder::new(transport, adex_behavior, local_peer_id)
             .executor(Box::new(&*SWARM_RUNTIME))
+            .connection_limits(connection_limits.to_connection_limits())
             .build()
     };
     swarm
         .behaviour_mut()
         .floodsub
         .subscribe(FloodsubTopic::new(PEERS_TOPIC.to_owned()));
+    swarm
+        .behaviour_mut()
+        .floodsub
+        .subscribe(FloodsubTopic::new(CIRCUITS_TOPIC.to_owned()));
 
     if let NodeType::Relay {
         ip,
@@ -783,6 +1456,13 @@ fn start_gossipsub(
         }
     }
 
+    for (peer, _) in swarm.behaviour().peers_exchange.best_stored_peers(mesh_n) {
+        match libp2p::Swarm::dial(&mut swarm, &peer) {
+            Ok(_) => info!("Dialed stored peer {}", peer),
+            Err(e) => error!("Dial stored peer {} failed: {:?}", peer, e),
+        }
+    }
+
     let mut check_connected_relays_interval = Interval::new_at(
         Instant::now() + CONNECTED_RELAYS_CHECK_INTERVAL,
         CONNECTED_RELAYS_CHECK_INTERVAL,
@@ -800,20 +1480,37 @@ fn start_gossipsub(
 
         loop {
             match swarm.poll_next_unpin(cx) {
-                Poll::Ready(Some(event)) => debug!("Swarm event {:?}", event),
+                Poll::Ready(Some(event)) => {
+                    debug!("Swarm event {:?}", event);
+                    process_swarm_event(&mut swarm, event);
+                },
                 Poll::Ready(None) => return Poll::Ready(()),
                 Poll::Pending => break,
             }
         }
 
-        if swarm.behaviour().gossipsub.is_relay() {
-            while let Poll::Ready(Some(())) = announce_interval.poll_next_unpin(cx) {
-                announce_my_addresses(&mut swarm);
-            }
+        if !swarm.behaviour().discovered_local_peers.is_empty() {
+            dial_discovered_local_peers(&mut swarm);
+        }
+        if !swarm.behaviour().local_peers_to_disconnect.is_empty() {
+            disconnect_rejected_local_peers(&mut swarm);
+        }
+
+        while let Poll::Ready(Some(())) = announce_interval.poll_next_unpin(cx) {
+            announce_my_addresses(&mut swarm);
         }
 
         while let Poll::Ready(Some(())) = check_connected_relays_interval.poll_next_unpin(cx) {
             maintain_connection_to_relays(&mut swarm, &bootstrap);
+            prune_idle_connections(&mut swarm);
+            let behaviour = swarm.behaviour_mut();
+            let connected = behaviour.gossipsub.get_peers_connections();
+            behaviour.ping.retain_connected(|peer| connected.contains_key(peer));
+            behaviour.update_topics_received_rates();
+            if !i_am_relay {
+                maintain_circuit_listeners(&mut swarm);
+            }
+            update_reachability(&mut swarm);
         }
 
         if !listening && i_am_relay {
@@ -832,14 +1529,29 @@ fn start_gossipsub(
     (cmd_tx, event_rx, local_peer_id, abort_handle)
 }
 
-fn generate_ed25519_keypair<R: Rng>(rng: &mut R, force_key: Option<[u8; 32]>) -> identity::Keypair {
-    let mut raw_key = match force_key {
-        Some(key) => key,
-        None => {
+/// The libp2p identity key of the node.
+pub enum NodeKey {
+    /// The random ed25519 key, the peer id changes on every start
+    Random,
+    /// The ed25519 key generated from the seed
+    Ed25519Seed([u8; 32]),
+    /// The secp256k1 secret key of the node, so the peer id can be derived from the node pubkey
+    /// by [`crate::peer_id_from_secp256k1_pubkey`]
+    Secp256k1([u8; 32]),
+}
+
+fn generate_keypair<R: Rng>(rng: &mut R, node_key: NodeKey) -> identity::Keypair {
+    let mut raw_key = match node_key {
+        NodeKey::Random => {
             let mut key = [0; 32];
             rng.fill_bytes(&mut key);
             key
         },
+        NodeKey::Ed25519Seed(key) => key,
+        NodeKey::Secp256k1(mut key) => {
+            let secret = identity::secp256k1::SecretKey::from_bytes(&mut key).expect("Valid secp256k1 secret");
+            return identity::Keypair::Secp256k1(secret.into());
+        },
     };
     let secret = identity::ed25519::SecretKey::from_bytes(&mut raw_key).expect("Secret length is 32 bytes");
     let keypair = identity::ed25519::Keypair::from(secret);
@@ -1,4 +1,5 @@
-use super::{spawn_gossipsub, AdexBehaviourCmd, AdexBehaviourEvent, AdexResponse, NodeType};
+use super::{is_local_netid_address, spawn_gossipsub, AdexBehaviourCmd, AdexBehaviourEvent, AdexResponse,
+            ConnectionLimitsConfig, DnsConfig, NodeKey, NodeType, PeerStore};
 use async_std::task::spawn;
 use futures::channel::{mpsc, oneshot};
 use futures::{Future, SinkExt, StreamExt};
@@ -30,8 +31,20 @@ impl Node {
             network_port,
             network_ws_port: network_port + 10,
         };
-        let (cmd_tx, mut event_rx, peer_id, _) =
-            spawn_gossipsub(333, None, spawn_boxed, seednodes, node_type, |_| {}).await;
+        let (cmd_tx, mut event_rx, peer_id, _) = spawn_gossipsub(
+            333,
+            NodeKey::Random,
+            spawn_boxed,
+            seednodes,
+            PeerStore::new(),
+            node_type,
+            DnsConfig::default(),
+            false,
+            ConnectionLimitsConfig::default(),
+            None,
+            |_| {},
+        )
+        .await;
 
         // spawn a response future
         let cmd_tx_fut = cmd_tx.clone();
@@ -384,3 +397,29 @@ async fn test_request_peers_ok_three_peers() {
     responses.sort_by(|x, y| x.0.cmp(&y.0));
     assert_eq!(responses, expected);
 }
+
+#[test]
+fn test_is_local_netid_address() {
+    assert!(is_local_netid_address(
+        &"/ip4/192.168.0.10/tcp/40333".parse().unwrap(),
+        40333
+    ));
+    assert!(is_local_netid_address(
+        &"/ip4/127.0.0.1/tcp/40333".parse().unwrap(),
+        40333
+    ));
+    // another netid
+    assert!(!is_local_netid_address(
+        &"/ip4/192.168.0.10/tcp/40334".parse().unwrap(),
+        40333
+    ));
+    // websocket listener
+    assert!(!is_local_netid_address(
+        &"/ip4/192.168.0.10/tcp/40333/ws".parse().unwrap(),
+        40333
+    ));
+    assert!(!is_local_netid_address(
+        &"/ip4/8.8.8.8/tcp/40333".parse().unwrap(),
+        40333
+    ));
+}
@@ -0,0 +1,316 @@
+//! The TCP transport resolving the DNS multiaddrs by the DNS-over-HTTPS nameservers (RFC 8484).
+//! The nameservers are connected by their IPs, so no plaintext DNS requests are made at all.
+//! If the SOCKS5 proxy is configured, the nameservers are connected through the proxy too.
+
+use crate::socks5_transport::transport_error_to_io;
+use futures::future::{self, BoxFuture};
+use hyper::client::connect::dns::Name;
+use hyper::client::HttpConnector;
+use hyper::header::{ACCEPT, CONTENT_TYPE};
+use hyper::service::Service;
+use hyper::{Body, Client, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use libp2p::core::{transport::{Boxed, TransportError},
+                   Multiaddr, Transport};
+use libp2p::multiaddr::Protocol;
+use libp2p::tcp::tokio::TcpStream;
+use log::{debug, warn};
+use socks5::{socks5_connect, Socks5ProxyConfig};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::{Name as DomainName, RecordType};
+
+type InnerTransport = Boxed<TcpStream>;
+
+const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
+
+fn other_io_err(e: impl ToString) -> io::Error { io::Error::new(io::ErrorKind::Other, e.to_string()) }
+
+/// Resolves the nameserver host to its configured IPs.
+#[derive(Clone)]
+struct NameserverIps(Arc<Vec<IpAddr>>);
+
+impl Service<Name> for NameserverIps {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> { Poll::Ready(Ok(())) }
+
+    /// The port is set by the `HttpConnector` from the nameserver URL.
+    fn call(&mut self, _name: Name) -> Self::Future {
+        let addrs: Vec<_> = self.0.iter().map(|ip| SocketAddr::new(*ip, 0)).collect();
+        future::ready(Ok(addrs.into_iter()))
+    }
+}
+
+/// Connects to the nameserver IPs through the SOCKS5 proxy if it's set, or directly otherwise.
+#[derive(Clone)]
+struct NameserverConnector {
+    http: HttpConnector<NameserverIps>,
+    ips: NameserverIps,
+    proxy: Option<Socks5ProxyConfig>,
+}
+
+impl NameserverConnector {
+    fn new(ips: Vec<IpAddr>, proxy: Option<Socks5ProxyConfig>) -> Self {
+        let ips = NameserverIps(Arc::new(ips));
+        let mut http = HttpConnector::new_with_resolver(ips.clone());
+        http.enforce_http(false);
+        NameserverConnector { http, ips, proxy }
+    }
+}
+
+/// Tries the nameserver IPs one by one through the `proxy`.
+async fn connect_through_proxy(
+    proxy: &Socks5ProxyConfig,
+    ips: &[IpAddr],
+    port: u16,
+) -> io::Result<tokio::net::TcpStream> {
+    let mut last_err = None;
+    for ip in ips {
+        match socks5_connect(proxy, &ip.to_string(), port).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| other_io_err("No nameserver IPs")))
+}
+
+impl Service<Uri> for NameserverConnector {
+    type Response = tokio::net::TcpStream;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(other_io_err)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let mut http = self.http.clone();
+        let ips = self.ips.0.clone();
+        let proxy = self.proxy.clone();
+        Box::pin(async move {
+            let proxy = match proxy {
+                Some(proxy) => proxy,
+                None => return http.call(uri).await.map_err(other_io_err),
+            };
+            let port = uri.port_u16().unwrap_or(443);
+            match connect_through_proxy(&proxy, &ips, port).await {
+                Ok(stream) => Ok(stream),
+                Err(e) if proxy.refuse_clearnet_fallback => Err(e),
+                Err(e) => {
+                    warn!(
+                        "Error {} connecting to {} through the proxy, connecting directly",
+                        e, uri
+                    );
+                    http.call(uri).await.map_err(other_io_err)
+                },
+            }
+        })
+    }
+}
+
+#[derive(Clone)]
+struct DohResolver {
+    client: Client<HttpsConnector<NameserverConnector>>,
+    url: String,
+}
+
+impl DohResolver {
+    fn new(ips: Vec<IpAddr>, port: u16, tls_dns_name: &str, proxy: Option<Socks5ProxyConfig>) -> Self {
+        let http = NameserverConnector::new(ips, proxy);
+        let mut tls_config = rustls::ClientConfig::new();
+        tls_config
+            .root_store
+            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        tls_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let https = HttpsConnector::from((http, Arc::new(tls_config)));
+        DohResolver {
+            client: Client::builder().build(https),
+            url: format!("https://{}:{}/dns-query", tls_dns_name, port),
+        }
+    }
+
+    async fn lookup(&self, host: &str, record_type: RecordType) -> Result<Vec<IpAddr>, io::Error> {
+        let mut query = Message::new();
+        // the message ID is 0 to make the responses cacheable, see RFC 8484 section 4.1
+        query
+            .set_id(0)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(
+                DomainName::from_ascii(host).map_err(other_io_err)?,
+                record_type,
+            ));
+        let request = Request::post(&self.url)
+            .header(CONTENT_TYPE, DNS_MESSAGE_CONTENT_TYPE)
+            .header(ACCEPT, DNS_MESSAGE_CONTENT_TYPE)
+            .body(Body::from(query.to_vec().map_err(other_io_err)?))
+            .map_err(other_io_err)?;
+
+        let response = self.client.request(request).await.map_err(other_io_err)?;
+        if response.status() != StatusCode::OK {
+            return Err(other_io_err(format!(
+                "{} responded with the status {}",
+                self.url,
+                response.status()
+            )));
+        }
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(other_io_err)?;
+        let message = Message::from_vec(&body).map_err(other_io_err)?;
+        if message.response_code() != ResponseCode::NoError {
+            return Err(other_io_err(format!(
+                "{} responded to the {} {} query with {}",
+                self.url,
+                host,
+                record_type,
+                message.response_code()
+            )));
+        }
+        Ok(message
+            .answers()
+            .iter()
+            .filter_map(|record| record.rdata().to_ip_addr())
+            .collect())
+    }
+}
+
+/// Finds the DNS component of the multiaddr returning its index, the host and the record types to query.
+fn dns_component(addr: &Multiaddr) -> Option<(usize, String, &'static [RecordType])> {
+    addr.iter().enumerate().find_map(|(i, protocol)| match protocol {
+        Protocol::Dns(host) => Some((i, host.into_owned(), &[RecordType::A, RecordType::AAAA][..])),
+        Protocol::Dns4(host) => Some((i, host.into_owned(), &[RecordType::A][..])),
+        Protocol::Dns6(host) => Some((i, host.into_owned(), &[RecordType::AAAA][..])),
+        _ => None,
+    })
+}
+
+/// Resolves the `/dns|dns4|dns6` multiaddrs by the DNS-over-HTTPS nameservers and dials the resolved IPs
+/// one by one by the `inner` transport. The rest of the calls are delegated to the `inner` transport.
+/// The nameservers are connected through the SOCKS5 `proxy` if it's set.
+#[derive(Clone)]
+pub struct DohTcpTransport {
+    resolver: DohResolver,
+    inner: InnerTransport,
+}
+
+impl DohTcpTransport {
+    pub fn new(
+        ips: Vec<IpAddr>,
+        port: u16,
+        tls_dns_name: &str,
+        proxy: Option<Socks5ProxyConfig>,
+        inner: InnerTransport,
+    ) -> Self {
+        DohTcpTransport {
+            resolver: DohResolver::new(ips, port, tls_dns_name, proxy),
+            inner,
+        }
+    }
+}
+
+impl Transport for DohTcpTransport {
+    type Output = TcpStream;
+    type Error = io::Error;
+    type Listener = <InnerTransport as Transport>::Listener;
+    type ListenerUpgrade = <InnerTransport as Transport>::ListenerUpgrade;
+    type Dial = BoxFuture<'static, Result<TcpStream, io::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        self.inner.listen_on(addr)
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let (index, host, record_types) = match dns_component(&addr) {
+            Some(component) => component,
+            None => return self.inner.dial(addr),
+        };
+        let fut = async move {
+            let mut last_err = None;
+            for record_type in record_types {
+                let ips = match self.resolver.lookup(&host, *record_type).await {
+                    Ok(ips) => ips,
+                    Err(e) => {
+                        last_err = Some(e);
+                        continue;
+                    },
+                };
+                for ip in ips {
+                    let ip = match ip {
+                        IpAddr::V4(ip) => Protocol::Ip4(ip),
+                        IpAddr::V6(ip) => Protocol::Ip6(ip),
+                    };
+                    let resolved = addr.replace(index, |_| Some(ip)).expect("`index` is a valid index");
+                    debug!("Dialing {} resolved from {}", resolved, addr);
+                    let result = match self.inner.clone().dial(resolved) {
+                        Ok(dial) => dial.await,
+                        Err(e) => Err(transport_error_to_io(e)),
+                    };
+                    match result {
+                        Ok(stream) => return Ok(stream),
+                        Err(e) => last_err = Some(e),
+                    }
+                }
+            }
+            Err(last_err.unwrap_or_else(|| other_io_err(format!("{} has no IP addresses", host))))
+        };
+        Ok(Box::pin(fut))
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dns_component() {
+        let addr: Multiaddr = "/dns4/example.com/tcp/38890".parse().unwrap();
+        assert_eq!(
+            dns_component(&addr),
+            Some((0, "example.com".to_owned(), &[RecordType::A][..]))
+        );
+
+        let addr: Multiaddr = "/dns/example.com/tcp/38890/ws".parse().unwrap();
+        assert_eq!(
+            dns_component(&addr),
+            Some((0, "example.com".to_owned(), &[RecordType::A, RecordType::AAAA][..]))
+        );
+
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/38890".parse().unwrap();
+        assert_eq!(dns_component(&addr), None);
+    }
+
+    #[tokio::test]
+    async fn test_nameserver_connector_uses_proxy() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let proxy_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut proxy = Socks5ProxyConfig::new(proxy_listener.local_addr().unwrap().to_string());
+        proxy.refuse_clearnet_fallback = true;
+        let proxy_fut = tokio::spawn(async move {
+            let (mut stream, _) = proxy_listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            // no acceptable authentication methods
+            stream.write_all(&[5, 0xff]).await.unwrap();
+            greeting
+        });
+
+        let mut connector = NameserverConnector::new(vec!["1.1.1.1".parse().unwrap()], Some(proxy));
+        let uri: Uri = "https://cloudflare-dns.com:443/dns-query".parse().unwrap();
+        // the clearnet fallback is refused, so the nameserver isn't connected directly
+        assert!(connector.call(uri).await.is_err());
+        assert_eq!(proxy_fut.await.unwrap(), [5, 1, 0]);
+    }
+}
@@ -2,20 +2,27 @@
 
 mod adex_ping;
 pub mod atomicdex_behaviour;
+#[cfg(not(target_arch = "wasm32"))] mod doh_transport;
+mod netid_check;
 pub mod peers_exchange;
 pub mod request_response;
 mod runtime;
+#[cfg(not(target_arch = "wasm32"))] mod socks5_transport;
 
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
 use lazy_static::lazy_static;
+use secp256k1::ecdh::SharedSecret;
 use secp256k1::{Message as SecpMessage, PublicKey as Secp256k1Pubkey, Secp256k1, SecretKey, SignOnly, Signature,
                 VerifyOnly};
 use serde::{de, ser::Serializer, Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
-pub use atomicdex_behaviour::{spawn_gossipsub, NodeType};
+pub use atomicdex_behaviour::{spawn_gossipsub, ConnectionLimitsConfig, DnsConfig, NetworkDiagnostics, NodeKey,
+                              NodeType};
 pub use atomicdex_gossipsub::{GossipsubEvent, GossipsubMessage, MessageId};
 pub use libp2p::PeerId;
-pub use peers_exchange::PeerAddresses;
+pub use peers_exchange::{PeerAddresses, PeerStore};
 
 lazy_static! {
     static ref SECP_VERIFY: Secp256k1<VerifyOnly> = Secp256k1::verification_only();
@@ -54,9 +61,8 @@ pub fn encode_and_sign<T: Serialize>(message: &T, secret: &[u8; 32]) -> Result<V
     encode_message(&msg)
 }
 
-pub fn decode_signed<'de, T: de::Deserialize<'de>>(
-    encoded: &'de [u8],
-) -> Result<(T, Signature, PublicKey), rmp_serde::decode::Error> {
+/// Decodes the signed message envelope and verifies the signature of its payload.
+fn decode_signed_envelope(encoded: &[u8]) -> Result<(SignedMessageSerdeHelper, Signature), rmp_serde::decode::Error> {
     let helper: SignedMessageSerdeHelper = decode_message(encoded)?;
     let signature = Signature::from_compact(helper.signature)
         .map_err(|e| rmp_serde::decode::Error::Syntax(format!("Failed to parse signature {}", e)))?;
@@ -68,11 +74,121 @@ pub fn decode_signed<'de, T: de::Deserialize<'de>>(
             }
         },
     }
+    Ok((helper, signature))
+}
 
+pub fn decode_signed<'de, T: de::Deserialize<'de>>(
+    encoded: &'de [u8],
+) -> Result<(T, Signature, PublicKey), rmp_serde::decode::Error> {
+    let (helper, signature) = decode_signed_envelope(encoded)?;
     let payload: T = decode_message(helper.payload)?;
     Ok((payload, signature, helper.pubkey))
 }
 
+/// Whether the message is the well-formed signed envelope with the valid signature,
+/// even if its payload can't be decoded, e.g. the message of a newer protocol version.
+pub fn is_signed_envelope_valid(encoded: &[u8]) -> bool { decode_signed_envelope(encoded).is_ok() }
+
+const ENCRYPTION_NONCE_LEN: usize = 12;
+/// The length of the Poly1305 authentication tag appended to the ciphertext.
+const ENCRYPTION_TAG_LEN: usize = 16;
+/// The length of the compressed ephemeral pubkey.
+const EPHEMERAL_PUBKEY_LEN: usize = 33;
+
+/// The signed message encrypted to the recipient pubkey with the key derived by ECDH of an ephemeral key,
+/// so neither the sender nor the content is visible to the relays.
+#[derive(Deserialize, Serialize)]
+struct EncryptedMessageSerdeHelper<'a> {
+    #[serde(with = "serde_bytes")]
+    ephemeral_pubkey: &'a [u8],
+    #[serde(with = "serde_bytes")]
+    nonce: &'a [u8],
+    #[serde(with = "serde_bytes")]
+    ciphertext: &'a [u8],
+}
+
+/// The recipient is identified by the unprefixed pubkey, so the shared key is derived from the X coordinate only
+/// to not depend on the parity of the recipient pubkey.
+fn ecdh_key(point: &Secp256k1Pubkey, scalar: &SecretKey) -> [u8; 32] {
+    let shared = SharedSecret::new_with_hash(point, scalar, |x, _| x.into());
+    sha256(&shared[..])
+}
+
+/// Signs the message and encrypts it to the `recipient` unprefixed secp256k1 pubkey.
+pub fn encode_sign_and_encrypt<T: Serialize>(
+    message: &T,
+    secret: &[u8; 32],
+    recipient: &[u8; 32],
+) -> Result<Vec<u8>, rmp_serde::encode::Error> {
+    let mut prefixed = [2u8; 33];
+    prefixed[1..].copy_from_slice(recipient);
+    let recipient = Secp256k1Pubkey::from_slice(&prefixed)
+        .map_err(|e| rmp_serde::encode::Error::Syntax(format!("Invalid recipient pubkey {}", e)))?;
+
+    let ephemeral_secret = loop {
+        if let Ok(secret) = SecretKey::from_slice(&rand::random::<[u8; 32]>()) {
+            break secret;
+        }
+    };
+    let ephemeral_pubkey = Secp256k1Pubkey::from_secret_key(&*SECP_SIGN, &ephemeral_secret).serialize();
+    let key = ecdh_key(&recipient, &ephemeral_secret);
+    let nonce: [u8; ENCRYPTION_NONCE_LEN] = rand::random();
+
+    let signed = encode_and_sign(message, secret)?;
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt(Nonce::from_slice(&nonce), signed.as_slice())
+        .map_err(|e| rmp_serde::encode::Error::Syntax(format!("Encryption error {:?}", e)))?;
+    let msg = EncryptedMessageSerdeHelper {
+        ephemeral_pubkey: &ephemeral_pubkey,
+        nonce: &nonce,
+        ciphertext: &ciphertext,
+    };
+    encode_message(&msg)
+}
+
+/// Checks that the bytes are the well-formed message encrypted by [`encode_sign_and_encrypt`]:
+/// the envelope fields have the expected lengths and no bytes follow the envelope.
+/// Such message can be decrypted by the recipient only.
+pub fn is_encrypted(encoded: &[u8]) -> bool {
+    let helper = match decode_message::<EncryptedMessageSerdeHelper>(encoded) {
+        Ok(helper) => helper,
+        Err(_) => return false,
+    };
+    if helper.ephemeral_pubkey.len() != EPHEMERAL_PUBKEY_LEN
+        || helper.nonce.len() != ENCRYPTION_NONCE_LEN
+        || helper.ciphertext.len() <= ENCRYPTION_TAG_LEN
+        || Secp256k1Pubkey::from_slice(helper.ephemeral_pubkey).is_err()
+    {
+        return false;
+    }
+    // the decoder ignores the trailing bytes, so the envelope is encoded back to compare the lengths
+    match encode_message(&helper) {
+        Ok(reencoded) => reencoded.len() == encoded.len(),
+        Err(_) => false,
+    }
+}
+
+/// Decrypts the message encrypted to our pubkey by [`encode_sign_and_encrypt`] and checks its signature.
+pub fn decrypt_and_decode_signed<T: de::DeserializeOwned>(
+    encoded: &[u8],
+    secret: &[u8; 32],
+) -> Result<(T, Signature, PublicKey), rmp_serde::decode::Error> {
+    let helper: EncryptedMessageSerdeHelper = decode_message(encoded)?;
+    if helper.nonce.len() != ENCRYPTION_NONCE_LEN {
+        return Err(rmp_serde::decode::Error::Syntax("Invalid nonce length".into()));
+    }
+    let ephemeral_pubkey = Secp256k1Pubkey::from_slice(helper.ephemeral_pubkey)
+        .map_err(|e| rmp_serde::decode::Error::Syntax(format!("Error {} parsing ephemeral pubkey", e)))?;
+    let secret = SecretKey::from_slice(secret)
+        .map_err(|e| rmp_serde::decode::Error::Syntax(format!("Invalid secret key {}", e)))?;
+    let key = ecdh_key(&ephemeral_pubkey, &secret);
+    let signed = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt(Nonce::from_slice(helper.nonce), helper.ciphertext)
+        .map_err(|_| rmp_serde::decode::Error::Syntax("Failed to decrypt the message".into()))?;
+    let (message, signature, pubkey) = decode_signed::<T>(&signed)?;
+    Ok((message, signature, pubkey))
+}
+
 fn sha256(input: impl AsRef<[u8]>) -> [u8; 32] { Sha256::new().chain(input).finalize().into() }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -131,6 +247,12 @@ impl From<Secp256k1Pubkey> for PublicKey {
 pub type TopicPrefix = &'static str;
 pub const TOPIC_SEPARATOR: char = '/';
 
+/// Returns the peer id of the node using its secp256k1 key as the libp2p identity, see [`NodeKey::Secp256k1`].
+pub fn peer_id_from_secp256k1_pubkey(pubkey: &[u8]) -> Result<PeerId, String> {
+    let pubkey = libp2p::identity::secp256k1::PublicKey::decode(pubkey).map_err(|e| e.to_string())?;
+    Ok(PeerId::from(libp2p::identity::PublicKey::Secp256k1(pubkey)))
+}
+
 pub fn pub_sub_topic(prefix: TopicPrefix, topic: &str) -> String {
     let mut res = prefix.to_owned();
     res.push(TOPIC_SEPARATOR);
@@ -147,3 +269,65 @@ fn signed_message_serde() {
     let (decoded, ..) = decode_signed::<Vec<u8>>(&signed_encoded).unwrap();
     assert_eq!(decoded, initial_msg);
 }
+
+#[test]
+fn signed_envelope_validity() {
+    let secret = [1u8; 32];
+    let signed_encoded = encode_and_sign(&"newer message", &secret).unwrap();
+    // the payload can't be decoded, but the envelope is valid
+    assert!(decode_signed::<u64>(&signed_encoded).is_err());
+    assert!(is_signed_envelope_valid(&signed_encoded));
+
+    let mut tampered = signed_encoded.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(!is_signed_envelope_valid(&tampered));
+    assert!(!is_signed_envelope_valid(&[1, 2, 3]));
+}
+
+#[test]
+fn encrypted_message_serde() {
+    let sender_secret = [1u8; 32];
+    let initial_msg = vec![0u8; 32];
+    // check the recipients with the even and odd pubkeys
+    for recipient_secret in [[2u8; 32], [3u8; 32], [4u8; 32]].iter() {
+        let recipient_pubkey =
+            Secp256k1Pubkey::from_secret_key(&*SECP_SIGN, &SecretKey::from_slice(recipient_secret).unwrap());
+        let mut recipient = [0; 32];
+        recipient.copy_from_slice(&recipient_pubkey.serialize()[1..33]);
+
+        let encrypted = encode_sign_and_encrypt(&initial_msg, &sender_secret, &recipient).unwrap();
+        assert!(is_encrypted(&encrypted));
+        let signed_encoded = encode_and_sign(&initial_msg, &sender_secret).unwrap();
+        assert!(!is_encrypted(&signed_encoded));
+
+        let (decoded, _, pubkey) = decrypt_and_decode_signed::<Vec<u8>>(&encrypted, recipient_secret).unwrap();
+        assert_eq!(decoded, initial_msg);
+        let (_, _, expected_pubkey) = decode_signed::<Vec<u8>>(&signed_encoded).unwrap();
+        assert_eq!(pubkey, expected_pubkey);
+
+        decrypt_and_decode_signed::<Vec<u8>>(&encrypted, &[5u8; 32]).unwrap_err();
+
+        let mut with_trailing = encrypted.clone();
+        with_trailing.push(0);
+        assert!(!is_encrypted(&with_trailing));
+        let tag_only = EncryptedMessageSerdeHelper {
+            ephemeral_pubkey: &recipient_pubkey.serialize(),
+            nonce: &[0; ENCRYPTION_NONCE_LEN],
+            ciphertext: &[0; ENCRYPTION_TAG_LEN],
+        };
+        assert!(!is_encrypted(&encode_message(&tag_only).unwrap()));
+    }
+}
+
+#[test]
+fn test_peer_id_from_secp256k1_pubkey() {
+    let secret = [1u8; 32];
+    let mut libp2p_secret = secret;
+    let libp2p_secret = libp2p::identity::secp256k1::SecretKey::from_bytes(&mut libp2p_secret).unwrap();
+    let keypair = libp2p::identity::Keypair::Secp256k1(libp2p_secret.into());
+
+    let pubkey = Secp256k1Pubkey::from_secret_key(&*SECP_SIGN, &SecretKey::from_slice(&secret).unwrap());
+    let peer_id = peer_id_from_secp256k1_pubkey(&pubkey.serialize()).unwrap();
+    assert_eq!(peer_id, PeerId::from(keypair.public()));
+    assert!(peer_id_from_secp256k1_pubkey(&[1; 33]).is_err());
+}
@@ -0,0 +1,149 @@
+use crate::request_response::{Codec, ProtocolCompression};
+use libp2p::swarm::NetworkBehaviour;
+use libp2p::{multiaddr::Multiaddr,
+             request_response::{handler::RequestProtocol, ProtocolName, ProtocolSupport, RequestResponse,
+                                RequestResponseConfig, RequestResponseEvent, RequestResponseMessage},
+             swarm::{NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters},
+             NetworkBehaviour, PeerId};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::iter;
+use std::task::{Context, Poll};
+
+#[derive(Debug, Clone)]
+pub enum NetidCheckProtocol {
+    Version1,
+}
+
+impl ProtocolName for NetidCheckProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        match self {
+            NetidCheckProtocol::Version1 => b"/netid-check/1",
+        }
+    }
+}
+
+impl ProtocolCompression for NetidCheckProtocol {
+    fn is_compressed(&self) -> bool { false }
+}
+
+type NetidCheckCodec = Codec<NetidCheckProtocol, NetidCheckRequest, NetidCheckResponse>;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum NetidCheckRequest {
+    GetNetid,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum NetidCheckResponse {
+    Netid { netid: u16 },
+}
+
+#[derive(Debug)]
+pub enum NetidCheckEvent {
+    /// The peer runs our netid
+    Verified(PeerId),
+    /// The peer runs another netid or doesn't support the check
+    Rejected(PeerId),
+}
+
+/// Behaviour that requests the netid of the peers discovered in the local network,
+/// since mDNS discovers all the libp2p nodes of the network regardless of their netid.
+/// The peer is dialed by the netid request, so it's not connected until it's asked.
+#[derive(NetworkBehaviour)]
+#[behaviour(out_event = "NetidCheckEvent", poll_method = "poll")]
+pub struct NetidCheck {
+    request_response: RequestResponse<NetidCheckCodec>,
+    #[behaviour(ignore)]
+    netid: u16,
+    /// The peers that are asked for their netid
+    #[behaviour(ignore)]
+    pending: HashSet<PeerId>,
+    #[behaviour(ignore)]
+    events: VecDeque<NetidCheckEvent>,
+}
+
+impl NetidCheck {
+    pub fn new(netid: u16) -> Self {
+        let codec = Codec::default();
+        let protocol = iter::once((NetidCheckProtocol::Version1, ProtocolSupport::Full));
+        let config = RequestResponseConfig::default();
+        let request_response = RequestResponse::new(codec, protocol, config);
+        NetidCheck {
+            request_response,
+            netid,
+            pending: HashSet::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Dials the `peer` at the `address` and asks its netid unless it's asked already.
+    pub fn check_peer(&mut self, peer: PeerId, address: Multiaddr) {
+        self.request_response.add_address(&peer, address);
+        if self.pending.insert(peer) {
+            self.request_response.send_request(&peer, NetidCheckRequest::GetNetid);
+        }
+    }
+
+    fn complete_check(&mut self, peer: PeerId, verified: bool) {
+        if !self.pending.remove(&peer) {
+            return;
+        }
+        // the addresses are known by the other behaviours once the peer is connected
+        for address in self.request_response.addresses_of_peer(&peer) {
+            self.request_response.remove_address(&peer, &address);
+        }
+        let event = if verified {
+            NetidCheckEvent::Verified(peer)
+        } else {
+            NetidCheckEvent::Rejected(peer)
+        };
+        self.events.push_back(event);
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context,
+        _params: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<RequestProtocol<NetidCheckCodec>, NetidCheckEvent>> {
+        if let Some(event) = self.events.pop_front() {
+            return Poll::Ready(NetworkBehaviourAction::GenerateEvent(event));
+        }
+        Poll::Pending
+    }
+}
+
+impl NetworkBehaviourEventProcess<RequestResponseEvent<NetidCheckRequest, NetidCheckResponse>> for NetidCheck {
+    fn inject_event(&mut self, event: RequestResponseEvent<NetidCheckRequest, NetidCheckResponse>) {
+        match event {
+            RequestResponseEvent::Message { message, peer } => match message {
+                RequestResponseMessage::Request { request, channel, .. } => match request {
+                    NetidCheckRequest::GetNetid => {
+                        let response = NetidCheckResponse::Netid { netid: self.netid };
+                        if let Err(_response) = self.request_response.send_response(channel, response) {
+                            warn!("Response channel has been closed already");
+                        }
+                    },
+                },
+                RequestResponseMessage::Response { response, .. } => match response {
+                    NetidCheckResponse::Netid { netid } => {
+                        debug!("Peer {} runs netid {}", peer, netid);
+                        self.complete_check(peer, netid == self.netid);
+                    },
+                },
+            },
+            RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+                debug!("Error {:?} while requesting the netid of peer {}", error, peer);
+                self.complete_check(peer, false);
+            },
+            RequestResponseEvent::InboundFailure { peer, error, .. } => {
+                debug!(
+                    "Inbound failure {:?} while processing the netid request of peer {}",
+                    error, peer
+                );
+            },
+            RequestResponseEvent::ResponseSent { .. } => (),
+        }
+    }
+}
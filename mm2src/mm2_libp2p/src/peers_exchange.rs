@@ -1,4 +1,4 @@
-use crate::request_response::Codec;
+use crate::request_response::{Codec, ProtocolCompression};
 use futures::StreamExt;
 use libp2p::swarm::NetworkBehaviour;
 use libp2p::{multiaddr::{Multiaddr, Protocol},
@@ -14,10 +14,14 @@ use std::{collections::{HashMap, VecDeque},
           iter,
           task::{Context, Poll},
           time::Duration};
-use wasm_timer::{Instant, Interval};
+use wasm_timer::{Instant, Interval, SystemTime, UNIX_EPOCH};
 
 pub type PeerAddresses = HashSet<Multiaddr>;
 
+/// The known peers with their connection statistics, is expected to be persisted by the caller
+/// and passed back to bootstrap from on the next start.
+pub type PeerStore = HashMap<PeerIdSerde, PeerStats>;
+
 #[derive(Debug, Clone)]
 pub enum PeersExchangeProtocol {
     Version1,
@@ -31,12 +35,20 @@ impl ProtocolName for PeersExchangeProtocol {
     }
 }
 
+impl ProtocolCompression for PeersExchangeProtocol {
+    fn is_compressed(&self) -> bool { false }
+}
+
 type PeersExchangeCodec = Codec<PeersExchangeProtocol, PeersExchangeRequest, PeersExchangeResponse>;
 
 const DEFAULT_PEERS_NUM: usize = 20;
 const REQUEST_PEERS_INITIAL_DELAY: u64 = 20;
 const REQUEST_PEERS_INTERVAL: u64 = 300;
 const MAX_PEERS: usize = 100;
+/// The stored peer is forgotten after this number of the consecutive dial failures.
+const MAX_STORED_PEER_FAILURES: u32 = 10;
+/// The stored peer that has not been seen for this number of seconds is not loaded.
+const STORED_PEER_MAX_AGE: u64 = 14 * 24 * 3600;
 
 #[derive(Debug, Clone, Eq, Hash, PartialEq)]
 pub struct PeerIdSerde(PeerId);
@@ -59,6 +71,24 @@ impl<'de> Deserialize<'de> for PeerIdSerde {
     }
 }
 
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct PeerStats {
+    pub addresses: PeerAddresses,
+    /// The UNIX timestamp in seconds of the last established outbound connection
+    pub last_seen: u64,
+    /// The number of the established outbound connections
+    pub successes: u32,
+    /// The number of the dial failures since the last established connection
+    pub failures: u32,
+}
+
+fn now_sec() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum PeersExchangeRequest {
     GetKnownPeers { num: usize },
@@ -79,6 +109,8 @@ pub struct PeersExchange {
     #[behaviour(ignore)]
     reserved_peers: Vec<PeerId>,
     #[behaviour(ignore)]
+    peer_stats: HashMap<PeerId, PeerStats>,
+    #[behaviour(ignore)]
     events: VecDeque<NetworkBehaviourAction<RequestProtocol<PeersExchangeCodec>, ()>>,
     #[behaviour(ignore)]
     maintain_peers_interval: Interval,
@@ -97,6 +129,7 @@ impl PeersExchange {
             request_response,
             known_peers: Vec::new(),
             reserved_peers: Vec::new(),
+            peer_stats: HashMap::new(),
             events: VecDeque::new(),
             maintain_peers_interval: Interval::new_at(
                 Instant::now() + Duration::from_secs(REQUEST_PEERS_INITIAL_DELAY),
@@ -126,6 +159,7 @@ impl PeersExchange {
 
     fn forget_peer(&mut self, peer: &PeerId) {
         self.known_peers.retain(|known_peer| known_peer != peer);
+        self.peer_stats.remove(peer);
         self.forget_peer_addresses(peer);
     }
 
@@ -154,6 +188,31 @@ impl PeersExchange {
         }
     }
 
+    /// Adds the circuit addresses of the peer listening through the relays.
+    /// Such peer is not a relay so it's not added to the known peers.
+    pub fn add_relayed_peer_addresses(&mut self, peer: &PeerId, addresses: PeerAddresses) {
+        let relayed_addresses: Vec<_> = addresses
+            .into_iter()
+            .filter_map(|address| {
+                let relay_address: Multiaddr = address
+                    .iter()
+                    .take_while(|protocol| *protocol != Protocol::P2pCircuit)
+                    .collect();
+                if relay_address != address && self.validate_global_multiaddr(&relay_address) {
+                    Some(address)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let already_known = self.request_response.addresses_of_peer(peer);
+        for address in relayed_addresses {
+            if !already_known.contains(&address) {
+                self.request_response.add_address(peer, address);
+            }
+        }
+    }
+
     pub fn add_peer_addresses_to_reserved_peers(&mut self, peer: &PeerId, addresses: PeerAddresses) {
         for address in addresses.iter() {
             if !self.validate_global_multiaddr(address) {
@@ -173,13 +232,82 @@ impl PeersExchange {
         }
     }
 
+    /// Adds the peers stored on the previous run to the known peers skipping the outdated ones.
+    pub fn load_peer_store(&mut self, store: PeerStore) {
+        let now = now_sec();
+        for (peer, stats) in store {
+            if stats.last_seen + STORED_PEER_MAX_AGE < now {
+                continue;
+            }
+            self.add_peer_addresses_to_known_peers(&peer.0, stats.addresses.clone());
+            if self.is_known_peer(&peer.0) {
+                self.peer_stats.insert(peer.0, stats);
+            }
+        }
+    }
+
+    pub fn peer_store(&self) -> PeerStore {
+        self.peer_stats
+            .iter()
+            .map(|(peer, stats)| ((*peer).into(), stats.clone()))
+            .collect()
+    }
+
+    /// Records the established outbound connection to the peer.
+    pub fn on_connection_established(&mut self, peer: &PeerId, address: Multiaddr) {
+        if !self.validate_global_multiaddr(&address) {
+            return;
+        }
+        self.add_peer_addresses_to_known_peers(peer, iter::once(address.clone()).collect());
+        let stats = self.peer_stats.entry(*peer).or_insert_with(PeerStats::default);
+        stats.addresses.insert(address);
+        stats.last_seen = now_sec();
+        stats.successes += 1;
+        stats.failures = 0;
+    }
+
+    /// Records the failed dial of the stored peer and forgets the peer failed too many times in a row.
+    pub fn on_dial_failure(&mut self, peer: &PeerId) {
+        let failures = match self.peer_stats.get_mut(peer) {
+            Some(stats) => {
+                stats.failures += 1;
+                stats.failures
+            },
+            None => return,
+        };
+        if failures >= MAX_STORED_PEER_FAILURES && !self.is_reserved_peer(peer) {
+            info!("Forgetting peer {} after {} dial failures", peer, failures);
+            self.forget_peer(peer);
+        }
+    }
+
+    /// Returns the stored peers with the best connection statistics to bootstrap from.
+    pub fn best_stored_peers(&self, num: usize) -> HashMap<PeerId, PeerAddresses> {
+        let mut stored: Vec<_> = self.peer_stats.iter().collect();
+        stored.sort_by(|(_, a), (_, b)| {
+            a.failures
+                .cmp(&b.failures)
+                .then(b.successes.cmp(&a.successes))
+                .then(b.last_seen.cmp(&a.last_seen))
+        });
+        stored
+            .into_iter()
+            .take(num)
+            .map(|(peer, stats)| (*peer, stats.addresses.clone()))
+            .collect()
+    }
+
     fn maintain_known_peers(&mut self) {
         if self.known_peers.len() > MAX_PEERS {
             let mut rng = rand::thread_rng();
             let to_remove_num = self.known_peers.len() - MAX_PEERS;
             self.known_peers.shuffle(&mut rng);
+            // prefer to keep the peers we have successfully connected to
+            let peer_stats = &self.peer_stats;
+            self.known_peers.sort_by_key(|peer| peer_stats.contains_key(peer));
             let removed_peers: Vec<_> = self.known_peers.drain(..to_remove_num).collect();
             for peer in removed_peers {
+                self.peer_stats.remove(&peer);
                 self.forget_peer_addresses(&peer);
             }
         }
@@ -210,6 +338,8 @@ impl PeersExchange {
         result
     }
 
+    pub fn netid_port(&self) -> u16 { self.netid_port }
+
     pub fn is_known_peer(&self, peer: &PeerId) -> bool { self.known_peers.contains(peer) }
 
     pub fn is_reserved_peer(&self, peer: &PeerId) -> bool { self.reserved_peers.contains(peer) }
@@ -240,7 +370,8 @@ impl PeersExchange {
             _ => return false,
         }
 
-        true
+        // the circuit addresses are validated by the relay part
+        !components.any(|protocol| protocol == Protocol::P2pCircuit)
     }
 
     fn validate_get_known_peers_response(&self, response: &HashMap<PeerIdSerde, PeerAddresses>) -> bool {
@@ -339,7 +470,8 @@ impl NetworkBehaviourEventProcess<RequestResponseEvent<PeersExchangeRequest, Pee
 
 #[cfg(test)]
 mod tests {
-    use crate::peers_exchange::{PeerIdSerde, PeersExchange};
+    use crate::peers_exchange::{now_sec, PeerIdSerde, PeerStats, PeerStore, PeersExchange, MAX_STORED_PEER_FAILURES,
+                                STORED_PEER_MAX_AGE};
     use crate::PeerId;
     use libp2p::core::Multiaddr;
     use std::collections::{HashMap, HashSet};
@@ -417,4 +549,50 @@ mod tests {
         assert_eq!(addresses.len(), 1);
         assert!(addresses.contains(&address));
     }
+
+    #[test]
+    fn test_peer_store() {
+        let mut behaviour = PeersExchange::new(3000);
+        let good_peer = PeerId::random();
+        let bad_peer = PeerId::random();
+        let address: Multiaddr = "/ip4/168.119.236.241/tcp/3000".parse().unwrap();
+        behaviour.on_connection_established(&good_peer, address.clone());
+        behaviour.on_connection_established(&good_peer, address.clone());
+        behaviour.on_connection_established(&bad_peer, "/ip4/168.119.236.242/tcp/3000".parse().unwrap());
+        // the local addresses are not stored
+        behaviour.on_connection_established(&PeerId::random(), "/ip4/127.0.0.1/tcp/3000".parse().unwrap());
+        behaviour.on_dial_failure(&bad_peer);
+
+        let best = behaviour.best_stored_peers(1);
+        assert_eq!(best.len(), 1);
+        assert!(best[&good_peer].contains(&address));
+
+        let store = behaviour.peer_store();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store[&PeerIdSerde(good_peer)].successes, 2);
+        assert_eq!(store[&PeerIdSerde(bad_peer)].failures, 1);
+
+        for _ in 1..MAX_STORED_PEER_FAILURES {
+            behaviour.on_dial_failure(&bad_peer);
+        }
+        assert!(!behaviour.is_known_peer(&bad_peer));
+        assert_eq!(behaviour.peer_store().len(), 1);
+
+        let mut store = store;
+        let outdated_peer = PeerId::random();
+        store.insert(PeerIdSerde(outdated_peer), PeerStats {
+            addresses: HashSet::from_iter(vec!["/ip4/168.119.236.243/tcp/3000".parse().unwrap()]),
+            last_seen: now_sec() - STORED_PEER_MAX_AGE - 1,
+            successes: 1,
+            failures: 0,
+        });
+        let serialized = rmp_serde::to_vec(&store).unwrap();
+        let deserialized: PeerStore = rmp_serde::from_read_ref(&serialized).unwrap();
+
+        let mut behaviour = PeersExchange::new(3000);
+        behaviour.load_peer_store(deserialized);
+        assert!(behaviour.is_known_peer(&good_peer));
+        assert!(behaviour.is_known_peer(&bad_peer));
+        assert!(!behaviour.is_known_peer(&outdated_peer));
+    }
 }
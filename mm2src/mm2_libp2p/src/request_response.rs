@@ -1,14 +1,14 @@
 use crate::{decode_message, encode_message};
 use async_trait::async_trait;
-use core::iter;
 use futures::channel::{mpsc, oneshot};
 use futures::io::{AsyncRead, AsyncWrite};
 use futures::task::{Context, Poll};
 use futures::StreamExt;
 use libp2p::core::upgrade::{read_length_prefixed, write_length_prefixed};
 use libp2p::request_response::handler::RequestProtocol;
-use libp2p::request_response::{ProtocolName, ProtocolSupport, RequestId, RequestResponse, RequestResponseCodec,
-                               RequestResponseConfig, RequestResponseEvent, RequestResponseMessage, ResponseChannel};
+use libp2p::request_response::{OutboundFailure, ProtocolName, ProtocolSupport, RequestId, RequestResponse,
+                               RequestResponseCodec, RequestResponseConfig, RequestResponseEvent,
+                               RequestResponseMessage, ResponseChannel};
 use libp2p::swarm::{NetworkBehaviourAction, NetworkBehaviourEventProcess, PollParameters};
 use libp2p::NetworkBehaviour;
 use libp2p::PeerId;
@@ -21,15 +21,32 @@ use std::time::Duration;
 use wasm_timer::{Instant, Interval};
 
 const MAX_BUFFER_SIZE: usize = 1024 * 1024 - 100;
+/// The max size of the decompressed message received over the compressed protocol
+#[cfg(not(target_arch = "wasm32"))]
+const MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+#[cfg(not(target_arch = "wasm32"))]
+const COMPRESSION_LEVEL: i32 = 3;
 
 pub type RequestResponseReceiver = mpsc::UnboundedReceiver<(PeerId, PeerRequest, oneshot::Sender<PeerResponse>)>;
 pub type RequestResponseSender = mpsc::UnboundedSender<(PeerId, PeerRequest, oneshot::Sender<PeerResponse>)>;
 type ReqResCodec = Codec<Protocol, PeerRequest, PeerResponse>;
 
+/// The compressed protocol is preferred, the peers not supporting it fall back to the `Version1`.
+#[cfg(not(target_arch = "wasm32"))]
+fn supported_protocols() -> Vec<(Protocol, ProtocolSupport)> {
+    vec![
+        (Protocol::Version2, ProtocolSupport::Full),
+        (Protocol::Version1, ProtocolSupport::Full),
+    ]
+}
+
+#[cfg(target_arch = "wasm32")]
+fn supported_protocols() -> Vec<(Protocol, ProtocolSupport)> { vec![(Protocol::Version1, ProtocolSupport::Full)] }
+
 /// Build a request-response network behaviour.
 pub fn build_request_response_behaviour() -> RequestResponseBehaviour {
     let config = RequestResponseConfig::default();
-    let protocol = iter::once((Protocol::Version1, ProtocolSupport::Full));
+    let protocol = supported_protocols();
     let inner = RequestResponse::new(Codec::default(), protocol, config);
 
     let (tx, rx) = mpsc::unbounded();
@@ -45,6 +62,7 @@ pub fn build_request_response_behaviour() -> RequestResponseBehaviour {
         events,
         timeout,
         timeout_interval,
+        stats: RequestResponseStats::default(),
         inner,
     }
 }
@@ -57,6 +75,18 @@ pub enum RequestResponseBehaviourEvent {
     },
 }
 
+/// The outcomes of the requests sent since the node start.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct RequestResponseStats {
+    pub sent: u64,
+    pub succeeded: u64,
+    /// The requests failed to be sent or responded with an error
+    pub failed: u64,
+    pub timed_out: u64,
+    /// The inbound requests we failed to receive or respond to
+    pub inbound_failures: u64,
+}
+
 struct PendingRequest {
     tx: oneshot::Sender<PeerResponse>,
     initiated_at: Instant,
@@ -81,6 +111,8 @@ pub struct RequestResponseBehaviour {
     /// Interval for request timeout check
     #[behaviour(ignore)]
     timeout_interval: Interval,
+    #[behaviour(ignore)]
+    stats: RequestResponseStats,
     /// The inner RequestResponse network behaviour.
     inner: RequestResponse<Codec<Protocol, PeerRequest, PeerResponse>>,
 }
@@ -88,6 +120,8 @@ pub struct RequestResponseBehaviour {
 impl RequestResponseBehaviour {
     pub fn sender(&self) -> RequestResponseSender { self.tx.clone() }
 
+    pub fn stats(&self) -> RequestResponseStats { self.stats }
+
     pub fn send_response(&mut self, ch: ResponseChannel<PeerResponse>, rs: PeerResponse) -> Result<(), PeerResponse> {
         self.inner.send_response(ch, rs)
     }
@@ -104,6 +138,7 @@ impl RequestResponseBehaviour {
             initiated_at: Instant::now(),
         };
         assert!(self.pending_requests.insert(request_id, pending_request).is_none());
+        self.stats.sent += 1;
         request_id
     }
 
@@ -131,10 +166,12 @@ impl RequestResponseBehaviour {
         while let Poll::Ready(Some(())) = self.timeout_interval.poll_next_unpin(cx) {
             let now = Instant::now();
             let timeout = self.timeout;
+            let stats = &mut self.stats;
             self.pending_requests.retain(|request_id, pending_request| {
                 let retain = now.duration_since(pending_request.initiated_at) < timeout;
                 if !retain {
                     warn!("Request {} timed out", request_id);
+                    stats.timed_out += 1;
                 }
                 retain
             });
@@ -174,6 +211,7 @@ impl NetworkBehaviourEventProcess<RequestResponseEvent<PeerRequest, PeerResponse
             RequestResponseEvent::Message { peer, message } => (peer, message),
             RequestResponseEvent::InboundFailure { error, .. } => {
                 error!("Error on receive a request: {:?}", error);
+                self.stats.inbound_failures += 1;
                 return;
             },
             RequestResponseEvent::OutboundFailure {
@@ -182,6 +220,12 @@ impl NetworkBehaviourEventProcess<RequestResponseEvent<PeerRequest, PeerResponse
                 error,
             } => {
                 error!("Error on send request {:?} to peer {:?}: {:?}", request_id, peer, error);
+                if self.pending_requests.contains_key(&request_id) {
+                    match error {
+                        OutboundFailure::Timeout => self.stats.timed_out += 1,
+                        _ => self.stats.failed += 1,
+                    }
+                }
                 let err_response = PeerResponse::Err {
                     err: format!("{:?}", error),
                 };
@@ -201,6 +245,13 @@ impl NetworkBehaviourEventProcess<RequestResponseEvent<PeerRequest, PeerResponse
                     "Received a response to the {:?} request from peer {:?}",
                     request_id, peer_id
                 );
+                // the responses to the requests already timed out are not counted
+                if self.pending_requests.contains_key(&request_id) {
+                    match response {
+                        PeerResponse::Err { .. } => self.stats.failed += 1,
+                        _ => self.stats.succeeded += 1,
+                    }
+                }
                 self.process_response(request_id, response)
             },
         }
@@ -223,6 +274,22 @@ impl<Proto, Req, Res> Default for Codec<Proto, Req, Res> {
 #[derive(Debug, Clone)]
 pub enum Protocol {
     Version1,
+    /// The messages are compressed with zstd
+    Version2,
+}
+
+/// Whether the messages of the negotiated protocol are compressed
+pub trait ProtocolCompression {
+    fn is_compressed(&self) -> bool;
+}
+
+impl ProtocolCompression for Protocol {
+    fn is_compressed(&self) -> bool {
+        match self {
+            Protocol::Version1 => false,
+            Protocol::Version2 => true,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -250,13 +317,14 @@ impl ProtocolName for Protocol {
     fn protocol_name(&self) -> &[u8] {
         match self {
             Protocol::Version1 => b"/request-response/1",
+            Protocol::Version2 => b"/request-response/2",
         }
     }
 }
 
 #[async_trait]
 impl<
-        Proto: Clone + ProtocolName + Send + Sync,
+        Proto: Clone + ProtocolName + ProtocolCompression + Send + Sync,
         Req: DeserializeOwned + Serialize + Send + Sync,
         Res: DeserializeOwned + Serialize + Send + Sync,
     > RequestResponseCodec for Codec<Proto, Req, Res>
@@ -265,52 +333,71 @@ impl<
     type Request = Req;
     type Response = Res;
 
-    async fn read_request<T>(&mut self, _protocol: &Self::Protocol, io: &mut T) -> io::Result<Self::Request>
+    async fn read_request<T>(&mut self, protocol: &Self::Protocol, io: &mut T) -> io::Result<Self::Request>
     where
         T: AsyncRead + Unpin + Send,
     {
-        read_to_end(io).await
+        read_to_end(io, protocol.is_compressed()).await
     }
 
-    async fn read_response<T>(&mut self, _protocol: &Self::Protocol, io: &mut T) -> io::Result<Self::Response>
+    async fn read_response<T>(&mut self, protocol: &Self::Protocol, io: &mut T) -> io::Result<Self::Response>
     where
         T: AsyncRead + Unpin + Send,
     {
-        read_to_end(io).await
+        read_to_end(io, protocol.is_compressed()).await
     }
 
-    async fn write_request<T>(&mut self, _protocol: &Self::Protocol, io: &mut T, req: Self::Request) -> io::Result<()>
+    async fn write_request<T>(&mut self, protocol: &Self::Protocol, io: &mut T, req: Self::Request) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        write_all(io, &req).await
+        write_all(io, &req, protocol.is_compressed()).await
     }
 
-    async fn write_response<T>(&mut self, _protocol: &Self::Protocol, io: &mut T, res: Self::Response) -> io::Result<()>
+    async fn write_response<T>(&mut self, protocol: &Self::Protocol, io: &mut T, res: Self::Response) -> io::Result<()>
     where
         T: AsyncWrite + Unpin + Send,
     {
-        write_all(io, &res).await
+        write_all(io, &res, protocol.is_compressed()).await
     }
 }
 
-async fn read_to_end<T, M>(io: &mut T) -> io::Result<M>
+#[cfg(not(target_arch = "wasm32"))]
+fn compress(data: &[u8]) -> io::Result<Vec<u8>> { zstd::block::compress(data, COMPRESSION_LEVEL) }
+
+#[cfg(not(target_arch = "wasm32"))]
+fn decompress(data: &[u8]) -> io::Result<Vec<u8>> { zstd::block::decompress(data, MAX_DECOMPRESSED_SIZE) }
+
+#[cfg(target_arch = "wasm32")]
+fn compress(_data: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Other, "Compression is not supported"))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn decompress(_data: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Other, "Compression is not supported"))
+}
+
+async fn read_to_end<T, M>(io: &mut T, compressed: bool) -> io::Result<M>
 where
     T: AsyncRead + Unpin + Send,
     M: DeserializeOwned,
 {
-    match read_length_prefixed(io, MAX_BUFFER_SIZE).await {
-        Ok(data) => Ok(try_io!(decode_message(&data))),
-        Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
-    }
+    let data = match read_length_prefixed(io, MAX_BUFFER_SIZE).await {
+        Ok(data) => data,
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    };
+    let data = if compressed { decompress(&data)? } else { data };
+    Ok(try_io!(decode_message(&data)))
 }
 
-async fn write_all<T, M>(io: &mut T, msg: &M) -> io::Result<()>
+async fn write_all<T, M>(io: &mut T, msg: &M, compressed: bool) -> io::Result<()>
 where
     T: AsyncWrite + Unpin + Send,
     M: Serialize,
 {
     let data = try_io!(encode_message(msg));
+    let data = if compressed { compress(&data)? } else { data };
     if data.len() > MAX_BUFFER_SIZE {
         return Err(io::Error::new(
             io::ErrorKind::InvalidData,
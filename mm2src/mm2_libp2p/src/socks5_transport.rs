@@ -0,0 +1,106 @@
+//! The TCP transport dialing the peers through the SOCKS5 proxy, e.g. Tor.
+//! The DNS multiaddrs are resolved by the proxy, so the DNS requests don't leak.
+
+use libp2p::core::{transport::{Boxed, TransportError},
+                   Multiaddr, Transport};
+use libp2p::multiaddr::Protocol;
+use libp2p::tcp::tokio::TcpStream;
+use log::warn;
+use socks5::{socks5_connect, Socks5ProxyConfig};
+use std::io;
+
+type InnerTransport = Boxed<TcpStream>;
+
+/// Dials the TCP multiaddrs through the proxy, the rest of the calls are delegated to the `inner` transport.
+/// The `inner` transport is also dialed if the proxy fails unless the `refuse_clearnet_fallback` is set.
+#[derive(Clone)]
+pub struct Socks5TcpTransport {
+    proxy: Socks5ProxyConfig,
+    inner: InnerTransport,
+}
+
+impl Socks5TcpTransport {
+    pub fn new(proxy: Socks5ProxyConfig, inner: InnerTransport) -> Self { Socks5TcpTransport { proxy, inner } }
+}
+
+/// Extracts the host and the port from the `/ip4|ip6|dns|dns4|dns6/<host>/tcp/<port>[/p2p/<peer>]` multiaddr.
+fn multiaddr_host_port(addr: &Multiaddr) -> Option<(String, u16)> {
+    let mut iter = addr.iter();
+    let host = match iter.next()? {
+        Protocol::Ip4(ip) => ip.to_string(),
+        Protocol::Ip6(ip) => ip.to_string(),
+        Protocol::Dns(host) | Protocol::Dns4(host) | Protocol::Dns6(host) => host.into_owned(),
+        _ => return None,
+    };
+    let port = match iter.next()? {
+        Protocol::Tcp(port) => port,
+        _ => return None,
+    };
+    match iter.next() {
+        None | Some(Protocol::P2p(_)) => Some((host, port)),
+        _ => None,
+    }
+}
+
+pub(crate) fn transport_error_to_io(e: TransportError<io::Error>) -> io::Error {
+    match e {
+        TransportError::MultiaddrNotSupported(addr) => {
+            io::Error::new(io::ErrorKind::Other, format!("Multiaddr {} is not supported", addr))
+        },
+        TransportError::Other(e) => e,
+    }
+}
+
+impl Transport for Socks5TcpTransport {
+    type Output = TcpStream;
+    type Error = io::Error;
+    type Listener = <InnerTransport as Transport>::Listener;
+    type ListenerUpgrade = <InnerTransport as Transport>::ListenerUpgrade;
+    type Dial = <InnerTransport as Transport>::Dial;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<Self::Error>> {
+        self.inner.listen_on(addr)
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let (host, port) = match multiaddr_host_port(&addr) {
+            Some(host_port) => host_port,
+            None if self.proxy.refuse_clearnet_fallback => return Err(TransportError::MultiaddrNotSupported(addr)),
+            None => return self.inner.dial(addr),
+        };
+        let fut = async move {
+            match socks5_connect(&self.proxy, &host, port).await {
+                Ok(stream) => Ok(TcpStream(stream)),
+                Err(e) if self.proxy.refuse_clearnet_fallback => Err(e),
+                Err(e) => {
+                    warn!("Error {} dialing {} through the proxy, dialing directly", e, addr);
+                    self.inner.dial(addr).map_err(transport_error_to_io)?.await
+                },
+            }
+        };
+        Ok(Box::pin(fut))
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multiaddr_host_port() {
+        let addr: Multiaddr = "/dns4/example.com/tcp/38890".parse().unwrap();
+        assert_eq!(multiaddr_host_port(&addr), Some(("example.com".to_owned(), 38890)));
+
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/38890/p2p/12D3KooWEsuiKcQaBaKEzuMtT6uFjs89P1E8MK3wGRZbeuCbCw6P"
+            .parse()
+            .unwrap();
+        assert_eq!(multiaddr_host_port(&addr), Some(("127.0.0.1".to_owned(), 38890)));
+
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/38900/ws".parse().unwrap();
+        assert_eq!(multiaddr_host_port(&addr), None);
+    }
+}
@@ -8419,6 +8419,7 @@ mod wasm_bindgen_tests {
             eth_taker,
             jst_taker,
             PAYMENT_LOCKTIME,
+            false,
         );
 
         let maker_swap = MakerSwap::new(
@@ -8433,6 +8434,7 @@ mod wasm_bindgen_tests {
             eth_maker,
             jst_maker,
             PAYMENT_LOCKTIME,
+            false,
         );
 
         let taker_swap_fut = run_taker_swap(RunTakerSwapInput::StartNew(taker_swap), ctx_taker);
@@ -34,6 +34,9 @@ fn test_match_maker_order_and_taker_request() {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     };
 
     let request = TakerRequest {
@@ -49,6 +52,7 @@ fn test_match_maker_order_and_taker_request() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let actual = maker.match_with_request(&request);
@@ -71,6 +75,9 @@ fn test_match_maker_order_and_taker_request() {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     };
 
     let request = TakerRequest {
@@ -86,6 +93,7 @@ fn test_match_maker_order_and_taker_request() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let actual = maker.match_with_request(&request);
@@ -108,6 +116,9 @@ fn test_match_maker_order_and_taker_request() {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     };
 
     let request = TakerRequest {
@@ -123,6 +134,7 @@ fn test_match_maker_order_and_taker_request() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let actual = maker.match_with_request(&request);
@@ -145,6 +157,9 @@ fn test_match_maker_order_and_taker_request() {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     };
 
     let request = TakerRequest {
@@ -160,6 +175,7 @@ fn test_match_maker_order_and_taker_request() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let actual = maker.match_with_request(&request);
@@ -182,6 +198,9 @@ fn test_match_maker_order_and_taker_request() {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     };
 
     let request = TakerRequest {
@@ -197,6 +216,7 @@ fn test_match_maker_order_and_taker_request() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let actual = maker.match_with_request(&request);
@@ -219,6 +239,9 @@ fn test_match_maker_order_and_taker_request() {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     };
 
     let request = TakerRequest {
@@ -234,6 +257,7 @@ fn test_match_maker_order_and_taker_request() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let actual = maker.match_with_request(&request);
@@ -258,6 +282,9 @@ fn test_match_maker_order_and_taker_request() {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     };
     let request = TakerRequest {
         base: "KMD".to_owned(),
@@ -272,6 +299,7 @@ fn test_match_maker_order_and_taker_request() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
     let actual = maker.match_with_request(&request);
     assert_eq!(actual, OrderMatchResult::NotMatched);
@@ -297,6 +325,9 @@ fn test_match_maker_order_and_taker_request() {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     };
     let request = TakerRequest {
         base: "REL".to_owned(),
@@ -311,6 +342,7 @@ fn test_match_maker_order_and_taker_request() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
     let actual = maker.match_with_request(&request);
     let expected_base_amount = MmNumber::from(3);
@@ -367,6 +399,9 @@ fn test_maker_order_available_amount() {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     };
     maker.matches.insert(Uuid::new_v4(), MakerMatch {
         request: TakerRequest {
@@ -382,6 +417,7 @@ fn test_maker_order_available_amount() {
             conf_settings: None,
             base_protocol_info: None,
             rel_protocol_info: None,
+            swap_msg_encryption: None,
         },
         reserved: MakerReserved {
             base: "BASE".into(),
@@ -395,6 +431,7 @@ fn test_maker_order_available_amount() {
             conf_settings: None,
             base_protocol_info: None,
             rel_protocol_info: None,
+            swap_msg_encryption: None,
         },
         connect: None,
         connected: None,
@@ -414,6 +451,7 @@ fn test_maker_order_available_amount() {
             conf_settings: None,
             base_protocol_info: None,
             rel_protocol_info: None,
+            swap_msg_encryption: None,
         },
         reserved: MakerReserved {
             base: "BASE".into(),
@@ -427,6 +465,7 @@ fn test_maker_order_available_amount() {
             conf_settings: None,
             base_protocol_info: None,
             rel_protocol_info: None,
+            swap_msg_encryption: None,
         },
         connect: None,
         connected: None,
@@ -455,6 +494,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let order = TakerOrder {
@@ -481,6 +521,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     assert_eq!(MatchReservedResult::Matched, order.match_reserved(&reserved));
@@ -498,6 +539,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let order = TakerOrder {
@@ -524,6 +566,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     assert_eq!(MatchReservedResult::Matched, order.match_reserved(&reserved));
@@ -541,6 +584,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let order = TakerOrder {
@@ -567,6 +611,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     assert_eq!(MatchReservedResult::Matched, order.match_reserved(&reserved));
@@ -584,6 +629,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let order = TakerOrder {
@@ -610,6 +656,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     assert_eq!(MatchReservedResult::NotMatched, order.match_reserved(&reserved));
@@ -627,6 +674,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let order = TakerOrder {
@@ -653,6 +701,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     assert_eq!(MatchReservedResult::Matched, order.match_reserved(&reserved));
@@ -670,6 +719,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let order = TakerOrder {
@@ -696,6 +746,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     assert_eq!(MatchReservedResult::Matched, order.match_reserved(&reserved));
@@ -713,6 +764,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let order = TakerOrder {
@@ -739,6 +791,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     assert_eq!(MatchReservedResult::Matched, order.match_reserved(&reserved));
@@ -756,6 +809,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let order = TakerOrder {
@@ -782,6 +836,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     assert_eq!(MatchReservedResult::NotMatched, order.match_reserved(&reserved));
@@ -803,6 +858,7 @@ fn test_taker_match_reserved() {
             conf_settings: None,
             base_protocol_info: None,
             rel_protocol_info: None,
+            swap_msg_encryption: None,
         },
         matches: HashMap::new(),
         order_type: OrderType::GoodTillCancelled,
@@ -825,6 +881,7 @@ fn test_taker_match_reserved() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     assert_eq!(MatchReservedResult::Matched, order.match_reserved(&reserved));
@@ -845,6 +902,7 @@ fn test_taker_order_cancellable() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let order = TakerOrder {
@@ -874,6 +932,7 @@ fn test_taker_order_cancellable() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let mut order = TakerOrder {
@@ -902,6 +961,7 @@ fn test_taker_order_cancellable() {
             conf_settings: None,
             base_protocol_info: None,
             rel_protocol_info: None,
+            swap_msg_encryption: None,
         },
         connect: TakerConnect {
             sender_pubkey: H256Json::default(),
@@ -940,6 +1000,9 @@ fn prepare_for_cancel_by(ctx: &MmArc) -> mpsc::Receiver<AdexBehaviourCmd> {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     });
     maker_orders.insert(Uuid::from_bytes([1; 16]), MakerOrder {
         uuid: Uuid::from_bytes([1; 16]),
@@ -957,6 +1020,9 @@ fn prepare_for_cancel_by(ctx: &MmArc) -> mpsc::Receiver<AdexBehaviourCmd> {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     });
     maker_orders.insert(Uuid::from_bytes([2; 16]), MakerOrder {
         uuid: Uuid::from_bytes([2; 16]),
@@ -974,6 +1040,9 @@ fn prepare_for_cancel_by(ctx: &MmArc) -> mpsc::Receiver<AdexBehaviourCmd> {
         save_in_history: false,
         base_orderbook_ticker: None,
         rel_orderbook_ticker: None,
+        expires_at: None,
+        price_peg: None,
+        private_for: None,
     });
     taker_orders.insert(Uuid::from_bytes([3; 16]), TakerOrder {
         matches: HashMap::new(),
@@ -991,6 +1060,7 @@ fn prepare_for_cancel_by(ctx: &MmArc) -> mpsc::Receiver<AdexBehaviourCmd> {
             conf_settings: None,
             base_protocol_info: None,
             rel_protocol_info: None,
+            swap_msg_encryption: None,
         },
         order_type: OrderType::GoodTillCancelled,
         min_volume: 0.into(),
@@ -1089,6 +1159,7 @@ fn test_taker_order_match_by() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     let mut order = TakerOrder {
@@ -1115,6 +1186,7 @@ fn test_taker_order_match_by() {
         conf_settings: None,
         base_protocol_info: None,
         rel_protocol_info: None,
+        swap_msg_encryption: None,
     };
 
     assert_eq!(MatchReservedResult::NotMatched, order.match_reserved(&reserved));
@@ -1149,9 +1221,10 @@ fn lp_connect_start_bob_should_not_be_invoked_if_order_match_already_connected()
 
     static mut CONNECT_START_CALLED: bool = false;
     lp_connect_start_bob.mock_safe(|_, _, _| {
-        MockResult::Return(unsafe {
+        unsafe {
             CONNECT_START_CALLED = true;
-        })
+        }
+        MockResult::Return(Box::pin(async { Ok(()) }))
     });
 
     let connect: TakerConnect = json::from_str(r#"{"taker_order_uuid":"2f9afe84-7a89-4194-8947-45fba563118f","maker_order_uuid":"5f6516ea-ccaa-453a-9e37-e1c2c0d527e3","method":"connect","sender_pubkey":"031d4256c4bc9f99ac88bf3dba21773132281f65f9bf23a59928bce08961e2f3","dest_pub_key":"c6a78589e18b482aea046975e6d0acbdea7bf7dbf04d9d5bd67fda917815e3ed"}"#).unwrap();
@@ -1580,6 +1653,9 @@ pub(super) fn make_random_orders(
             pair_trie_root: H64::default(),
             base_protocol_info: vec![],
             rel_protocol_info: vec![],
+            expires_at: None,
+            nonce: 0,
+            signature: vec![],
         };
 
         orders.push((order, pubkey.clone()).into());
@@ -1632,6 +1708,12 @@ fn test_process_get_orderbook_request() {
     pubkey1_orders.sort_unstable_by(|x, y| x.uuid.cmp(&y.uuid));
     pubkey2_orders.sort_unstable_by(|x, y| x.uuid.cmp(&y.uuid));
     pubkey3_orders.sort_unstable_by(|x, y| x.uuid.cmp(&y.uuid));
+    // the expiration and the signature aren't a part of the trie state, but they must be synced too
+    for (i, order) in pubkey1_orders.iter_mut().enumerate() {
+        order.expires_at = Some(now_ms() / 1000 + 3600);
+        order.nonce = i as u64 + 1;
+        order.signature = vec![i as u8; 65];
+    }
 
     let mut orders_by_pubkeys = HashMap::new();
     orders_by_pubkeys.insert(pubkey1, pubkey1_orders);
@@ -1668,7 +1750,10 @@ fn test_process_get_orderbook_request() {
         let mut actual: Vec<OrderbookItem> = item
             .orders
             .iter()
-            .map(|(_uuid, order)| OrderbookItem::from_p2p_and_proto_info(order.clone(), BaseRelProtocolInfo::default()))
+            .map(|(uuid, order)| {
+                let sync_info = orderbook.sync_infos.get(uuid).cloned().unwrap_or_default();
+                OrderbookItem::from_p2p_and_info(order.clone(), BaseRelProtocolInfo::default(), sync_info)
+            })
             .collect();
         actual.sort_unstable_by(|x, y| x.uuid.cmp(&y.uuid));
         log!([pubkey]"-"[actual.len()]);
@@ -1713,6 +1798,76 @@ fn test_process_get_orderbook_request_limit() {
     assert!(err.contains("Orderbook too large"));
 }
 
+#[test]
+fn test_process_get_orderbook_chunk_request() {
+    const PUBKEYS_NUMBER: usize = 4;
+
+    let (ctx, _pubkey, _secret) = make_ctx_for_tests();
+
+    let ordermatch_ctx = Arc::new(OrdermatchContext::default());
+    let ordermatch_ctx_clone = ordermatch_ctx.clone();
+    OrdermatchContext::from_ctx.mock_safe(move |_| MockResult::Return(Ok(ordermatch_ctx_clone.clone())));
+
+    let mut orderbook = block_on(ordermatch_ctx.orderbook.lock());
+
+    // every pubkey has more than a third of the chunk limit, so each chunk fits two pubkeys
+    let orders_number = ORDERBOOK_CHUNK_MAX_ORDERS / 3 + 1;
+    let mut pubkeys = Vec::with_capacity(PUBKEYS_NUMBER);
+    for idx in 0..PUBKEYS_NUMBER {
+        let (pubkey, secret) = pubkey_and_secret_for_test(&format!("passphrase-{}", idx));
+        for order in make_random_orders(pubkey.clone(), &secret, "RICK".into(), "MORTY".into(), orders_number) {
+            orderbook.insert_or_update_order_update_trie(order);
+        }
+        pubkeys.push(pubkey);
+    }
+    pubkeys.sort();
+
+    // avoid dead lock on orderbook as process_get_orderbook_chunk_request also acquires it
+    drop(orderbook);
+
+    let mut received_pubkeys = Vec::new();
+    let mut from_pubkey = None;
+    let mut chunks = 0;
+    loop {
+        let encoded = block_on(process_get_orderbook_chunk_request(
+            ctx.clone(),
+            "RICK".into(),
+            "MORTY".into(),
+            from_pubkey,
+        ))
+        .unwrap()
+        .unwrap();
+        let chunk = decode_message::<GetOrderbookChunkRes>(&encoded).unwrap();
+        chunks += 1;
+
+        let orders_in_chunk: usize = chunk
+            .orderbook
+            .pubkey_orders
+            .values()
+            .map(|item| item.orders.len())
+            .sum();
+        assert!(orders_in_chunk <= ORDERBOOK_CHUNK_MAX_ORDERS);
+        assert_eq!(chunk.orderbook.protocol_infos.len(), orders_in_chunk);
+
+        let mut chunk_pubkeys: Vec<_> = chunk
+            .orderbook
+            .pubkey_orders
+            .into_iter()
+            .map(|(pubkey, _)| pubkey)
+            .collect();
+        chunk_pubkeys.sort();
+        received_pubkeys.extend(chunk_pubkeys);
+
+        from_pubkey = chunk.next_pubkey;
+        if from_pubkey.is_none() {
+            break;
+        }
+    }
+
+    assert_eq!(chunks, 2);
+    assert_eq!(received_pubkeys, pubkeys);
+}
+
 #[test]
 fn test_request_and_fill_orderbook() {
     const PUBKEYS_NUMBER: usize = 3;
@@ -1747,9 +1902,10 @@ fn test_request_and_fill_orderbook() {
         }
     }
 
-    let expected_request = P2PRequest::Ordermatch(OrdermatchRequest::GetOrderbook {
+    let expected_request = P2PRequest::Ordermatch(OrdermatchRequest::GetOrderbookChunk {
         base: "RICK".into(),
         rel: "MORTY".into(),
+        from_pubkey: None,
     });
 
     let orders = expected_orders.clone();
@@ -1776,11 +1932,15 @@ fn test_request_and_fill_orderbook() {
                 (pubkey, item)
             })
             .collect();
-        let orderbook = GetOrderbookRes {
-            pubkey_orders: result,
-            protocol_infos: HashMap::new(),
+        let chunk = GetOrderbookChunkRes {
+            orderbook: GetOrderbookRes {
+                pubkey_orders: result,
+                protocol_infos: HashMap::new(),
+                sync_infos: HashMap::new(),
+            },
+            next_pubkey: None,
         };
-        let encoded = encode_message(&orderbook).unwrap();
+        let encoded = encode_message(&chunk).unwrap();
 
         // send the response through the response channel
         response_tx.send(Some((PeerId::random(), encoded))).unwrap();
@@ -2238,7 +2398,8 @@ fn test_process_sync_pubkey_orderbook_state_after_new_orders_added() {
             (
                 *uuid.as_bytes(),
                 order.map(|o| {
-                    let o = OrderbookItem::from_p2p_and_proto_info(o, BaseRelProtocolInfo::default());
+                    let o =
+                        OrderbookItem::from_p2p_and_info(o, BaseRelProtocolInfo::default(), OrderSyncInfo::default());
                     o.trie_state_bytes()
                 }),
             )
@@ -2680,6 +2841,7 @@ fn test_orderbook_sync_trie_diff_time_cache() {
             .map(|(uuid, order)| (uuid, order.into()))
             .collect(),
         &HashMap::new(),
+        &HashMap::new(),
     );
 
     assert_eq!(new_alice_root, *bob_root);
@@ -2728,6 +2890,7 @@ fn test_orderbook_sync_trie_diff_time_cache() {
             .map(|(uuid, order)| (uuid, order.map(From::from)))
             .collect(),
         &HashMap::new(),
+        &HashMap::new(),
     );
     assert_eq!(new_alice_root, *bob_root);
 }
@@ -2853,6 +3016,9 @@ fn test_trie_state_bytes() {
         created_at,
         base_protocol_info: vec![1, 2, 3],
         rel_protocol_info: vec![4, 5, 6],
+        expires_at: Some(now_ms() / 1000),
+        nonce: 1,
+        signature: vec![7, 8, 9],
     };
 
     let new_bytes = new.trie_state_bytes();
@@ -2990,3 +3156,177 @@ fn check_sync_pubkey_state_p2p_res_serde() {
 
     let _old_from_new: SyncPubkeyOrderbookStateResV1 = rmp_serde::from_read_ref(&new_serialized).unwrap();
 }
+
+#[test]
+fn test_maker_order_is_expired() {
+    let coin = MmCoinEnum::Test(TestCoin::default());
+    let now = now_ms() / 1000;
+
+    let maker_order = MakerOrderBuilder::new(&coin, &coin)
+        .with_max_base_vol(1.into())
+        .with_price(1.into())
+        .with_expires_at(Some(now + 10))
+        .build_unchecked();
+    assert!(!maker_order.is_expired(now));
+    assert!(maker_order.is_expired(now + 10));
+
+    let maker_order = MakerOrderBuilder::new(&coin, &coin)
+        .with_max_base_vol(1.into())
+        .with_price(1.into())
+        .build_unchecked();
+    assert!(!maker_order.is_expired(now + 10));
+}
+
+#[test]
+fn test_maker_order_private_for() {
+    let coin = MmCoinEnum::Test(TestCoin::default());
+    let allowed = key_pair_from_seed("allowed").unwrap();
+    let other = key_pair_from_seed("other").unwrap();
+    let taker_pubkey = |public: &[u8]| {
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&public[1..]);
+        H256Json::from(bytes)
+    };
+
+    let maker_order = MakerOrderBuilder::new(&coin, &coin)
+        .with_max_base_vol(1.into())
+        .with_price(1.into())
+        .with_private_for(Some(vec![hex::encode(&**allowed.public())]))
+        .build_unchecked();
+    assert!(maker_order.is_private());
+    assert!(maker_order.is_allowed_taker(&taker_pubkey(&**allowed.public())));
+    assert!(!maker_order.is_allowed_taker(&taker_pubkey(&**other.public())));
+
+    let maker_order = MakerOrderBuilder::new(&coin, &coin)
+        .with_max_base_vol(1.into())
+        .with_price(1.into())
+        .build_unchecked();
+    assert!(!maker_order.is_private());
+    assert!(maker_order.is_allowed_taker(&taker_pubkey(&**other.public())));
+
+    assert!(validate_private_for(vec![]).is_err());
+    assert!(validate_private_for(vec!["02".into()]).is_err());
+}
+
+#[test]
+fn test_set_price_req_expires_at() {
+    let req = json!({
+        "base": "RICK",
+        "rel": "MORTY",
+        "price": 1,
+        "volume": 1,
+    });
+
+    let mut with_expires_at = req.clone();
+    with_expires_at["expires_at"] = 1000.into();
+    let set_price: SetPriceReq = json::from_value(with_expires_at).unwrap();
+    assert_eq!(set_price.expires_at().unwrap(), Some(1000));
+
+    let mut with_expires_in = req.clone();
+    with_expires_in["expires_in"] = 1000.into();
+    let set_price: SetPriceReq = json::from_value(with_expires_in).unwrap();
+    let expires_at = set_price.expires_at().unwrap().unwrap();
+    assert!(expires_at >= now_ms() / 1000 + 1000);
+
+    let mut with_both = req.clone();
+    with_both["expires_at"] = 1000.into();
+    with_both["expires_in"] = 1000.into();
+    let set_price: SetPriceReq = json::from_value(with_both).unwrap();
+    set_price.expires_at().unwrap_err();
+
+    let set_price: SetPriceReq = json::from_value(req).unwrap();
+    assert_eq!(set_price.expires_at().unwrap(), None);
+}
+
+#[test]
+fn test_order_message_signature() {
+    let (ctx, pubkey, secret) = make_ctx_for_tests();
+    let pubkey_bytes = hex::decode(&pubkey).unwrap();
+    let mut order = make_random_orders(pubkey.clone(), &secret, "RICK".into(), "MORTY".into(), 1).remove(0);
+    order.nonce = next_order_nonce(&ctx);
+    order.signature = sign_order_payload(&ctx, &order.signing_payload());
+    assert!(is_order_signature_valid(
+        &pubkey_bytes,
+        &order.signing_payload(),
+        &order.signature
+    ));
+    assert!(block_on(insert_or_update_order_if_newer(&ctx, order.clone())));
+
+    // the update is signed along with the resulting order state
+    let mut message = new_protocol::MakerOrderUpdated::new(order.uuid);
+    message.with_new_price(BigRational::from_integer(2.into()));
+    message.with_nonce(next_order_nonce(&ctx));
+    let mut updated = order.clone();
+    updated.apply_updated(&message);
+    message.with_signature(sign_order_payload(&ctx, &updated.signing_payload()));
+    assert!(block_on(process_maker_order_updated(
+        ctx.clone(),
+        pubkey.clone(),
+        message.clone()
+    )));
+
+    // the unsigned copy for the older nodes is forwarded but not applied
+    assert!(block_on(process_maker_order_updated(
+        ctx.clone(),
+        pubkey.clone(),
+        message.to_v2()
+    )));
+    let mut unsigned = new_protocol::MakerOrderUpdated::new(order.uuid);
+    unsigned.with_new_price(BigRational::from_integer(5.into()));
+    assert!(block_on(process_maker_order_updated(
+        ctx.clone(),
+        pubkey.clone(),
+        unsigned.to_v2()
+    )));
+
+    // the signature doesn't match the tampered update
+    let signature = message.signature().to_vec();
+    let mut tampered = new_protocol::MakerOrderUpdated::new(order.uuid);
+    tampered.with_new_price(BigRational::from_integer(3.into()));
+    tampered.with_nonce(next_order_nonce(&ctx));
+    tampered.with_signature(signature);
+    assert!(!block_on(process_maker_order_updated(ctx.clone(), pubkey, tampered)));
+
+    let ordermatch_ctx = OrdermatchContext::from_ctx(&ctx).unwrap();
+    let orderbook = block_on(ordermatch_ctx.orderbook.lock());
+    let stored = orderbook.order_set.get(&order.uuid).unwrap();
+    assert_eq!(stored.price, BigRational::from_integer(2.into()));
+    assert!(is_order_signature_valid(
+        &pubkey_bytes,
+        &stored.signing_payload(),
+        &stored.signature
+    ));
+
+    // the messages of the older nodes are not signed
+    assert!(is_order_signature_valid(&pubkey_bytes, &order.signing_payload(), &[]));
+}
+
+#[test]
+fn test_next_order_nonce_increases() {
+    let (ctx, _pubkey, _secret) = make_ctx_for_tests();
+    let first = next_order_nonce(&ctx);
+    let second = next_order_nonce(&ctx);
+    assert!(first >= now_ms() - 1000);
+    assert!(second > first);
+}
+
+#[test]
+fn test_replayed_order_messages_rejected() {
+    let (ctx, pubkey, secret) = make_ctx_for_tests();
+    let mut order = make_random_orders(pubkey.clone(), &secret, "RICK".into(), "MORTY".into(), 1).remove(0);
+    order.nonce = 10;
+    assert!(block_on(insert_or_update_order_if_newer(&ctx, order.clone())));
+
+    // the replayed message has the same nonce
+    assert!(!block_on(insert_or_update_order_if_newer(&ctx, order.clone())));
+
+    // the cancellation older than the latest order message is stale
+    assert!(!block_on(delete_order(&ctx, &pubkey, order.uuid, 9)));
+    assert!(block_on(delete_order(&ctx, &pubkey, order.uuid, 11)));
+
+    // the order can't be brought back by the replayed message
+    order.nonce = 11;
+    assert!(!block_on(insert_or_update_order_if_newer(&ctx, order.clone())));
+    order.nonce = 12;
+    assert!(block_on(insert_or_update_order_if_newer(&ctx, order)));
+}
@@ -9,9 +9,12 @@ use serde_json::{self as json, Value as Json};
 use std::net::SocketAddr;
 
 use super::lp_commands::*;
-use crate::mm2::lp_ordermatch::{best_orders_rpc, buy, cancel_all_orders, cancel_order, my_orders, order_status,
-                                orderbook_depth_rpc, orderbook_rpc, orders_history_by_filter, sell, set_price,
-                                update_maker_order};
+use crate::mm2::lp_ordermatch::{best_orders_rpc, buy, cancel_all_orders, cancel_order, grid_strategy_status,
+                                my_orders, order_status, orderbook_depth_rpc, orderbook_rpc, orders_history_by_filter,
+                                private_offers, push_oracle_price, sell, set_price, start_grid_strategy,
+                                stop_grid_strategy, update_maker_order};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::mm2::lp_ordermatch::{ohlcv, ticker_24h, trade_tape};
 use crate::mm2::lp_swap::{active_swaps_rpc, all_swaps_uuids_by_filter, ban_pubkey_rpc, coins_needed_for_kick_start,
                           import_swaps, list_banned_pubkeys_rpc, max_taker_vol, my_recent_swaps, my_swap_status,
                           recover_funds_of_swap, stats_swap_status, unban_pubkeys_rpc};
@@ -59,7 +62,6 @@ pub fn dispatcher(req: Json, ctx: MmArc) -> DispatcherRes {
     };
     DispatcherRes::Match(match &method[..] {
         // Sorted alphanumerically (on the first latter) for readability.
-        // "autoprice" => lp_autoprice (ctx, req),
         "active_swaps" => hyres(active_swaps_rpc(ctx, req)),
         "all_swaps_uuids_by_filter" => all_swaps_uuids_by_filter(ctx, req),
         "ban_pubkey" => hyres(ban_pubkey_rpc(ctx, req)),
@@ -75,12 +77,15 @@ pub fn dispatcher(req: Json, ctx: MmArc) -> DispatcherRes {
         "enable" => hyres(enable(ctx, req)),
         "get_enabled_coins" => hyres(get_enabled_coins(ctx)),
         "get_gossip_mesh" => hyres(get_gossip_mesh(ctx)),
+        "get_gossip_peer_scores" => hyres(get_gossip_peer_scores(ctx)),
         "get_gossip_peer_topics" => hyres(get_gossip_peer_topics(ctx)),
         "get_gossip_topic_peers" => hyres(get_gossip_topic_peers(ctx)),
         "get_my_peer_id" => hyres(get_my_peer_id(ctx)),
+        "get_network_diagnostics" => hyres(get_network_diagnostics(ctx)),
         "get_peers_info" => hyres(get_peers_info(ctx)),
         "get_relay_mesh" => hyres(get_relay_mesh(ctx)),
         "get_trade_fee" => hyres(get_trade_fee(ctx, req)),
+        "grid_strategy_status" => hyres(grid_strategy_status(ctx, req)),
         // "fundvalue" => lp_fundvalue (ctx, req, false),
         "help" => help(),
         "import_swaps" => {
@@ -104,10 +109,22 @@ pub fn dispatcher(req: Json, ctx: MmArc) -> DispatcherRes {
         "my_recent_swaps" => my_recent_swaps(ctx, req),
         "my_swap_status" => my_swap_status(ctx, req),
         "my_tx_history" => hyres(my_tx_history(ctx, req)),
+        "ohlcv" => {
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                hyres(ohlcv(ctx, req))
+            }
+            #[cfg(target_arch = "wasm32")]
+            {
+                return DispatcherRes::NoMatch(req);
+            }
+        },
         "orders_history_by_filter" => hyres(orders_history_by_filter(ctx, req)),
         "order_status" => hyres(order_status(ctx, req)),
         "orderbook" => hyres(orderbook_rpc(ctx, req)),
         "orderbook_depth" => hyres(orderbook_depth_rpc(ctx, req)),
+        "private_offers" => hyres(private_offers(ctx, req)),
+        "push_oracle_price" => hyres(push_oracle_price(ctx, req)),
         "sim_panic" => hyres(sim_panic(req)),
         "recover_funds_of_swap" => {
             #[cfg(not(target_arch = "wasm32"))]
@@ -125,9 +142,31 @@ pub fn dispatcher(req: Json, ctx: MmArc) -> DispatcherRes {
         "set_required_confirmations" => hyres(set_required_confirmations(ctx, req)),
         "set_requires_notarization" => hyres(set_requires_notarization(ctx, req)),
         "setprice" => hyres(set_price(ctx, req)),
+        "start_grid_strategy" => hyres(start_grid_strategy(ctx, req)),
         "stats_swap_status" => stats_swap_status(ctx, req),
         "stop" => stop(ctx),
+        "stop_grid_strategy" => hyres(stop_grid_strategy(ctx, req)),
+        "ticker_24h" => {
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                hyres(ticker_24h(ctx, req))
+            }
+            #[cfg(target_arch = "wasm32")]
+            {
+                return DispatcherRes::NoMatch(req);
+            }
+        },
         "trade_preimage" => hyres(into_legacy::trade_preimage(ctx, req)),
+        "trade_tape" => {
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                hyres(trade_tape(ctx, req))
+            }
+            #[cfg(target_arch = "wasm32")]
+            {
+                return DispatcherRes::NoMatch(req);
+            }
+        },
         "unban_pubkeys" => hyres(unban_pubkeys_rpc(ctx, req)),
         "update_maker_order" => hyres(update_maker_order(ctx, req)),
         "validateaddress" => hyres(validate_address(ctx, req)),
@@ -1,5 +1,7 @@
 use super::lp_protocol::{MmRpcBuilder, MmRpcRequest};
 use super::{DispatcherError, DispatcherResult, PUBLIC_METHODS};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::mm2::lp_swap::{export_swaps, restore_swaps, swap_refund_manager_status};
 use crate::mm2::rpc::rate_limiter::{process_rate_limit, RateLimitContext};
 use crate::{mm2::lp_stats::{add_node_to_version_stat, remove_node_from_version_stat, start_version_stat_collection,
                             stop_version_stat_collection, update_version_stat_collection},
@@ -93,10 +95,16 @@ async fn auth(request: &MmRpcRequest, ctx: &MmArc, client: &SocketAddr) -> Dispa
 async fn dispatcher(request: MmRpcRequest, ctx: MmArc) -> DispatcherResult<Response<Vec<u8>>> {
     match request.method.as_str() {
         "add_node_to_version_stat" => handle_mmrpc(ctx, request, add_node_to_version_stat).await,
+        #[cfg(not(target_arch = "wasm32"))]
+        "export_swaps" => handle_mmrpc(ctx, request, export_swaps).await,
         "get_public_key" => handle_mmrpc(ctx, request, get_public_key).await,
         "remove_node_from_version_stat" => handle_mmrpc(ctx, request, remove_node_from_version_stat).await,
+        #[cfg(not(target_arch = "wasm32"))]
+        "restore_swaps" => handle_mmrpc(ctx, request, restore_swaps).await,
         "start_version_stat_collection" => handle_mmrpc(ctx, request, start_version_stat_collection).await,
         "stop_version_stat_collection" => handle_mmrpc(ctx, request, stop_version_stat_collection).await,
+        #[cfg(not(target_arch = "wasm32"))]
+        "swap_refund_manager_status" => handle_mmrpc(ctx, request, swap_refund_manager_status).await,
         "update_version_stat_collection" => handle_mmrpc(ctx, request, update_version_stat_collection).await,
         "trade_preimage" => handle_mmrpc(ctx, request, trade_preimage_rpc).await,
         "withdraw" => handle_mmrpc(ctx, request, withdraw).await,
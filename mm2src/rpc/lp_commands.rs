@@ -245,12 +245,14 @@ pub fn version() -> HyRes {
 
 pub async fn get_peers_info(ctx: MmArc) -> Result<Response<Vec<u8>>, String> {
     use crate::mm2::lp_network::P2PContext;
-    use mm2_libp2p::atomicdex_behaviour::get_peers_info;
+    use mm2_libp2p::atomicdex_behaviour::{get_peers_info, get_reachability};
     let ctx = P2PContext::fetch_from_mm_arc(&ctx);
     let cmd_tx = ctx.cmd_tx.lock().await.clone();
-    let result = get_peers_info(cmd_tx).await;
+    let result = get_peers_info(cmd_tx.clone()).await;
+    let reachability = get_reachability(cmd_tx).await;
     let result = json!({
         "result": result,
+        "reachability": reachability,
     });
     let res = try_s!(json::to_vec(&result));
     Ok(try_s!(Response::builder().body(res)))
@@ -269,6 +271,19 @@ pub async fn get_gossip_mesh(ctx: MmArc) -> Result<Response<Vec<u8>>, String> {
     Ok(try_s!(Response::builder().body(res)))
 }
 
+pub async fn get_gossip_peer_scores(ctx: MmArc) -> Result<Response<Vec<u8>>, String> {
+    use crate::mm2::lp_network::P2PContext;
+    use mm2_libp2p::atomicdex_behaviour::get_gossip_peer_scores;
+    let ctx = P2PContext::fetch_from_mm_arc(&ctx);
+    let cmd_tx = ctx.cmd_tx.lock().await.clone();
+    let result = get_gossip_peer_scores(cmd_tx).await;
+    let result = json!({
+        "result": result,
+    });
+    let res = try_s!(json::to_vec(&result));
+    Ok(try_s!(Response::builder().body(res)))
+}
+
 pub async fn get_gossip_peer_topics(ctx: MmArc) -> Result<Response<Vec<u8>>, String> {
     use crate::mm2::lp_network::P2PContext;
     use mm2_libp2p::atomicdex_behaviour::get_gossip_peer_topics;
@@ -308,6 +323,19 @@ pub async fn get_relay_mesh(ctx: MmArc) -> Result<Response<Vec<u8>>, String> {
     Ok(try_s!(Response::builder().body(res)))
 }
 
+pub async fn get_network_diagnostics(ctx: MmArc) -> Result<Response<Vec<u8>>, String> {
+    use crate::mm2::lp_network::P2PContext;
+    use mm2_libp2p::atomicdex_behaviour::get_network_diagnostics;
+    let ctx = P2PContext::fetch_from_mm_arc(&ctx);
+    let cmd_tx = ctx.cmd_tx.lock().await.clone();
+    let result = get_network_diagnostics(cmd_tx).await;
+    let result = json!({
+        "result": result,
+    });
+    let res = try_s!(json::to_vec(&result));
+    Ok(try_s!(Response::builder().body(res)))
+}
+
 pub async fn get_my_peer_id(ctx: MmArc) -> Result<Response<Vec<u8>>, String> {
     let peer_id = try_s!(ctx.peer_id.ok_or("Peer ID is not initialized"));
     let result = json!({
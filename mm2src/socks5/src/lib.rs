@@ -0,0 +1,374 @@
+//! The SOCKS5 proxy client (RFC 1928) routing the outbound TCP connections, e.g. through Tor.
+//! The proxy is configured globally by the `socks5_proxy` object of the MM2 config,
+//! e.g. `{"socks5_proxy": {"address": "127.0.0.1:9050", "refuse_clearnet_fallback": true}}`.
+//! The username/password authentication (RFC 1929) is used if the `username` and `password` are set.
+
+#[macro_use] extern crate lazy_static;
+
+#[cfg(not(target_arch = "wasm32"))] use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+#[cfg(not(target_arch = "wasm32"))] use std::net::IpAddr;
+use std::sync::RwLock;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::{io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream};
+
+const SOCKS5_VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+const AUTH_SUCCEEDED: u8 = 0;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN_NAME: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+const REPLY_SUCCEEDED: u8 = 0;
+
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+pub struct Socks5ProxyConfig {
+    /// The `host:port` of the proxy, e.g. `127.0.0.1:9050` of the Tor daemon
+    pub address: String,
+    /// Fail the connections if the proxy doesn't work instead of connecting directly
+    #[serde(default)]
+    pub refuse_clearnet_fallback: bool,
+    /// The username of the RFC 1929 authentication, the `password` is expected to be set too
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// The password of the RFC 1929 authentication
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/// Doesn't print the password to the logs.
+impl fmt::Debug for Socks5ProxyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Socks5ProxyConfig")
+            .field("address", &self.address)
+            .field("refuse_clearnet_fallback", &self.refuse_clearnet_fallback)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "***"))
+            .finish()
+    }
+}
+
+impl Socks5ProxyConfig {
+    pub fn new(address: String) -> Socks5ProxyConfig {
+        Socks5ProxyConfig {
+            address,
+            refuse_clearnet_fallback: false,
+            username: None,
+            password: None,
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn credentials(&self) -> io::Result<Option<(&str, &str)>> {
+        match (&self.username, &self.password) {
+            (Some(username), Some(password)) => {
+                if username.is_empty() || username.len() > u8::MAX as usize {
+                    return Err(invalid_input(format!(
+                        "Invalid SOCKS5 username length {}",
+                        username.len()
+                    )));
+                }
+                if password.is_empty() || password.len() > u8::MAX as usize {
+                    return Err(invalid_input(format!(
+                        "Invalid SOCKS5 password length {}",
+                        password.len()
+                    )));
+                }
+                Ok(Some((username.as_str(), password.as_str())))
+            },
+            (None, None) => Ok(None),
+            _ => Err(invalid_input(
+                "Both the SOCKS5 username and password are expected to be set".into(),
+            )),
+        }
+    }
+}
+
+lazy_static! {
+    static ref GLOBAL_PROXY: RwLock<Option<Socks5ProxyConfig>> = RwLock::new(None);
+}
+
+/// Sets the proxy used by [`connect_tcp`] and the shared HTTP client, the proxy is not supported in WASM.
+pub fn set_global_proxy(proxy: Option<Socks5ProxyConfig>) { *GLOBAL_PROXY.write().expect("!write") = proxy; }
+
+pub fn global_proxy() -> Option<Socks5ProxyConfig> { GLOBAL_PROXY.read().expect("!read").clone() }
+
+#[cfg(not(target_arch = "wasm32"))]
+fn proxy_error(description: String) -> io::Error { io::Error::new(io::ErrorKind::Other, description) }
+
+fn invalid_input(description: String) -> io::Error { io::Error::new(io::ErrorKind::InvalidInput, description) }
+
+/// Splits the `host:port` address, the IPv6 host is expected in the square brackets.
+pub fn split_host_port(address: &str) -> io::Result<(&str, u16)> {
+    let invalid_address = || invalid_input(format!("Invalid address {}", address));
+    let pos = address.rfind(':').ok_or_else(invalid_address)?;
+    let port = address[pos + 1..].parse().map_err(|_| invalid_address())?;
+    let host = address[..pos].trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(invalid_address());
+    }
+    Ok((host, port))
+}
+
+/// Authenticates by the username and password (RFC 1929) after the method is selected by the proxy.
+#[cfg(not(target_arch = "wasm32"))]
+async fn authenticate(stream: &mut TcpStream, username: &str, password: &str) -> io::Result<()> {
+    let mut request = Vec::with_capacity(3 + username.len() + password.len());
+    request.push(USERNAME_PASSWORD_VERSION);
+    request.push(username.len() as u8);
+    request.extend_from_slice(username.as_bytes());
+    request.push(password.len() as u8);
+    request.extend_from_slice(password.as_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != USERNAME_PASSWORD_VERSION {
+        return Err(proxy_error(format!(
+            "Unexpected SOCKS5 authentication reply version {}",
+            reply[0]
+        )));
+    }
+    if reply[1] != AUTH_SUCCEEDED {
+        return Err(proxy_error(format!(
+            "SOCKS5 authentication failed, status {}",
+            reply[1]
+        )));
+    }
+    Ok(())
+}
+
+/// Connects to the `host:port` through the SOCKS5 `proxy`.
+/// The `host` is resolved by the proxy unless it's an IP address, so the DNS requests don't leak.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn socks5_connect(proxy: &Socks5ProxyConfig, host: &str, port: u16) -> io::Result<TcpStream> {
+    let credentials = proxy.credentials()?;
+    let mut stream = TcpStream::connect(&proxy.address).await?;
+
+    let greeting = match credentials {
+        Some(_) => [SOCKS5_VERSION, 1, USERNAME_PASSWORD],
+        None => [SOCKS5_VERSION, 1, NO_AUTHENTICATION],
+    };
+    stream.write_all(&greeting).await?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await?;
+    if method[0] != SOCKS5_VERSION {
+        return Err(proxy_error(format!("Unexpected SOCKS5 reply version {}", method[0])));
+    }
+    match (method[1], credentials) {
+        (NO_AUTHENTICATION, None) => (),
+        (USERNAME_PASSWORD, Some((username, password))) => authenticate(&mut stream, username, password).await?,
+        (NO_ACCEPTABLE_METHODS, _) => {
+            return Err(proxy_error(format!(
+                "SOCKS5 proxy {} accepted none of the offered authentication methods",
+                proxy.address
+            )))
+        },
+        (method, _) => {
+            return Err(proxy_error(format!(
+                "SOCKS5 proxy {} selected an unexpected authentication method {}",
+                proxy.address, method
+            )))
+        },
+    }
+
+    let mut request = vec![SOCKS5_VERSION, CMD_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        },
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        },
+        Err(_) => {
+            if host.len() > u8::MAX as usize {
+                return Err(proxy_error(format!("Host name {} is too long", host)));
+            }
+            request.push(ATYP_DOMAIN_NAME);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        },
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS5_VERSION {
+        return Err(proxy_error(format!("Unexpected SOCKS5 reply version {}", reply[0])));
+    }
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(proxy_error(format!(
+            "SOCKS5 proxy {} failed to connect to {}:{}, reply code {}",
+            proxy.address, host, port, reply[1]
+        )));
+    }
+
+    // skip the address bound by the proxy
+    let bound_address_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN_NAME => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        },
+        address_type => return Err(proxy_error(format!("Unknown SOCKS5 address type {}", address_type))),
+    };
+    let mut bound_address = vec![0u8; bound_address_len + 2];
+    stream.read_exact(&mut bound_address).await?;
+    Ok(stream)
+}
+
+/// Connects to the `host:port` address through the global proxy if it's set.
+/// Connects directly if the proxy fails unless the `refuse_clearnet_fallback` is set.
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn connect_tcp(address: &str) -> io::Result<TcpStream> {
+    let proxy = match global_proxy() {
+        Some(proxy) => proxy,
+        None => return TcpStream::connect(address).await,
+    };
+    let (host, port) = split_host_port(address)?;
+    match socks5_connect(&proxy, host, port).await {
+        Ok(stream) => Ok(stream),
+        Err(e) if proxy.refuse_clearnet_fallback => Err(e),
+        Err(e) => {
+            warn!(
+                "Error {} connecting to {} through the proxy, connecting directly",
+                e, address
+            );
+            TcpStream::connect(address).await
+        },
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Replies to the connect request of `example.com:443` and sends the `payload`.
+    async fn serve_connect(stream: &mut TcpStream) {
+        let mut request = [0u8; 5 + 11 + 2];
+        stream.read_exact(&mut request).await.unwrap();
+        assert_eq!(&request[..5], &[SOCKS5_VERSION, CMD_CONNECT, 0, ATYP_DOMAIN_NAME, 11]);
+        assert_eq!(&request[5..16], b"example.com");
+        assert_eq!(&request[16..], &443u16.to_be_bytes());
+        stream
+            .write_all(&[SOCKS5_VERSION, REPLY_SUCCEEDED, 0, ATYP_IPV4, 127, 0, 0, 1, 0, 80])
+            .await
+            .unwrap();
+        stream.write_all(b"payload").await.unwrap();
+    }
+
+    async fn read_payload(proxy: &Socks5ProxyConfig) {
+        let mut stream = socks5_connect(proxy, "example.com", 443).await.unwrap();
+        let mut payload = [0u8; 7];
+        stream.read_exact(&mut payload).await.unwrap();
+        assert_eq!(&payload, b"payload");
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(
+            split_host_port("electrum1.cipig.net:10017").unwrap(),
+            ("electrum1.cipig.net", 10017)
+        );
+        assert_eq!(split_host_port("[::1]:9050").unwrap(), ("::1", 9050));
+        assert!(split_host_port("electrum1.cipig.net").is_err());
+        assert!(split_host_port(":10017").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = Socks5ProxyConfig::new(listener.local_addr().unwrap().to_string());
+
+        let server = async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [SOCKS5_VERSION, 1, NO_AUTHENTICATION]);
+            stream.write_all(&[SOCKS5_VERSION, NO_AUTHENTICATION]).await.unwrap();
+            serve_connect(&mut stream).await;
+        };
+        tokio::join!(server, read_payload(&proxy));
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect_username_password() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = Socks5ProxyConfig {
+            username: Some("user".into()),
+            password: Some("secret".into()),
+            ..Socks5ProxyConfig::new(listener.local_addr().unwrap().to_string())
+        };
+
+        let server = async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [SOCKS5_VERSION, 1, USERNAME_PASSWORD]);
+            stream.write_all(&[SOCKS5_VERSION, USERNAME_PASSWORD]).await.unwrap();
+
+            let mut auth = [0u8; 3 + 4 + 6];
+            stream.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth[..2], &[USERNAME_PASSWORD_VERSION, 4]);
+            assert_eq!(&auth[2..6], b"user");
+            assert_eq!(auth[6], 6);
+            assert_eq!(&auth[7..], b"secret");
+            stream
+                .write_all(&[USERNAME_PASSWORD_VERSION, AUTH_SUCCEEDED])
+                .await
+                .unwrap();
+            serve_connect(&mut stream).await;
+        };
+        tokio::join!(server, read_payload(&proxy));
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect_auth_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy = Socks5ProxyConfig {
+            username: Some("user".into()),
+            password: Some("wrong".into()),
+            ..Socks5ProxyConfig::new(listener.local_addr().unwrap().to_string())
+        };
+
+        let server = async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            stream.write_all(&[SOCKS5_VERSION, USERNAME_PASSWORD]).await.unwrap();
+            let mut auth = [0u8; 3 + 4 + 5];
+            stream.read_exact(&mut auth).await.unwrap();
+            stream.write_all(&[USERNAME_PASSWORD_VERSION, 1]).await.unwrap();
+        };
+        let client = async { socks5_connect(&proxy, "example.com", 443).await.unwrap_err() };
+        let (_, error) = tokio::join!(server, client);
+        assert!(error.to_string().contains("authentication failed"), "{}", error);
+    }
+
+    #[test]
+    fn test_socks5_credentials() {
+        let mut proxy = Socks5ProxyConfig::new("127.0.0.1:9050".into());
+        assert_eq!(proxy.credentials().unwrap(), None);
+        proxy.username = Some("user".into());
+        assert!(proxy.credentials().is_err());
+        proxy.password = Some("x".repeat(256));
+        assert!(proxy.credentials().is_err());
+        proxy.password = Some("secret".into());
+        assert_eq!(proxy.credentials().unwrap(), Some(("user", "secret")));
+        assert!(!format!("{:?}", proxy).contains("secret"));
+    }
+}